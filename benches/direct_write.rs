@@ -0,0 +1,72 @@
+//! Compares reading sources into buffer of the native format (the samples
+//! are copied directly) with reading into buffer that needs per sample
+//! conversion.
+//!
+//! Usage: `cargo bench --bench direct_write`
+
+use std::{hint::black_box, time::Instant};
+
+use cpal::SampleFormat;
+use raplay::{
+    sample_buffer::SampleBufferMut,
+    source::{BufferSource, DeviceConfig, FnSource, Source},
+};
+
+const CHANNELS: u32 = 2;
+const RATE: u32 = 48000;
+const BUFFER_LEN: usize = 1024;
+const ITERATIONS: usize = 20000;
+
+/// Runs `read` `ITERATIONS` times, each time it should read one buffer.
+/// Returns the average time per sample in nanoseconds.
+fn measure(mut read: impl FnMut()) -> f64 {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        read();
+    }
+    start.elapsed().as_nanos() as f64 / (ITERATIONS * BUFFER_LEN) as f64
+}
+
+/// Measures the source with both [`f32`] (direct) and [`i16`] (converted)
+/// output.
+fn compare(name: &str, mut src: impl Source) {
+    src.init(&DeviceConfig {
+        channel_count: CHANNELS,
+        sample_rate: RATE,
+        sample_format: SampleFormat::F32,
+    })
+    .unwrap();
+
+    let mut f32_buf = vec![0_f32; BUFFER_LEN];
+    let direct = measure(|| {
+        black_box(src.read(&mut SampleBufferMut::F32(&mut f32_buf)).0);
+        black_box(&f32_buf);
+    });
+
+    let mut i16_buf = vec![0_i16; BUFFER_LEN];
+    let converted = measure(|| {
+        black_box(src.read(&mut SampleBufferMut::I16(&mut i16_buf)).0);
+        black_box(&i16_buf);
+    });
+
+    println!(
+        "{name:>8}: direct {direct:>6.3} ns/sample, converted \
+         {converted:>6.3} ns/sample ({:.1}x)",
+        converted / direct
+    );
+}
+
+fn main() {
+    // Long enough to not run out during the measurement.
+    let samples = BUFFER_LEN * ITERATIONS * 2;
+    let data = (0..samples).map(|i| (i as f32 * 0.01).sin()).collect();
+
+    compare("buffer", BufferSource::new(data, CHANNELS, RATE));
+    compare(
+        "fn",
+        FnSource::new(|_, out| {
+            out.fill(0.25);
+            out.len()
+        }),
+    );
+}
@@ -0,0 +1,19 @@
+//! Lists the available output devices and their capabilities.
+//!
+//! Usage: `cargo run --example devices`
+
+use anyhow::Result;
+use raplay::Sink;
+
+fn main() -> Result<()> {
+    for device in Sink::default().list_output_devices()? {
+        let default = if device.is_default { " (default)" } else { "" };
+        println!("{}{default}", device.name);
+        if let Some((min, max)) = device.sample_rates {
+            println!("    {min} - {max} Hz");
+        }
+        println!("    channels: {:?}", device.channel_counts);
+        println!("    formats: {:?}", device.sample_formats);
+    }
+    Ok(())
+}
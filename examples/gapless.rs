@@ -0,0 +1,59 @@
+//! Plays the files one after another without gaps. The next file is opened
+//! on a worker thread shortly before the current file ends and the sink
+//! switches to it without any gap.
+//!
+//! Usage: `cargo run --example gapless <file1> <file2> ...`
+
+use std::{env, time::Duration};
+
+use anyhow::{bail, Result};
+use raplay::{
+    source::{Source, Symph},
+    CallbackInfo, EventReceiver, Sink,
+};
+
+fn open(path: &str) -> Result<Symph> {
+    Ok(Symph::open(path, &Default::default())?)
+}
+
+fn main() -> Result<()> {
+    let paths: Vec<_> = env::args().skip(1).collect();
+    if paths.is_empty() {
+        bail!("Usage: gapless <file1> <file2> ...");
+    }
+
+    // The events are received on the main thread.
+    let mut sink = Sink::default();
+    let events = sink.event_receiver()?;
+    sink.on_err_callback(Some(|e| eprintln!("error: {e}")))?;
+
+    println!("Playing {}", paths[0]);
+    sink.play_file(&paths[0], true)?;
+
+    for path in &paths[1..] {
+        let p = path.clone();
+        sink.prefetch_with(Box::new(move || {
+            Ok(Box::new(open(&p)?) as Box<dyn Source>)
+        }))?;
+
+        if next_ended(&events)? {
+            return Ok(());
+        }
+        println!("Playing {path}");
+    }
+
+    while !next_ended(&events)? {}
+    Ok(())
+}
+
+/// Waits until the next source starts playing or the playback ends. Returns
+/// true if the playback ended.
+fn next_ended(events: &EventReceiver) -> Result<bool> {
+    loop {
+        match events.recv_timeout(Duration::from_secs(1))? {
+            Some(CallbackInfo::PrefetchSwapped(_)) => return Ok(false),
+            Some(CallbackInfo::SourceEnded) => return Ok(true),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,58 @@
+//! Plays a file and controls the playback from the standard input.
+//!
+//! Usage: `cargo run --example play <file> [device]`
+//!
+//! Commands:
+//! - `p`: play/pause
+//! - `s <secs>`: seek to the given position
+//! - `v <volume>`: set the volume
+//! - `q`: quit
+
+use std::{env, io::stdin, time::Duration};
+
+use anyhow::{bail, Result};
+use raplay::{source::Symph, Sink};
+
+fn main() -> Result<()> {
+    let mut args = env::args().skip(1);
+    let Some(path) = args.next() else {
+        bail!("Usage: play <file> [device]");
+    };
+
+    let mut builder = Sink::builder();
+    if let Some(device) = args.next() {
+        let Some(d) = Sink::default().find_device(&device)? else {
+            bail!("No device with the name '{device}'");
+        };
+        builder = builder.device(d);
+    }
+
+    let src = Symph::open(path, &Default::default())?;
+    let sink = builder
+        .callback(|c| println!("callback: {c:?}"))
+        .err_callback(|e| eprintln!("error: {e}"))
+        .fade(Duration::from_millis(200))
+        .source(src, true)
+        .build()?;
+
+    for line in stdin().lines() {
+        let line = line?;
+        let (cmd, arg) = line.trim().split_once(' ').unwrap_or((&line, ""));
+        match cmd {
+            "p" => sink.play(!sink.is_playing()?)?,
+            "s" => {
+                let ts =
+                    sink.seek_to(Duration::from_secs_f32(arg.parse()?))?;
+                println!("{:?}/{:?}", ts.current, ts.total);
+            }
+            "v" => sink.volume(arg.parse()?)?,
+            "q" => break,
+            _ => {
+                let ts = sink.get_timestamp()?;
+                println!("{:?}/{:?}", ts.current, ts.total);
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,73 @@
+//! Decodes a file into 32-bit float wav file without playing it, using the
+//! [`ManualSink`].
+//!
+//! Usage: `cargo run --example render <file> <out.wav>`
+
+use std::{
+    env,
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+    time::Instant,
+};
+
+use anyhow::{bail, Result};
+use cpal::SampleFormat;
+use raplay::{
+    sample_buffer::SampleBufferMut,
+    source::{DeviceConfig, Symph},
+    ManualSink,
+};
+
+const CHANNELS: u32 = 2;
+const RATE: u32 = 48000;
+
+fn write_header(out: &mut impl Write, data_len: u32) -> Result<()> {
+    out.write_all(b"RIFF")?;
+    out.write_all(&(36 + data_len).to_le_bytes())?;
+    out.write_all(b"WAVEfmt ")?;
+    out.write_all(&16_u32.to_le_bytes())?;
+    out.write_all(&3_u16.to_le_bytes())?; // IEEE float
+    out.write_all(&(CHANNELS as u16).to_le_bytes())?;
+    out.write_all(&RATE.to_le_bytes())?;
+    out.write_all(&(RATE * CHANNELS * 4).to_le_bytes())?;
+    out.write_all(&(CHANNELS as u16 * 4).to_le_bytes())?;
+    out.write_all(&32_u16.to_le_bytes())?;
+    out.write_all(b"data")?;
+    out.write_all(&data_len.to_le_bytes())?;
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let args: Vec<_> = env::args().skip(1).collect();
+    let [input, output] = &args[..] else {
+        bail!("Usage: render <file> <out.wav>");
+    };
+
+    let sink = ManualSink::new(DeviceConfig {
+        channel_count: CHANNELS,
+        sample_rate: RATE,
+        sample_format: SampleFormat::F32,
+    });
+    sink.on_err_callback(Some(|e| eprintln!("error: {e}")))?;
+    sink.load(
+        Symph::try_new(File::open(input)?, &Default::default())?,
+        true,
+    )?;
+
+    let mut out = BufWriter::new(File::create(output)?);
+    write_header(&mut out, 0)?;
+
+    let mut buf = vec![0_f32; 1024 * CHANNELS as usize];
+    let mut len = 0;
+    while sink.has_source()? {
+        sink.fill(&mut SampleBufferMut::F32(&mut buf), Instant::now());
+        for s in &buf {
+            out.write_all(&s.to_le_bytes())?;
+        }
+        len += buf.len() as u32 * 4;
+    }
+
+    out.seek(SeekFrom::Start(0))?;
+    write_header(&mut out, len)?;
+    Ok(())
+}
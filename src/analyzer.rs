@@ -0,0 +1,112 @@
+use std::{f32::consts::PI, time::Duration};
+
+use cpal::{FromSample, Sample};
+
+/// Lightweight analyzer that computes magnitudes of log-spaced frequency
+/// bands using bank of goertzel filters.
+pub(crate) struct BandAnalyzer {
+    /// Number of bands and the interval, used to detect changes of the
+    /// configuration
+    config: (usize, Duration),
+    /// Goertzel coefficient for each band
+    coefs: Vec<f32>,
+    /// The last two states of the filter for each band
+    state: Vec<(f32, f32)>,
+    /// Number of channels of the analyzed signal
+    channels: usize,
+    /// Number of frames in single block
+    block_len: usize,
+    /// Number of frames processed in the current block
+    frames: usize,
+    /// Sum of samples of the current frame
+    frame_sum: f32,
+    /// Index of the channel in the current frame
+    channel: usize,
+}
+
+impl BandAnalyzer {
+    /// Creates analyzer with `n_bands` bands that produces the levels for
+    /// each `interval` of audio with the given `sample_rate`.
+    pub(crate) fn new(
+        n_bands: usize,
+        interval: Duration,
+        sample_rate: u32,
+        channels: u32,
+    ) -> Self {
+        let rate = sample_rate.max(1) as f32;
+        let low = 50_f32;
+        let high = (rate / 2. * 0.9).clamp(low * 2., 16000.);
+        let coefs = (0..n_bands)
+            .map(|i| {
+                let f =
+                    low * (high / low).powf((i as f32 + 0.5) / n_bands as f32);
+                2. * (2. * PI * f / rate).cos()
+            })
+            .collect();
+
+        Self {
+            config: (n_bands, interval),
+            coefs,
+            state: vec![(0., 0.); n_bands],
+            channels: channels.max(1) as usize,
+            block_len: ((interval.as_secs_f32() * rate) as usize).max(1),
+            frames: 0,
+            frame_sum: 0.,
+            channel: 0,
+        }
+    }
+
+    /// Gets the number of bands and the interval of the analyzer
+    pub(crate) fn config(&self) -> (usize, Duration) {
+        self.config
+    }
+
+    /// Processes the samples. Calls `publish` with the band levels each
+    /// time a block is completed.
+    pub(crate) fn process<T>(
+        &mut self,
+        data: &[T],
+        mut publish: impl FnMut(&[f32]),
+    ) where
+        T: Sample,
+        f32: FromSample<T>,
+    {
+        let mut levels = [0_f32; 32];
+        for s in data {
+            self.frame_sum += f32::from_sample_(*s);
+            self.channel += 1;
+            if self.channel < self.channels {
+                continue;
+            }
+
+            let x = self.frame_sum / self.channels as f32;
+            self.frame_sum = 0.;
+            self.channel = 0;
+
+            for (c, (s1, s2)) in self.coefs.iter().zip(self.state.iter_mut()) {
+                let s = x + c * *s1 - *s2;
+                *s2 = *s1;
+                *s1 = s;
+            }
+
+            self.frames += 1;
+            if self.frames < self.block_len {
+                continue;
+            }
+
+            let n = self.coefs.len().min(levels.len());
+            for ((l, c), (s1, s2)) in levels[..n]
+                .iter_mut()
+                .zip(&self.coefs)
+                .zip(self.state.iter_mut())
+            {
+                let power = (*s1 * *s1 + *s2 * *s2 - c * *s1 * *s2).max(0.);
+                *l = power.sqrt() * 2. / self.block_len as f32;
+                *s1 = 0.;
+                *s2 = 0.;
+            }
+            self.frames = 0;
+            publish(&levels[..n]);
+        }
+    }
+}
@@ -1,8 +1,11 @@
 use std::time::Duration;
 
 use cpal::SupportedBufferSize;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 #[derive(Copy, Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum BufferSize {
     #[default]
     Auto,
@@ -11,6 +14,18 @@ pub enum BufferSize {
 }
 
 impl BufferSize {
+    /// Gets the duration of the buffer with the given sample rate. Returns
+    /// [`None`] for [`BufferSize::Auto`].
+    pub fn duration(&self, sample_rate: u32) -> Option<Duration> {
+        match self {
+            BufferSize::Auto => None,
+            BufferSize::Fixed(n) => Some(Duration::from_secs_f64(
+                *n as f64 / sample_rate.max(1) as f64,
+            )),
+            BufferSize::ByDuration(d) => Some(*d),
+        }
+    }
+
     pub fn to_cpal(
         &self,
         limits: &SupportedBufferSize,
@@ -1,49 +1,182 @@
 use std::{
+    collections::VecDeque,
     fmt::Debug,
-    sync::{Arc, Mutex},
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, ThreadId},
 };
 
-use crate::err::Result;
+use crate::{
+    err::{Error, Result},
+    mixer::is_mixer_thread,
+};
 
-type ArcMutex<T> = Arc<Mutex<T>>;
 type OptionBox<T> = Option<Box<T>>;
 
 /// Mutexed callback function.
-pub struct Callback<T>(ArcMutex<OptionBox<dyn FnMut(T) + Send>>);
+///
+/// The function is not locked while it runs, so it may use the object that
+/// invokes it (e.g. it may call methods of [`crate::Sink`]):
+/// - If the function invokes the callback again on the same thread, the
+///   invocation is deferred and the function is called with the arguments
+///   after the current call returns.
+/// - If the callback is invoked from another thread while the function
+///   runs, the other thread waits until the function returns. The playback
+///   loop never waits, its invocation is deferred and the function is
+///   called with the arguments on the thread that runs it.
+/// - If the function is replaced while it runs, the new function is used for
+///   the following invocations (including the deferred ones).
+pub struct Callback<T>(Arc<Shared<T>>);
+
+/// Data of the callback shared between its clones
+struct Shared<T> {
+    /// State of the callback
+    state: Mutex<State<T>>,
+    /// Notified when the function returns
+    finished: Condvar,
+}
+
+/// State of the callback
+struct State<T> {
+    /// The function, it is [`None`] while it runs
+    f: OptionBox<dyn FnMut(T) + Send>,
+    /// Thread on which the function currently runs
+    running: Option<ThreadId>,
+    /// Arguments of the invocations made while the function ran
+    deferred: VecDeque<T>,
+    /// True if the function was replaced while it ran
+    replaced: bool,
+    /// True if the function panicked
+    poisoned: bool,
+}
+
+/// Returns the function to the callback when it finishes or panics
+struct RunGuard<'a, T> {
+    /// The callback
+    shared: &'a Shared<T>,
+    /// The running function
+    f: OptionBox<dyn FnMut(T) + Send>,
+    /// True if the function was already returned to the callback
+    released: bool,
+}
 
 impl<T> Callback<T> {
     /// Create new callback function
     pub fn new(f: Option<Box<dyn FnMut(T) + Send>>) -> Self {
-        Self(Arc::new(Mutex::new(f)))
+        Self(Arc::new(Shared {
+            state: Mutex::new(State {
+                f,
+                running: None,
+                deferred: VecDeque::new(),
+                replaced: false,
+                poisoned: false,
+            }),
+            finished: Condvar::new(),
+        }))
     }
 
     /// Invoke the callback function. It is locked only while it is invoked.
+    /// See [`Callback`] for what happens with reentrant invocations.
     ///
     /// # Errors
-    /// - Callback panicked when called previously on another thread.
-    ///
-    /// # Panics
-    /// - The callback invoked itself.
+    /// - Callback panicked when called previously.
     pub fn invoke(&self, args: T) -> Result<()> {
-        if let Some(cb) = self.0.lock()?.as_mut() {
-            cb(args);
+        let me = thread::current().id();
+        let mut state = self.0.state.lock()?;
+        if state.running == Some(me)
+            || (state.running.is_some() && is_mixer_thread())
+        {
+            state.deferred.push_back(args);
+            return Ok(());
+        }
+
+        while state.running.is_some() {
+            state = self.0.finished.wait(state)?;
+        }
+
+        if state.poisoned {
+            return Err(Error::PoisonError);
+        }
+
+        let Some(f) = state.f.take() else {
+            return Ok(());
+        };
+        state.running = Some(me);
+        state.replaced = false;
+        drop(state);
+
+        let mut guard = RunGuard {
+            shared: &self.0,
+            f: Some(f),
+            released: false,
+        };
+        let mut args = args;
+        loop {
+            if let Some(f) = guard.f.as_mut() {
+                f(args);
+            }
+
+            let mut state = self.0.state.lock()?;
+            if state.replaced {
+                state.replaced = false;
+                guard.f = state.f.take();
+            }
+            // Release in the same lock, so that no invocation is deferred
+            // after the last check.
+            let Some(a) = state.deferred.pop_front() else {
+                guard.release(&mut state);
+                return Ok(());
+            };
+            args = a;
         }
-        Ok(())
     }
 
-    /// Sets new value of the error callback.
+    /// Sets new value of the callback. This also clears the error from
+    /// previous panic of the function.
     pub(super) fn set(
         &self,
         f: Option<Box<dyn FnMut(T) + Send>>,
     ) -> Result<()> {
-        *self.0.lock()? = f;
+        let mut state = self.0.state.lock()?;
+        state.f = f;
+        state.poisoned = false;
+        if state.running.is_some() {
+            state.replaced = true;
+        }
         Ok(())
     }
 }
 
+impl<T> RunGuard<'_, T> {
+    /// Returns the function to the callback.
+    fn release(&mut self, state: &mut State<T>) {
+        self.released = true;
+        state.running = None;
+        if thread::panicking() {
+            state.poisoned = true;
+            state.deferred.clear();
+        } else if state.replaced {
+            state.replaced = false;
+        } else {
+            state.f = self.f.take();
+        }
+    }
+}
+
+impl<T> Drop for RunGuard<'_, T> {
+    fn drop(&mut self) {
+        if !self.released {
+            let shared = self.shared;
+            let mut state =
+                shared.state.lock().unwrap_or_else(|e| e.into_inner());
+            self.release(&mut state);
+        }
+        self.shared.finished.notify_all();
+    }
+}
+
 impl<T> Default for Callback<T> {
     fn default() -> Self {
-        Self(Default::default())
+        Self::new(None)
     }
 }
 
@@ -58,3 +191,110 @@ impl<T> Clone for Callback<T> {
         Self(self.0.clone())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{mpsc, Arc, Mutex},
+        thread,
+        time::Duration,
+    };
+
+    use super::*;
+
+    /// Runs `f` on another thread and fails if it doesn't finish in time.
+    fn no_deadlock(f: impl FnOnce() + Send + 'static) {
+        let (send, recv) = mpsc::channel();
+        thread::spawn(move || {
+            f();
+            _ = send.send(());
+        });
+        recv.recv_timeout(Duration::from_secs(5))
+            .expect("the callback deadlocked");
+    }
+
+    #[test]
+    fn reentrant_invoke_is_deferred() {
+        no_deadlock(|| {
+            let calls = Arc::new(Mutex::new(vec![]));
+            let cb = Callback::<u32>::default();
+            let inner = cb.clone();
+            let log = calls.clone();
+            cb.set(Some(Box::new(move |n| {
+                log.lock().unwrap().push(n);
+                if n < 3 {
+                    // Runs after this call returns, not recursively.
+                    inner.invoke(n + 1).unwrap();
+                    log.lock().unwrap().push(n + 10);
+                }
+            })))
+            .unwrap();
+
+            cb.invoke(1).unwrap();
+            assert_eq!(*calls.lock().unwrap(), [1, 11, 2, 12, 3]);
+        });
+    }
+
+    #[test]
+    fn replaced_while_running() {
+        no_deadlock(|| {
+            let calls = Arc::new(Mutex::new(vec![]));
+            let cb = Callback::<u32>::default();
+            let inner = cb.clone();
+            let log = calls.clone();
+            cb.set(Some(Box::new(move |n| {
+                log.lock().unwrap().push(n);
+                let log = log.clone();
+                inner
+                    .set(Some(Box::new(move |n| {
+                        log.lock().unwrap().push(n * 100)
+                    })))
+                    .unwrap();
+                inner.invoke(2).unwrap();
+            })))
+            .unwrap();
+
+            cb.invoke(1).unwrap();
+            cb.invoke(3).unwrap();
+            assert_eq!(*calls.lock().unwrap(), [1, 200, 300]);
+        });
+    }
+
+    #[test]
+    fn other_thread_waits() {
+        no_deadlock(|| {
+            let calls = Arc::new(Mutex::new(vec![]));
+            let cb = Callback::<u32>::default();
+            let log = calls.clone();
+            let (started, wait_start) = mpsc::channel();
+            cb.set(Some(Box::new(move |n| {
+                if n == 1 {
+                    _ = started.send(());
+                    thread::sleep(Duration::from_millis(50));
+                }
+                log.lock().unwrap().push(n);
+            })))
+            .unwrap();
+
+            let other = cb.clone();
+            let t = thread::spawn(move || other.invoke(1).unwrap());
+            wait_start.recv().unwrap();
+            cb.invoke(2).unwrap();
+            t.join().unwrap();
+            assert_eq!(*calls.lock().unwrap(), [1, 2]);
+        });
+    }
+
+    #[test]
+    fn panic_poisons_until_set() {
+        let cb = Callback::<u32>::default();
+        cb.set(Some(Box::new(|_| panic!("callback panic"))))
+            .unwrap();
+        let inner = cb.clone();
+        assert!(thread::spawn(move || inner.invoke(1)).join().is_err());
+        assert!(matches!(cb.invoke(2), Err(Error::PoisonError)));
+
+        cb.set(Some(Box::new(|_| {}))).unwrap();
+        cb.invoke(3).unwrap();
+    }
+}
@@ -0,0 +1,92 @@
+use std::time::Instant;
+
+#[cfg(feature = "test-util")]
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    time::Duration,
+};
+
+/// Source of time for the time based features. By default this is the real
+/// clock, with the `test-util` feature it may be manually controlled clock.
+#[derive(Clone, Default)]
+pub(crate) enum Clock {
+    /// The real clock
+    #[default]
+    Real,
+    /// Clock that moves only when it is advanced
+    #[cfg(feature = "test-util")]
+    Manual(Arc<ManualClock>),
+}
+
+impl Clock {
+    /// Gets the current time
+    #[inline]
+    pub(crate) fn now(&self) -> Instant {
+        match self {
+            Self::Real => Instant::now(),
+            #[cfg(feature = "test-util")]
+            Self::Manual(c) => c.now(),
+        }
+    }
+}
+
+/// Clock that moves only when it is advanced with [`ManualClock::advance`].
+/// Use it with [`crate::Sink::with_clock`] to test time based features
+/// without waiting.
+#[cfg(feature = "test-util")]
+#[derive(Debug)]
+pub struct ManualClock {
+    /// The time when the clock was created
+    base: Instant,
+    /// How much was the clock advanced
+    offset: Mutex<Duration>,
+    /// Wakes sleeping threads when the clock is advanced
+    advanced: Condvar,
+}
+
+#[cfg(feature = "test-util")]
+impl ManualClock {
+    /// Creates new manual clock
+    pub fn new() -> Self {
+        Self {
+            base: Instant::now(),
+            offset: Mutex::new(Duration::ZERO),
+            advanced: Condvar::new(),
+        }
+    }
+
+    /// Gets the current time of the clock
+    pub fn now(&self) -> Instant {
+        self.base + self.elapsed()
+    }
+
+    /// Gets how much was the clock advanced since it was created
+    pub fn elapsed(&self) -> Duration {
+        *self.offset.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Moves the clock forward and wakes threads that sleep until this time
+    pub fn advance(&self, dur: Duration) {
+        *self.offset.lock().unwrap_or_else(|e| e.into_inner()) += dur;
+        self.advanced.notify_all();
+    }
+
+    /// Blocks the current thread until the clock is advanced by `dur`
+    pub fn sleep(&self, dur: Duration) {
+        let mut offset = self.offset.lock().unwrap_or_else(|e| e.into_inner());
+        let target = *offset + dur;
+        while *offset < target {
+            offset = self
+                .advanced
+                .wait(offset)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+#[cfg(feature = "test-util")]
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
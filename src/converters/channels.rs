@@ -25,6 +25,28 @@ impl<S: Sample, I: Iterator<Item = S>> ChannelConverter<S, I> {
             index: 0,
         }
     }
+
+    /// Gets the number of samples produced when the source has `n` remaining
+    /// samples. Returns [`None`] if the number doesn't fit into [`usize`].
+    fn output_len(&self, n: usize) -> Option<usize> {
+        let src = self.source_channels as usize;
+        let tgt = self.target_channels as usize;
+        // Count the samples as if the current frame was read from its start
+        // and subtract the samples that were already produced.
+        match src.cmp(&tgt) {
+            std::cmp::Ordering::Less => {
+                let n = n.checked_add(self.index.min(src))?;
+                let res = n.checked_div(src)?.checked_mul(tgt)?;
+                Some(res.checked_add(n % src)? - self.index)
+            }
+            std::cmp::Ordering::Equal => Some(n),
+            std::cmp::Ordering::Greater => {
+                let n = n.checked_add(self.index)?;
+                let res = n.checked_div(src)?.checked_mul(tgt)?;
+                Some(res.checked_add((n % src).min(tgt))? - self.index)
+            }
+        }
+    }
 }
 
 impl<S: Sample, I: Iterator<Item = S>> Iterator for ChannelConverter<S, I> {
@@ -55,4 +77,17 @@ impl<S: Sample, I: Iterator<Item = S>> Iterator for ChannelConverter<S, I> {
             }
         }
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.source.size_hint();
+        (
+            self.output_len(lower).unwrap_or(usize::MAX),
+            upper.and_then(|u| self.output_len(u)),
+        )
+    }
+}
+
+impl<S: Sample, I: ExactSizeIterator<Item = S>> ExactSizeIterator
+    for ChannelConverter<S, I>
+{
 }
@@ -0,0 +1,272 @@
+use std::f32::consts::{FRAC_1_SQRT_2, FRAC_PI_2, FRAC_PI_4};
+
+/// Matrix of gains. Each row corresponds to target channel and each column
+/// corresponds to source channel.
+pub type Matrix = Vec<Vec<f32>>;
+
+/// Known channel layouts. The order of channels is the same as in wav files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelLayout {
+    /// Single channel
+    Mono,
+    /// Left, right
+    Stereo,
+    /// Front left, front right, center, LFE, back left, back right
+    Surround51,
+    /// Front left, front right, center, LFE, back left, back right, side
+    /// left, side right
+    Surround71,
+    /// Unknown layout with the given number of channels
+    Other(u32),
+}
+
+impl ChannelLayout {
+    /// Gets the most common layout with the given channel count
+    pub fn from_count(channels: u32) -> Self {
+        match channels {
+            1 => Self::Mono,
+            2 => Self::Stereo,
+            6 => Self::Surround51,
+            8 => Self::Surround71,
+            n => Self::Other(n),
+        }
+    }
+
+    /// Gets the number of channels in the layout
+    pub fn channel_count(&self) -> u32 {
+        match self {
+            Self::Mono => 1,
+            Self::Stereo => 2,
+            Self::Surround51 => 6,
+            Self::Surround71 => 8,
+            Self::Other(n) => *n,
+        }
+    }
+}
+
+/// Gets gains of left and right channel for the given `pan` using constant
+/// power pan law. `pan` is in range `-1..=1`, -1 is full left, 0 is center
+/// (-3 dB on both channels) and 1 is full right.
+pub fn pan_gains(pan: f32) -> (f32, f32) {
+    let angle = (pan.clamp(-1., 1.) + 1.) * FRAC_PI_4;
+    (angle.cos(), angle.sin())
+}
+
+/// Gets gains of left and right channel for the given stereo `balance`.
+/// `balance` is in range `-1..=1`, -1 is full left, 0 is center (unity gain
+/// on both channels) and 1 is full right. Only the channel on the other side
+/// than the balance is attenuated (with cosine curve), the gain never
+/// exceeds 1.
+pub fn balance_gains(balance: f32) -> (f32, f32) {
+    let balance = balance.clamp(-1., 1.);
+    let att = (balance.abs() * FRAC_PI_2).cos().max(0.);
+    if balance < 0. {
+        (1., att)
+    } else if balance > 0. {
+        (att, 1.)
+    } else {
+        (1., 1.)
+    }
+}
+
+/// Volume in decibels at and below which the volume is considered silent
+pub const SILENCE_DB: f32 = -96.;
+
+/// Converts decibels to linear gain.
+pub fn db_to_linear(db: f32) -> f32 {
+    10_f32.powf(db / 20.)
+}
+
+/// Converts decibels to linear volume. Values at or below [`SILENCE_DB`]
+/// (including negative infinity) are converted to 0.
+pub fn db_to_volume(db: f32) -> f32 {
+    if db > SILENCE_DB {
+        db_to_linear(db)
+    } else {
+        0.
+    }
+}
+
+/// Converts linear gain to decibels. Returns negative infinity for zero.
+pub fn linear_to_db(gain: f32) -> f32 {
+    20. * gain.abs().log10()
+}
+
+/// Soft clips the sample `x`. Samples with magnitude up to `threshold` are
+/// unchanged, larger samples are smoothly compressed with tanh-shaped knee so
+/// that the result never exceeds 1. `threshold` is linear in range `0..1`.
+pub fn soft_clip(x: f32, threshold: f32) -> f32 {
+    let mag = x.abs();
+    if mag <= threshold {
+        return x;
+    }
+    let head = 1. - threshold;
+    (threshold + head * ((mag - threshold) / head).tanh()).copysign(x)
+}
+
+/// Gets the weights for converting audio from the layout `src` to the
+/// layout `dst`.
+///
+/// Downmixing uses -3 dB for center and surround channels and drops LFE, the
+/// rows are normalized so that the weights of each target channel sum to 1.
+/// Unknown layouts copy the matching channels and silence the rest.
+pub fn downmix_weights(src: ChannelLayout, dst: ChannelLayout) -> Matrix {
+    use ChannelLayout::*;
+
+    const C: f32 = FRAC_1_SQRT_2;
+
+    match (src, dst) {
+        (Mono, Stereo) => vec![vec![C], vec![C]],
+        (Stereo, Mono) => vec![vec![0.5, 0.5]],
+        (Surround51, Stereo) => normalized(vec![
+            vec![1., 0., C, 0., C, 0.],
+            vec![0., 1., C, 0., 0., C],
+        ]),
+        (Surround51, Mono) => normalized(vec![vec![C, C, 1., 0., 0.5, 0.5]]),
+        (Surround71, Stereo) => normalized(vec![
+            vec![1., 0., C, 0., C, 0., C, 0.],
+            vec![0., 1., C, 0., 0., C, 0., C],
+        ]),
+        (Surround71, Mono) => {
+            normalized(vec![vec![C, C, 1., 0., 0.5, 0.5, 0.5, 0.5]])
+        }
+        (Surround71, Surround51) => vec![
+            vec![1., 0., 0., 0., 0., 0., 0., 0.],
+            vec![0., 1., 0., 0., 0., 0., 0., 0.],
+            vec![0., 0., 1., 0., 0., 0., 0., 0.],
+            vec![0., 0., 0., 1., 0., 0., 0., 0.],
+            vec![0., 0., 0., 0., C, 0., C, 0.],
+            vec![0., 0., 0., 0., 0., C, 0., C],
+        ],
+        _ => {
+            let sc = src.channel_count() as usize;
+            (0..dst.channel_count() as usize)
+                .map(|d| {
+                    (0..sc).map(|s| if s == d { 1. } else { 0. }).collect()
+                })
+                .collect()
+        }
+    }
+}
+
+/// Normalizes the rows of the matrix so that they sum to 1
+fn normalized(mut matrix: Matrix) -> Matrix {
+    for row in &mut matrix {
+        let sum: f32 = row.iter().sum();
+        if sum != 0. {
+            row.iter_mut().for_each(|w| *w /= sum);
+        }
+    }
+    matrix
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn balance_center_is_unity() {
+        assert_eq!(balance_gains(0.), (1., 1.));
+    }
+
+    #[test]
+    fn balance_never_boosts() {
+        for i in -100..=100 {
+            let (l, r) = balance_gains(i as f32 / 100.);
+            assert!(l <= 1. && r <= 1.);
+            assert!(l >= 0. && r >= 0.);
+        }
+    }
+
+    #[test]
+    fn balance_attenuates_other_side() {
+        let (l, r) = balance_gains(1.);
+        assert_eq!(r, 1.);
+        assert!(l.abs() < 1e-6);
+
+        let (l, r) = balance_gains(-0.5);
+        assert_eq!(l, 1.);
+        assert!((r - FRAC_1_SQRT_2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn pan_center_is_minus_3_db() {
+        let (l, r) = pan_gains(0.);
+        assert!((l - FRAC_1_SQRT_2).abs() < 1e-6);
+        assert!((r - FRAC_1_SQRT_2).abs() < 1e-6);
+        assert!((linear_to_db(l) + 3.0103).abs() < 1e-3);
+    }
+
+    #[test]
+    fn pan_has_constant_power() {
+        for i in -100..=100 {
+            let (l, r) = pan_gains(i as f32 / 100.);
+            assert!((l * l + r * r - 1.).abs() < 1e-5);
+        }
+        let (l, r) = pan_gains(-1.);
+        assert!((l - 1.).abs() < 1e-6 && r.abs() < 1e-6);
+        assert_eq!(pan_gains(2.), pan_gains(1.));
+    }
+
+    #[test]
+    fn db_round_trip() {
+        for db in [-60., -20., -6., 0., 6.] {
+            assert!((linear_to_db(db_to_linear(db)) - db).abs() < 1e-4);
+        }
+        assert_eq!(db_to_linear(0.), 1.);
+        assert_eq!(linear_to_db(0.), f32::NEG_INFINITY);
+        assert_eq!(db_to_volume(SILENCE_DB), 0.);
+        assert_eq!(db_to_volume(f32::NEG_INFINITY), 0.);
+    }
+
+    #[test]
+    fn downmix_rows_sum_to_unity() {
+        use ChannelLayout::*;
+
+        for (src, dst) in [
+            (Stereo, Mono),
+            (Surround51, Stereo),
+            (Surround51, Mono),
+            (Surround71, Stereo),
+            (Surround71, Mono),
+        ] {
+            let m = downmix_weights(src, dst);
+            assert_eq!(m.len(), dst.channel_count() as usize);
+            for row in m {
+                assert_eq!(row.len(), src.channel_count() as usize);
+                let sum: f32 = row.iter().sum();
+                assert!((sum - 1.).abs() < 1e-6, "{src:?} -> {dst:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn downmix_center_is_minus_3_db() {
+        let m = downmix_weights(ChannelLayout::Mono, ChannelLayout::Stereo);
+        assert_eq!(m, [[FRAC_1_SQRT_2], [FRAC_1_SQRT_2]]);
+
+        // Center is 3 dB below the front channels.
+        let m =
+            downmix_weights(ChannelLayout::Surround51, ChannelLayout::Stereo);
+        assert!((m[0][2] / m[0][0] - FRAC_1_SQRT_2).abs() < 1e-6);
+        assert_eq!(m[0][3], 0.);
+    }
+
+    #[test]
+    fn unknown_layout_copies_channels() {
+        let m =
+            downmix_weights(ChannelLayout::Other(3), ChannelLayout::Stereo);
+        assert_eq!(m, [[1., 0., 0.], [0., 1., 0.]]);
+    }
+
+    #[test]
+    fn soft_clip_is_bounded() {
+        assert_eq!(soft_clip(0.5, 0.8), 0.5);
+        for i in -100..=100 {
+            let x = i as f32 / 10.;
+            let y = soft_clip(x, 0.8);
+            assert!(y.abs() <= 1.);
+            assert_eq!(y.signum(), x.signum());
+        }
+    }
+}
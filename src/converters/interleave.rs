@@ -20,11 +20,36 @@ impl<I: Iterator<Item = T>, T> Iterator for Interleave<I, T> {
     type Item = T;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let r = self.iterators[self.index].next();
+        let r = self.iterators.get_mut(self.index)?.next();
         self.index += 1;
         if self.index >= self.iterators.len() {
             self.index = 0;
         }
         r
     }
+
+    /// The hint is the number of samples until the first channel runs out.
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let cnt = self.iterators.len();
+        if cnt == 0 {
+            return (0, Some(0));
+        }
+
+        let mut lower = usize::MAX;
+        let mut upper: Option<usize> = None;
+        let order = self.iterators[self.index..]
+            .iter()
+            .chain(&self.iterators[..self.index]);
+        for (pos, it) in order.enumerate() {
+            // The channel is used for every `cnt`th sample starting at `pos`
+            let len = |n: usize| n.checked_mul(cnt)?.checked_add(pos);
+            let (lo, hi) = it.size_hint();
+            lower = lower.min(len(lo).unwrap_or(usize::MAX));
+            if let Some(hi) = hi.and_then(len) {
+                upper = Some(upper.map_or(hi, |u| u.min(hi)));
+            }
+        }
+
+        (lower, upper)
+    }
 }
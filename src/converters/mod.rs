@@ -7,6 +7,8 @@ use self::{
 
 /// Contains iterator that converts between channel counts
 pub mod channels;
+/// Contains gain laws and channel mixing weights
+pub mod gain;
 /// Contais iterator that interleaves channels
 pub mod interleave;
 /// Contains iterator that converts rate
@@ -30,7 +32,7 @@ pub fn channels<S: Sample, I: Iterator<Item = S>>(
 }
 
 /// Creates iterator that converts the sample rate of `source` from
-/// `source_rate` to `target_rate` by lineary interpolating the values
+/// `source_rate` to `target_rate` by linearly interpolating the values
 pub fn rate<S, I, R>(
     source: I,
     source_rate: R,
@@ -48,7 +50,7 @@ where
 /// Creates iterator that interleaves the channels of `source`, than
 /// converts the interleaved audio channel count of from `source_channels` to
 /// `target_channels` and than converts the sample rate of from `source_rate`
-/// to `target_rate` by lineary interpolating the values.
+/// to `target_rate` by linearly interpolating the values.
 ///
 /// This is equivalent to chaining the functions `rate(channels(interleave()))`
 pub fn do_interleave_channels_rate<S, I, R, II>(
@@ -74,7 +76,7 @@ where
 
 /// Creates iterator that converts the interleaved audio channel count of
 /// `source` from `source_channels` to `target_channels`, and than converts
-/// the sample rate from `source_rate` to `target_rate` by lineary
+/// the sample rate from `source_rate` to `target_rate` by linearly
 /// interpolating the values
 ///
 /// This is equivalent to chaining functions `rate(channels())`
@@ -136,3 +138,156 @@ impl<T> UniSample for T where
         + FromSample<f64>
 {
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use crate::test_util::*;
+
+    use super::{ChannelConverter, Interleave, RateConverter};
+
+    /// Maximum difference from the golden output
+    const TOLERANCE: f32 = 1e-6;
+
+    fn golden(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/golden")
+            .join(name)
+    }
+
+    fn check(name: &str, output: &[f32]) {
+        let cmp = check_golden(golden(name), output).unwrap();
+        assert!(
+            cmp.within(TOLERANCE),
+            "{name}: max error {}, snr {} dB, same length: {}",
+            cmp.max_error,
+            cmp.snr,
+            cmp.same_len,
+        );
+    }
+
+    #[test]
+    fn rate_sweep_up() {
+        let sweep = sine_sweep(20., 20000., 44100, 4410);
+        check(
+            "rate_sweep_44100_48000.f32",
+            &render_rate(&sweep, 44100, 48000),
+        );
+    }
+
+    #[test]
+    fn rate_sweep_down() {
+        let sweep = sine_sweep(20., 20000., 48000, 4800);
+        check(
+            "rate_sweep_48000_44100.f32",
+            &render_rate(&sweep, 48000, 44100),
+        );
+    }
+
+    #[test]
+    fn rate_impulse() {
+        let imp = impulse(100, 441);
+        check(
+            "rate_impulse_44100_48000.f32",
+            &render_rate(&imp, 44100, 48000),
+        );
+    }
+
+    #[test]
+    fn channels_down() {
+        let sig = channel_id(6, 64);
+        check("channels_6_2.f32", &render_channels(&sig, 6, 2));
+        let sig = channel_id(2, 64);
+        check("channels_2_1.f32", &render_channels(&sig, 2, 1));
+    }
+
+    #[test]
+    fn channels_up() {
+        let sig = channel_id(1, 64);
+        check("channels_1_2.f32", &render_channels(&sig, 1, 2));
+        let sig = channel_id(2, 64);
+        check("channels_2_6.f32", &render_channels(&sig, 2, 6));
+    }
+
+    /// Simple deterministic generator of random numbers for the property
+    /// tests
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn below(&mut self, max: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % max as u64) as usize
+        }
+    }
+
+    /// Checks that the hint of `it` contains the actual number of items
+    /// until the first [`None`].
+    fn check_hint(mut it: impl Iterator<Item = f32>, what: &str) {
+        let (lower, upper) = it.size_hint();
+        let mut cnt = 0;
+        while it.next().is_some() {
+            cnt += 1;
+        }
+        assert!(lower <= cnt, "{what}: lower {lower} > {cnt}");
+        assert!(upper.is_none_or(|u| cnt <= u), "{what}: {upper:?} < {cnt}");
+    }
+
+    #[test]
+    fn rate_size_hint() {
+        let mut rng = XorShift(756);
+        let rates = [8000, 11025, 22050, 44100, 48000, 96000, 192000];
+        for _ in 0..2000 {
+            let len = rng.below(300);
+            let from = rates[rng.below(rates.len())];
+            let to = rates[rng.below(rates.len())];
+            let skip = rng.below(len + 2);
+            let mut it =
+                RateConverter::new(vec![0.5_f32; len].into_iter(), from, to);
+            it.by_ref().take(skip).for_each(drop);
+            check_hint(
+                it,
+                &format!("{len} samples {from} -> {to}, skip {skip}"),
+            );
+        }
+    }
+
+    #[test]
+    fn channels_size_hint() {
+        let mut rng = XorShift(756);
+        for _ in 0..2000 {
+            let len = rng.below(100);
+            let from = rng.below(8) as u32 + 1;
+            let to = rng.below(8) as u32 + 1;
+            let skip = rng.below(len + 2);
+            let mut it = ChannelConverter::new(
+                vec![0.5_f32; len].into_iter(),
+                from,
+                to,
+            );
+            it.by_ref().take(skip).for_each(drop);
+            let (lower, upper) = it.size_hint();
+            let cnt = it.count();
+            let what = format!("{len} samples {from} -> {to}, skip {skip}");
+            assert_eq!(Some(lower), upper, "{what}");
+            assert_eq!(lower, cnt, "{what}");
+        }
+    }
+
+    #[test]
+    fn interleave_size_hint() {
+        let mut rng = XorShift(756);
+        for _ in 0..2000 {
+            let channels = rng.below(6);
+            let lens: Vec<_> = (0..channels).map(|_| rng.below(50)).collect();
+            let skip = rng.below(100);
+            let mut it = Interleave::new(
+                lens.iter().map(|l| vec![0.5_f32; *l].into_iter()),
+            );
+            it.by_ref().take(skip).for_each(drop);
+            check_hint(it, &format!("{lens:?}, skip {skip}"));
+        }
+    }
+}
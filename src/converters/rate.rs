@@ -37,6 +37,35 @@ where
             b: None,
         }
     }
+
+    /// Gets the bounds of the number of samples produced when the source has
+    /// `n` remaining samples.
+    ///
+    /// The expected number of samples is `ceil((len - index) / ratio)` where
+    /// `len` is the distance from the first unused sample to the last sample.
+    /// The index accumulates rounding errors of at most epsilon per sample,
+    /// so the bounds are widened by the maximum possible drift plus one.
+    fn output_bounds(&self, n: usize) -> (usize, Option<usize>) {
+        let (len, index) = if self.a.is_none() {
+            if n < 2 {
+                return (n, Some(n));
+            }
+            (n - 1, 0.)
+        } else if self.b.is_none() {
+            return (0, Some(0));
+        } else {
+            (n.saturating_add(1), self.index.to_f64().unwrap_or_default())
+        };
+
+        let ratio = self.ratio.to_f64().unwrap_or(1.);
+        let eps = S::Float::epsilon().to_f64().unwrap_or_default();
+        let est = ((len as f64 - index) / ratio).ceil();
+        let margin = (est * eps / ratio).ceil() + 1.;
+
+        // The lower bound is clamped to zero, this also maps NaN to zero.
+        let lower = (est - margin).max(0.).to_usize().unwrap_or_default();
+        (lower, (est + margin).to_usize())
+    }
 }
 
 impl<S, I> Iterator for RateConverter<S, I>
@@ -81,4 +110,16 @@ where
 
         Some(res)
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.ratio.is_one() {
+            return self.source.size_hint();
+        }
+
+        let (lower, upper) = self.source.size_hint();
+        (
+            self.output_bounds(lower).0,
+            upper.and_then(|u| self.output_bounds(u).1),
+        )
+    }
 }
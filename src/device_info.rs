@@ -0,0 +1,50 @@
+use cpal::{traits::DeviceTrait, Device, SampleFormat};
+
+/// Information about output device, see [`crate::Sink::list_output_devices`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceInfo {
+    /// Name of the device, it can be used with [`crate::Sink::find_device`]
+    pub name: String,
+    /// True if this is the default output device
+    pub is_default: bool,
+    /// The lowest and the highest supported sample rate, [`None`] if the
+    /// device reports no configurations
+    pub sample_rates: Option<(u32, u32)>,
+    /// Supported numbers of channels in ascending order
+    pub channel_counts: Vec<u32>,
+    /// Supported sample formats
+    pub sample_formats: Vec<SampleFormat>,
+}
+
+impl DeviceInfo {
+    /// Gets the information about the output device. `default` is the name
+    /// of the default device. Returns [`None`] if the device doesn't report
+    /// its name or configurations.
+    pub(crate) fn new(device: &Device, default: Option<&str>) -> Option<Self> {
+        let name = device.name().ok()?;
+        let mut info = Self {
+            is_default: default == Some(name.as_str()),
+            name,
+            sample_rates: None,
+            channel_counts: vec![],
+            sample_formats: vec![],
+        };
+
+        for c in device.supported_output_configs().ok()? {
+            let (min, max) = (c.min_sample_rate().0, c.max_sample_rate().0);
+            info.sample_rates = Some(match info.sample_rates {
+                Some((lo, hi)) => (lo.min(min), hi.max(max)),
+                None => (min, max),
+            });
+            if !info.channel_counts.contains(&(c.channels() as u32)) {
+                info.channel_counts.push(c.channels() as u32);
+            }
+            if !info.sample_formats.contains(&c.sample_format()) {
+                info.sample_formats.push(c.sample_format());
+            }
+        }
+        info.channel_counts.sort_unstable();
+
+        Some(info)
+    }
+}
@@ -1,6 +1,6 @@
 use thiserror::Error;
 
-use crate::source::symph;
+use crate::source::{symph, DeviceConfig};
 
 /// Result with this crate error type [`enum@Error`]
 pub type Result<T> = std::result::Result<T, Error>;
@@ -19,18 +19,61 @@ pub enum Error {
     /// Returned when the sink fails to select output device
     #[error("No available output device was found")]
     NoOutDevice,
+    /// Returned when the capture source fails to select input device
+    #[error("No available input device was found")]
+    NoInDevice,
+    /// Returned when the device doesn't support the forced configuration
+    #[error("The device doesn't support the configuration {0:?}")]
+    UnsupportedConfig(DeviceConfig),
+    /// Returned when the selected audio host is not available
+    #[error("Audio host {} is not available", .0.name())]
+    HostUnavailable(cpal::HostId),
     /// Returned when some feature is not supported
     #[error("{component} doesn't support {feature}")]
     Unsupported {
         component: &'static str,
         feature: &'static str,
     },
+    /// Returned when playing after the output device was disconnected, see
+    /// [`crate::Sink::restart_device`]
+    #[error("The output device was disconnected")]
+    DeviceDisconnected,
+    /// Returned by [`crate::SinkHandle::play`] when the stream was released
+    /// and only the sink can create it again
+    #[error("The stream was released, it must be resumed with the sink")]
+    StreamReleased,
+    /// Returned by the non-blocking methods when the resource is used by
+    /// the playback loop
+    #[error("The resource is locked and the operation would block")]
+    WouldBlock,
     /// Returned when Sink tries to do action on Source, but there is no source
     #[error("Cannot operate on a source because there is no source playing")]
     NoSourceIsPlaying,
+    /// The source failed to produce any samples or returned error in the
+    /// given number of consecutive reads
+    #[error("Source produced no samples or failed in {0} consecutive reads")]
+    SourceStalled(usize),
+    /// The [`crate::source::StreamSource`] ran out of samples and plays
+    /// silence until more samples are written
+    #[error("The stream ran out of samples")]
+    StreamUnderrun,
+    /// The [`crate::source::CaptureSource`] captured more samples than it
+    /// can buffer and some samples were dropped
+    #[error("The captured samples overflowed the buffer")]
+    CaptureOverrun,
+    /// The channel map doesn't match the device with the given number of
+    /// channels
+    #[error("Channel map is not valid for device with {0} channels")]
+    InvalidChannelMap(u32),
+    /// The error with the message `msg` was repeated `count` more times
+    #[error("{msg} (repeated {count} times)")]
+    Repeated { msg: String, count: usize },
     /// Cpal errors
     #[error(transparent)]
     Cpal(#[from] CpalError),
+    /// Failed to open or read file
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
     /// Errors from the [`crate::source::Symph`] source
     #[error(transparent)]
     Symph(#[from] symph::Error),
@@ -55,6 +98,15 @@ impl<T> From<std::sync::PoisonError<T>> for Error {
     }
 }
 
+impl<T> From<std::sync::TryLockError<T>> for Error {
+    fn from(value: std::sync::TryLockError<T>) -> Self {
+        match value {
+            std::sync::TryLockError::Poisoned(_) => Self::PoisonError,
+            std::sync::TryLockError::WouldBlock => Self::WouldBlock,
+        }
+    }
+}
+
 macro_rules! impl_cpal {
     ($($i:ident -> $t:ty),+ $(,)?) => {
         $(
@@ -0,0 +1,95 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+use crate::{shared::SharedData, CallbackInfo};
+
+/// State of [`EventFuture`] shared with the playback loop
+pub(crate) struct EventWait {
+    /// Selects the events that resolve the future
+    filter: fn(&CallbackInfo) -> bool,
+    /// Waker of the task that awaits the future
+    waker: Option<Waker>,
+    /// The event that resolved the future
+    event: Option<CallbackInfo>,
+}
+
+/// Future that resolves with the first event of the given kind, see
+/// [`crate::Sink::played_to_end`], [`crate::Sink::prefetch_time`] and
+/// [`crate::Sink::pause_ended`].
+///
+/// Only the events invoked after the future is created resolve it. When the
+/// future is dropped, it is removed from the sink, so the playback loop
+/// never wakes a task that no longer waits.
+pub struct EventFuture {
+    /// Data of the sink
+    shared: Arc<SharedData>,
+    /// State shared with the playback loop
+    wait: Arc<Mutex<EventWait>>,
+}
+
+impl EventWait {
+    /// Resolves the wait with the event if it matches the filter. Returns
+    /// true if the wait was resolved.
+    pub(crate) fn resolve(&mut self, event: &CallbackInfo) -> bool {
+        if !(self.filter)(event) {
+            return false;
+        }
+        self.event = Some(event.clone());
+        if let Some(w) = self.waker.take() {
+            w.wake();
+        }
+        true
+    }
+}
+
+impl EventFuture {
+    /// Creates future that resolves with the first event accepted by
+    /// `filter`.
+    pub(crate) fn new(
+        shared: Arc<SharedData>,
+        filter: fn(&CallbackInfo) -> bool,
+    ) -> Self {
+        let wait = Arc::new(Mutex::new(EventWait {
+            filter,
+            waker: None,
+            event: None,
+        }));
+        shared.add_event_wait(wait.clone());
+        Self { shared, wait }
+    }
+
+    /// Resolves the future with the event immediately.
+    pub(crate) fn resolve(&self, event: CallbackInfo) {
+        let mut wait = self.wait.lock().unwrap_or_else(|e| e.into_inner());
+        wait.event.get_or_insert(event);
+    }
+}
+
+impl Future for EventFuture {
+    type Output = CallbackInfo;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut wait = self.wait.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(e) = wait.event.take() {
+            return Poll::Ready(e);
+        }
+        wait.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for EventFuture {
+    fn drop(&mut self) {
+        self.shared.remove_event_wait(&self.wait);
+    }
+}
+
+impl std::fmt::Debug for EventFuture {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventFuture").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,141 @@
+use std::{
+    collections::VecDeque,
+    mem,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Condvar, Mutex, TryLockError,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{err::Result, CallbackInfo};
+
+/// Bounded queue of events shared between the sink and [`EventReceiver`]
+pub(crate) struct EventQueue {
+    /// The events that weren't received yet
+    events: Mutex<VecDeque<CallbackInfo>>,
+    /// Notified when event is pushed
+    pushed: Condvar,
+    /// Maximum number of events in the queue
+    capacity: usize,
+    /// Number of events that were dropped because the queue was full or
+    /// locked
+    dropped: AtomicU64,
+}
+
+/// Receives the events of [`crate::Sink`] (the same events as the callback),
+/// see [`crate::Sink::event_receiver`].
+///
+/// The queue is bounded. When it is full, the oldest event is dropped, so
+/// the playback loop never waits for the receiver. Event is also dropped if
+/// the receiver is accessing the queue or a new receiver is being created at
+/// the same moment. The number of dropped events is reported by
+/// [`EventReceiver::dropped`].
+pub struct EventReceiver {
+    /// The queue of events
+    queue: Arc<EventQueue>,
+}
+
+impl EventQueue {
+    /// Creates new queue with the given capacity
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            events: Mutex::new(VecDeque::with_capacity(capacity)),
+            pushed: Condvar::new(),
+            capacity: capacity.max(1),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Adds event to the queue. If the queue is full, the oldest event is
+    /// dropped. If the receiver holds the lock, the new event is dropped so
+    /// that the playback loop doesn't wait.
+    pub(crate) fn push(&self, event: CallbackInfo) -> Result<()> {
+        let mut events = match self.events.try_lock() {
+            Ok(e) => e,
+            Err(TryLockError::WouldBlock) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                return Ok(());
+            }
+            Err(TryLockError::Poisoned(e)) => return Err(e.into()),
+        };
+        if events.len() >= self.capacity {
+            events.pop_front();
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        events.push_back(event);
+        drop(events);
+        self.pushed.notify_one();
+        Ok(())
+    }
+
+    /// Counts events that were dropped before they reached the queue.
+    pub(crate) fn add_dropped(&self, cnt: u64) {
+        self.dropped.fetch_add(cnt, Ordering::Relaxed);
+    }
+}
+
+impl EventReceiver {
+    /// Creates receiver of the queue
+    pub(crate) fn new(queue: Arc<EventQueue>) -> Self {
+        Self { queue }
+    }
+
+    /// Takes the oldest event from the queue. Returns [`None`] if there is
+    /// no event.
+    ///
+    /// # Errors
+    /// - the sink panicked while pushing event
+    pub fn try_recv(&self) -> Result<Option<CallbackInfo>> {
+        Ok(self.queue.events.lock()?.pop_front())
+    }
+
+    /// Takes the oldest event from the queue. If there is no event, waits
+    /// for it at most `timeout`. Returns [`None`] if there is no event after
+    /// the timeout.
+    ///
+    /// # Errors
+    /// - the sink panicked while pushing event
+    pub fn recv_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<Option<CallbackInfo>> {
+        let deadline = Instant::now() + timeout;
+        let mut events = self.queue.events.lock()?;
+        loop {
+            if let Some(e) = events.pop_front() {
+                return Ok(Some(e));
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            events = self.queue.pushed.wait_timeout(events, deadline - now)?.0;
+        }
+    }
+
+    /// Takes all the events that are in the queue.
+    ///
+    /// # Errors
+    /// - the sink panicked while pushing event
+    pub fn drain(&self) -> Result<Vec<CallbackInfo>> {
+        // Allocate outside of the lock so that the sink doesn't have to.
+        let empty = VecDeque::with_capacity(self.queue.capacity);
+        let events = mem::replace(&mut *self.queue.events.lock()?, empty);
+        Ok(events.into())
+    }
+
+    /// Gets the number of events that were dropped because the queue was
+    /// full or because it was locked when the event was pushed.
+    pub fn dropped(&self) -> u64 {
+        self.queue.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl std::fmt::Debug for EventReceiver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventReceiver")
+            .field("dropped", &self.dropped())
+            .finish_non_exhaustive()
+    }
+}
@@ -35,16 +35,73 @@ pub mod sample_buffer;
 pub mod sink;
 /// Audio sources that can be played in [`Sink`].
 pub mod source;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
 
+mod analyzer;
 mod buffer_size;
+mod clock;
+mod device_info;
+#[cfg(feature = "async")]
+mod event_future;
+mod events;
+mod manual_sink;
 mod mixer;
+mod output_stream;
+mod overlay;
+mod passthrough;
+mod render;
+mod self_test;
 mod shared;
+mod signal_path;
+mod sink_builder;
+mod sink_handle;
+mod tap;
+mod thread;
 mod timestamp;
 
+#[cfg(feature = "async")]
+pub use self::event_future::EventFuture;
 pub use self::{
-    buffer_size::*, err::Error, shared::*, sink::Sink, timestamp::*,
+    buffer_size::*,
+    device_info::DeviceInfo,
+    err::Error,
+    events::EventReceiver,
+    manual_sink::ManualSink,
+    overlay::OverlayId,
+    render::{render, RenderIter},
+    self_test::SelfTestReport,
+    shared::*,
+    signal_path::*,
+    sink::{
+        LoadOptions, LoadStatus, PlaybackSnapshot, Sink, SourceFactory,
+        StreamMode, WaitStatus,
+    },
+    sink_builder::SinkBuilder,
+    sink_handle::SinkHandle,
+    tap::{AnalysisTapConfig, SampleTap},
+    thread::ThreadInfo,
+    timestamp::*,
 };
 
+/// Creates slice from the buffer
+#[macro_export]
+#[deprecated(note = "use `SampleBufferMut::slice` instead")]
+macro_rules! slice_sbuf {
+    ($buf:expr, $range:expr) => {
+        ($buf).slice($range)
+    };
+}
+
+/// Writes silence into the buffer
+#[macro_export]
+#[deprecated(note = "use `SampleBufferMut::silence` instead")]
+macro_rules! silence_sbuf {
+    ($buf:expr) => {
+        ($buf).silence()
+    };
+}
+
 #[cfg(test)]
 mod tests {
     /*use std::{fs::File, io::stdin, time::Duration};
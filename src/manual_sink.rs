@@ -0,0 +1,283 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    err::{Error, Result},
+    mixer::Mixer,
+    sample_buffer::SampleBufferMut,
+    shared::{CallbackInfo, SharedData, SourceState},
+    source::{DeviceConfig, InitContext, Source},
+    SinkHandle, Timestamp,
+};
+
+/// Player that doesn't own any output stream. The audio is produced by
+/// calling [`ManualSink::fill`] from your own audio callback.
+///
+/// [`ManualSink::fill`] should be called on the real-time thread. It locks
+/// mutexes that are also locked by the other methods of [`ManualSink`], so
+/// avoid calling the other methods in tight loops. The callbacks are invoked
+/// from the thread that calls [`ManualSink::fill`], so they should be short.
+///
+/// All the methods take `&self`, so the sink can be shared (e.g. in
+/// [`Arc`]) between the audio callback and the rest of the program. The
+/// playback can be also controlled with [`ManualSink::handle`].
+pub struct ManualSink {
+    /// Data shared with the mixer
+    shared: Arc<SharedData>,
+    /// The playback loop, it is locked only by [`ManualSink::fill`]
+    mixer: Mutex<Mixer>,
+    /// Configuration of the output
+    info: DeviceConfig,
+}
+
+impl ManualSink {
+    /// Creates new manual sink that will produce audio with the given
+    /// configuration.
+    pub fn new(info: DeviceConfig) -> Self {
+        let shared = Arc::new(SharedData::new());
+        Self {
+            mixer: Mutex::new(Mixer::new(shared.clone(), info.clone())),
+            shared,
+            info,
+        }
+    }
+
+    /// Gets handle that controls the playback, see [`crate::Sink::handle`].
+    pub fn handle(&self) -> SinkHandle {
+        SinkHandle::new(self.shared.clone())
+    }
+
+    /// Writes the audio into the buffer. `play_time` is the time when the
+    /// buffer will be played.
+    ///
+    /// The format of the buffer should match the configuration of the sink.
+    /// Concurrent calls wait for each other. If previous call panicked, the
+    /// buffer is silent.
+    pub fn fill(&self, data: &mut SampleBufferMut, play_time: Instant) {
+        match self.mixer.lock() {
+            Ok(mut mixer) => mixer.mix(data, play_time, None),
+            Err(_) => data.silence(),
+        }
+    }
+
+    /// Sets the callback method.
+    ///
+    /// The function is called from the thread that calls
+    /// [`ManualSink::fill`].
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    pub fn on_callback(
+        &self,
+        callback: Option<impl FnMut(CallbackInfo) + Send + 'static>,
+    ) -> Result<()> {
+        self.shared.callback().set(
+            callback.map(|c| -> Box<dyn FnMut(CallbackInfo) + Send> {
+                Box::new(c)
+            }),
+        )
+    }
+
+    /// Sets the error callback method.
+    ///
+    /// The function is called from the thread that calls
+    /// [`ManualSink::fill`].
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    pub fn on_err_callback(
+        &self,
+        callback: Option<impl FnMut(Error) + Send + 'static>,
+    ) -> Result<()> {
+        self.shared.err_callback().set(
+            callback.map(|c| -> Box<dyn FnMut(Error) + Send> { Box::new(c) }),
+        )
+    }
+
+    /// Discards the old source and sets the new source. Starts playing if
+    /// `play` is set to true.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    /// - source fails to init
+    pub fn load(
+        &self,
+        mut src: impl Source + 'static,
+        play: bool,
+    ) -> Result<()> {
+        src.set_err_callback(&self.shared.source_err_callback());
+        let preferred = src.preferred_config();
+        src.init_with(&InitContext::new(
+            self.info.clone(),
+            preferred.as_ref(),
+            None,
+        ))?;
+
+        let mut controls = self.shared.controls()?;
+        let mut source = self.shared.source()?;
+        self.shared.set_play(play);
+        controls.new_source = true;
+        *source = Some(Box::new(src));
+        self.shared.next_source_generation();
+        self.shared.set_source_state(SourceState::Loaded);
+        Ok(())
+    }
+
+    /// Sets the source that will play after the current source ends, see
+    /// [`crate::Sink::prefetch`].
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    /// - source fails to init
+    pub fn prefetch(&self, src: impl Source + 'static) -> Result<()> {
+        self.handle().prefetch(src)
+    }
+
+    /// Resumes the playback if `play` is true, otherwise pauses the
+    /// playback.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    pub fn play(&self, play: bool) -> Result<()> {
+        self.handle().play(play)
+    }
+
+    /// Returns true if the source is playing, otherwise returns false
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    pub fn is_playing(&self) -> Result<bool> {
+        self.handle().is_playing()
+    }
+
+    /// Checks whether there is source loaded. This doesn't lock the source.
+    pub fn has_source(&self) -> Result<bool> {
+        self.handle().has_source()
+    }
+
+    /// Checks whether the last loaded source has ended and no other source
+    /// was loaded since. This doesn't lock the source.
+    pub fn has_ended(&self) -> Result<bool> {
+        self.handle().has_ended()
+    }
+
+    /// Sets the volume of the playback, 0 = mute, 1 = full volume.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    pub fn volume(&self, volume: f32) -> Result<()> {
+        self.handle().volume(volume)
+    }
+
+    /// Gets the volume of the playback, 0 = mute, 1 = full volume.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    pub fn get_volume(&self) -> Result<f32> {
+        self.handle().get_volume()
+    }
+
+    /// Enables or disables the passthrough check, see
+    /// [`crate::Sink::enable_passthrough_check`].
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    pub fn enable_passthrough_check(&self, enable: bool) -> Result<()> {
+        self.shared.controls()?.passthrough_check = enable;
+        Ok(())
+    }
+
+    /// Sets the fade-in/fade-out time for play/pause
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    pub fn set_fade_len(&self, fade: Duration) -> Result<()> {
+        let mut controls = self.shared.controls()?;
+        controls.fade_in = fade;
+        controls.fade_out = fade;
+        Ok(())
+    }
+
+    /// Seeks to the given position
+    ///
+    /// # Errors
+    /// - no source is playing
+    /// - the source doesn't support this
+    /// - failed to seek
+    pub fn seek_to(&self, timestamp: Duration) -> Result<Timestamp> {
+        self.handle().seek_to(timestamp)
+    }
+
+    /// Seeks by the given amount. If `forward` is true, seeks forward,
+    /// otherwise seeks backward
+    ///
+    /// # Errors
+    /// - no source is playing
+    /// - the source doesn't support this
+    /// - failed to seek
+    pub fn seek_by(&self, time: Duration, forward: bool) -> Result<Timestamp> {
+        self.handle().seek_by(time, forward)
+    }
+
+    /// Gets the current timestamp and the total length of the currently
+    /// playing source.
+    ///
+    /// # Errors
+    /// - no source is playing
+    /// - the source doesn't support this
+    pub fn get_timestamp(&self) -> Result<Timestamp> {
+        self.handle().get_timestamp()
+    }
+
+    /// Gets the configuration of the output
+    pub fn get_info(&self) -> &DeviceConfig {
+        &self.info
+    }
+}
+
+impl std::fmt::Debug for ManualSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ManualSink")
+            .field("info", &self.info)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use cpal::SampleFormat;
+
+    use super::*;
+    use crate::source::SineSource;
+
+    #[test]
+    fn fill_from_other_thread() {
+        let sink = Arc::new(ManualSink::new(DeviceConfig {
+            channel_count: 2,
+            sample_rate: 48000,
+            sample_format: SampleFormat::F32,
+        }));
+        sink.set_fade_len(Duration::ZERO).unwrap();
+        sink.load(SineSource::new(440.), true).unwrap();
+        sink.volume(0.5).unwrap();
+
+        let filler = sink.clone();
+        let peak = thread::spawn(move || {
+            let mut data = vec![0_f32; 960];
+            for _ in 0..4 {
+                let mut buf = SampleBufferMut::F32(&mut data);
+                filler.fill(&mut buf, Instant::now());
+            }
+            data.iter().fold(0_f32, |m, s| m.max(s.abs()))
+        })
+        .join()
+        .unwrap();
+
+        assert!((peak - 0.5).abs() < 0.01);
+        assert!(sink.handle().has_source().unwrap());
+    }
+}
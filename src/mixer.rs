@@ -1,51 +1,313 @@
-use std::{sync::Arc, time::Instant};
+use std::{
+    cell::Cell,
+    mem,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use cpal::Sample;
+use cpal::{
+    FromSample, OutputCallbackInfo, OutputStreamTimestamp, Sample,
+    StreamInstant,
+};
 
 use crate::{
-    err::Result,
+    analyzer::BandAnalyzer,
+    converters::{
+        convert_sample,
+        gain::{balance_gains, soft_clip},
+    },
+    err::{Error, Result},
     operate_samples,
+    output_stream::PauseRequest,
+    passthrough::PassthroughCheck,
     sample_buffer::{write_silence, SampleBufferMut},
-    shared::{CallbackInfo, Controls, SharedData},
-    silence_sbuf, slice_sbuf,
-    source::{DeviceConfig, VolumeIterator},
+    shared::{
+        CallbackInfo, Controls, DeferredLoad, FadeDirection, PrefetchState,
+        SharedData, SourceState,
+    },
+    source::{DeviceConfig, InitContext, Source, VolumeIterator},
+    tap::SampleTap,
+    SourceSpec, Timestamp,
 };
 
+/// Maximum number of channels supported by the channel map
+pub(super) const MAX_MAP_CHANNELS: usize = 32;
+
+/// Duration of the change of the balance over the whole range
+const BALANCE_RAMP: Duration = Duration::from_millis(5);
+
+/// Level (linear, -1 dBFS) above which the limiter compresses the samples
+const LIMITER_THRESHOLD: f32 = 0.891_250_9;
+
+thread_local! {
+    /// True while the current thread runs the playback loop
+    static IN_MIXER: Cell<bool> = const { Cell::new(false) };
+}
+
 /// Struct that handles the playback loop
 pub(super) struct Mixer {
     /// Data shared with [`Sink`]
     shared: Arc<SharedData>,
     /// Volume iterator presented to the source
     volume: VolumeIterator,
+    /// Generation of the source that already has the current custom volume
+    /// envelope and whether it supports volume. The source advances its
+    /// copy of the envelope by itself, so it is not cloned each callback.
+    envelope_source: Option<(u64, bool)>,
     /// The last status of play
     last_play: Option<bool>,
+    /// Status of play for the current callback
+    play: bool,
+    /// The volume that was last applied to the volume iterator
+    applied_volume: Option<f32>,
+    /// True if the volume iterator transitions because the volume changed,
+    /// not because of fade
+    ramping: bool,
+    /// Volume for the current callback
+    target_volume: f32,
+    /// The last controls that were read. They are used when the controls
+    /// are locked by other thread, so that the playback loop never waits.
+    controls: Controls,
+    /// True if the change of the signal path should be reported with the
+    /// next callback
+    path_changed: bool,
     last_sound: bool,
     /// Info about the device that is playing
     info: DeviceConfig,
+    /// Timestamp of the first callback of the stream
+    origin: Option<StreamInstant>,
+    /// Number of consecutive reads that produced no samples
+    stalled_reads: usize,
+    /// Number of consecutive reads that returned error
+    errored_reads: usize,
+    /// True if [`CallbackInfo::SourceEnded`] was invoked and no source was
+    /// played since then
+    ended_notified: bool,
+    /// Time of the audio that fades out during transition inside the source
+    outgoing_time: Option<Timestamp>,
+    /// True if the source has ended and its buffered samples are flushed
+    flushing: bool,
+    /// Analyzer of frequency bands of the output
+    analyzer: Option<BandAnalyzer>,
+    /// Number of frames played on the stream
+    frame_pos: u64,
+    /// Number of consecutive near-silent frames
+    quiet_frames: u64,
+    /// Change of the spec of the source that will be reported
+    spec_change: Option<(SourceSpec, SourceSpec)>,
+    /// Compares the output with the raw frames of the source
+    passthrough: PassthroughCheck,
+    /// True if the source records hashes of its raw frames
+    raw_recording: bool,
+    /// Index of the first frame that didn't pass through unchanged, it will
+    /// be reported
+    mismatch: Option<u64>,
+    /// The last configuration requested by the source
+    requested: Option<DeviceConfig>,
+    /// True if the requested configuration should be forwarded to the sink
+    request_pending: bool,
+    /// Source that was stopped and that is fading out
+    stopping: Option<Box<dyn Source>>,
+    /// True if the source started again from the start in repeat mode
+    looped: bool,
+    /// True if the prefetch notification was triggered for the source
+    prefetch_notified: bool,
+    /// Remaining time and played fraction of the source for the prefetch
+    /// notification that will be reported
+    prefetch_time: Option<(Duration, f32)>,
+    /// The current stereo balance, it moves towards the balance in the
+    /// controls
+    balance: f32,
+    /// Number of samples remaining in the current scrub burst
+    scrub_left: usize,
+    /// Position to which the source will be seeked after fading out
+    pending_seek: Option<Duration>,
+    /// Gain of the fade around seek
+    seek_gain: f32,
+    /// Buffer for reading the overlays
+    overlay_buf: Vec<f32>,
+    /// Buffer for converting the output for the sample tap
+    tap_buf: Vec<f32>,
+    /// Buffer in which integer output is mixed when the limiter is enabled
+    limit_buf: Vec<f32>,
+    /// Initialization of the prefetched source that replaces the ended
+    /// source, see `QueuedSource`
+    next_init: Option<(DeviceConfig, Option<DeviceConfig>)>,
+    /// Track gain of the prefetched source that replaces the ended source
+    next_gain: Option<f32>,
+    /// Pauses the stream when the playback loop becomes idle
+    pause_request: Option<PauseRequest>,
 }
 
 impl Mixer {
     /// Creates new [`Mixer`]
     pub(super) fn new(shared: Arc<SharedData>, info: DeviceConfig) -> Self {
+        shared.set_stream_config(info.clone());
         Self {
             shared,
             volume: VolumeIterator::default(),
+            envelope_source: None,
             last_play: None,
+            play: false,
+            applied_volume: None,
+            ramping: false,
+            target_volume: 1.,
+            controls: Controls::new(),
+            path_changed: false,
             last_sound: false,
             info,
+            origin: None,
+            stalled_reads: 0,
+            errored_reads: 0,
+            ended_notified: false,
+            outgoing_time: None,
+            flushing: false,
+            analyzer: None,
+            frame_pos: 0,
+            quiet_frames: 0,
+            spec_change: None,
+            passthrough: PassthroughCheck::default(),
+            raw_recording: false,
+            mismatch: None,
+            requested: None,
+            request_pending: false,
+            stopping: None,
+            looped: false,
+            prefetch_notified: false,
+            prefetch_time: None,
+            balance: 0.,
+            scrub_left: 0,
+            pending_seek: None,
+            seek_gain: 1.,
+            overlay_buf: vec![],
+            tap_buf: vec![],
+            limit_buf: vec![],
+            next_init: None,
+            next_gain: None,
+            pause_request: None,
         }
     }
 
-    /// Writes the data from the source to the buffer `data`
+    /// Sets the request used to pause the stream when the playback loop
+    /// becomes idle.
+    pub(super) fn set_pause_request(&mut self, pause: PauseRequest) {
+        self.pause_request = Some(pause);
+    }
+
+    /// Writes the data from the source to the buffer `data`. `play_time` is
+    /// the time when the buffer will be played and `timestamp` is the
+    /// timestamp reported by the device if available.
     pub(super) fn mix<'a, 'b: 'a>(
         &mut self,
         data: &'a mut SampleBufferMut<'b>,
         play_time: Instant,
+        timestamp: Option<OutputStreamTimestamp>,
     ) {
-        if let Err(e) = self.try_mix(data, play_time) {
-            silence_sbuf!(data);
+        let frames = data.len() / self.info.channel_count.max(1) as usize;
+        self.shared.mark_callback(Duration::from_secs_f64(
+            frames as f64 / self.info.sample_rate.max(1) as f64,
+        ));
+        self.shared.set_first_buffer_frames(frames as u32);
+        IN_MIXER.set(true);
+        if let Some(ts) = timestamp {
+            self.shared.set_output_delay(
+                ts.playback.duration_since(&ts.callback).unwrap_or_default(),
+            );
+        }
+        _ = self.shared.flush_err_callback();
+        if let Some(load) = self.shared.take_deferred_load() {
+            if let Err(e) = self.apply_load(load) {
+                _ = self.shared.invoke_err_callback(e);
+            }
+        }
+        if self.shared.idle() {
+            data.silence();
+            IN_MIXER.set(false);
+            return;
+        }
+        let res = if self.limiter_enabled() && !data.format().is_float() {
+            self.try_mix_limited(data, play_time, timestamp)
+        } else {
+            self.try_mix(data, play_time, timestamp)
+        };
+        if let Err(e) = res {
+            data.silence();
             _ = self.shared.invoke_err_callback(e);
         }
+        IN_MIXER.set(false);
+    }
+
+    /// Sets the source that was loaded from the playback loop. The source is
+    /// already prepared by the caller, so this only swaps it in. If the
+    /// source prefers different configuration, reconfiguration is requested.
+    fn apply_load(&mut self, load: DeferredLoad) -> Result<()> {
+        if let Some(g) = load.track_gain {
+            self.shared.set_track_gain(g);
+        }
+
+        self.requested = self.unsatisfied(load.preferred, &self.controls);
+        self.request_pending = self.requested.is_some();
+        // The stale request is cleared later if the controls are locked.
+        if let Ok(mut controls) = self.shared.try_controls() {
+            controls.reconfigure = None;
+        }
+        self.reset_source();
+        self.path_changed |= load.path_changed;
+        self.shared.set_idle(false);
+        self.shared.set_play(load.play);
+        // The caller usually already removed the old source.
+        let old = {
+            let mut src = self.shared.source()?;
+            self.shared.next_source_generation();
+            src.replace(load.source)
+        };
+        self.shared.set_source_state(SourceState::Loaded);
+        drop(old);
+        Ok(())
+    }
+
+    /// Resets the state of the playback loop that is related to the source,
+    /// so that new source starts clean.
+    fn reset_source(&mut self) {
+        self.pending_seek = None;
+        self.seek_gain = 1.;
+        self.flushing = false;
+        self.stalled_reads = 0;
+        self.errored_reads = 0;
+        self.raw_recording = false;
+        self.passthrough.reset();
+        self.stopping = None;
+        self.prefetch_notified = false;
+        self.shared.set_prefetch_due(false);
+    }
+
+    /// Checks whether the limiter is enabled. The last controls are used if
+    /// they are locked by other thread.
+    fn limiter_enabled(&self) -> bool {
+        self.shared
+            .try_controls()
+            .map_or(self.controls.limiter, |c| c.limiter)
+    }
+
+    /// Mixes in [`f32`] and converts the result to the integer buffer
+    /// `data`. The volume, overlays and the limiter are applied before the
+    /// conversion, so the samples are soft clipped instead of saturated.
+    fn try_mix_limited(
+        &mut self,
+        data: &mut SampleBufferMut,
+        play_time: Instant,
+        timestamp: Option<OutputStreamTimestamp>,
+    ) -> Result<()> {
+        let mut buf = mem::take(&mut self.limit_buf);
+        buf.resize(data.len(), 0.);
+        let res = self.try_mix(
+            &mut SampleBufferMut::F32(&mut buf),
+            play_time,
+            timestamp,
+        );
+        data.write_from_f32(&buf);
+        self.limit_buf = buf;
+        res
     }
 
     /// Tries to write the data from the source to the buffer `data`
@@ -53,41 +315,144 @@ impl Mixer {
         &mut self,
         data: &'a mut SampleBufferMut<'b>,
         play_time: Instant,
+        timestamp: Option<OutputStreamTimestamp>,
     ) -> Result<()> {
-        let controls = { self.shared.controls()?.clone() };
+        if let Some(s) = self.shared.take_stopping() {
+            self.stopping = Some(s);
+        }
+
+        let mut path_changed = std::mem::take(&mut self.path_changed);
+        let new_source = match self.shared.try_controls() {
+            Ok(mut controls) => {
+                if let Some(envelope) = controls.envelope.take() {
+                    self.volume = VolumeIterator::Custom(envelope);
+                    self.envelope_source = None;
+                }
+                path_changed |=
+                    std::mem::take(&mut controls.signal_path_changed);
+                if let Some(target) = controls.pending_seek.take() {
+                    self.pending_seek = Some(target);
+                }
+                let new_source = std::mem::take(&mut controls.new_source);
+                if std::mem::take(&mut controls.prefetch_rearm) {
+                    self.prefetch_notified = false;
+                }
+                self.controls = controls.clone();
+                new_source
+            }
+            // Other thread changes the controls, use the last controls
+            // instead of waiting. The changes are applied with the next
+            // callback.
+            Err(Error::WouldBlock) => false,
+            Err(e) => return Err(e),
+        };
+        if new_source {
+            self.reset_source();
+        }
+        let controls = self.controls.clone();
+
+        // Play and volume are read only once, so that the whole callback
+        // (and the fade transition with `last_play`) sees the same values
+        // even if they change in the middle of the callback. The skip flag
+        // is read first, so that play is at least as new as the flag.
+        let skip_fade = self.shared.take_skip_fade();
+        self.play = self.shared.play();
+        self.target_volume = self.shared.volume() * self.shared.track_gain();
 
-        let lp = self.last_play.unwrap_or(controls.play);
-        self.last_play = Some(controls.play);
+        if let Some(ts) = timestamp.filter(|_| controls.timing_events) {
+            self.invoke_timing(data.len(), ts);
+        }
+
+        if path_changed {
+            if let Err(e) =
+                self.shared.invoke_callback(CallbackInfo::SignalPathChanged)
+            {
+                _ = self.shared.invoke_err_callback(e);
+            }
+        }
 
-        self.volume.set_volume(controls.volume, lp);
+        let lp = self.last_play.unwrap_or(self.play);
+        self.last_play = Some(self.play);
 
-        if controls.play {
+        // Volume changes while playing are ramped so that dragging the
+        // volume doesn't produce zipper noise. Fades are only retargeted.
+        // Changing the volume cancels the custom envelope.
+        let changed =
+            self.applied_volume.is_some_and(|v| v != self.target_volume);
+        let custom = matches!(self.volume, VolumeIterator::Custom(_));
+        let can_ramp =
+            self.ramping || custom || self.volume.until_target().is_none();
+        if self.play
+            && lp
+            && can_ramp
+            && changed
+            && !controls.volume_ramp.is_zero()
+        {
+            self.volume.to_linear_time_rate(
+                self.target_volume,
+                self.info.sample_rate,
+                controls.volume_ramp,
+                self.info.channel_count as usize,
+            );
+            self.ramping = true;
+        } else if changed && custom {
+            self.volume = VolumeIterator::constant(self.target_volume);
+        } else {
+            self.volume.set_volume(self.target_volume, lp);
+        }
+        self.applied_volume = Some(self.target_volume);
+
+        // There is nothing to fade when not playing.
+        if !self.play || controls.scrub.is_some() {
+            self.apply_seek()?;
+            self.seek_gain = 1.;
+        }
+
+        if let Some(burst) = controls.scrub {
+            self.play_scrub(data, burst, &controls)?;
+        } else if self.play {
             self.last_sound = true;
 
             // Change the volume transition if the transition is to pause or
             // if it was previously paused
-            if !lp {
+            if !lp || skip_fade {
+                self.ramping = false;
                 if self.volume.until_target().is_none() {
                     self.volume.set_volume(0., lp);
                 }
 
+                let fade = if skip_fade {
+                    Duration::ZERO
+                } else {
+                    controls.fade_in
+                };
                 self.volume.to_linear_time_rate(
-                    controls.volume,
+                    self.target_volume,
                     self.info.sample_rate,
-                    controls.fade_duration,
+                    fade,
                     self.info.channel_count as usize,
                 );
             }
 
-            self.play_source(data, controls)?;
+            if self.pending_seek.is_some() || self.seek_gain < 1. {
+                self.play_seek_fade(data, &controls)?;
+            } else {
+                self.play_chunked(data, &controls)?;
+            }
         } else {
             // Change the volume transition if the transition is to play or
             // if it was previously played
-            if lp {
+            if lp || skip_fade {
+                self.ramping = false;
+                let fade = if skip_fade {
+                    Duration::ZERO
+                } else {
+                    controls.fade_out
+                };
                 self.volume.to_linear_time_rate(
                     0.,
                     self.info.sample_rate,
-                    controls.fade_duration,
+                    fade,
                     self.info.channel_count as usize,
                 );
             }
@@ -98,13 +463,20 @@ impl Mixer {
 
             if len != 0 {
                 // play the silencing
-                self.play_source(&mut slice_sbuf!(data, 0..len), controls)?;
+                self.play_chunked(&mut data.slice(..len), &controls)?;
                 self.last_sound = true;
             }
 
             // than pause
-            let data_len = data.len();
-            silence_sbuf!(slice_sbuf!(data, len..data_len));
+            data.slice(len..).silence();
+
+            if len == 0 && self.stopping.take().is_some() {
+                if let Err(e) =
+                    self.shared.invoke_callback(CallbackInfo::Stopped)
+                {
+                    _ = self.shared.invoke_err_callback(e);
+                }
+            }
 
             if len == 0 && self.last_sound {
                 if let Err(e) = self
@@ -114,28 +486,802 @@ impl Mixer {
                     _ = self.shared.invoke_err_callback(e);
                 };
                 self.last_sound = false;
+                if controls.auto_hard_pause {
+                    self.shared.set_idle(true);
+                    if let Some(p) = &self.pause_request {
+                        p.send();
+                    }
+                }
+            }
+        }
+
+        self.apply_channel_map(data, &controls);
+        self.apply_balance(data, &controls);
+        self.mix_overlays(data)?;
+        self.limit(data, &controls);
+
+        // The request stays pending until the controls can be locked
+        // without waiting.
+        if self.request_pending {
+            match (self.requested.clone(), self.shared.try_controls()) {
+                (None, _) => self.request_pending = false,
+                (Some(config), Ok(mut controls)) => {
+                    controls.reconfigure = Some(config.clone());
+                    drop(controls);
+                    self.request_pending = false;
+                    let request = CallbackInfo::ReconfigureRequested(config);
+                    if let Err(e) = self.shared.invoke_callback(request) {
+                        _ = self.shared.invoke_err_callback(e);
+                    }
+                }
+                (Some(_), Err(Error::WouldBlock)) => {}
+                (Some(_), Err(e)) => return Err(e),
+            }
+        }
+
+        if let Some((remaining, played)) = self.prefetch_time.take() {
+            let prefetch = CallbackInfo::PrefetchTime { remaining, played };
+            if let Err(e) = self.shared.invoke_callback(prefetch) {
+                _ = self.shared.invoke_err_callback(e);
+            }
+        }
+
+        if std::mem::take(&mut self.looped) {
+            if let Err(e) = self.shared.invoke_callback(CallbackInfo::Looped) {
+                _ = self.shared.invoke_err_callback(e);
+            }
+        }
+
+        if let Some(frame) = self.mismatch.take() {
+            let mismatch = CallbackInfo::PassthroughMismatch { frame };
+            if let Err(e) = self.shared.invoke_callback(mismatch) {
+                _ = self.shared.invoke_err_callback(e);
+            }
+        }
+
+        if let Some((old, new)) = self.spec_change.take() {
+            let change = CallbackInfo::SourceSpecChanged { old, new };
+            if let Err(e) = self.shared.invoke_callback(change) {
+                _ = self.shared.invoke_err_callback(e);
+            }
+        }
+
+        self.shared.set_outgoing_time(
+            self.stopping
+                .as_ref()
+                .and_then(|s| s.get_time())
+                .or(self.outgoing_time),
+        );
+        self.update_fade(&controls);
+        self.analyze(data, &controls);
+        self.tap(data, &controls);
+        self.tap_samples(data, play_time, &controls);
+        self.detect_gaps(data, &controls)?;
+
+        self.frame_pos +=
+            (data.len() / self.info.channel_count.max(1) as usize) as u64;
+
+        Ok(())
+    }
+
+    /// Detects runs of near-silent frames while a source is playing
+    fn detect_gaps(
+        &mut self,
+        data: &SampleBufferMut,
+        controls: &Controls,
+    ) -> Result<()> {
+        let Some((threshold, min_frames)) = controls.gap_detection else {
+            self.quiet_frames = 0;
+            return Ok(());
+        };
+
+        if !self.play || self.shared.source()?.is_none() {
+            self.quiet_frames = 0;
+            return Ok(());
+        }
+
+        let channels = self.info.channel_count.max(1) as usize;
+        let mut pos = self.frame_pos;
+        let mut gap = None;
+        operate_samples!(data, d, {
+            for frame in d.chunks(channels) {
+                let quiet = frame
+                    .iter()
+                    .all(|s| f32::from_sample_(*s).abs() < threshold);
+                if quiet {
+                    self.quiet_frames += 1;
+                } else {
+                    if self.quiet_frames >= min_frames as u64 {
+                        gap =
+                            Some((pos - self.quiet_frames, self.quiet_frames));
+                    }
+                    self.quiet_frames = 0;
+                }
+                pos += 1;
+            }
+        });
+
+        if let Some((start, len)) = gap {
+            let rate = self.info.sample_rate.max(1) as f64;
+            let gap = CallbackInfo::GapDetected {
+                at: Duration::from_secs_f64(start as f64 / rate),
+                length: Duration::from_secs_f64(len as f64 / rate),
+            };
+            if let Err(e) = self.shared.invoke_callback(gap) {
+                _ = self.shared.invoke_err_callback(e);
             }
         }
 
         Ok(())
     }
 
+    /// Analyzes the frequency bands of the output if enabled
+    fn analyze(&mut self, data: &SampleBufferMut, controls: &Controls) {
+        let Some((n, interval)) = controls.bands else {
+            self.analyzer = None;
+            return;
+        };
+
+        let analyzer = match &mut self.analyzer {
+            Some(a) if a.config() == (n, interval) => a,
+            a => a.insert(BandAnalyzer::new(
+                n,
+                interval,
+                self.info.sample_rate,
+                self.info.channel_count,
+            )),
+        };
+
+        let shared = &self.shared;
+        operate_samples!(data, d, {
+            analyzer.process(d, |l| shared.try_set_band_levels(l))
+        });
+    }
+
+    /// Seeks to the latest scrub position and plays short burst of audio
+    /// there. The rest of the buffer is silent.
+    fn play_scrub(
+        &mut self,
+        data: &mut SampleBufferMut,
+        burst: Duration,
+        controls: &Controls,
+    ) -> Result<()> {
+        if let Some(target) = self.shared.take_scrub_target() {
+            if let Some(s) = self.shared.source()?.as_mut() {
+                if let Err(e) = s.seek(target) {
+                    _ = self.shared.invoke_err_callback(e.into());
+                }
+            }
+            self.scrub_left =
+                (burst.as_secs_f64() * self.info.sample_rate as f64) as usize
+                    * self.info.channel_count as usize;
+        }
+
+        let len = self.scrub_left.min(data.len());
+        if len != 0 {
+            // The bursts are audible even when paused.
+            let volume = std::mem::replace(
+                &mut self.volume,
+                VolumeIterator::constant(self.target_volume),
+            );
+            let res = self.play_chunked(&mut data.slice(..len), controls);
+            self.volume = volume;
+            res?;
+            self.scrub_left -= len;
+        }
+        data.slice(len..).silence();
+        Ok(())
+    }
+
+    /// Gets the configuration that should be requested for the
+    /// configuration preferred by source. Nothing is requested if the
+    /// device satisfies it or if the configuration is forced.
+    fn unsatisfied(
+        &self,
+        preferred: Option<DeviceConfig>,
+        controls: &Controls,
+    ) -> Option<DeviceConfig> {
+        controls.unsatisfied(&self.info, preferred)
+    }
+
+    /// Seeks the source to the pending position
+    fn apply_seek(&mut self) -> Result<()> {
+        let Some(target) = self.pending_seek.take() else {
+            return Ok(());
+        };
+        if let Some(s) = self.shared.source()?.as_mut() {
+            if let Err(e) = s.seek(target) {
+                _ = self.shared.invoke_err_callback(e.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Fades out before the pending seek and fades in after it, so that the
+    /// jump in the waveform doesn't click. The seek is done in the middle of
+    /// the buffer when the fade-out ends.
+    fn play_seek_fade(
+        &mut self,
+        data: &mut SampleBufferMut,
+        controls: &Controls,
+    ) -> Result<()> {
+        let channels = self.info.channel_count.max(1) as usize;
+        let step = 1.
+            / (controls.seek_fade.as_secs_f32()
+                * self.info.sample_rate as f32)
+                .max(1.);
+
+        let mut start = 0;
+        if self.pending_seek.is_some() {
+            let frames = (self.seek_gain / step).ceil() as usize;
+            start = (frames * channels).min(data.len());
+            let mut out = data.slice(..start);
+            self.play_chunked(&mut out, controls)?;
+            self.ramp_seek_gain(&mut out, 0., step);
+            if self.seek_gain > 0. {
+                return Ok(());
+            }
+            self.apply_seek()?;
+        }
+
+        let mut rest = data.slice(start..);
+        if !rest.is_empty() {
+            self.play_chunked(&mut rest, controls)?;
+            self.ramp_seek_gain(&mut rest, 1., step);
+        }
+        Ok(())
+    }
+
+    /// Moves the seek fade gain towards `target` by `step` for each frame
+    /// and applies it to the frames.
+    fn ramp_seek_gain(
+        &mut self,
+        data: &mut SampleBufferMut,
+        target: f32,
+        step: f32,
+    ) {
+        let channels = self.info.channel_count.max(1) as usize;
+        let gain = &mut self.seek_gain;
+        operate_samples!(data, d, {
+            for frame in d.chunks_mut(channels) {
+                *gain = if (target - *gain).abs() <= step {
+                    target
+                } else {
+                    *gain + step * (target - *gain).signum()
+                };
+
+                #[allow(clippy::useless_conversion)]
+                for s in frame {
+                    *s = s.mul_amp((*gain).into());
+                }
+            }
+        });
+    }
+
+    /// Routes the channels of the output according to the channel map
+    fn apply_channel_map(
+        &self,
+        data: &mut SampleBufferMut,
+        controls: &Controls,
+    ) {
+        let Some(map) = &controls.channel_map else {
+            return;
+        };
+        if check_channel_map(map, self.info.channel_count).is_err() {
+            return;
+        }
+        operate_samples!(data, d, route_channels(d, map));
+    }
+
+    /// Applies the stereo balance to the first two channels of the output.
+    /// Changes of the balance are ramped to avoid zipper noise.
+    fn apply_balance(
+        &mut self,
+        data: &mut SampleBufferMut,
+        controls: &Controls,
+    ) {
+        let target = controls.balance.clamp(-1., 1.);
+        let channels = self.info.channel_count as usize;
+        if channels < 2 || (target == 0. && self.balance == 0.) {
+            self.balance = target;
+            return;
+        }
+
+        let step = 2.
+            / (self.info.sample_rate as f32 * BALANCE_RAMP.as_secs_f32())
+                .max(1.);
+        let balance = &mut self.balance;
+        operate_samples!(data, d, {
+            let mut gains = balance_gains(*balance);
+            for frame in d.chunks_mut(channels) {
+                if *balance != target {
+                    *balance = if (target - *balance).abs() <= step {
+                        target
+                    } else {
+                        *balance + step * (target - *balance).signum()
+                    };
+                    gains = balance_gains(*balance);
+                }
+
+                #[allow(clippy::useless_conversion)]
+                {
+                    frame[0] = frame[0].mul_amp(gains.0.into());
+                    if let Some(r) = frame.get_mut(1) {
+                        *r = r.mul_amp(gains.1.into());
+                    }
+                }
+            }
+        });
+    }
+
+    /// Adds the overlays to the output and removes the overlays that ended.
+    /// If the overlays are locked, they are delayed until the next buffer.
+    fn mix_overlays(&mut self, data: &mut SampleBufferMut) -> Result<()> {
+        let mut overlays = match self.shared.try_overlays() {
+            Ok(o) => o,
+            Err(Error::WouldBlock) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        if overlays.is_empty() {
+            return Ok(());
+        }
+
+        if self.overlay_buf.len() < data.len() {
+            self.overlay_buf.resize(data.len(), 0.);
+        }
+        let buf = &mut self.overlay_buf[..data.len()];
+        let mut ended = vec![];
+        overlays.retain_mut(|o| {
+            let (end, e) = o.mix(data, buf);
+            if let Err(e) = e {
+                _ = self.shared.invoke_err_callback(e.into());
+            }
+            if end {
+                ended.push(o.id);
+            }
+            !end
+        });
+        drop(overlays);
+
+        for id in ended {
+            let ended = CallbackInfo::OverlayEnded(id);
+            if let Err(e) = self.shared.invoke_callback(ended) {
+                _ = self.shared.invoke_err_callback(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Soft clips the samples above [`LIMITER_THRESHOLD`] if the limiter is
+    /// enabled. Samples below the threshold are left untouched.
+    fn limit(&self, data: &mut SampleBufferMut, controls: &Controls) {
+        if !controls.limiter {
+            return;
+        }
+
+        let mut cnt = 0;
+        operate_samples!(data, d, {
+            for s in d.iter_mut() {
+                let v = f32::from_sample_(*s);
+                if v.abs() > LIMITER_THRESHOLD {
+                    *s = Sample::from_sample(soft_clip(v, LIMITER_THRESHOLD));
+                    cnt += 1;
+                }
+            }
+        });
+
+        if cnt != 0 {
+            self.shared.add_limited_samples(cnt);
+        }
+    }
+
+    /// Passes the output to the analysis tap if enabled
+    fn tap(&self, data: &SampleBufferMut, controls: &Controls) {
+        if !controls.analysis_tap {
+            return;
+        }
+
+        // Skip the buffer rather than block the playback loop.
+        let Some(mut tap) = self.shared.try_analysis_tap() else {
+            return;
+        };
+        let Some(tap) = tap.as_mut() else {
+            return;
+        };
+
+        let (rate, channels) =
+            (self.info.sample_rate, self.info.channel_count);
+        operate_samples!(data, d, tap.process(d, rate, channels));
+    }
+
+    /// Passes the output converted to f32 to the sample tap if enabled
+    fn tap_samples(
+        &mut self,
+        data: &SampleBufferMut,
+        play_time: Instant,
+        controls: &Controls,
+    ) {
+        if !controls.sample_tap {
+            return;
+        }
+
+        // Skip the buffer rather than block the playback loop.
+        let Some(mut tap) = self.shared.try_sample_tap() else {
+            return;
+        };
+        let Some(f) = tap.as_mut() else {
+            return;
+        };
+
+        let samples = if let SampleBufferMut::F32(d) = data {
+            &d[..]
+        } else {
+            self.tap_buf.clear();
+            operate_samples!(data, d, {
+                self.tap_buf
+                    .extend(d.iter().map(|s| convert_sample::<_, f32>(*s)))
+            });
+            &self.tap_buf[..]
+        };
+
+        f(&SampleTap {
+            samples,
+            channels: self.info.channel_count,
+            sample_rate: self.info.sample_rate,
+            play_time,
+        });
+    }
+
+    /// Updates the fade progress in the shared data
+    fn update_fade(&self, controls: &Controls) {
+        let fade = self.volume.until_target().map(|rem| {
+            let (len, direction) = if self.play {
+                (controls.fade_in, FadeDirection::In)
+            } else {
+                (controls.fade_out, FadeDirection::Out)
+            };
+            let total = len.as_secs_f32() * self.info.sample_rate as f32;
+            let progress = if total <= 0. {
+                1.
+            } else {
+                (1. - rem as f32 / total).clamp(0., 1.)
+            };
+            (progress, direction)
+        });
+        self.shared.set_fade(fade);
+    }
+
+    /// Compares the output with the raw frames of the source if no conversion
+    /// is needed and the volume is 1.
+    fn check_passthrough(
+        &mut self,
+        data: &SampleBufferMut,
+        spec: Option<SourceSpec>,
+    ) {
+        let channels = self.info.channel_count;
+        let unconverted = spec.is_some_and(|s| {
+            s.channel_count == channels
+                && s.sample_rate == self.info.sample_rate
+        });
+        let unit_volume = self.target_volume == 1.
+            && matches!(self.volume, VolumeIterator::Constant(v) if v == 1.);
+
+        if !unconverted || !unit_volume {
+            self.passthrough.skip(data.len() / channels.max(1) as usize);
+            return;
+        }
+
+        let passthrough = &mut self.passthrough;
+        if let Some(frame) =
+            operate_samples!(data, d, { passthrough.check(d, channels) })
+        {
+            self.mismatch.get_or_insert(frame);
+        }
+    }
+
+    /// Invokes the callback with the timing of the buffer
+    fn invoke_timing(&mut self, len: usize, ts: OutputStreamTimestamp) {
+        let origin = *self.origin.get_or_insert(ts.callback);
+        let timing = CallbackInfo::BufferTiming {
+            callback: ts.callback.duration_since(&origin).unwrap_or_default(),
+            playback: ts.playback.duration_since(&origin).unwrap_or_default(),
+            frames: (len / self.info.channel_count.max(1) as usize) as u32,
+        };
+        if let Err(e) = self.shared.invoke_callback(timing) {
+            _ = self.shared.invoke_err_callback(e);
+        }
+    }
+
+    /// Writes the data from the source to the buffer `data` in chunks limited
+    /// by [`Controls::max_decode_chunk`]. The source is unlocked between the
+    /// chunks.
+    fn play_chunked(
+        &mut self,
+        data: &mut SampleBufferMut,
+        controls: &Controls,
+    ) -> Result<()> {
+        let channels = self.info.channel_count.max(1) as usize;
+        let frames = (controls.max_decode_chunk.as_secs_f64()
+            * self.info.sample_rate as f64) as usize;
+        if frames == 0 {
+            return self.play_source(data, controls);
+        }
+
+        let chunk = frames * channels;
+        let mut start = 0;
+        while start < data.len() {
+            let end = (start + chunk).min(data.len());
+            self.play_source(&mut data.slice(start..end), controls)?;
+            start = end;
+        }
+        Ok(())
+    }
+
+    /// Initializes the prefetched source that replaced the ended source and
+    /// writes its data to the rest of the buffer `data`. Sources that fail
+    /// to initialize are skipped.
+    fn swap_prefetch(
+        &mut self,
+        data: &mut SampleBufferMut,
+        controls: &Controls,
+    ) -> Result<()> {
+        self.prefetch_notified = false;
+        self.shared.set_prefetch_due(false);
+        self.raw_recording = false;
+        self.passthrough.reset();
+
+        let preferred = {
+            let mut src = self.shared.source()?;
+            loop {
+                let Some(s) = src.as_mut() else {
+                    break None;
+                };
+                // The source is usually already initialized when it was
+                // queued, so that it can start without any delay.
+                let init = self.next_init.take();
+                if let Some((_, preferred)) =
+                    init.filter(|(c, _)| *c == self.info)
+                {
+                    break Some(preferred);
+                }
+                let preferred = s.preferred_config();
+                let ctx = InitContext::new(
+                    self.info.clone(),
+                    preferred.as_ref(),
+                    None,
+                );
+                match s.init_with(&ctx) {
+                    Ok(_) => break Some(preferred),
+                    Err(e) => {
+                        _ = self.shared.invoke_err_callback(e.into());
+                        let next = self.shared.take_prefetch();
+                        self.shared.next_source_generation();
+                        *src = next.map(|n| {
+                            self.next_init = n.init;
+                            self.next_gain = n.gain;
+                            n.src
+                        });
+                    }
+                }
+            }
+        };
+
+        let Some(preferred) = preferred else {
+            // No source can play, so it ends.
+            self.shared.set_source_state(SourceState::Ended);
+            data.silence();
+            return self.source_ended();
+        };
+
+        self.requested = self.unsatisfied(preferred, controls);
+        self.request_pending = self.requested.is_some();
+        let state = if self.request_pending {
+            PrefetchState::Reconfigure
+        } else {
+            PrefetchState::Gapless
+        };
+
+        // The gain of the next source applies from its first sample.
+        if let Some(g) = self.next_gain.take() {
+            self.shared.set_track_gain(g);
+            self.target_volume = self.shared.volume() * g;
+            self.volume.set_volume(self.target_volume, true);
+            self.applied_volume = Some(self.target_volume);
+        }
+
+        self.path_changed = true;
+        let swapped = CallbackInfo::PrefetchSwapped(state);
+        if let Err(e) = self.shared.invoke_callback(swapped) {
+            _ = self.shared.invoke_err_callback(e);
+        }
+        self.play_source(data, controls)
+    }
+
+    /// Invokes [`CallbackInfo::SourceEnded`] only once for each transition
+    /// into the state without source.
+    fn source_ended(&mut self) -> Result<()> {
+        if mem::replace(&mut self.ended_notified, true) {
+            Ok(())
+        } else {
+            self.shared.invoke_callback(CallbackInfo::SourceEnded)
+        }
+    }
+
     /// Writes the data from the source to the buffer `data`
     fn play_source(
         &mut self,
         data: &mut SampleBufferMut,
-        controls: Controls,
+        controls: &Controls,
     ) -> Result<()> {
         let mut src = self.shared.source()?;
+        // The stopped source is played only while it fades out.
+        let mut stopping = if src.is_none() {
+            self.stopping.take()
+        } else {
+            None
+        };
 
-        match src.as_mut() {
+        let was_stopping = stopping.is_some();
+        self.outgoing_time = None;
+
+        if src.is_some() {
+            self.ended_notified = false;
+        }
+
+        match src.as_mut().or(stopping.as_mut()) {
             Some(s) => {
-                let supports_volume = s.volume(self.volume);
+                let generation = self.shared.source_generation();
+                let supports_volume =
+                    match (&self.volume, self.envelope_source) {
+                        (VolumeIterator::Custom(_), Some((g, sup)))
+                            if g == generation =>
+                        {
+                            sup
+                        }
+                        (v, _) => {
+                            let sup = s.volume(v.clone());
+                            self.envelope_source =
+                                matches!(v, VolumeIterator::Custom(_))
+                                    .then_some((generation, sup));
+                            sup
+                        }
+                    };
 
-                let (cnt, e) = s.read(data);
+                if self.raw_recording != controls.passthrough_check {
+                    self.raw_recording = controls.passthrough_check;
+                    s.record_raw_hashes(self.raw_recording);
+                    self.passthrough.reset();
+                }
 
-                if let Err(e) = e {
-                    _ = self.shared.invoke_err_callback(e.into());
+                let (mut cnt, e) = if self.flushing {
+                    (s.flush(data), Ok(()))
+                } else {
+                    s.read(data)
+                };
+
+                if let Some(change) = s.take_spec_change() {
+                    self.spec_change = Some(change);
+                }
+
+                let mut outgoing_ended = false;
+                if !was_stopping {
+                    self.shared.set_cached_time(s.get_time());
+                    self.outgoing_time = s.outgoing_time();
+                    outgoing_ended = s.take_outgoing_ended();
+                }
+
+                let request =
+                    self.unsatisfied(s.wants_reconfigure(), controls);
+                if request.is_some() && request != self.requested {
+                    self.request_pending = true;
+                }
+                self.requested = request;
+
+                let mut ended = match e {
+                    Ok(_) => {
+                        self.errored_reads = 0;
+                        cnt < data.len()
+                    }
+                    Err(e) => {
+                        self.errored_reads += 1;
+                        _ = self.shared.invoke_err_callback(e.into());
+                        false
+                    }
+                };
+
+                // Drain the samples buffered in the source before ending it.
+                if ended && !self.flushing {
+                    self.flushing = true;
+                    cnt += s.flush(&mut data.slice(cnt..));
+                    ended = cnt < data.len();
+                }
+
+                // Safety valve for sources that fail to produce any samples
+                // or keep failing and never end.
+                let mut stalled = false;
+                if cnt == 0 && !ended && !data.is_empty() {
+                    self.stalled_reads += 1;
+                } else {
+                    self.stalled_reads = 0;
+                }
+                let reads = self.stalled_reads.max(self.errored_reads);
+                if !ended
+                    && controls.stall_limit != 0
+                    && reads >= controls.stall_limit
+                {
+                    _ = self
+                        .shared
+                        .invoke_err_callback(Error::SourceStalled(reads));
+                    ended = true;
+                    stalled = true;
+                }
+
+                // Start the source again from the start in repeat mode.
+                // Short sources may end multiple times within one buffer, so
+                // repeat until the buffer is full or the source doesn't
+                // produce any samples.
+                while ended && !stalled && !was_stopping && controls.repeat {
+                    if let Err(e) = s.seek(Duration::ZERO) {
+                        _ = self.shared.invoke_err_callback(e.into());
+                        break;
+                    }
+                    self.flushing = false;
+                    self.looped = true;
+                    self.prefetch_notified = false;
+                    ended = false;
+
+                    let (mut n, e) = s.read(&mut data.slice(cnt..));
+                    cnt += n;
+                    let end = match e {
+                        Ok(_) => cnt < data.len(),
+                        Err(e) => {
+                            _ = self.shared.invoke_err_callback(e.into());
+                            false
+                        }
+                    };
+                    if !end {
+                        break;
+                    }
+
+                    self.flushing = true;
+                    let f = s.flush(&mut data.slice(cnt..));
+                    cnt += f;
+                    n += f;
+                    if n == 0 {
+                        break;
+                    }
+                    ended = cnt < data.len();
+                }
+                if ended {
+                    self.stalled_reads = 0;
+                    self.errored_reads = 0;
+                    self.flushing = false;
+                }
+
+                // Trigger the prefetch notification when the source is
+                // close to its end.
+                let notify = controls.prefetch_notify;
+                let fraction = controls.prefetch_notify_fraction;
+                if !ended
+                    && !was_stopping
+                    && !self.prefetch_notified
+                    && (!notify.is_zero() || fraction > 0.)
+                {
+                    if let Some(t) = s.get_time() {
+                        let rem = t.remaining();
+                        let played = if t.total.is_zero() {
+                            0.
+                        } else {
+                            t.current.as_secs_f32() / t.total.as_secs_f32()
+                        };
+                        if (!notify.is_zero() && rem <= notify)
+                            || (fraction > 0. && played >= fraction)
+                        {
+                            self.prefetch_notified = true;
+                            self.prefetch_time = Some((rem, played));
+                            self.shared.set_prefetch_due(true);
+                        }
+                    }
                 }
 
                 if supports_volume {
@@ -146,30 +1292,905 @@ impl Mixer {
                     // manually change the volume of each sample if the
                     // source doesn't support volume
                     if !supports_volume {
-                        if controls.volume != 1. {
+                        if !matches!(
+                            self.volume,
+                            VolumeIterator::Constant(v) if v == 1.
+                        ) {
                             #[allow(clippy::useless_conversion)]
                             for s in d.iter_mut() {
                                 *s = (*s)
                                     .mul_amp(self.volume.next_vol().into());
                             }
-                        } else if controls.volume == 0. {
+                        } else if self.target_volume == 0. {
                             write_silence(&mut d[..cnt]);
                         }
                     }
 
                     write_silence(&mut d[cnt..]);
-                    if cnt < d.len() {
-                        *src = None;
-                        self.shared.invoke_callback(CallbackInfo::SourceEnded)
-                    } else {
-                        Ok(())
+                });
+
+                let spec = self.raw_recording.then(|| {
+                    s.take_raw_hashes(&mut self.passthrough.raw);
+                    s.source_spec()
+                });
+
+                // Continue with the prefetched source without any gap.
+                let next = if ended && !was_stopping {
+                    self.shared.take_prefetch()
+                } else {
+                    None
+                };
+                let swapped = next.is_some();
+                if let Some(next) = next {
+                    self.next_init = next.init;
+                    self.next_gain = next.gain;
+                    self.shared.next_source_generation();
+                    *src = Some(next.src);
+                } else if ended && src.take().is_some() {
+                    self.shared.set_source_state(SourceState::Ended);
+                }
+                drop(src);
+
+                if outgoing_ended {
+                    let outgoing = CallbackInfo::OutgoingEnded;
+                    if let Err(e) = self.shared.invoke_callback(outgoing) {
+                        _ = self.shared.invoke_err_callback(e);
                     }
-                })
+                }
+
+                if !ended {
+                    self.stopping = stopping.take().or(self.stopping.take());
+                }
+
+                if let Some(spec) = spec {
+                    self.check_passthrough(&data.slice(..cnt), spec);
+                }
+
+                if !ended {
+                    return Ok(());
+                }
+
+                if was_stopping {
+                    return self.shared.invoke_callback(CallbackInfo::Stopped);
+                }
+
+                if swapped {
+                    return self
+                        .swap_prefetch(&mut data.slice(cnt..), controls);
+                }
+
+                if controls.pause_on_end {
+                    self.shared.set_play(false);
+                }
+                self.source_ended()
             }
             None => {
-                silence_sbuf!(data);
+                data.silence();
                 Ok(())
             }
         }
     }
 }
+
+/// Checks that the channel map has entry for each of the `channels` output
+/// channels and that it refers only to existing channels.
+pub(super) fn check_channel_map(
+    map: &[Option<u32>],
+    channels: u32,
+) -> Result<()> {
+    if map.len() == channels as usize
+        && map.len() <= MAX_MAP_CHANNELS
+        && map.iter().flatten().all(|c| *c < channels)
+    {
+        Ok(())
+    } else {
+        Err(Error::InvalidChannelMap(channels))
+    }
+}
+
+/// Routes the channels of each frame, output channel `i` gets the source
+/// channel `map[i]` or silence. The map must be valid.
+fn route_channels<T: Sample>(data: &mut [T], map: &[Option<u32>]) {
+    let mut frame = [T::EQUILIBRIUM; MAX_MAP_CHANNELS];
+    let frame = &mut frame[..map.len()];
+    for f in data.chunks_exact_mut(map.len()) {
+        frame.copy_from_slice(f);
+        for (s, m) in f.iter_mut().zip(map) {
+            *s = m.map_or(T::EQUILIBRIUM, |c| frame[c as usize]);
+        }
+    }
+}
+
+/// Checks whether the current thread runs the playback loop (e.g. when
+/// called from a callback invoked by the playback loop).
+pub(super) fn is_mixer_thread() -> bool {
+    IN_MIXER.get()
+}
+
+/// Gets the time when the buffer will be played. `now` is the current time.
+pub(super) fn get_play_time(
+    info: &OutputCallbackInfo,
+    now: Instant,
+) -> Instant {
+    now + info
+        .timestamp()
+        .playback
+        .duration_since(&info.timestamp().callback)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{
+        collections::VecDeque,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc::{self, Receiver},
+            Mutex,
+        },
+        thread,
+    };
+
+    use cpal::SampleFormat;
+
+    use super::*;
+    use crate::{
+        sample_buffer::SampleBuffer,
+        source::{
+            symph::{self, Symph},
+            BufferSource, ChainSource, ConfigPolicy, CrossFadeSource,
+            SilenceSource, SineSource, VolumeEnvelope,
+        },
+        SinkHandle,
+    };
+
+    /// Configuration of the output in the tests
+    pub(crate) fn config() -> DeviceConfig {
+        DeviceConfig {
+            channel_count: 2,
+            sample_rate: 48000,
+            sample_format: SampleFormat::F32,
+        }
+    }
+
+    /// Loads the source and creates mixer that plays it with [`config`].
+    /// The returned receiver gets the arguments of the callback.
+    pub(crate) fn mixer_for(
+        shared: &Arc<SharedData>,
+        mut src: impl Source + 'static,
+    ) -> (Mixer, Receiver<CallbackInfo>) {
+        src.init(&config()).unwrap();
+        *shared.source().unwrap() = Some(Box::new(src));
+        shared.next_source_generation();
+        shared.set_source_state(SourceState::Loaded);
+        shared.set_play(true);
+
+        let (send, recv) = mpsc::channel();
+        shared
+            .callback()
+            .set(Some(Box::new(move |info| _ = send.send(info))))
+            .unwrap();
+        (Mixer::new(shared.clone(), config()), recv)
+    }
+
+    /// Creates new shared data and mixer that plays the source, see
+    /// [`mixer_for`].
+    fn mixer_with(
+        src: impl Source + 'static,
+    ) -> (Mixer, Arc<SharedData>, Receiver<CallbackInfo>) {
+        let shared = Arc::new(SharedData::new());
+        let (mixer, events) = mixer_for(&shared, src);
+        (mixer, shared, events)
+    }
+
+    /// Gets receiver of the arguments of the error callback.
+    fn errors(shared: &SharedData) -> Receiver<Error> {
+        let (send, recv) = mpsc::channel();
+        shared
+            .err_callback()
+            .set(Some(Box::new(move |e| _ = send.send(e))))
+            .unwrap();
+        recv
+    }
+
+    #[test]
+    fn callback_running_on_other_thread_is_deferred() {
+        let src = SilenceSource::new(Duration::from_millis(15));
+        let (mut mixer, shared, _) = mixer_with(src);
+        let (started, wait_start) = mpsc::channel();
+        let (finish, wait_finish) = mpsc::channel::<()>();
+        let calls = Arc::new(Mutex::new(vec![]));
+        let log = calls.clone();
+        shared
+            .callback()
+            .set(Some(Box::new(move |info| match info {
+                CallbackInfo::StreamStalled => {
+                    _ = started.send(());
+                    _ = wait_finish.recv();
+                    log.lock().unwrap().push("stalled");
+                }
+                CallbackInfo::SourceEnded => log.lock().unwrap().push("ended"),
+                _ => {}
+            })))
+            .unwrap();
+
+        let other = shared.clone();
+        let t = thread::spawn(move || {
+            other.invoke_callback(CallbackInfo::StreamStalled)
+        });
+        wait_start.recv().unwrap();
+
+        // The playback loop would wait until the other call returns.
+        let (send, recv) = mpsc::channel();
+        thread::spawn(move || {
+            mix(&mut mixer, 3);
+            _ = send.send(());
+        });
+        recv.recv_timeout(Duration::from_secs(5))
+            .expect("the playback loop waited for the callback");
+        assert!(calls.lock().unwrap().is_empty());
+
+        finish.send(()).unwrap();
+        t.join().unwrap().unwrap();
+        assert_eq!(*calls.lock().unwrap(), ["stalled", "ended"]);
+    }
+
+    /// Mixes `cnt` buffers of 10 ms and returns them concatenated.
+    pub(crate) fn mix(mixer: &mut Mixer, cnt: usize) -> Vec<f32> {
+        mix_len(mixer, cnt, 960)
+    }
+
+    /// Mixes `cnt` buffers of `len` samples and returns them concatenated.
+    /// The buffers are not silent before mixing, so samples that the mixer
+    /// doesn't write are noticed.
+    fn mix_len(mixer: &mut Mixer, cnt: usize, len: usize) -> Vec<f32> {
+        let mut res = Vec::with_capacity(cnt * len);
+        let mut data = vec![0_f32; len];
+        for _ in 0..cnt {
+            data.fill(1.);
+            let mut buf = SampleBufferMut::F32(&mut data);
+            mixer.mix(&mut buf, Instant::now(), None);
+            res.extend_from_slice(&data);
+        }
+        res
+    }
+
+    /// Counts the events that match `f`.
+    fn count(
+        events: &Receiver<CallbackInfo>,
+        f: impl Fn(&CallbackInfo) -> bool,
+    ) -> usize {
+        events.try_iter().filter(f).count()
+    }
+
+    #[test]
+    fn source_ended_is_invoked_once() {
+        let src = SilenceSource::new(Duration::from_millis(15));
+        let (mut mixer, shared, events) = mixer_with(src);
+        mix(&mut mixer, 32);
+
+        let ended = count(&events, |e| matches!(e, CallbackInfo::SourceEnded));
+        assert_eq!(ended, 1);
+        assert!(shared.play());
+    }
+
+    #[test]
+    fn pause_on_end_pauses() {
+        let src = SilenceSource::new(Duration::from_millis(15));
+        let (mut mixer, shared, _) = mixer_with(src);
+        shared.controls().unwrap().pause_on_end = true;
+
+        let playing: Vec<_> = (0..4)
+            .map(|_| {
+                mix(&mut mixer, 1);
+                shared.play()
+            })
+            .collect();
+
+        // The source ends in the second buffer.
+        assert_eq!(playing, [true, false, false, false]);
+        assert_eq!(shared.source_state(), SourceState::Ended);
+    }
+
+    #[test]
+    fn crossfade_reports_outgoing_source() {
+        // The overlap is from 30 ms to 50 ms and the whole source is 80 ms.
+        let src = CrossFadeSource::new(
+            SilenceSource::new(Duration::from_millis(50)),
+            SilenceSource::new(Duration::from_millis(50)),
+            Duration::from_millis(20),
+        );
+        let (mut mixer, shared, events) = mixer_with(src);
+
+        let mut outgoing = vec![];
+        let mut ended = vec![];
+        for i in 0..10 {
+            mix(&mut mixer, 1);
+            outgoing.push(shared.outgoing_time().unwrap().is_some());
+            let ev: Vec<_> = events
+                .try_iter()
+                .filter(|e| {
+                    matches!(
+                        e,
+                        CallbackInfo::OutgoingEnded
+                            | CallbackInfo::SourceEnded
+                    )
+                })
+                .collect();
+            if !ev.is_empty() {
+                ended.push((i, ev));
+            }
+        }
+
+        assert_eq!(
+            outgoing,
+            [
+                false, false, false, true, false, false, false, false, false,
+                false
+            ]
+        );
+        assert_eq!(ended.len(), 2);
+        assert_eq!(ended[0].0, 4);
+        assert!(matches!(ended[0].1[..], [CallbackInfo::OutgoingEnded]));
+        assert!(ended[1].0 >= 7);
+        assert!(matches!(ended[1].1[..], [CallbackInfo::SourceEnded]));
+    }
+
+    /// Source that fills each buffer and fails on every `fail`th read
+    struct FailingSource {
+        fail: usize,
+        reads: usize,
+    }
+
+    impl Source for FailingSource {
+        fn init(&mut self, _: &DeviceConfig) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn read(
+            &mut self,
+            buffer: &mut SampleBufferMut,
+        ) -> (usize, anyhow::Result<()>) {
+            self.reads += 1;
+            let n = buffer.len();
+            buffer.silence();
+            if self.reads.is_multiple_of(self.fail) {
+                (n, Err(anyhow::anyhow!("read failed")))
+            } else {
+                (n, Ok(()))
+            }
+        }
+    }
+
+    /// Plays [`FailingSource`] with stall limit of 4 and returns whether the
+    /// source was stalled and its state.
+    fn play_failing(fail: usize) -> (bool, SourceState) {
+        let (mut mixer, shared, _) =
+            mixer_with(FailingSource { fail, reads: 0 });
+        shared.controls().unwrap().stall_limit = 4;
+        let errors = errors(&shared);
+        mix(&mut mixer, 16);
+
+        let stalled = errors
+            .try_iter()
+            .filter(|e| matches!(e, Error::SourceStalled(4)))
+            .count();
+        (stalled == 1, shared.source_state())
+    }
+
+    #[test]
+    fn persistent_errors_end_source() {
+        // The source keeps producing samples, but every read fails.
+        assert_eq!(play_failing(1), (true, SourceState::Ended));
+    }
+
+    #[test]
+    fn occasional_errors_dont_end_source() {
+        assert_eq!(play_failing(2), (false, SourceState::Loaded));
+    }
+
+    /// Plays the file from the test corpus until it ends. Returns the
+    /// errors and the events (without the prefetch time) in the order in
+    /// which they were reported.
+    fn play_corpus(
+        name: &str,
+    ) -> Vec<std::result::Result<CallbackInfo, Error>> {
+        let path =
+            format!("{}/tests/corpus/{name}", env!("CARGO_MANIFEST_DIR"));
+        let src = Symph::open(path, &Default::default()).unwrap();
+        let (mut mixer, shared, _) = mixer_with(src);
+        let (send, recv) = mpsc::channel();
+        let err_send = send.clone();
+        shared
+            .callback()
+            .set(Some(Box::new(move |info| _ = send.send(Ok(info)))))
+            .unwrap();
+        shared
+            .err_callback()
+            .set(Some(Box::new(move |e| _ = err_send.send(Err(e)))))
+            .unwrap();
+
+        for _ in 0..100 {
+            mix(&mut mixer, 1);
+            if shared.source_state() == SourceState::Ended {
+                break;
+            }
+        }
+        recv.try_iter()
+            .filter(|e| !matches!(e, Ok(CallbackInfo::PrefetchTime { .. })))
+            .collect()
+    }
+
+    /// Constant envelope that counts how many times it was cloned
+    struct CountingEnvelope(f32, Arc<AtomicUsize>);
+
+    impl VolumeEnvelope for CountingEnvelope {
+        fn next_vol(&mut self) -> f32 {
+            self.0
+        }
+
+        fn clone_box(&self) -> Box<dyn VolumeEnvelope> {
+            self.1.fetch_add(1, Ordering::Relaxed);
+            Box::new(CountingEnvelope(self.0, self.1.clone()))
+        }
+    }
+
+    /// Gets the largest absolute value of the samples.
+    fn peak(data: &[f32]) -> f32 {
+        data.iter().fold(0_f32, |m, s| m.max(s.abs()))
+    }
+
+    #[test]
+    fn truncated_file_reports_error_before_end() {
+        let truncated = play_corpus("truncated_data.wav");
+        assert!(
+            matches!(
+                truncated.as_slice(),
+                [
+                    Err(Error::Symph(symph::Error::UnexpectedEnd)),
+                    Ok(CallbackInfo::SourceEnded)
+                ]
+            ),
+            "{truncated:?}"
+        );
+
+        let clean = play_corpus("valid.wav");
+        assert!(
+            matches!(clean.as_slice(), [Ok(CallbackInfo::SourceEnded)]),
+            "{clean:?}"
+        );
+    }
+
+    #[test]
+    fn envelope_is_not_cloned_each_buffer() {
+        let (mut mixer, shared, _) = mixer_with(SineSource::new(440.));
+        let clones = Arc::new(AtomicUsize::new(0));
+        {
+            let mut controls = shared.controls().unwrap();
+            controls.volume_ramp = Duration::ZERO;
+            controls.fade_in = Duration::ZERO;
+            controls.envelope =
+                Some(Box::new(CountingEnvelope(0.25, clones.clone())));
+        }
+
+        mix(&mut mixer, 9);
+        let last = mix(&mut mixer, 1);
+        assert!(clones.load(Ordering::Relaxed) <= 1);
+        assert!((peak(&last) - 0.25).abs() < 0.01);
+
+        // Changing the volume cancels the envelope.
+        shared.set_volume(0.5);
+        mix(&mut mixer, 1);
+        let last = mix(&mut mixer, 1);
+        assert!((peak(&last) - 0.5).abs() < 0.01);
+    }
+
+    /// Number of envelopes given to [`ZeroSized`]
+    static ZST_ENVELOPES: AtomicUsize = AtomicUsize::new(0);
+
+    /// Source without any state, all its instances have the same address
+    struct ZeroSized;
+
+    impl Source for ZeroSized {
+        fn init(&mut self, _: &DeviceConfig) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn read(
+            &mut self,
+            buffer: &mut SampleBufferMut,
+        ) -> (usize, anyhow::Result<()>) {
+            buffer.silence();
+            (buffer.len(), Ok(()))
+        }
+
+        fn volume(&mut self, _: VolumeIterator) -> bool {
+            ZST_ENVELOPES.fetch_add(1, Ordering::Relaxed);
+            true
+        }
+    }
+
+    #[test]
+    fn envelope_is_given_to_each_zero_sized_source() {
+        let (mut mixer, shared, _) = mixer_with(ZeroSized);
+        {
+            let mut controls = shared.controls().unwrap();
+            controls.volume_ramp = Duration::ZERO;
+            controls.envelope =
+                Some(Box::new(CountingEnvelope(0.25, Arc::default())));
+        }
+        mix(&mut mixer, 3);
+        assert_eq!(ZST_ENVELOPES.load(Ordering::Relaxed), 1);
+
+        // Load the second source the same way as the sink.
+        {
+            let mut controls = shared.controls().unwrap();
+            let mut src = shared.source().unwrap();
+            controls.new_source = true;
+            *src = Some(Box::new(ZeroSized));
+            shared.next_source_generation();
+        }
+        mix(&mut mixer, 3);
+        assert_eq!(ZST_ENVELOPES.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn repeat_fills_buffer_with_short_source() {
+        // 100 frames, the buffer has 480 frames.
+        let src = BufferSource::new(vec![0.5; 200], 2, 48000);
+        let (mut mixer, shared, _) = mixer_with(src);
+        {
+            let mut controls = shared.controls().unwrap();
+            controls.fade_in = Duration::ZERO;
+            controls.fade_out = Duration::ZERO;
+            controls.repeat = true;
+        }
+        shared.set_play_now(true);
+
+        let data = mix(&mut mixer, 3);
+        assert!(data.iter().all(|s| (s - 0.5).abs() < 1e-6));
+    }
+
+    /// Source that never produces any samples and always fails
+    struct StalledSource;
+
+    impl Source for StalledSource {
+        fn init(&mut self, _: &DeviceConfig) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn read(
+            &mut self,
+            _: &mut SampleBufferMut,
+        ) -> (usize, anyhow::Result<()>) {
+            (0, Err(anyhow::anyhow!("no data")))
+        }
+    }
+
+    #[test]
+    fn stalled_source_is_ended() {
+        let (mut mixer, shared, events) = mixer_with(StalledSource);
+        shared.set_play_now(true);
+        let errors = errors(&shared);
+
+        // The playback loop keeps producing silence until the source is
+        // ended after the default limit of 64 reads.
+        let data = mix(&mut mixer, 80);
+        assert!(data.iter().all(|s| *s == 0.));
+
+        let stalled: Vec<_> = errors
+            .try_iter()
+            .filter_map(|e| match e {
+                Error::SourceStalled(n) => Some(n),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(stalled, [64]);
+        let ended = count(&events, |e| matches!(e, CallbackInfo::SourceEnded));
+        assert_eq!(ended, 1);
+        assert_eq!(shared.source_state(), SourceState::Ended);
+    }
+
+    /// Source that delays the inner source by the given number of samples
+    struct DelaySource {
+        inner: BufferSource,
+        delay: VecDeque<f32>,
+    }
+
+    impl DelaySource {
+        fn new(inner: BufferSource, delay: usize) -> Self {
+            Self {
+                inner,
+                delay: vec![0.; delay].into(),
+            }
+        }
+    }
+
+    impl Source for DelaySource {
+        fn init(&mut self, info: &DeviceConfig) -> anyhow::Result<()> {
+            self.inner.init(info)
+        }
+
+        fn read(
+            &mut self,
+            buffer: &mut SampleBufferMut,
+        ) -> (usize, anyhow::Result<()>) {
+            let (n, res) = self.inner.read(buffer);
+            let buf = buffer.as_f32_mut().unwrap();
+            for s in &mut buf[..n] {
+                self.delay.push_back(*s);
+                *s = self.delay.pop_front().unwrap();
+            }
+            (n, res)
+        }
+
+        fn flush(&mut self, buffer: &mut SampleBufferMut) -> usize {
+            let buf = buffer.as_f32_mut().unwrap();
+            let n = buf.len().min(self.delay.len());
+            for (d, s) in buf.iter_mut().zip(self.delay.drain(..n)) {
+                *d = s;
+            }
+            n
+        }
+    }
+
+    /// Renders the source and returns the output
+    fn render_all(src: Box<dyn Source>) -> Vec<f32> {
+        crate::render(src, config(), 256)
+            .unwrap()
+            .flat_map(|b| match b {
+                SampleBuffer::F32(d) => d,
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn flush_drains_delayed_samples() {
+        // Samples are never zero, so the rendered silence can be told apart.
+        let signal: Vec<f32> = (1..=1000).map(|i| i as f32 / 1000.).collect();
+        let buffer = || BufferSource::new(signal.clone(), 2, 48000);
+        const DELAY: usize = 300;
+
+        let plain = render_all(Box::new(buffer()));
+        let delayed = render_all(Box::new(DelaySource::new(buffer(), DELAY)));
+
+        assert_eq!(plain[..signal.len()], signal[..]);
+        assert!(delayed[..DELAY].iter().all(|s| *s == 0.));
+        assert_eq!(delayed[DELAY..DELAY + signal.len()], signal[..]);
+        let count = |d: &[f32]| d.iter().filter(|s| **s != 0.).count();
+        assert_eq!(count(&plain), count(&delayed));
+    }
+
+    #[test]
+    fn chain_flushes_inner_sources() {
+        let signal: Vec<f32> = (1..=1000).map(|i| i as f32 / 1000.).collect();
+        let delayed = || {
+            let src = BufferSource::new(signal.clone(), 2, 48000);
+            Box::new(DelaySource::new(src, 300)) as Box<dyn Source>
+        };
+
+        let out =
+            render_all(Box::new(ChainSource::new(vec![delayed(), delayed()])));
+
+        // The tail of the first source is played before the second source.
+        assert_eq!(out[300..1300], signal[..]);
+        assert!(out[1300..1600].iter().all(|s| *s == 0.));
+        assert_eq!(out[1600..2600], signal[..]);
+    }
+
+    /// Source of silence that reports change of its spec on the second read
+    struct SpecChangeSource(usize);
+
+    impl Source for SpecChangeSource {
+        fn init(&mut self, _: &DeviceConfig) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn read(
+            &mut self,
+            buffer: &mut SampleBufferMut,
+        ) -> (usize, anyhow::Result<()>) {
+            self.0 += 1;
+            buffer.silence();
+            (buffer.len(), Ok(()))
+        }
+
+        fn take_spec_change(&mut self) -> Option<(SourceSpec, SourceSpec)> {
+            let spec = |channel_count| SourceSpec {
+                channel_count,
+                sample_rate: 48000,
+                bits_per_sample: None,
+                codec: None,
+            };
+            (self.0 == 2).then(|| (spec(2), spec(1)))
+        }
+    }
+
+    #[test]
+    fn spec_change_is_reported() {
+        let (mut mixer, shared, events) = mixer_with(SpecChangeSource(0));
+        shared.set_play_now(true);
+        mix(&mut mixer, 4);
+
+        let changes: Vec<_> = events
+            .try_iter()
+            .filter_map(|e| match e {
+                CallbackInfo::SourceSpecChanged { old, new } => {
+                    Some((old.channel_count, new.channel_count))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(changes, [(2, 1)]);
+    }
+
+    /// Silent source that prefers the given sample format.
+    struct FormatSource {
+        inner: SilenceSource,
+        format: SampleFormat,
+    }
+
+    impl FormatSource {
+        fn new(format: SampleFormat) -> Self {
+            Self {
+                inner: SilenceSource::new(Duration::from_millis(15)),
+                format,
+            }
+        }
+    }
+
+    impl Source for FormatSource {
+        fn init(&mut self, info: &DeviceConfig) -> anyhow::Result<()> {
+            self.inner.init(info)
+        }
+
+        fn read(
+            &mut self,
+            buffer: &mut SampleBufferMut,
+        ) -> (usize, anyhow::Result<()>) {
+            self.inner.read(buffer)
+        }
+
+        fn preferred_config(&mut self) -> Option<DeviceConfig> {
+            Some(DeviceConfig {
+                sample_format: self.format,
+                ..config()
+            })
+        }
+    }
+
+    /// Plays sources preferring the given formats back to back on F32 stream
+    /// and returns how each of the queued sources replaced the previous.
+    fn play_formats(
+        formats: &[SampleFormat],
+        policy: ConfigPolicy,
+    ) -> Vec<PrefetchState> {
+        let (mut mixer, shared, events) =
+            mixer_with(FormatSource::new(formats[0]));
+        shared.controls().unwrap().config_policy = policy;
+        let handle = SinkHandle::new(shared.clone());
+        for f in &formats[1..] {
+            handle.queue(Box::new(FormatSource::new(*f))).unwrap();
+        }
+
+        mix(&mut mixer, formats.len() * 2 + 2);
+        assert_eq!(shared.prefetch().unwrap().len(), 0);
+        events
+            .try_iter()
+            .filter_map(|e| match e {
+                CallbackInfo::PrefetchSwapped(s) => Some(s),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lossless_formats_reuse_stream() {
+        use SampleFormat::*;
+        let formats = [I16, F32, I16, U8, F32];
+        assert_eq!(
+            play_formats(&formats, ConfigPolicy::LosslessFormat),
+            [PrefetchState::Gapless; 4]
+        );
+
+        // I32 doesn't fit into the mantissa of F32.
+        assert_eq!(
+            play_formats(&[F32, I32], ConfigPolicy::LosslessFormat),
+            [PrefetchState::Reconfigure]
+        );
+        assert_eq!(
+            play_formats(&[F32, I16, F32], ConfigPolicy::Exact),
+            [PrefetchState::Reconfigure, PrefetchState::Gapless]
+        );
+    }
+
+    #[test]
+    fn prefetch_notify_with_resampling() {
+        // 1 s source at 44.1 kHz
+        let src = BufferSource::new(vec![0.5; 88200], 2, 44100);
+        let (mut mixer, shared, events) = mixer_with(src);
+        {
+            let mut controls = shared.controls().unwrap();
+            controls.prefetch_notify = Duration::from_millis(300);
+            controls.prefetch_rearm = true;
+        }
+
+        let mut notified = None;
+        for i in 0..100 {
+            mix(&mut mixer, 1);
+            for e in events.try_iter() {
+                if let CallbackInfo::PrefetchTime { remaining, .. } = e {
+                    notified = Some((i, remaining));
+                }
+            }
+        }
+
+        // 10 ms buffers, the source reaches 700 ms in the 70th buffer.
+        let (buf, remaining) = notified.unwrap();
+        assert!((68..=70).contains(&buf), "notified in buffer {buf}");
+        let diff = remaining.abs_diff(Duration::from_millis(300));
+        assert!(diff <= Duration::from_millis(10), "{remaining:?} remaining");
+    }
+
+    /// Mixes 44.1 kHz sine with the given maximum decode chunk in 100 ms
+    /// buffers.
+    fn mix_chunked(chunk: Duration) -> Vec<f32> {
+        let samples = (0..44100)
+            .flat_map(|i| {
+                let s =
+                    (i as f32 * 440. / 44100. * std::f32::consts::TAU).sin();
+                [s, s]
+            })
+            .collect();
+        let src = BufferSource::new(samples, 2, 44100);
+        let (mut mixer, shared, _) = mixer_with(src);
+        shared.controls().unwrap().max_decode_chunk = chunk;
+        shared.set_volume(0.5);
+
+        mix_len(&mut mixer, 12, 9600)
+    }
+
+    #[test]
+    fn chunked_decode_is_identical() {
+        let whole = mix_chunked(Duration::ZERO);
+        assert!(whole.iter().any(|s| s.abs() > 0.1));
+        // 7 ms doesn't divide the buffer evenly.
+        for ms in [1, 7, 25, 200] {
+            let chunked = mix_chunked(Duration::from_millis(ms));
+            assert!(whole == chunked, "{ms} ms chunks differ");
+        }
+    }
+
+    /// Mixes one 10 ms buffer of constant 0.8 at volume 1.25 to [`i16`].
+    fn mix_loud_i16(limiter: bool) -> (Vec<i16>, u64) {
+        let src = BufferSource::new(vec![0.8; 9600], 2, 48000);
+        let (mut mixer, shared, _) = mixer_with(src);
+        {
+            let mut controls = shared.controls().unwrap();
+            controls.fade_in = Duration::ZERO;
+            controls.limiter = limiter;
+        }
+        shared.set_volume(1.25);
+        shared.set_play_now(true);
+
+        let mut data = vec![0_i16; 960];
+        let mut buf = SampleBufferMut::I16(&mut data);
+        mixer.mix(&mut buf, Instant::now(), None);
+        (data, shared.limited_samples())
+    }
+
+    #[test]
+    fn limiter_soft_clips_integer_output() {
+        // Without the limiter, the samples saturate.
+        let (data, limited) = mix_loud_i16(false);
+        assert!(data.iter().all(|s| *s == i16::MAX));
+        assert_eq!(limited, 0);
+
+        let (data, limited) = mix_loud_i16(true);
+        let expected: i16 = soft_clip(1., LIMITER_THRESHOLD).to_sample();
+        assert!(expected < i16::MAX - 500);
+        assert!(data.iter().all(|s| s.abs_diff(expected) <= 1), "{data:?}");
+        assert_eq!(limited, data.len() as u64);
+    }
+}
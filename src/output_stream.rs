@@ -0,0 +1,276 @@
+use std::{
+    sync::{
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Weak,
+    },
+    thread::JoinHandle,
+};
+
+use anyhow::anyhow;
+use cpal::{traits::StreamTrait, Stream};
+
+use crate::{
+    err::{Error, Result},
+    mixer::is_mixer_thread,
+    shared::SharedData,
+    thread,
+};
+
+/// Maximum number of commands waiting for the stream thread
+const COMMAND_CAPACITY: usize = 8;
+
+/// Command for the thread that owns the stream
+enum Command {
+    /// Resumes the stream and sends the result
+    Play(SyncSender<Result<()>>),
+    /// Pauses the stream and sends the result
+    Pause(SyncSender<Result<()>>),
+    /// Pauses the stream if the playback loop is still idle
+    IdlePause,
+    /// Resumes the stream if the playback is still requested
+    Resume,
+    /// Drops the stream and stops the thread
+    Stop,
+}
+
+/// Output stream owned by separate thread. cpal streams can be controlled
+/// only by the thread that owns them, so this allows the playback loop to
+/// pause the stream when it becomes idle.
+pub(crate) struct OutputStream {
+    /// Sends commands to the thread
+    commands: SyncSender<Command>,
+    /// The thread that owns the stream
+    thread: Option<JoinHandle<()>>,
+}
+
+/// Requests pause of [`OutputStream`] when the playback loop becomes idle.
+#[derive(Clone)]
+pub(crate) struct PauseRequest(SyncSender<Command>);
+
+/// Requests [`OutputStream`] to resume from threads that don't own it (e.g.
+/// from [`crate::SinkHandle`]).
+#[derive(Clone)]
+pub(crate) struct ResumeRequest(SyncSender<Command>);
+
+/// Stream that can be controlled by [`OutputStream`]
+pub(crate) trait StreamControl {
+    /// Resumes the stream
+    fn play(&self) -> Result<()>;
+
+    /// Pauses the stream
+    fn pause(&self) -> Result<()>;
+}
+
+impl StreamControl for Stream {
+    fn play(&self) -> Result<()> {
+        StreamTrait::play(self).map_err(Into::into)
+    }
+
+    fn pause(&self) -> Result<()> {
+        StreamTrait::pause(self).map_err(Into::into)
+    }
+}
+
+impl OutputStream {
+    /// Creates the stream with `build` on a new thread. `build` gets request
+    /// that can be used to pause the stream from the playback loop, the
+    /// stream is then paused only if [`SharedData::idle`] is still true.
+    /// The stream can be resumed with the request in the shared data, see
+    /// [`SharedData::resume_stream`].
+    pub(crate) fn new<S, F>(shared: Weak<SharedData>, build: F) -> Result<Self>
+    where
+        S: StreamControl,
+        F: FnOnce(PauseRequest) -> Result<S> + Send + 'static,
+    {
+        let (commands, recv) = mpsc::sync_channel(COMMAND_CAPACITY);
+        let pause = PauseRequest(commands.clone());
+        let (res_send, res_recv) = mpsc::channel();
+        let thread_shared = shared.clone();
+        let thread =
+            thread::spawn("stream", false, move || match build(pause) {
+                Ok(stream) => {
+                    _ = res_send.send(Ok(()));
+                    run(&stream, recv, thread_shared);
+                }
+                Err(e) => _ = res_send.send(Err(e)),
+            })?;
+
+        res_recv.recv().map_err(|_| {
+            Error::Other(anyhow!("The stream thread panicked"))
+        })??;
+
+        if let Some(shared) = shared.upgrade() {
+            let resume = ResumeRequest(commands.clone());
+            shared.set_resume_request(Some(resume))?;
+        }
+
+        Ok(Self {
+            commands,
+            thread: Some(thread),
+        })
+    }
+
+    /// Resumes the stream.
+    ///
+    /// # Errors
+    /// - failed to resume the stream
+    pub(crate) fn play(&self) -> Result<()> {
+        self.command(Command::Play)
+    }
+
+    /// Pauses the stream.
+    ///
+    /// # Errors
+    /// - failed to pause the stream
+    pub(crate) fn pause(&self) -> Result<()> {
+        self.command(Command::Pause)
+    }
+
+    /// Sends the command and waits for the result.
+    fn command(
+        &self,
+        cmd: impl FnOnce(SyncSender<Result<()>>) -> Command,
+    ) -> Result<()> {
+        let (send, recv) = mpsc::sync_channel(1);
+        let stopped = || Error::Other(anyhow!("The stream thread stopped"));
+        self.commands.send(cmd(send)).map_err(|_| stopped())?;
+        recv.recv().map_err(|_| stopped())?
+    }
+}
+
+impl Drop for OutputStream {
+    fn drop(&mut self) {
+        _ = self.commands.send(Command::Stop);
+        // Dropping the stream waits for the playback loop, so the thread
+        // can't be joined from it.
+        if let Some(t) = self.thread.take().filter(|_| !is_mixer_thread()) {
+            _ = t.join();
+        }
+    }
+}
+
+impl PauseRequest {
+    /// Requests the pause without blocking.
+    pub(crate) fn send(&self) {
+        _ = self.0.try_send(Command::IdlePause);
+    }
+}
+
+impl ResumeRequest {
+    /// Requests the stream to resume without waiting for the result. The
+    /// errors from resuming are reported to the error callback.
+    ///
+    /// # Errors
+    /// - the stream was dropped
+    /// - too many commands are waiting for the stream thread
+    pub(crate) fn send(&self) -> Result<()> {
+        self.0.try_send(Command::Resume).map_err(|e| match e {
+            TrySendError::Full(_) => Error::WouldBlock,
+            TrySendError::Disconnected(_) => Error::StreamReleased,
+        })
+    }
+}
+
+/// Executes the commands for the stream until it is stopped.
+fn run(
+    stream: &impl StreamControl,
+    commands: Receiver<Command>,
+    shared: Weak<SharedData>,
+) {
+    for cmd in commands {
+        match cmd {
+            Command::Play(res) => _ = res.send(stream.play()),
+            Command::Pause(res) => _ = res.send(stream.pause()),
+            Command::IdlePause => {
+                let Some(shared) = shared.upgrade() else {
+                    continue;
+                };
+                if shared.idle() && stream.pause().is_ok() {
+                    shared.set_stream_running(false);
+                }
+            }
+            Command::Resume => {
+                let Some(shared) = shared.upgrade() else {
+                    continue;
+                };
+                if !shared.play() {
+                    continue;
+                }
+                match stream.play() {
+                    Ok(_) => shared.set_stream_running(true),
+                    Err(e) => _ = shared.invoke_err_callback(e),
+                }
+            }
+            Command::Stop => return,
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+    use crate::SinkHandle;
+
+    /// Stream that only remembers whether it is playing
+    pub(crate) struct FakeStream(pub(crate) Arc<AtomicBool>);
+
+    impl StreamControl for FakeStream {
+        fn play(&self) -> Result<()> {
+            self.0.store(true, Ordering::Relaxed);
+            Ok(())
+        }
+
+        fn pause(&self) -> Result<()> {
+            self.0.store(false, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    /// Creates stream for the shared data. Returns the stream and flag
+    /// that is true while the stream is playing.
+    pub(crate) fn fake_stream(
+        shared: &Arc<SharedData>,
+    ) -> (OutputStream, Arc<AtomicBool>) {
+        let playing = Arc::new(AtomicBool::new(false));
+        let fake = FakeStream(playing.clone());
+        let stream =
+            OutputStream::new(Arc::downgrade(shared), move |_| Ok(fake))
+                .unwrap();
+        (stream, playing)
+    }
+
+    /// Waits until the condition is true, panics after a second.
+    pub(crate) fn wait_until(cond: impl Fn() -> bool) {
+        let start = Instant::now();
+        while !cond() {
+            assert!(start.elapsed() < Duration::from_secs(1), "timeout");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn handle_resumes_hard_paused_stream() {
+        let shared = Arc::new(SharedData::new());
+        let (stream, playing) = fake_stream(&shared);
+        stream.pause().unwrap();
+        shared.set_stream_running(false);
+
+        let handle = SinkHandle::new(shared.clone());
+        handle.play(true).unwrap();
+        wait_until(|| shared.stream_running());
+        assert!(playing.load(Ordering::Relaxed));
+
+        // The released stream can be created only by the sink.
+        shared.set_stream_running(false);
+        drop(stream);
+        assert!(matches!(handle.play(true), Err(Error::StreamReleased)));
+    }
+}
@@ -0,0 +1,67 @@
+use cpal::{FromSample, SampleFormat};
+
+use crate::{
+    operate_samples,
+    sample_buffer::SampleBufferMut,
+    source::{DeviceConfig, InitContext, Source},
+};
+
+/// Identifies source that plays over the main source, see
+/// [`crate::Sink::play_overlay`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OverlayId(pub(crate) u64);
+
+/// Source that plays over the main source
+pub(crate) struct Overlay {
+    /// Identifier of the overlay
+    pub(crate) id: OverlayId,
+    /// The source of the overlay, it produces `f32` samples
+    pub(crate) source: Box<dyn Source>,
+    /// Volume of the overlay
+    pub(crate) volume: f32,
+}
+
+impl Overlay {
+    /// Creates new overlay with full volume
+    pub(crate) fn new(id: OverlayId, source: Box<dyn Source>) -> Self {
+        Self {
+            id,
+            source,
+            volume: 1.,
+        }
+    }
+
+    /// Reads the overlay into `buf` and adds it to `data`. Returns true if
+    /// the overlay has ended.
+    pub(crate) fn mix(
+        &mut self,
+        data: &mut SampleBufferMut,
+        buf: &mut [f32],
+    ) -> (bool, anyhow::Result<()>) {
+        buf.fill(0.);
+        let (cnt, e) = self.source.read(&mut SampleBufferMut::F32(buf));
+        let ended = e.is_ok() && cnt < buf.len();
+
+        // Sum through `f32` so that integer samples don't overflow, the
+        // conversion back saturates.
+        let volume = self.volume;
+        operate_samples!(data, d, {
+            for (d, s) in d.iter_mut().zip(&buf[..cnt]) {
+                let sum = f32::from_sample_(*d) + s * volume;
+                *d = FromSample::from_sample_(sum);
+            }
+        });
+
+        (ended, e)
+    }
+}
+
+/// Gets the init context for overlays on device with the given
+/// configuration. Overlays always produce `f32` samples.
+pub(crate) fn overlay_context(info: &DeviceConfig) -> InitContext {
+    let config = DeviceConfig {
+        sample_format: SampleFormat::F32,
+        ..info.clone()
+    };
+    InitContext::new(config, None, None)
+}
@@ -0,0 +1,132 @@
+use std::collections::VecDeque;
+
+use cpal::{FromSample, Sample};
+
+/// Offset basis of the FNV-1a hash
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+/// Prime of the FNV-1a hash
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// Hashes samples frame by frame. The samples are hashed as [`f64`] so that
+/// samples of different formats that represent the same value have the same
+/// hash.
+#[derive(Debug, Clone)]
+pub(crate) struct FrameHasher {
+    /// Number of channels in a frame
+    channels: u32,
+    /// Number of samples hashed in the current frame
+    cnt: u32,
+    /// Hash of the current frame
+    hash: u64,
+    /// Hashes of the finished frames
+    frames: Vec<u64>,
+}
+
+impl FrameHasher {
+    /// Creates new hasher for frames with the given number of channels
+    pub(crate) fn new(channels: u32) -> Self {
+        Self {
+            channels: channels.max(1),
+            cnt: 0,
+            hash: FNV_OFFSET,
+            frames: vec![],
+        }
+    }
+
+    /// Adds the next sample
+    #[inline]
+    pub(crate) fn push<T: Sample>(&mut self, sample: T)
+    where
+        f64: FromSample<T>,
+    {
+        self.hash = hash_sample(self.hash, sample);
+        self.cnt += 1;
+        if self.cnt == self.channels {
+            self.frames.push(self.hash);
+            self.cnt = 0;
+            self.hash = FNV_OFFSET;
+        }
+    }
+
+    /// Changes the number of channels, the unfinished frame is discarded
+    pub(crate) fn set_channels(&mut self, channels: u32) {
+        self.channels = channels.max(1);
+        self.cnt = 0;
+        self.hash = FNV_OFFSET;
+    }
+
+    /// Moves the hashes of the finished frames to `out`
+    pub(crate) fn take(&mut self, out: &mut VecDeque<u64>) {
+        out.extend(self.frames.drain(..));
+    }
+}
+
+/// Compares the output of the mixer with the raw frames of the source
+#[derive(Debug, Default)]
+pub(crate) struct PassthroughCheck {
+    /// Hashes of the raw frames of the source that were not yet compared
+    pub(crate) raw: VecDeque<u64>,
+    /// Index of the next output frame since the start of the source
+    frame: u64,
+    /// True if mismatch was already reported for this source
+    reported: bool,
+}
+
+impl PassthroughCheck {
+    /// Resets the check for new source
+    pub(crate) fn reset(&mut self) {
+        self.raw.clear();
+        self.frame = 0;
+        self.reported = false;
+    }
+
+    /// Skips the given number of output frames that can't be compared
+    pub(crate) fn skip(&mut self, frames: usize) {
+        self.raw.clear();
+        self.frame += frames as u64;
+    }
+
+    /// Compares the output with the raw frames. Returns the index of the
+    /// first frame that differs if it wasn't reported yet.
+    pub(crate) fn check<T: Sample>(
+        &mut self,
+        data: &[T],
+        channels: u32,
+    ) -> Option<u64>
+    where
+        f64: FromSample<T>,
+    {
+        let mut mismatch = None;
+        for frame in data.chunks(channels.max(1) as usize) {
+            let Some(raw) = self.raw.pop_front() else {
+                // The source doesn't provide the raw frames.
+                self.frame += 1;
+                continue;
+            };
+            let hash =
+                frame.iter().fold(FNV_OFFSET, |h, s| hash_sample(h, *s));
+            if hash != raw && !self.reported && mismatch.is_none() {
+                mismatch = Some(self.frame);
+            }
+            self.frame += 1;
+        }
+
+        if mismatch.is_some() {
+            self.reported = true;
+        }
+        mismatch
+    }
+}
+
+/// Adds the sample to the FNV-1a hash
+#[inline]
+fn hash_sample<T: Sample>(hash: u64, sample: T) -> u64
+where
+    f64: FromSample<T>,
+{
+    f64::from_sample_(sample)
+        .to_bits()
+        .to_le_bytes()
+        .iter()
+        .fold(hash, |h, b| (h ^ *b as u64).wrapping_mul(FNV_PRIME))
+}
@@ -0,0 +1,182 @@
+use std::time::{Duration, Instant};
+
+use crate::{
+    err::{Error, Result},
+    sample_buffer::SampleBuffer,
+    source::{DeviceConfig, Source},
+    ManualSink,
+};
+
+/// Iterator over the audio rendered with [`render`]
+pub struct RenderIter {
+    /// Sink that runs the playback loop
+    sink: ManualSink,
+    /// Number of samples in each buffer
+    len: usize,
+    /// Time when the first buffer would be played
+    start: Instant,
+    /// Number of rendered frames
+    frames: u64,
+    /// True if the source ended
+    ended: bool,
+}
+
+/// Plays the source without any device and returns the produced audio as
+/// buffers of `buffer_frames` frames with the given configuration. The
+/// audio goes through the same playback loop as with [`crate::Sink`], so
+/// it is converted to the configuration and the volume is applied.
+///
+/// The iterator ends after the buffer in which the source ended, so the
+/// last buffer may end with silence. If the source never ends, the iterator
+/// never ends either. The play times passed to the playback loop are
+/// derived from the number of rendered frames, so the rendering is
+/// deterministic.
+///
+/// # Errors
+/// - the sample format of the configuration is not supported
+/// - the source fails to init
+pub fn render(
+    src: Box<dyn Source>,
+    config: DeviceConfig,
+    buffer_frames: usize,
+) -> Result<RenderIter> {
+    let len = buffer_frames * config.channel_count as usize;
+    if SampleBuffer::silent(config.sample_format, 0).is_none() {
+        return Err(Error::UnsupportedSampleFormat);
+    }
+
+    let sink = ManualSink::new(config);
+    sink.load(src, true)?;
+    Ok(RenderIter {
+        sink,
+        len,
+        start: Instant::now(),
+        frames: 0,
+        ended: false,
+    })
+}
+
+impl RenderIter {
+    /// Gets the sink that renders the audio, e.g. to change the volume
+    /// between the buffers.
+    pub fn sink(&mut self) -> &mut ManualSink {
+        &mut self.sink
+    }
+}
+
+impl Iterator for RenderIter {
+    type Item = SampleBuffer;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.ended || self.len == 0 {
+            return None;
+        }
+
+        let info = self.sink.get_info();
+        let format = info.sample_format;
+        let rate = info.sample_rate.max(1) as u64;
+        let channels = info.channel_count.max(1) as u64;
+        let play_time = self.start
+            + Duration::from_nanos(self.frames * 1_000_000_000 / rate);
+
+        let mut buf = SampleBuffer::silent(format, self.len)?;
+        self.sink.fill(&mut buf.as_mut(), play_time);
+        self.frames += self.len as u64 / channels;
+        self.ended = self.sink.has_ended().unwrap_or(true);
+        Some(buf)
+    }
+}
+
+impl std::fmt::Debug for RenderIter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RenderIter")
+            .field("sink", &self.sink)
+            .field("frames", &self.frames)
+            .field("ended", &self.ended)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cpal::SampleFormat;
+
+    use super::*;
+    use crate::source::BufferSource;
+
+    fn config(
+        channel_count: u32,
+        sample_format: SampleFormat,
+    ) -> DeviceConfig {
+        DeviceConfig {
+            channel_count,
+            sample_rate: 48000,
+            sample_format,
+        }
+    }
+
+    /// Renders the source as [`f32`] and concatenates the buffers.
+    fn render_f32(src: BufferSource, channels: u32) -> Vec<f32> {
+        render(Box::new(src), config(channels, SampleFormat::F32), 256)
+            .unwrap()
+            .flat_map(|b| match b {
+                SampleBuffer::F32(d) => d,
+                b => panic!("unexpected format {:?}", b.format()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn render_ends_with_source() {
+        let samples: Vec<_> = (0..2000).map(|i| i as f32 / 2000.).collect();
+        let res = render_f32(BufferSource::new(samples.clone(), 2, 48000), 2);
+
+        // 1000 frames fit into 4 buffers of 256 frames.
+        assert_eq!(res.len(), 4 * 256 * 2);
+        assert_eq!(res[..2000], samples);
+        assert!(res[2000..].iter().all(|s| *s == 0.));
+    }
+
+    #[test]
+    fn render_is_deterministic() {
+        let samples: Vec<_> =
+            (0..44100).map(|i| (i as f32 * 0.03).sin()).collect();
+        let src = BufferSource::new(samples, 2, 44100);
+        let first = render_f32(src.clone(), 2);
+        assert_eq!(first, render_f32(src, 2));
+        assert!(first.iter().any(|s| s.abs() > 0.1));
+    }
+
+    #[test]
+    fn render_converts_channels() {
+        let samples: Vec<_> = (0..500).map(|i| i as f32 / 500.).collect();
+        let res = render_f32(BufferSource::new(samples, 1, 48000), 2);
+        assert_eq!(res.len(), 2 * 256 * 2);
+        // The added channels are silent.
+        assert!(res.chunks(2).all(|f| f[1] == 0.));
+        assert!(res[..1000].chunks(2).map(|f| f[0]).is_sorted());
+    }
+
+    #[test]
+    fn render_converts_format() {
+        let src = BufferSource::new(vec![0.5; 500], 2, 48000);
+        let bufs: Vec<_> =
+            render(Box::new(src), config(2, SampleFormat::I16), 256)
+                .unwrap()
+                .collect();
+        assert_eq!(bufs.len(), 1);
+        let SampleBuffer::I16(d) = &bufs[0] else {
+            panic!("unexpected format {:?}", bufs[0].format());
+        };
+        assert!(d[..500].iter().all(|s| *s == i16::MAX / 2 + 1));
+        assert!(d[500..].iter().all(|s| *s == 0));
+    }
+
+    #[test]
+    fn render_empty_buffers() {
+        let src = BufferSource::new(vec![0.5; 512], 2, 48000);
+        let mut iter =
+            render(Box::new(src), config(2, SampleFormat::F32), 0).unwrap();
+        assert!(iter.next().is_none());
+    }
+}
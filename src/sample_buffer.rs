@@ -1,5 +1,23 @@
+use std::ops::{Bound, RangeBounds};
+
+use cpal::{FromSample, SampleFormat};
+
 /// Buffer of samples, this is enum that contains the possible types
 /// of samples in a buffer
+///
+/// Sources that already have the data in the format of the device can use
+/// [`SampleBufferMut::format`] together with the typed accessors (such as
+/// [`SampleBufferMut::as_i16_mut`]) to copy the data directly into the
+/// buffer and fall back to the per sample conversion only when the formats
+/// don't match:
+/// ```rust,ignore
+/// if let Some(buf) = buffer.as_f32_mut() {
+///     let len = buf.len().min(data.len());
+///     buf[..len].copy_from_slice(&data[..len]);
+/// } else {
+///     buffer.write_from_f32(data);
+/// }
+/// ```
 #[non_exhaustive]
 pub enum SampleBufferMut<'a> {
     // documentation is copied from sample_formats.rs in cpal
@@ -53,37 +71,6 @@ macro_rules! operate_samples {
     }};
 }
 
-// I wasn't able to make the following macros into functions because of some
-// lifetime requirements.
-
-/// Creates slice from the buffer
-#[macro_export]
-macro_rules! slice_sbuf {
-    ($buf:expr, $range:expr) => {{
-        use $crate::sample_buffer::SampleBufferMut;
-        match $buf {
-            SampleBufferMut::I8(d) => SampleBufferMut::I8(&mut d[$range]),
-            SampleBufferMut::I16(d) => SampleBufferMut::I16(&mut d[$range]),
-            SampleBufferMut::I32(d) => SampleBufferMut::I32(&mut d[$range]),
-            SampleBufferMut::I64(d) => SampleBufferMut::I64(&mut d[$range]),
-            SampleBufferMut::U8(d) => SampleBufferMut::U8(&mut d[$range]),
-            SampleBufferMut::U16(d) => SampleBufferMut::U16(&mut d[$range]),
-            SampleBufferMut::U32(d) => SampleBufferMut::U32(&mut d[$range]),
-            SampleBufferMut::U64(d) => SampleBufferMut::U64(&mut d[$range]),
-            SampleBufferMut::F32(d) => SampleBufferMut::F32(&mut d[$range]),
-            SampleBufferMut::F64(d) => SampleBufferMut::F64(&mut d[$range]),
-        }
-    }};
-}
-
-/// Writes silence into the buffer
-#[macro_export]
-macro_rules! silence_sbuf {
-    ($buf:expr) => {
-        operate_samples!($buf, b, write_silence(b))
-    };
-}
-
 impl<'a> SampleBufferMut<'a> {
     /// Gets the number of items in the buffer
     pub fn len(&self) -> usize {
@@ -94,9 +81,336 @@ impl<'a> SampleBufferMut<'a> {
     pub fn is_empty(&self) -> bool {
         operate_samples!(self, b, b.is_empty())
     }
+
+    /// Creates slice from the buffer
+    ///
+    /// # Panics
+    /// - the range is out of bounds of the buffer
+    pub fn slice(
+        &mut self,
+        range: impl RangeBounds<usize>,
+    ) -> SampleBufferMut<'_> {
+        let range: (Bound<usize>, Bound<usize>) =
+            (range.start_bound().cloned(), range.end_bound().cloned());
+        match self {
+            Self::I8(d) => SampleBufferMut::I8(&mut d[range]),
+            Self::I16(d) => SampleBufferMut::I16(&mut d[range]),
+            Self::I32(d) => SampleBufferMut::I32(&mut d[range]),
+            Self::I64(d) => SampleBufferMut::I64(&mut d[range]),
+            Self::U8(d) => SampleBufferMut::U8(&mut d[range]),
+            Self::U16(d) => SampleBufferMut::U16(&mut d[range]),
+            Self::U32(d) => SampleBufferMut::U32(&mut d[range]),
+            Self::U64(d) => SampleBufferMut::U64(&mut d[range]),
+            Self::F32(d) => SampleBufferMut::F32(&mut d[range]),
+            Self::F64(d) => SampleBufferMut::F64(&mut d[range]),
+        }
+    }
+
+    /// Writes silence into the whole buffer
+    pub fn silence(&mut self) {
+        operate_samples!(self, b, write_silence(b))
+    }
+
+    /// Converts the samples from `data` and writes them to the buffer.
+    /// Returns the number of written samples.
+    pub fn write_from_f32(&mut self, data: &[f32]) -> usize {
+        operate_samples!(self, b, {
+            let len = b.len().min(data.len());
+            for (d, s) in b.iter_mut().zip(data) {
+                *d = FromSample::from_sample_(*s);
+            }
+            len
+        })
+    }
+
+    /// Gets the sample format of the buffer
+    pub fn format(&self) -> SampleFormat {
+        match self {
+            Self::I8(_) => SampleFormat::I8,
+            Self::I16(_) => SampleFormat::I16,
+            Self::I32(_) => SampleFormat::I32,
+            Self::I64(_) => SampleFormat::I64,
+            Self::U8(_) => SampleFormat::U8,
+            Self::U16(_) => SampleFormat::U16,
+            Self::U32(_) => SampleFormat::U32,
+            Self::U64(_) => SampleFormat::U64,
+            Self::F32(_) => SampleFormat::F32,
+            Self::F64(_) => SampleFormat::F64,
+        }
+    }
+
+    /// Gets the underlying slice if the buffer contains samples of type
+    /// `i8`
+    pub fn as_i8_mut(&mut self) -> Option<&mut [i8]> {
+        match self {
+            Self::I8(d) => Some(d),
+            _ => None,
+        }
+    }
+
+    /// Gets the underlying slice if the buffer contains samples of type
+    /// `i16`
+    pub fn as_i16_mut(&mut self) -> Option<&mut [i16]> {
+        match self {
+            Self::I16(d) => Some(d),
+            _ => None,
+        }
+    }
+
+    /// Gets the underlying slice if the buffer contains samples of type
+    /// `i32`
+    pub fn as_i32_mut(&mut self) -> Option<&mut [i32]> {
+        match self {
+            Self::I32(d) => Some(d),
+            _ => None,
+        }
+    }
+
+    /// Gets the underlying slice if the buffer contains samples of type
+    /// `i64`
+    pub fn as_i64_mut(&mut self) -> Option<&mut [i64]> {
+        match self {
+            Self::I64(d) => Some(d),
+            _ => None,
+        }
+    }
+
+    /// Gets the underlying slice if the buffer contains samples of type
+    /// `u8`
+    pub fn as_u8_mut(&mut self) -> Option<&mut [u8]> {
+        match self {
+            Self::U8(d) => Some(d),
+            _ => None,
+        }
+    }
+
+    /// Gets the underlying slice if the buffer contains samples of type
+    /// `u16`
+    pub fn as_u16_mut(&mut self) -> Option<&mut [u16]> {
+        match self {
+            Self::U16(d) => Some(d),
+            _ => None,
+        }
+    }
+
+    /// Gets the underlying slice if the buffer contains samples of type
+    /// `u32`
+    pub fn as_u32_mut(&mut self) -> Option<&mut [u32]> {
+        match self {
+            Self::U32(d) => Some(d),
+            _ => None,
+        }
+    }
+
+    /// Gets the underlying slice if the buffer contains samples of type
+    /// `u64`
+    pub fn as_u64_mut(&mut self) -> Option<&mut [u64]> {
+        match self {
+            Self::U64(d) => Some(d),
+            _ => None,
+        }
+    }
+
+    /// Gets the underlying slice if the buffer contains samples of type
+    /// `f32`
+    pub fn as_f32_mut(&mut self) -> Option<&mut [f32]> {
+        match self {
+            Self::F32(d) => Some(d),
+            _ => None,
+        }
+    }
+
+    /// Gets the underlying slice if the buffer contains samples of type
+    /// `f64`
+    pub fn as_f64_mut(&mut self) -> Option<&mut [f64]> {
+        match self {
+            Self::F64(d) => Some(d),
+            _ => None,
+        }
+    }
 }
 
+/// Sample type of one of the variants of [`SampleBufferMut`]
+pub(crate) trait BufferSample: Sized {
+    /// Gets the underlying slice if the buffer contains samples of this type
+    fn typed<'a>(buf: &'a mut SampleBufferMut<'_>) -> Option<&'a mut [Self]>;
+}
+
+macro_rules! impl_buffer_sample {
+    ($($t:ty => $as_mut:ident),* $(,)?) => {
+        $(impl BufferSample for $t {
+            fn typed<'a>(
+                buf: &'a mut SampleBufferMut<'_>,
+            ) -> Option<&'a mut [Self]> {
+                buf.$as_mut()
+            }
+        })*
+    };
+}
+
+impl_buffer_sample!(
+    i8 => as_i8_mut,
+    i16 => as_i16_mut,
+    i32 => as_i32_mut,
+    i64 => as_i64_mut,
+    u8 => as_u8_mut,
+    u16 => as_u16_mut,
+    u32 => as_u32_mut,
+    u64 => as_u64_mut,
+    f32 => as_f32_mut,
+    f64 => as_f64_mut,
+);
+
 /// Writes silence to the buffer
 pub fn write_silence<T: cpal::Sample>(data: &mut [T]) {
     data.fill(T::EQUILIBRIUM);
 }
+
+/// Owned buffer of samples, see [`SampleBufferMut`]
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum SampleBuffer {
+    /// Samples of type `i8`
+    I8(Vec<i8>),
+    /// Samples of type `i16`
+    I16(Vec<i16>),
+    /// Samples of type `i32`
+    I32(Vec<i32>),
+    /// Samples of type `i64`
+    I64(Vec<i64>),
+    /// Samples of type `u8`
+    U8(Vec<u8>),
+    /// Samples of type `u16`
+    U16(Vec<u16>),
+    /// Samples of type `u32`
+    U32(Vec<u32>),
+    /// Samples of type `u64`
+    U64(Vec<u64>),
+    /// Samples of type `f32`
+    F32(Vec<f32>),
+    /// Samples of type `f64`
+    F64(Vec<f64>),
+}
+
+impl SampleBuffer {
+    /// Creates buffer of silence with `len` samples of the given format.
+    /// Returns [`None`] if the format is not supported.
+    pub fn silent(format: SampleFormat, len: usize) -> Option<Self> {
+        let mut buf = match format {
+            SampleFormat::I8 => Self::I8(vec![0; len]),
+            SampleFormat::I16 => Self::I16(vec![0; len]),
+            SampleFormat::I32 => Self::I32(vec![0; len]),
+            SampleFormat::I64 => Self::I64(vec![0; len]),
+            SampleFormat::U8 => Self::U8(vec![0; len]),
+            SampleFormat::U16 => Self::U16(vec![0; len]),
+            SampleFormat::U32 => Self::U32(vec![0; len]),
+            SampleFormat::U64 => Self::U64(vec![0; len]),
+            SampleFormat::F32 => Self::F32(vec![0.; len]),
+            SampleFormat::F64 => Self::F64(vec![0.; len]),
+            _ => return None,
+        };
+        buf.as_mut().silence();
+        Some(buf)
+    }
+
+    /// Borrows the buffer as [`SampleBufferMut`]
+    pub fn as_mut(&mut self) -> SampleBufferMut<'_> {
+        match self {
+            Self::I8(d) => SampleBufferMut::I8(d),
+            Self::I16(d) => SampleBufferMut::I16(d),
+            Self::I32(d) => SampleBufferMut::I32(d),
+            Self::I64(d) => SampleBufferMut::I64(d),
+            Self::U8(d) => SampleBufferMut::U8(d),
+            Self::U16(d) => SampleBufferMut::U16(d),
+            Self::U32(d) => SampleBufferMut::U32(d),
+            Self::U64(d) => SampleBufferMut::U64(d),
+            Self::F32(d) => SampleBufferMut::F32(d),
+            Self::F64(d) => SampleBufferMut::F64(d),
+        }
+    }
+
+    /// Gets the number of samples in the buffer
+    pub fn len(&self) -> usize {
+        match self {
+            Self::I8(d) => d.len(),
+            Self::I16(d) => d.len(),
+            Self::I32(d) => d.len(),
+            Self::I64(d) => d.len(),
+            Self::U8(d) => d.len(),
+            Self::U16(d) => d.len(),
+            Self::U32(d) => d.len(),
+            Self::U64(d) => d.len(),
+            Self::F32(d) => d.len(),
+            Self::F64(d) => d.len(),
+        }
+    }
+
+    /// Checks if the buffer is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Gets the sample format of the buffer
+    pub fn format(&self) -> SampleFormat {
+        match self {
+            Self::I8(_) => SampleFormat::I8,
+            Self::I16(_) => SampleFormat::I16,
+            Self::I32(_) => SampleFormat::I32,
+            Self::I64(_) => SampleFormat::I64,
+            Self::U8(_) => SampleFormat::U8,
+            Self::U16(_) => SampleFormat::U16,
+            Self::U32(_) => SampleFormat::U32,
+            Self::U64(_) => SampleFormat::U64,
+            Self::F32(_) => SampleFormat::F32,
+            Self::F64(_) => SampleFormat::F64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cpal::{Sample, SampleFormat};
+
+    use super::{SampleBuffer, SampleBufferMut};
+
+    fn is_silent<T: Sample>(data: &[T]) -> bool {
+        data.iter().all(|s| *s == T::EQUILIBRIUM)
+    }
+
+    #[test]
+    fn silence_is_equilibrium() {
+        let formats = [
+            SampleFormat::I8,
+            SampleFormat::I16,
+            SampleFormat::I32,
+            SampleFormat::I64,
+            SampleFormat::U8,
+            SampleFormat::U16,
+            SampleFormat::U32,
+            SampleFormat::U64,
+            SampleFormat::F32,
+            SampleFormat::F64,
+        ];
+        for format in formats {
+            let mut buf = SampleBuffer::silent(format, 8).unwrap();
+            assert_eq!(buf.format(), format);
+            let mut data = buf.as_mut();
+            data.write_from_f32(&[0.5; 8]);
+            data.slice(2..).silence();
+            assert_eq!(data.len(), 8);
+            let silent = operate_samples!(data, d, {
+                !is_silent(&d[..2]) && is_silent(&d[2..])
+            });
+            assert!(silent, "{format:?}");
+        }
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn deprecated_macros_use_methods() {
+        let mut data = [1_i16; 8];
+        let mut buf = SampleBufferMut::I16(&mut data);
+        crate::silence_sbuf!(crate::slice_sbuf!(buf, 2..6));
+        assert_eq!(data, [1, 1, 0, 0, 0, 0, 1, 1]);
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    f32::consts::PI,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use cpal::FromSample;
+
+use crate::{
+    operate_samples,
+    sample_buffer::SampleBufferMut,
+    source::{DeviceConfig, Source, VolumeIterator},
+};
+
+/// Amplitude of the test tone, it is inaudible
+const AMPLITUDE: f32 = 0.0005;
+/// Frequency of the test tone
+const FREQUENCY: f32 = 18000.;
+
+/// Result of [`crate::Sink::self_test`]
+#[derive(Debug, Clone)]
+pub struct SelfTestReport {
+    /// Name of the device that was used if it is known
+    pub device: Option<String>,
+    /// The negotiated configuration of the device
+    pub config: DeviceConfig,
+    /// Duration of the test
+    pub duration: Duration,
+    /// Number of callbacks of the device during the test
+    pub callbacks: usize,
+    /// Average interval between callbacks, [`None`] if there were less than
+    /// two callbacks
+    pub mean_interval: Option<Duration>,
+    /// Standard deviation of the interval between callbacks, [`None`] if
+    /// there were less than two callbacks
+    pub jitter: Option<Duration>,
+    /// Errors delivered to the error callback during the test
+    pub errors: Vec<String>,
+}
+
+impl SelfTestReport {
+    /// Creates the report from the times of the callbacks
+    pub(crate) fn new(
+        device: Option<String>,
+        config: DeviceConfig,
+        duration: Duration,
+        calls: &[Instant],
+        errors: Vec<String>,
+    ) -> Self {
+        let intervals: Vec<_> = calls
+            .windows(2)
+            .map(|w| (w[1] - w[0]).as_secs_f64())
+            .collect();
+        let (mean_interval, jitter) = if intervals.is_empty() {
+            (None, None)
+        } else {
+            let n = intervals.len() as f64;
+            let mean = intervals.iter().sum::<f64>() / n;
+            let var =
+                intervals.iter().map(|i| (i - mean).powi(2)).sum::<f64>() / n;
+            (
+                Some(Duration::from_secs_f64(mean)),
+                Some(Duration::from_secs_f64(var.sqrt())),
+            )
+        };
+
+        Self {
+            device,
+            config,
+            duration,
+            callbacks: calls.len(),
+            mean_interval,
+            jitter,
+            errors,
+        }
+    }
+
+    /// Checks whether the device requested audio and no errors occured.
+    pub fn passed(&self) -> bool {
+        self.callbacks > 0 && self.errors.is_empty()
+    }
+}
+
+/// Inaudible tone that records the time of each read
+pub(crate) struct TestTone {
+    /// Times of the reads
+    calls: Arc<Mutex<Vec<Instant>>>,
+    /// Number of channels of the result
+    channels: usize,
+    /// How much to step on the x axis for each frame
+    iter_step: f32,
+    /// The x axis of the sine function
+    iter: f32,
+}
+
+impl TestTone {
+    /// Creates the tone and the list of the times of its reads
+    pub(crate) fn new() -> (Self, Arc<Mutex<Vec<Instant>>>) {
+        let calls = Arc::new(Mutex::new(vec![]));
+        let tone = Self {
+            calls: calls.clone(),
+            channels: 0,
+            iter_step: 0.,
+            iter: 0.,
+        };
+        (tone, calls)
+    }
+
+    /// Generates the tone
+    fn generate<T: FromSample<f32> + Clone>(&mut self, data: &mut [T]) {
+        for frame in data.chunks_mut(self.channels.max(1)) {
+            frame.fill(T::from_sample_(self.iter.sin() * AMPLITUDE));
+            self.iter += self.iter_step;
+            if self.iter > 2. * PI {
+                self.iter -= 2. * PI
+            }
+        }
+    }
+}
+
+impl Source for TestTone {
+    fn init(&mut self, info: &DeviceConfig) -> Result<()> {
+        self.channels = info.channel_count as usize;
+        // Keep the tone below the nyquist frequency.
+        let freq = FREQUENCY.min(info.sample_rate as f32 / 4.);
+        self.iter_step = 2. * PI * freq / info.sample_rate.max(1) as f32;
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut SampleBufferMut) -> (usize, Result<()>) {
+        if let Ok(mut c) = self.calls.try_lock() {
+            c.push(Instant::now());
+        }
+        operate_samples!(buffer, b, {
+            self.generate(b);
+            (b.len(), Ok(()))
+        })
+    }
+
+    fn volume(&mut self, volume: VolumeIterator) -> bool {
+        // The tone ignores the volume, so that it is the same for each test.
+        _ = volume;
+        true
+    }
+}
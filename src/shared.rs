@@ -1,51 +1,521 @@
 use std::{
-    sync::{Mutex, MutexGuard},
+    collections::VecDeque,
+    mem::{discriminant, Discriminant},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, Ordering},
+        Arc, Condvar, Mutex, MutexGuard, PoisonError, TryLockError, Weak,
+    },
     time::{Duration, Instant},
 };
 
-use crate::{callback::Callback, err::Result, source::Source, Error};
+#[cfg(feature = "async")]
+use crate::event_future::EventWait;
+use crate::{
+    callback::Callback,
+    clock::Clock,
+    err::Result,
+    events::EventQueue,
+    output_stream::ResumeRequest,
+    overlay::{Overlay, OverlayId},
+    sink::{SourceFactory, WaitStatus},
+    source::{
+        ConfigPolicy, DeviceConfig, InitContext, Source, VolumeEnvelope,
+    },
+    tap::{AnalysisTap, SampleTapFn},
+    Error, FallbackReason, SourceSpec, Timestamp,
+};
+
+/// Subscribed functions with their identifiers
+type Subscribers = Arc<[(SubscriptionId, Callback<CallbackInfo>)]>;
 
 /// Data shared between sink and the playback loop
 pub(super) struct SharedData {
     /// Used to control the playback loop from the [`Sink`]
     controls: Mutex<Controls>,
+    /// When true, playback plays, when false playback is paused
+    play: AtomicBool,
+    /// When true, the playback loop changes to the current play state
+    /// without fading
+    skip_fade: AtomicBool,
+    /// Volume of the playback as bits of f32
+    volume: AtomicU32,
+    /// Linear track gain applied on top of the volume as bits of f32
+    track_gain: AtomicU32,
     /// The source for the audio
     source: Mutex<Option<Box<dyn Source>>>,
     /// Function used as callback from the playback loop on events
     callback: Callback<CallbackInfo>,
+    /// Functions that are invoked on events after the callback. The list is
+    /// replaced when it changes, so the playback loop only clones the
+    /// [`Arc`].
+    subscribers: Mutex<Subscribers>,
+    /// Identifier of the next subscriber
+    next_subscription: AtomicU64,
+    /// Queues of the event receivers
+    event_queues: Mutex<Vec<Weak<EventQueue>>>,
+    /// Number of events that were dropped because the queues were locked
+    /// and that weren't yet counted by the queues
+    missed_events: AtomicU64,
+    /// Futures that wait for events
+    #[cfg(feature = "async")]
+    event_waits: Mutex<Vec<Arc<Mutex<EventWait>>>>,
     /// Function used as callback when errors occur on the playback loop
     err_callback: Callback<Error>,
+    /// Coalesces repeated errors
+    err_dedup: Mutex<ErrDedup>,
+    /// Progress of the current fade as bits of f32
+    fade_progress: AtomicU32,
+    /// Direction of the current fade, see [`FadeDirection`]
+    fade_direction: AtomicU8,
+    /// The latest levels of the frequency bands
+    band_levels: Mutex<Vec<f32>>,
+    /// Time used as reference for the other times
+    created: Instant,
+    /// Time of the last callback in nanoseconds since `created`
+    last_callback: AtomicU64,
+    /// Duration of the last buffer in nanoseconds
+    buffer_len: AtomicU64,
+    /// True if the stream is expected to invoke callbacks
+    stream_running: AtomicBool,
+    /// Resumes the current stream, [`None`] if no stream was created yet or
+    /// the sink was dropped
+    resume_request: Mutex<Option<ResumeRequest>>,
+    /// True if the watchdog should check its settings without waiting
+    watchdog_woken: Mutex<bool>,
+    /// Wakes the watchdog
+    watchdog_wake: Condvar,
+    /// Source of time for the time based features
+    clock: Clock,
+    /// Source loaded from the playback loop that will be applied by the
+    /// playback loop
+    deferred_load: Mutex<Option<DeferredLoad>>,
+    /// Source that was stopped and that should fade out
+    stopping: Mutex<Option<Box<dyn Source>>>,
+    /// State of the source, see [`SourceState`]
+    source_state: AtomicU8,
+    /// Number of loaded sources, it is locked when the state of the source
+    /// changes so that the waiting threads are notified
+    loads: Mutex<u64>,
+    /// Notifies the threads waiting for the end of the source
+    state_changed: Condvar,
+    /// True if the device of the stream is no longer available
+    device_lost: AtomicBool,
+    /// True if the pause fade completed and the playback loop only writes
+    /// silence until the playback resumes
+    idle: AtomicBool,
+    /// The last delay between callback and playback in nanoseconds
+    output_delay: AtomicU64,
+    /// Number of frames in the first callback of the stream, zero if there
+    /// was no callback yet
+    first_buffer_frames: AtomicU32,
+    /// Number of samples changed by the limiter
+    limited_samples: AtomicU64,
+    /// Position of the source in nanoseconds after the last read by the
+    /// playback loop, [`u64::MAX`] if unknown
+    cached_current: AtomicU64,
+    /// Length of the source in nanoseconds after the last read by the
+    /// playback loop, [`u64::MAX`] if unknown
+    cached_total: AtomicU64,
+    /// Converts the output for the analysis tap
+    analysis_tap: Mutex<Option<AnalysisTap>>,
+    /// Receives the samples sent to the device
+    sample_tap: Mutex<Option<SampleTapFn>>,
+    /// Timestamp of the stopped source that is fading out
+    outgoing_time: Mutex<Option<Timestamp>>,
+    /// Sources that will play when the current source ends
+    prefetch: Mutex<VecDeque<QueuedSource>>,
+    /// Configuration of the current stream, the queued sources are
+    /// initialized for it
+    stream_config: Mutex<Option<DeviceConfig>>,
+    /// Factory of the prefetched source
+    prefetch_factory: Mutex<PrefetchFactory>,
+    /// Sources that play over the main source
+    overlays: Mutex<Vec<Overlay>>,
+    /// Identifier of the next overlay
+    next_overlay: AtomicU64,
+    /// True if the current source is close to its end, so the prefetch
+    /// factory should be invoked
+    prefetch_due: AtomicBool,
+    /// Seek targets while scrubbing
+    scrub_target: Mutex<ScrubTarget>,
+    /// Incremented each time the current source is replaced
+    source_generation: AtomicU64,
+}
+
+/// Seek targets while scrubbing
+#[derive(Debug, Default)]
+pub(super) struct ScrubTarget {
+    /// The latest target that wasn't applied by the playback loop yet
+    pub(super) pending: Option<Duration>,
+    /// The latest target, the playback continues from it when scrubbing
+    /// ends
+    pub(super) last: Option<Duration>,
+}
+
+/// Factory of the prefetched source that is invoked on a worker thread
+#[derive(Default)]
+pub(super) struct PrefetchFactory {
+    /// The factory, [`None`] if there is no pending factory
+    pub(super) factory: Option<SourceFactory>,
+    /// True if the worker thread that invokes the factory runs
+    pub(super) worker: bool,
+    /// Incremented each time the factory is replaced or cleared, so that
+    /// the worker can discard source created by outdated factory
+    pub(super) generation: u64,
+}
+
+impl PrefetchFactory {
+    /// Replaces the factory and invalidates any source that is being
+    /// created by the previous factory.
+    pub(super) fn set(&mut self, factory: Option<SourceFactory>) {
+        self.factory = factory;
+        self.generation = self.generation.wrapping_add(1);
+    }
+}
+
+/// State of the source that can be read without locking the source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum SourceState {
+    /// No source was loaded or it was stopped
+    Empty = 0,
+    /// Source is loaded
+    Loaded = 1,
+    /// The source has ended and it was removed
+    Ended = 2,
+}
+
+/// Load that was requested from the playback loop and that will be applied
+/// before the next buffer
+pub(super) struct DeferredLoad {
+    /// The new source, it is already initialized and seeked
+    pub(super) source: Box<dyn Source>,
+    /// The configuration preferred by the source
+    pub(super) preferred: Option<DeviceConfig>,
+    /// True if the signal path changed with the new source
+    pub(super) path_changed: bool,
+    /// Whether the source should play
+    pub(super) play: bool,
+    /// Linear track gain of the source, [`None`] keeps the current gain
+    pub(super) track_gain: Option<f32>,
+}
+
+/// State of the deduplication of repeated errors
+struct ErrDedup {
+    /// Repeated errors within this window are coalesced, zero disables the
+    /// deduplication
+    window: Duration,
+    /// The last delivered error and the time when it was delivered
+    last: Option<(Discriminant<Error>, String, Instant)>,
+    /// How many times was the last error repeated since it was delivered
+    count: usize,
 }
 
 /// Used to control the playback loop from the sink
 #[derive(Clone)]
 pub(super) struct Controls {
-    /// Fade duration when play/pause
-    pub(super) fade_duration: Duration,
-    /// When true, playback plays, when false playback is paused
-    pub(super) play: bool,
-    /// Sets the volume of the playback
-    pub(super) volume: f32,
+    /// Fade duration when resuming
+    pub(super) fade_in: Duration,
+    /// Fade duration when pausing
+    pub(super) fade_out: Duration,
+    /// Duration of the transition when the volume changes while playing
+    pub(super) volume_ramp: Duration,
+    /// Custom volume envelope that will be used by the playback loop
+    pub(super) envelope: Option<Box<dyn VolumeEnvelope>>,
+    /// When true, the playback loop will report change of the signal path
+    pub(super) signal_path_changed: bool,
+    /// When true, the playback loop reports timing of each buffer
+    pub(super) timing_events: bool,
+    /// Number of consecutive reads without any samples after which the
+    /// source is considered stalled, zero means no limit
+    pub(super) stall_limit: usize,
+    /// Number of bands and interval of the band analyzer, [`None`] if the
+    /// analyzer is disabled
+    pub(super) bands: Option<(usize, Duration)>,
+    /// Threshold (linear) and minimum number of frames of detected gaps,
+    /// [`None`] if gap detection is disabled
+    pub(super) gap_detection: Option<(f32, usize)>,
+    /// When true, the playback is paused when the source ends
+    pub(super) pause_on_end: bool,
+    /// Set to true when new source is loaded
+    pub(super) new_source: bool,
+    /// Number of buffer durations without callback after which the stream is
+    /// considered stalled, [`None`] if the watchdog is disabled
+    pub(super) watchdog: Option<f32>,
+    /// When true, the output is compared with the raw frames of the source
+    pub(super) passthrough_check: bool,
+    /// Configuration requested by the source that wasn't applied yet
+    pub(super) reconfigure: Option<DeviceConfig>,
+    /// Decides whether the stream has to be created again for a source
+    pub(super) config_policy: ConfigPolicy,
+    /// When true, the configuration of the device is forced and the
+    /// sources never request different configuration
+    pub(super) forced_config: bool,
+    /// Maximum duration of audio read from the source at once, zero means
+    /// no limit
+    pub(super) max_decode_chunk: Duration,
+    /// When true, the source starts again from the start when it ends
+    pub(super) repeat: bool,
+    /// When true, the output is passed to the analysis tap
+    pub(super) analysis_tap: bool,
+    /// When true, the output is passed to the sample tap
+    pub(super) sample_tap: bool,
+    /// Remaining time of the source when the prefetch notification is
+    /// invoked, zero disables the notification
+    pub(super) prefetch_notify: Duration,
+    /// Played fraction of the source when the prefetch notification is
+    /// invoked, zero disables this criterion
+    pub(super) prefetch_notify_fraction: f32,
+    /// When true, the prefetch notification may be invoked again for the
+    /// current source
+    pub(super) prefetch_rearm: bool,
+    /// Stereo balance, -1 is full left, 1 is full right
+    pub(super) balance: f32,
+    /// Source channel for each output channel, [`None`] if the channels are
+    /// not routed
+    pub(super) channel_map: Option<Arc<[Option<u32>]>>,
+    /// Duration of the audio bursts while scrubbing, [`None`] if not
+    /// scrubbing
+    pub(super) scrub: Option<Duration>,
+    /// Length of the fade-out before seek and the fade-in after it, zero
+    /// means that the seeks are immediate
+    pub(super) seek_fade: Duration,
+    /// Position to which the playback loop seeks after fading out
+    pub(super) pending_seek: Option<Duration>,
+    /// When true, the playback loop becomes idle after the pause fade
+    /// completes and it requests pause of the stream
+    pub(super) auto_hard_pause: bool,
+    /// When true, the output is passed through the soft limiter
+    pub(super) limiter: bool,
+}
+
+/// Source in the queue of prefetched sources
+pub(super) struct QueuedSource {
+    /// The source
+    pub(super) src: Box<dyn Source>,
+    /// Configuration that the source was initialized for and the
+    /// configuration preferred by the source, [`None`] if it wasn't
+    /// initialized yet
+    pub(super) init: Option<(DeviceConfig, Option<DeviceConfig>)>,
+    /// Linear track gain of the source, [`None`] keeps the current gain
+    pub(super) gain: Option<f32>,
+}
+
+/// Direction of volume fade on play/pause
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FadeDirection {
+    /// Fading in on play
+    In,
+    /// Fading out on pause
+    Out,
 }
 
+/// How the playback moved to the prefetched source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrefetchState {
+    /// The source plays without any gap with the current configuration of
+    /// the device
+    Gapless,
+    /// The source plays without any gap, but the device doesn't satisfy its
+    /// preferred configuration, so reconfiguration was requested (see
+    /// [`CallbackInfo::ReconfigureRequested`])
+    Reconfigure,
+}
+
+/// Identifies function subscribed with [`crate::Sink::subscribe`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
 /// Callback type and asociated information
 #[non_exhaustive]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum CallbackInfo {
-    /// Invoked when the current source has reached end
+    /// Invoked when the current source has reached end and there is no
+    /// prefetched source to continue with
     SourceEnded,
     /// Invoked when no sound is playing and you can call hard_pause
     PauseEnds(Instant),
+    /// Invoked when the signal path changes, use [`crate::Sink::signal_path`]
+    /// to get the new signal path
+    SignalPathChanged,
+    /// Invoked for each buffer sent to the device if enabled with
+    /// [`crate::Sink::enable_timing_events`]. The times are relative to the
+    /// first buffer of the stream as reported by the device.
+    BufferTiming {
+        /// Time when the buffer was requested
+        callback: Duration,
+        /// Time when the buffer will be played
+        playback: Duration,
+        /// Number of frames in the buffer
+        frames: u32,
+    },
+    /// Invoked when the watchdog is enabled with
+    /// [`crate::Sink::enable_watchdog`] and the stream stopped requesting
+    /// data while playing.
+    StreamStalled,
+    /// Invoked when gap detection is enabled with
+    /// [`crate::Sink::enable_gap_detection`] and a run of near-silent frames
+    /// was played while playing a source.
+    GapDetected {
+        /// Start of the gap relative to the start of the stream
+        at: Duration,
+        /// Length of the gap
+        length: Duration,
+    },
+    /// Invoked once for each source when its remaining time drops below the
+    /// threshold set with [`crate::Sink::set_prefetch_notify`] or when the
+    /// played fraction reaches the threshold set with
+    /// [`crate::Sink::set_prefetch_notify_fraction`], whichever happens
+    /// first. This is the time to call [`crate::Sink::prefetch`].
+    PrefetchTime {
+        /// Remaining time of the source
+        remaining: Duration,
+        /// Played fraction of the source, 0 if the length of the source is
+        /// unknown
+        played: f32,
+    },
+    /// Invoked when the current source ended and the next prefetched source
+    /// started playing in its place. [`CallbackInfo::SourceEnded`] is not
+    /// invoked in this case.
+    PrefetchSwapped(PrefetchState),
+    /// Invoked when the repeat mode is enabled with
+    /// [`crate::Sink::set_repeat`] and the source started again from the
+    /// start.
+    Looped,
+    /// Invoked when the source was removed with [`crate::Sink::stop`] after
+    /// it faded out.
+    Stopped,
+    /// Invoked when the audio that fades out during a transition inside the
+    /// source finished playing (e.g. the first source of
+    /// [`crate::source::CrossFadeSource`] at the end of the overlap).
+    /// [`CallbackInfo::SourceEnded`] is invoked only when the whole source
+    /// ends.
+    OutgoingEnded,
+    /// Invoked when the overlay (see [`crate::Sink::play_overlay`]) ended
+    /// and it was removed.
+    OverlayEnded(OverlayId),
+    /// Invoked when the output device is no longer available (e.g. it was
+    /// unplugged). Call [`crate::Sink::check_device`] or
+    /// [`crate::Sink::restart_device`] (not from the callback) to continue
+    /// the playback on the default device.
+    DeviceDisconnected {
+        /// Name of the device that was disconnected
+        device_name: Option<String>,
+        /// The last known position in the source
+        position: Option<Timestamp>,
+    },
+    /// Invoked when the stream was created again on the new default device
+    /// after the device was lost. Contains the new configuration.
+    DeviceChanged(DeviceConfig),
+    /// Invoked when the output device was released with
+    /// [`crate::Sink::release_device`].
+    DeviceReleased,
+    /// Invoked when the output stream was created again after the device
+    /// was released.
+    DeviceReacquired,
+    /// Invoked when the passthrough check is enabled with
+    /// [`crate::Sink::enable_passthrough_check`] and the output differs from
+    /// the raw frames of the source even though no conversion is needed and
+    /// the volume is 1. Reported at most once for each source.
+    PassthroughMismatch {
+        /// Index of the first frame that differs since the start of the
+        /// source
+        frame: u64,
+    },
+    /// Invoked when the output stream is created and the configuration
+    /// preferred by the source is not supported by the device, so different
+    /// configuration is used.
+    ConfigFallback {
+        /// The configuration preferred by the source
+        preferred: DeviceConfig,
+        /// The configuration that is used
+        actual: DeviceConfig,
+        /// The constraint that couldn't be satisfied
+        reason: FallbackReason,
+    },
+    /// Invoked when source is prefetched and the current stream doesn't
+    /// satisfy its preferred configuration (contained). The source will
+    /// play without any gap, but reconfiguration will be requested when it
+    /// starts (see [`PrefetchState::Reconfigure`]).
+    PrefetchIncompatible(DeviceConfig),
+    /// Invoked when the current source requests different configuration of
+    /// the device. Call [`crate::Sink::reconfigure`] (not from the callback)
+    /// to create the stream with the new configuration.
+    ReconfigureRequested(DeviceConfig),
+    /// Invoked when the native properties of the playing source changed in
+    /// the middle of the stream (e.g. the number of channels changed). The
+    /// audio is converted to the device configuration, so the playback
+    /// continues, but the signal path (see [`crate::Sink::signal_path`])
+    /// changed.
+    SourceSpecChanged {
+        /// The previous properties of the source
+        old: SourceSpec,
+        /// The new properties of the source
+        new: SourceSpec,
+    },
 }
 
 impl SharedData {
     /// Creates new shared data
     pub(super) fn new() -> Self {
+        Self::with_clock(Clock::default())
+    }
+
+    /// Creates new shared data that uses the given clock
+    pub(super) fn with_clock(clock: Clock) -> Self {
         Self {
             controls: Mutex::new(Controls::new()),
+            play: AtomicBool::new(false),
+            skip_fade: AtomicBool::new(false),
+            volume: AtomicU32::new(1_f32.to_bits()),
+            track_gain: AtomicU32::new(1_f32.to_bits()),
             source: Mutex::new(None),
             callback: Callback::default(),
+            subscribers: Mutex::new(Arc::new([])),
+            next_subscription: AtomicU64::new(0),
+            event_queues: Mutex::new(vec![]),
+            missed_events: AtomicU64::new(0),
+            #[cfg(feature = "async")]
+            event_waits: Mutex::new(vec![]),
             err_callback: Callback::default(),
+            err_dedup: Mutex::new(ErrDedup {
+                window: Duration::ZERO,
+                last: None,
+                count: 0,
+            }),
+            fade_progress: AtomicU32::new(0),
+            fade_direction: AtomicU8::new(0),
+            band_levels: Mutex::new(vec![]),
+            created: clock.now(),
+            last_callback: AtomicU64::new(0),
+            buffer_len: AtomicU64::new(0),
+            stream_running: AtomicBool::new(false),
+            resume_request: Mutex::new(None),
+            watchdog_woken: Mutex::new(false),
+            watchdog_wake: Condvar::new(),
+            clock,
+            deferred_load: Mutex::new(None),
+            stopping: Mutex::new(None),
+            source_state: AtomicU8::new(SourceState::Empty as u8),
+            loads: Mutex::new(0),
+            state_changed: Condvar::new(),
+            device_lost: AtomicBool::new(false),
+            idle: AtomicBool::new(false),
+            output_delay: AtomicU64::new(0),
+            first_buffer_frames: AtomicU32::new(0),
+            limited_samples: AtomicU64::new(0),
+            cached_current: AtomicU64::new(u64::MAX),
+            cached_total: AtomicU64::new(u64::MAX),
+            analysis_tap: Mutex::new(None),
+            sample_tap: Mutex::new(None),
+            outgoing_time: Mutex::new(None),
+            prefetch: Mutex::new(VecDeque::new()),
+            stream_config: Mutex::new(None),
+            prefetch_factory: Mutex::new(PrefetchFactory::default()),
+            overlays: Mutex::new(vec![]),
+            next_overlay: AtomicU64::new(0),
+            prefetch_due: AtomicBool::new(false),
+            scrub_target: Mutex::new(ScrubTarget::default()),
+            source_generation: AtomicU64::new(0),
         }
     }
 
@@ -54,6 +524,84 @@ impl SharedData {
         Ok(self.controls.lock()?)
     }
 
+    /// Resumes the playback if `play` is true, otherwise pauses it
+    pub(super) fn set_play(&self, play: bool) {
+        self.play.store(play, Ordering::Relaxed);
+        self.skip_fade.store(false, Ordering::SeqCst);
+    }
+
+    /// Sets whether the playback plays and skips the fade to the new state.
+    pub(super) fn set_play_now(&self, play: bool) {
+        self.play.store(play, Ordering::Relaxed);
+        // Stored after play, so that the playback loop that sees the flag
+        // also sees the new play state.
+        self.skip_fade.store(true, Ordering::SeqCst);
+    }
+
+    /// Checks whether the fade should be skipped and clears the flag.
+    pub(super) fn take_skip_fade(&self) -> bool {
+        self.skip_fade.swap(false, Ordering::SeqCst)
+    }
+
+    /// Checks whether the playback plays
+    pub(super) fn play(&self) -> bool {
+        self.play.load(Ordering::Relaxed)
+    }
+
+    /// Sets the volume of the playback
+    pub(super) fn set_volume(&self, volume: f32) {
+        self.volume.store(volume.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Gets the volume of the playback
+    pub(super) fn volume(&self) -> f32 {
+        f32::from_bits(self.volume.load(Ordering::Relaxed))
+    }
+
+    /// Sets the linear track gain
+    pub(super) fn set_track_gain(&self, gain: f32) {
+        self.track_gain.store(gain.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Gets the linear track gain
+    pub(super) fn track_gain(&self) -> f32 {
+        f32::from_bits(self.track_gain.load(Ordering::Relaxed))
+    }
+
+    /// Aquires lock on controls if it isn't locked
+    pub(super) fn try_controls(&self) -> Result<MutexGuard<'_, Controls>> {
+        Ok(self.controls.try_lock()?)
+    }
+
+    /// Aquires lock on source if it isn't locked
+    pub(super) fn try_source(
+        &self,
+    ) -> Result<MutexGuard<'_, Option<Box<dyn Source>>>> {
+        Ok(self.source.try_lock()?)
+    }
+
+    /// Sets the timestamp of the source after the last read
+    pub(super) fn set_cached_time(&self, time: Option<Timestamp>) {
+        let (current, total) = time.map_or((u64::MAX, u64::MAX), |t| {
+            (t.current.as_nanos() as u64, t.total.as_nanos() as u64)
+        });
+        self.cached_current.store(current, Ordering::Relaxed);
+        self.cached_total.store(total, Ordering::Relaxed);
+    }
+
+    /// Gets the timestamp of the source after the last read
+    pub(super) fn cached_time(&self) -> Option<Timestamp> {
+        let current = self.cached_current.load(Ordering::Relaxed);
+        let total = self.cached_total.load(Ordering::Relaxed);
+        if current == u64::MAX || total == u64::MAX {
+            return None;
+        }
+        Some(Timestamp::new(
+            Duration::from_nanos(current),
+            Duration::from_nanos(total),
+        ))
+    }
+
     /// Aquires lock on source
     pub(super) fn source(
         &self,
@@ -61,16 +609,659 @@ impl SharedData {
         Ok(self.source.lock()?)
     }
 
-    /// Invokes callback function
+    /// Sets the progress of the current fade
+    pub(super) fn set_fade(&self, fade: Option<(f32, FadeDirection)>) {
+        let (progress, direction) = match fade {
+            Some((p, FadeDirection::In)) => (p, 1),
+            Some((p, FadeDirection::Out)) => (p, 2),
+            None => (0., 0),
+        };
+        self.fade_progress
+            .store(progress.to_bits(), Ordering::Relaxed);
+        self.fade_direction.store(direction, Ordering::Relaxed);
+    }
+
+    /// Gets the progress of the current fade
+    pub(super) fn fade(&self) -> Option<(f32, FadeDirection)> {
+        let direction = match self.fade_direction.load(Ordering::Relaxed) {
+            1 => FadeDirection::In,
+            2 => FadeDirection::Out,
+            _ => return None,
+        };
+        let progress =
+            f32::from_bits(self.fade_progress.load(Ordering::Relaxed));
+        Some((progress, direction))
+    }
+
+    /// Records that the stream invoked callback with buffer of the given
+    /// duration.
+    pub(super) fn mark_callback(&self, buffer_len: Duration) {
+        self.last_callback
+            .store(self.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        self.buffer_len
+            .store(buffer_len.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Records the delay between the callback and the playback of the
+    /// buffer.
+    pub(super) fn set_output_delay(&self, delay: Duration) {
+        self.output_delay
+            .store(delay.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Records the number of frames in the callback if it is the first
+    /// callback of the stream.
+    pub(super) fn set_first_buffer_frames(&self, frames: u32) {
+        _ = self.first_buffer_frames.compare_exchange(
+            0,
+            frames,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Forgets the number of frames in the first callback, so that it is
+    /// recorded again for new stream.
+    pub(super) fn reset_first_buffer_frames(&self) {
+        self.first_buffer_frames.store(0, Ordering::Relaxed);
+    }
+
+    /// Gets the number of frames in the first callback of the stream.
+    pub(super) fn first_buffer_frames(&self) -> Option<u32> {
+        match self.first_buffer_frames.load(Ordering::Relaxed) {
+            0 => None,
+            n => Some(n),
+        }
+    }
+
+    /// Adds to the number of samples changed by the limiter
+    pub(super) fn add_limited_samples(&self, cnt: u64) {
+        self.limited_samples.fetch_add(cnt, Ordering::Relaxed);
+    }
+
+    /// Gets the number of samples changed by the limiter
+    pub(super) fn limited_samples(&self) -> u64 {
+        self.limited_samples.load(Ordering::Relaxed)
+    }
+
+    /// Marks that the current source was replaced. This should be called
+    /// while the source is locked.
+    pub(super) fn next_source_generation(&self) {
+        self.source_generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Gets the number of times the current source was replaced, it
+    /// identifies the current source.
+    pub(super) fn source_generation(&self) -> u64 {
+        self.source_generation.load(Ordering::Relaxed)
+    }
+
+    /// Gets the latency of the output: the delay between the callback and
+    /// the playback and the duration of the last buffer.
+    pub(super) fn latency(&self) -> Duration {
+        Duration::from_nanos(
+            self.output_delay.load(Ordering::Relaxed)
+                + self.buffer_len.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Gets the time since the last callback and the duration of the last
+    /// buffer.
+    pub(super) fn since_callback(&self) -> (Duration, Duration) {
+        let last =
+            Duration::from_nanos(self.last_callback.load(Ordering::Relaxed));
+        let len =
+            Duration::from_nanos(self.buffer_len.load(Ordering::Relaxed));
+        (self.elapsed().saturating_sub(last), len)
+    }
+
+    /// Sets whether the stream is expected to invoke callbacks.
+    pub(super) fn set_stream_running(&self, running: bool) {
+        if running {
+            self.last_callback
+                .store(self.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        }
+        self.stream_running.store(running, Ordering::Relaxed);
+    }
+
+    /// Checks whether the stream is expected to invoke callbacks.
+    pub(super) fn stream_running(&self) -> bool {
+        self.stream_running.load(Ordering::Relaxed)
+    }
+
+    /// Sets the request that resumes the current stream.
+    pub(super) fn set_resume_request(
+        &self,
+        request: Option<ResumeRequest>,
+    ) -> Result<()> {
+        *self.resume_request.lock()? = request;
+        Ok(())
+    }
+
+    /// Requests the stream to resume if it isn't running. The stream is
+    /// resumed asynchronously by the thread that owns it. Nothing happens
+    /// if no stream was created yet.
+    ///
+    /// # Errors
+    /// - the stream was released, see [`Error::StreamReleased`]
+    /// - too many commands are waiting for the stream thread
+    pub(super) fn resume_stream(&self) -> Result<()> {
+        if self.stream_running() {
+            return Ok(());
+        }
+        match self.resume_request.lock()?.as_ref() {
+            Some(r) => r.send(),
+            None => Ok(()),
+        }
+    }
+
+    /// Wakes the watchdog, so that it checks its settings.
+    pub(super) fn wake_watchdog(&self) {
+        *self
+            .watchdog_woken
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = true;
+        self.watchdog_wake.notify_all();
+    }
+
+    /// Waits until the timeout (in real time, regardless of the clock)
+    /// elapses or the watchdog is woken.
+    pub(super) fn wait_watchdog(&self, timeout: Duration) {
+        let woken = self
+            .watchdog_woken
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let mut woken = self
+            .watchdog_wake
+            .wait_timeout_while(woken, timeout, |w| !*w)
+            .unwrap_or_else(PoisonError::into_inner)
+            .0;
+        *woken = false;
+    }
+
+    /// Sets the load that will be applied by the playback loop. This
+    /// replaces any previous deferred load, the previous load is returned.
+    pub(super) fn defer_load(
+        &self,
+        load: DeferredLoad,
+    ) -> Result<Option<DeferredLoad>> {
+        Ok(self.deferred_load.lock()?.replace(load))
+    }
+
+    /// Takes the deferred load if there is any and it doesn't block.
+    pub(super) fn take_deferred_load(&self) -> Option<DeferredLoad> {
+        self.deferred_load.try_lock().ok()?.take()
+    }
+
+    /// Sets the source that was stopped and that should fade out.
+    pub(super) fn set_stopping(
+        &self,
+        source: Option<Box<dyn Source>>,
+    ) -> Result<()> {
+        *self.stopping.lock()? = source;
+        Ok(())
+    }
+
+    /// Takes the stopped source if there is any and it doesn't block.
+    pub(super) fn take_stopping(&self) -> Option<Box<dyn Source>> {
+        self.stopping.try_lock().ok()?.take()
+    }
+
+    /// Sets the timestamp of the source that is fading out if it doesn't
+    /// block.
+    pub(super) fn set_outgoing_time(&self, time: Option<Timestamp>) {
+        if let Ok(mut t) = self.outgoing_time.try_lock() {
+            *t = time;
+        }
+    }
+
+    /// Gets the timestamp of the stopped source that is fading out.
+    pub(super) fn outgoing_time(&self) -> Result<Option<Timestamp>> {
+        // The playback loop didn't take the source yet.
+        if let Some(s) = self.stopping.lock()?.as_ref() {
+            return Ok(s.get_time());
+        }
+        Ok(*self.outgoing_time.lock()?)
+    }
+
+    /// Aquires lock on the queue of prefetched sources
+    pub(super) fn prefetch(
+        &self,
+    ) -> Result<MutexGuard<'_, VecDeque<QueuedSource>>> {
+        Ok(self.prefetch.lock()?)
+    }
+
+    /// Takes the next prefetched source if there is any and it doesn't
+    /// block.
+    pub(super) fn take_prefetch(&self) -> Option<QueuedSource> {
+        self.prefetch.try_lock().ok()?.pop_front()
+    }
+
+    /// Sets the configuration of the current stream
+    pub(super) fn set_stream_config(&self, config: DeviceConfig) {
+        if let Ok(mut c) = self.stream_config.lock() {
+            *c = Some(config);
+        }
+    }
+
+    /// Initializes the source for the configuration of the current stream
+    /// before it is queued, so that the playback loop doesn't have to
+    /// initialize it when the current source ends. Invokes
+    /// [`CallbackInfo::PrefetchIncompatible`] if the stream doesn't satisfy
+    /// the configuration preferred by the source. If there is no stream
+    /// yet, the source is initialized by the playback loop.
+    pub(super) fn init_queued(
+        &self,
+        mut src: Box<dyn Source>,
+    ) -> Result<QueuedSource> {
+        let Some(config) = self.stream_config.lock()?.clone() else {
+            return Ok(QueuedSource {
+                src,
+                init: None,
+                gain: None,
+            });
+        };
+
+        let preferred = src.preferred_config();
+        let ctx = InitContext::new(config.clone(), preferred.as_ref(), None);
+        src.init_with(&ctx)?;
+
+        let unsatisfied =
+            self.controls()?.unsatisfied(&config, preferred.clone());
+        if let Some(p) = unsatisfied {
+            self.invoke_callback(CallbackInfo::PrefetchIncompatible(p))?;
+        }
+
+        Ok(QueuedSource {
+            src,
+            init: Some((config, preferred)),
+            gain: None,
+        })
+    }
+
+    /// Aquires lock on the factory of the prefetched source
+    pub(super) fn prefetch_factory(
+        &self,
+    ) -> Result<MutexGuard<'_, PrefetchFactory>> {
+        Ok(self.prefetch_factory.lock()?)
+    }
+
+    /// Aquires lock on the overlays
+    pub(super) fn overlays(&self) -> Result<MutexGuard<'_, Vec<Overlay>>> {
+        Ok(self.overlays.lock()?)
+    }
+
+    /// Aquires lock on the overlays if it isn't locked
+    pub(super) fn try_overlays(&self) -> Result<MutexGuard<'_, Vec<Overlay>>> {
+        Ok(self.overlays.try_lock()?)
+    }
+
+    /// Adds overlay with the source and returns its identifier
+    pub(super) fn add_overlay(
+        &self,
+        source: Box<dyn Source>,
+    ) -> Result<OverlayId> {
+        let id = OverlayId(self.next_overlay.fetch_add(1, Ordering::Relaxed));
+        self.overlays()?.push(Overlay::new(id, source));
+        Ok(id)
+    }
+
+    /// Sets whether the prefetch factory should be invoked
+    pub(super) fn set_prefetch_due(&self, due: bool) {
+        self.prefetch_due.store(due, Ordering::Relaxed);
+    }
+
+    /// Checks whether the prefetch factory should be invoked
+    pub(super) fn prefetch_due(&self) -> bool {
+        self.prefetch_due.load(Ordering::Relaxed)
+    }
+
+    /// Aquires lock on the seek targets while scrubbing
+    pub(super) fn scrub_target(&self) -> Result<MutexGuard<'_, ScrubTarget>> {
+        Ok(self.scrub_target.lock()?)
+    }
+
+    /// Takes the latest seek target while scrubbing that wasn't applied yet
+    /// if there is any and it doesn't block.
+    pub(super) fn take_scrub_target(&self) -> Option<Duration> {
+        self.scrub_target.try_lock().ok()?.pending.take()
+    }
+
+    /// Marks the device as no longer available. Returns true if it was
+    /// already marked.
+    pub(super) fn set_device_lost(&self) -> bool {
+        self.device_lost.swap(true, Ordering::Relaxed)
+    }
+
+    /// Checks whether the device is no longer available.
+    pub(super) fn device_lost(&self) -> bool {
+        self.device_lost.load(Ordering::Relaxed)
+    }
+
+    /// Checks whether the device is no longer available and clears the mark.
+    pub(super) fn take_device_lost(&self) -> bool {
+        self.device_lost.swap(false, Ordering::Relaxed)
+    }
+
+    /// Sets whether the playback loop only writes silence, see
+    /// [`Controls::auto_hard_pause`].
+    pub(super) fn set_idle(&self, idle: bool) {
+        self.idle.store(idle, Ordering::Relaxed);
+    }
+
+    /// Checks whether the playback loop only writes silence.
+    pub(super) fn idle(&self) -> bool {
+        self.idle.load(Ordering::Relaxed)
+    }
+
+    /// Creates error callback for sources that delivers the errors through
+    /// the shared data, so that they are deduplicated.
+    pub(super) fn source_err_callback(self: &Arc<Self>) -> Callback<Error> {
+        let shared = Arc::downgrade(self);
+        Callback::new(Some(Box::new(move |e| {
+            if let Some(s) = shared.upgrade() {
+                _ = s.invoke_err_callback(e);
+            }
+        })))
+    }
+
+    /// Sets the state of the source and wakes the threads waiting for the
+    /// end of the source.
+    pub(super) fn set_source_state(&self, state: SourceState) {
+        self.source_state.store(state as u8, Ordering::Relaxed);
+        self.set_cached_time(None);
+        if let Ok(mut loads) = self.loads.lock() {
+            if state == SourceState::Loaded {
+                *loads += 1;
+            }
+        }
+        self.state_changed.notify_all();
+    }
+
+    /// Blocks until the current source ends, new source is loaded or the
+    /// deadline passes.
+    pub(super) fn wait_for_end(
+        &self,
+        deadline: Option<Instant>,
+    ) -> Result<WaitStatus> {
+        let mut loads = self.loads.lock()?;
+        let start = *loads;
+        loop {
+            if *loads != start {
+                return Ok(WaitStatus::Replaced);
+            }
+            if self.source_state() != SourceState::Loaded {
+                return Ok(WaitStatus::Ended);
+            }
+
+            loads = match deadline {
+                None => self.state_changed.wait(loads)?,
+                Some(d) => {
+                    let now = Instant::now();
+                    if now >= d {
+                        return Ok(WaitStatus::TimedOut);
+                    }
+                    self.state_changed.wait_timeout(loads, d - now)?.0
+                }
+            };
+        }
+    }
+
+    /// Gets the state of the source
+    pub(super) fn source_state(&self) -> SourceState {
+        match self.source_state.load(Ordering::Relaxed) {
+            1 => SourceState::Loaded,
+            2 => SourceState::Ended,
+            _ => SourceState::Empty,
+        }
+    }
+
+    /// Gets the source of time
+    pub(super) fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    /// Gets the time since the shared data was created
+    fn elapsed(&self) -> Duration {
+        self.clock.now().saturating_duration_since(self.created)
+    }
+
+    /// Aquires lock on the latest band levels
+    pub(super) fn band_levels(&self) -> Result<MutexGuard<'_, Vec<f32>>> {
+        Ok(self.band_levels.lock()?)
+    }
+
+    /// Aquires lock on the analysis tap
+    pub(super) fn analysis_tap(
+        &self,
+    ) -> Result<MutexGuard<'_, Option<AnalysisTap>>> {
+        Ok(self.analysis_tap.lock()?)
+    }
+
+    /// Aquires lock on the sample tap
+    pub(super) fn sample_tap(
+        &self,
+    ) -> Result<MutexGuard<'_, Option<SampleTapFn>>> {
+        Ok(self.sample_tap.lock()?)
+    }
+
+    /// Aquires lock on the sample tap if it doesn't block.
+    pub(super) fn try_sample_tap(
+        &self,
+    ) -> Option<MutexGuard<'_, Option<SampleTapFn>>> {
+        self.sample_tap.try_lock().ok()
+    }
+
+    /// Aquires lock on the analysis tap if it doesn't block.
+    pub(super) fn try_analysis_tap(
+        &self,
+    ) -> Option<MutexGuard<'_, Option<AnalysisTap>>> {
+        self.analysis_tap.try_lock().ok()
+    }
+
+    /// Sets the latest band levels if it doesn't block.
+    pub(super) fn try_set_band_levels(&self, levels: &[f32]) {
+        if let Ok(mut l) = self.band_levels.try_lock() {
+            l.clear();
+            l.extend_from_slice(levels);
+        }
+    }
+
+    /// Pushes the event to the event receivers and invokes callback
+    /// function and then all the subscribers. The subscribers are not
+    /// locked while they run.
     pub(super) fn invoke_callback(&self, args: CallbackInfo) -> Result<()> {
-        self.callback.invoke(args)
+        #[cfg(feature = "async")]
+        self.resolve_event_waits(&args);
+        let pushed = self.push_event(&args);
+        let subscribers = self.subscribers.lock()?.clone();
+        if subscribers.is_empty() {
+            return pushed.and(self.callback.invoke(args));
+        }
+
+        let mut res = pushed.and(self.callback.invoke(args.clone()));
+        for (_, s) in subscribers.iter() {
+            let r = s.invoke(args.clone());
+            res = res.and(r);
+        }
+        res
+    }
+
+    /// Pushes the event to the queues of the event receivers and removes
+    /// the queues of dropped receivers. If the queues are locked, the event
+    /// is dropped so that the playback loop doesn't wait.
+    fn push_event(&self, event: &CallbackInfo) -> Result<()> {
+        let mut queues = match self.event_queues.try_lock() {
+            Ok(q) => q,
+            Err(TryLockError::WouldBlock) => {
+                self.missed_events.fetch_add(1, Ordering::Relaxed);
+                return Ok(());
+            }
+            Err(TryLockError::Poisoned(e)) => return Err(e.into()),
+        };
+        if queues.is_empty() {
+            return Ok(());
+        }
+        let missed = self.missed_events.swap(0, Ordering::Relaxed);
+        let mut res = Ok(());
+        queues.retain(|q| match q.upgrade() {
+            Some(q) => {
+                q.add_dropped(missed);
+                if let Err(e) = q.push(event.clone()) {
+                    res = Err(e);
+                }
+                true
+            }
+            None => false,
+        });
+        res
+    }
+
+    /// Resolves the futures that wait for the event and removes them.
+    #[cfg(feature = "async")]
+    fn resolve_event_waits(&self, event: &CallbackInfo) {
+        let Ok(mut waits) = self.event_waits.lock() else {
+            return;
+        };
+        waits.retain(|w| match w.lock() {
+            Ok(mut w) => !w.resolve(event),
+            Err(_) => false,
+        });
+    }
+
+    /// Adds future that waits for event
+    #[cfg(feature = "async")]
+    pub(super) fn add_event_wait(&self, wait: Arc<Mutex<EventWait>>) {
+        if let Ok(mut waits) = self.event_waits.lock() {
+            waits.push(wait);
+        }
+    }
+
+    /// Removes future that waits for event
+    #[cfg(feature = "async")]
+    pub(super) fn remove_event_wait(&self, wait: &Arc<Mutex<EventWait>>) {
+        if let Ok(mut waits) = self.event_waits.lock() {
+            waits.retain(|w| !Arc::ptr_eq(w, wait));
+        }
+    }
+
+    /// Adds queue of event receiver
+    pub(super) fn add_event_queue(
+        &self,
+        queue: &Arc<EventQueue>,
+    ) -> Result<()> {
+        let mut queues = self.event_queues.lock()?;
+        // Events missed before the queue was added are counted only by the
+        // existing queues.
+        let missed = self.missed_events.swap(0, Ordering::Relaxed);
+        for q in queues.iter().filter_map(Weak::upgrade) {
+            q.add_dropped(missed);
+        }
+        queues.push(Arc::downgrade(queue));
+        Ok(())
+    }
+
+    /// Adds function that is invoked on events and returns its identifier
+    pub(super) fn subscribe(
+        &self,
+        f: Box<dyn FnMut(CallbackInfo) + Send>,
+    ) -> Result<SubscriptionId> {
+        let id = SubscriptionId(
+            self.next_subscription.fetch_add(1, Ordering::Relaxed),
+        );
+        let sub = (id, Callback::new(Some(f)));
+        self.update_subscribers(|s| {
+            s.iter().cloned().chain([sub.clone()]).collect()
+        })?;
+        Ok(id)
+    }
+
+    /// Removes the subscribed function. Returns false if there is no such
+    /// function.
+    pub(super) fn unsubscribe(&self, id: SubscriptionId) -> Result<bool> {
+        let (old, new) = self.update_subscribers(|s| {
+            s.iter().filter(|(i, _)| *i != id).cloned().collect()
+        })?;
+        Ok(old != new)
+    }
+
+    /// Replaces the subscribers with the list created by `f` from the
+    /// current list. The new list is created without holding the lock, so
+    /// that the playback loop doesn't wait for the allocation. Returns the
+    /// old and new number of subscribers.
+    fn update_subscribers(
+        &self,
+        f: impl Fn(&Subscribers) -> Subscribers,
+    ) -> Result<(usize, usize)> {
+        loop {
+            let old = self.subscribers.lock()?.clone();
+            let new = f(&old);
+            let mut cur = self.subscribers.lock()?;
+            // Retry if another thread changed the list in the meantime.
+            if Arc::ptr_eq(&cur, &old) {
+                let lens = (old.len(), new.len());
+                *cur = new;
+                return Ok(lens);
+            }
+        }
     }
 
-    /// Invokes error callback function
+    /// Invokes error callback function. Repeated errors are coalesced if
+    /// the deduplication window is set.
     pub(super) fn invoke_err_callback(&self, args: Error) -> Result<()> {
+        let mut dedup = self.err_dedup.lock()?;
+        if dedup.window.is_zero() {
+            drop(dedup);
+            return self.err_callback.invoke(args);
+        }
+
+        let now = self.clock.now();
+        let kind = discriminant(&args);
+        let msg = args.to_string();
+        if let Some((k, m, t)) = &dedup.last {
+            if *k == kind && *m == msg && now - *t < dedup.window {
+                dedup.count += 1;
+                return Ok(());
+            }
+        }
+
+        let repeated = dedup.take_repeated();
+        dedup.last = Some((kind, msg, now));
+        drop(dedup);
+
+        if let Some(r) = repeated {
+            self.err_callback.invoke(r)?;
+        }
         self.err_callback.invoke(args)
     }
 
+    /// Reports the coalesced errors if the deduplication window has passed.
+    pub(super) fn flush_err_callback(&self) -> Result<()> {
+        let mut dedup = self.err_dedup.lock()?;
+        let Some((_, _, t)) = &dedup.last else {
+            return Ok(());
+        };
+        if self.clock.now().saturating_duration_since(*t) < dedup.window {
+            return Ok(());
+        }
+
+        let repeated = dedup.take_repeated();
+        dedup.last = None;
+        drop(dedup);
+
+        if let Some(r) = repeated {
+            self.err_callback.invoke(r)?;
+        }
+        Ok(())
+    }
+
+    /// Sets the window in which repeated errors are coalesced. Zero disables
+    /// the deduplication.
+    pub(super) fn set_err_dedup_window(&self, window: Duration) -> Result<()> {
+        self.err_dedup.lock()?.window = window;
+        Ok(())
+    }
+
     /// Gets the callback function
     pub(super) fn callback(&self) -> &Callback<CallbackInfo> {
         &self.callback
@@ -88,13 +1279,68 @@ impl Default for SharedData {
     }
 }
 
+impl ErrDedup {
+    /// Creates the error that reports the repetitions of the last error and
+    /// resets the count.
+    fn take_repeated(&mut self) -> Option<Error> {
+        let count = std::mem::take(&mut self.count);
+        match &self.last {
+            Some((_, msg, _)) if count != 0 => Some(Error::Repeated {
+                msg: msg.clone(),
+                count,
+            }),
+            _ => None,
+        }
+    }
+}
+
 impl Controls {
+    /// Gets the preferred configuration if `config` doesn't satisfy it with
+    /// the current policy.
+    pub(super) fn unsatisfied(
+        &self,
+        config: &DeviceConfig,
+        preferred: Option<DeviceConfig>,
+    ) -> Option<DeviceConfig> {
+        if self.forced_config {
+            return None;
+        }
+        preferred.filter(|p| !config.satisfies(p, self.config_policy))
+    }
+
     /// Creates new controls
     pub(super) fn new() -> Self {
         Self {
-            fade_duration: Duration::ZERO,
-            play: false,
-            volume: 1.,
+            fade_in: Duration::ZERO,
+            fade_out: Duration::ZERO,
+            volume_ramp: Duration::from_millis(10),
+            envelope: None,
+            signal_path_changed: false,
+            timing_events: false,
+            stall_limit: 64,
+            bands: None,
+            gap_detection: None,
+            pause_on_end: false,
+            new_source: false,
+            watchdog: None,
+            passthrough_check: false,
+            reconfigure: None,
+            config_policy: ConfigPolicy::default(),
+            forced_config: false,
+            max_decode_chunk: Duration::ZERO,
+            repeat: false,
+            analysis_tap: false,
+            sample_tap: false,
+            prefetch_notify: Duration::from_secs(5),
+            prefetch_notify_fraction: 0.,
+            prefetch_rearm: false,
+            balance: 0.,
+            channel_map: None,
+            scrub: None,
+            seek_fade: Duration::ZERO,
+            pending_seek: None,
+            auto_hard_pause: false,
+            limiter: false,
         }
     }
 }
@@ -104,3 +1350,36 @@ impl Default for Controls {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::events::EventReceiver;
+
+    #[test]
+    fn locked_event_queues_drop_event() {
+        let shared = Arc::new(SharedData::new());
+        let queue = Arc::new(EventQueue::new(4));
+        shared.add_event_queue(&queue).unwrap();
+        let events = EventReceiver::new(queue);
+
+        let queues = shared.event_queues.lock().unwrap();
+        let other = shared.clone();
+        thread::spawn(move || {
+            other.invoke_callback(CallbackInfo::StreamStalled)
+        })
+        .join()
+        .unwrap()
+        .unwrap();
+        drop(queues);
+        assert_eq!(events.dropped(), 0);
+
+        // The dropped event is counted with the next event.
+        shared.invoke_callback(CallbackInfo::SourceEnded).unwrap();
+        assert_eq!(events.dropped(), 1);
+        let received = events.drain().unwrap();
+        assert!(matches!(received[..], [CallbackInfo::SourceEnded]));
+    }
+}
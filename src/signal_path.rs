@@ -0,0 +1,146 @@
+use cpal::{StreamConfig, SupportedBufferSize};
+
+use crate::source::DeviceConfig;
+
+/// Native properties of the audio produced by a source
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceSpec {
+    /// Number of channels of the source
+    pub channel_count: u32,
+    /// Sample rate of the source
+    pub sample_rate: u32,
+    /// Bit depth of the source if it is known
+    pub bits_per_sample: Option<u32>,
+    /// Name of the codec if it is known
+    pub codec: Option<&'static str>,
+}
+
+/// Describes the path of the signal from the source to the device
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignalPath {
+    /// Native properties of the source, [`None`] if the source doesn't
+    /// report them
+    pub source: Option<SourceSpec>,
+    /// Configuration of the output device
+    pub device: DeviceConfig,
+}
+
+impl SignalPath {
+    /// Creates new signal path
+    pub fn new(source: Option<SourceSpec>, device: DeviceConfig) -> Self {
+        Self { source, device }
+    }
+
+    /// Gets the ratio of the resampling (source rate / device rate).
+    /// Returns [`None`] if there is no resampling or it is not known.
+    pub fn resample_ratio(&self) -> Option<f64> {
+        self.source
+            .as_ref()
+            .filter(|s| s.sample_rate != self.device.sample_rate)
+            .map(|s| s.sample_rate as f64 / self.device.sample_rate as f64)
+    }
+
+    /// Checks whether the number of channels is converted. Returns false if
+    /// it is not known.
+    pub fn channels_changed(&self) -> bool {
+        self.source
+            .as_ref()
+            .map(|s| s.channel_count != self.device.channel_count)
+            .unwrap_or_default()
+    }
+
+    /// Checks whether the bit depth of the source is reduced. Returns false
+    /// if it is not known.
+    pub fn bit_depth_reduced(&self) -> bool {
+        self.source
+            .as_ref()
+            .and_then(|s| s.bits_per_sample)
+            .map(|b| b > self.device.sample_format.sample_size() as u32 * 8)
+            .unwrap_or_default()
+    }
+
+    /// Checks whether the signal is played without any conversions of rate,
+    /// channels or bit depth. Returns false if it is not known.
+    pub fn is_unconverted(&self) -> bool {
+        self.source.is_some()
+            && self.resample_ratio().is_none()
+            && !self.channels_changed()
+            && !self.bit_depth_reduced()
+    }
+}
+
+/// Constraint of the preferred configuration that couldn't be satisfied by
+/// the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FallbackReason {
+    /// The device doesn't support the sample rate
+    SampleRate,
+    /// The device doesn't support the number of channels with the sample
+    /// rate
+    Channels,
+    /// The device doesn't support the sample format with the sample rate
+    /// and number of channels
+    SampleFormat,
+}
+
+/// Result of selecting the configuration of the output device
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigDecision {
+    /// The configuration preferred by the source, [`None`] if the source
+    /// had no preference
+    pub preferred: Option<DeviceConfig>,
+    /// The configuration that is used
+    pub actual: DeviceConfig,
+    /// Why the preferred configuration wasn't used, [`None`] if it is used
+    /// or there was no preference
+    pub fallback: Option<FallbackReason>,
+}
+
+impl ConfigDecision {
+    /// Creates the decision and determines the reason for fallback
+    pub fn new(preferred: Option<DeviceConfig>, actual: DeviceConfig) -> Self {
+        let fallback = preferred.as_ref().and_then(|p| {
+            if p.sample_rate != actual.sample_rate {
+                Some(FallbackReason::SampleRate)
+            } else if p.channel_count != actual.channel_count {
+                Some(FallbackReason::Channels)
+            } else if p.sample_format != actual.sample_format {
+                Some(FallbackReason::SampleFormat)
+            } else {
+                None
+            }
+        });
+        Self {
+            preferred,
+            actual,
+            fallback,
+        }
+    }
+}
+
+/// Where the configuration of the stream came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigOrigin {
+    /// The configuration was forced with [`crate::Sink::set_forced_config`]
+    Forced,
+    /// The device supports configuration matching the configuration
+    /// preferred by the source
+    Preferred,
+    /// The default configuration of the device is used
+    Default,
+}
+
+/// The configuration negotiated with the device for the current stream, see
+/// [`crate::Sink::get_stream_config`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamConfigInfo {
+    /// The configuration passed to cpal, including the requested buffer
+    /// size
+    pub config: StreamConfig,
+    /// Range of buffer sizes supported by the device with the configuration
+    pub buffer_size_limits: SupportedBufferSize,
+    /// Name of the device, [`None`] if it couldn't be determined
+    pub device_name: Option<String>,
+    /// Where the configuration came from
+    pub origin: ConfigOrigin,
+}
@@ -1,39 +1,181 @@
 use std::{
-    sync::Arc,
-    time::{Duration, Instant},
+    mem,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, PoisonError, Weak,
+    },
+    thread::JoinHandle,
+    time::Duration,
 };
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 use cpal::{
-    traits::{DeviceTrait, HostTrait, StreamTrait},
-    Device, Devices, OutputCallbackInfo, SampleFormat, SampleRate, Stream,
-    SupportedOutputConfigs, SupportedStreamConfig,
+    traits::{DeviceTrait, HostTrait},
+    Device, Devices, Host, HostId, SampleFormat, SampleRate, StreamError,
+    SupportedBufferSize, SupportedOutputConfigs, SupportedStreamConfig,
 };
 
+#[cfg(feature = "async")]
+use crate::event_future::EventFuture;
+#[cfg(feature = "test-util")]
+use crate::{clock::Clock, test_util::ManualClock};
 use crate::{
+    converters::gain::{db_to_linear, db_to_volume, linear_to_db},
     err::{Error, Result},
-    mixer::Mixer,
+    events::EventQueue,
+    mixer::{check_channel_map, get_play_time, is_mixer_thread, Mixer},
+    output_stream::OutputStream,
+    overlay::overlay_context,
     sample_buffer::SampleBufferMut,
-    shared::{CallbackInfo, SharedData},
-    source::{DeviceConfig, Source},
-    BufferSize, Timestamp,
+    self_test::{SelfTestReport, TestTone},
+    shared::{
+        CallbackInfo, DeferredLoad, FadeDirection, ScrubTarget, SharedData,
+        SourceState, SubscriptionId,
+    },
+    source::{
+        ConfigPolicy, DeviceConfig, InitContext, Source, Symph, VolumeEnvelope,
+    },
+    tap::{AnalysisTap, AnalysisTapConfig, SampleTap, SampleTapFn},
+    thread, BufferSize, ConfigDecision, ConfigOrigin, DeviceInfo,
+    EventReceiver, OverlayId, SignalPath, SinkBuilder, SinkHandle,
+    StreamConfigInfo, ThreadInfo, Timestamp,
 };
 
+/// Maximum number of events in the queue of [`EventReceiver`]
+const EVENT_QUEUE_LEN: usize = 256;
+
+/// Options for [`Sink::load_with_options`]
+#[derive(Debug, Clone, Default)]
+pub struct LoadOptions {
+    /// Position where the playback starts. The source is seeked before it is
+    /// played, so no audio from the start is played.
+    pub start_at: Option<Duration>,
+    /// When true, the queued sources (see [`Sink::queue`]) are removed.
+    /// Otherwise they play after the loaded source.
+    pub clear_queue: bool,
+    /// Track gain of the source in decibels, see [`Sink::set_track_gain`].
+    /// [`None`] keeps the current gain.
+    pub track_gain: Option<f32>,
+}
+
+/// Result of [`Sink::wait_for_end`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitStatus {
+    /// The source ended or it was removed, or there was no source
+    Ended,
+    /// New source was loaded while waiting
+    Replaced,
+    /// The timeout passed before the source ended
+    TimedOut,
+}
+
+/// Result of loading a source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadStatus {
+    /// The source was loaded
+    Loaded,
+    /// The load was called from a callback invoked by the playback loop, so
+    /// the source will be loaded by the playback loop before the next
+    /// buffer.
+    Deferred,
+}
+
+/// Function that creates source, see [`Sink::prefetch_with`]
+pub type SourceFactory = Box<dyn FnOnce() -> Result<Box<dyn Source>> + Send>;
+
+/// Snapshot of the state of the playback created with [`Sink::snapshot`].
+/// The snapshot doesn't contain the source itself, only its position.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PlaybackSnapshot {
+    /// Volume of the playback
+    pub volume: f32,
+    /// Fade-in time when resuming
+    pub fade_in: Duration,
+    /// Fade-out time when pausing
+    pub fade_out: Duration,
+    /// True if the playback was playing
+    pub play: bool,
+    /// Name of the output device
+    pub device: Option<String>,
+    /// The preferred buffer size
+    pub buffer_size: BufferSize,
+    /// Position in the source, [`None`] if there was no source or it
+    /// doesn't support getting the timestamp
+    pub position: Option<Timestamp>,
+}
+
+/// How the stream uses the output device, see [`Sink::set_stream_options`].
+///
+/// cpal doesn't expose exclusive access to the device on any platform, so
+/// only the shared mode is available.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum StreamMode {
+    /// The device is shared with other applications and the system mixer
+    /// may convert the audio.
+    #[default]
+    Shared,
+}
+
 /// A player that can play `Source`
 pub struct Sink {
     /// Data shared with the playback loop ([`Mixer`])
     shared: Arc<SharedData>,
     // The stream is never read, it just stays alive so that the audio plays
-    /// The stream, if you drop this the playbakc loop will stop
-    stream: Option<Stream>,
+    /// The stream, if you drop this the playbakc loop will stop. It is
+    /// behind mutex so that the released stream can be created again by
+    /// [`Sink::play`].
+    stream: Mutex<Option<OutputStream>>,
     /// Info about the current device configuration
     info: DeviceConfig,
+    /// Audio host selected by the user, [`None`] = the default host
+    host: Option<HostId>,
     /// Prefered device set by the user
     device: Option<Device>,
+    /// Device of the current stream. The released stream is created again
+    /// on this device.
+    stream_device: Option<Device>,
     /// Sink will try to get the buffer size to be this
     preferred_buffer_size: BufferSize,
+    /// Configuration that is always used for the stream
+    forced_config: Option<DeviceConfig>,
+    /// Configuration negotiated with the device for the current stream
+    stream_config: Option<StreamConfigInfo>,
+    /// The last reported signal path
+    signal_path: Option<SignalPath>,
+    /// Thread of the watchdog
+    watchdog: Option<JoinHandle<()>>,
+    /// True if the device was released with [`Sink::release_device`] and
+    /// the stream should be created again when playing
+    released: AtomicBool,
+    /// How was the configuration of the current stream selected
+    config_decision: Option<ConfigDecision>,
+    /// True if the device was set by the user
+    explicit_device: bool,
+    /// When true, the stream is created again on the default device when
+    /// the device is no longer available
+    auto_restart: bool,
+    /// Timeout passed to cpal when creating the stream
+    stream_timeout: Option<Duration>,
 }
 
 impl Sink {
+    /// Gets handle that controls the playback from other threads. The
+    /// handle is cheap to clone and it can be sent to other threads.
+    pub fn handle(&self) -> SinkHandle {
+        SinkHandle::new(self.shared.clone())
+    }
+
+    /// Creates builder that creates configured sink in one go. Use
+    /// [`Sink::default`] to create sink with the default settings.
+    pub fn builder() -> SinkBuilder {
+        SinkBuilder::new()
+    }
+
     /// Creates the output stream and starts the playback loop.
     /// `config` is preffered device configuration, [`None`] = choose
     /// default.
@@ -41,27 +183,48 @@ impl Sink {
         &mut self,
         config: Option<DeviceConfig>,
     ) -> Result<()> {
-        let mut device =
-            self.device.take().map(Ok).unwrap_or_else(|| -> Result<_> {
-                cpal::default_host()
-                    .default_output_device()
-                    .ok_or(Error::NoOutDevice)
-            })?;
+        self.negotiate(config)?;
+        let stream = self.start_stream()?;
+        let old = self.stream.get_mut()?.replace(stream);
+        drop(old);
+        self.mark_reacquired();
+        Ok(())
+    }
+
+    /// Selects the device and the configuration of the stream without
+    /// opening the stream. `config` is preffered device configuration,
+    /// [`None`] = choose default.
+    fn negotiate(&mut self, config: Option<DeviceConfig>) -> Result<()> {
+        let mut device = match self.device.take() {
+            Some(d) => d,
+            None => self.default_device()?,
+        };
 
         let sup = if let Ok(c) = device.supported_output_configs() {
             c
         } else {
-            device = cpal::default_host()
-                .default_output_device()
-                .ok_or(Error::NoOutDevice)?;
+            device = self.default_device()?;
             device.supported_output_configs()?
         };
 
-        let supported_config = match config {
-            Some(c) => select_config(c, sup)
-                .unwrap_or(device.default_output_config()?),
-            None => device.default_output_config()?,
-        };
+        let (supported_config, origin) =
+            match (&self.forced_config, config.clone()) {
+                (Some(f), _) => (
+                    find_config(f, sup)
+                        .ok_or_else(|| Error::UnsupportedConfig(f.clone()))?,
+                    ConfigOrigin::Forced,
+                ),
+                (None, Some(c)) => match select_config(c, sup) {
+                    Some(c) => (c, ConfigOrigin::Preferred),
+                    None => (
+                        device.default_output_config()?,
+                        ConfigOrigin::Default,
+                    ),
+                },
+                (None, None) => {
+                    (device.default_output_config()?, ConfigOrigin::Default)
+                }
+            };
 
         self.info = DeviceConfig {
             channel_count: supported_config.channels() as u32,
@@ -69,62 +232,128 @@ impl Sink {
             sample_format: supported_config.sample_format(),
         };
 
-        let shared = self.shared.clone();
-        let mut mixer = Mixer::new(shared.clone(), self.info.clone());
+        let decision = ConfigDecision::new(config, self.info.clone());
+        if let (Some(reason), Some(preferred)) =
+            (decision.fallback, &decision.preferred)
+        {
+            let fallback = CallbackInfo::ConfigFallback {
+                preferred: preferred.clone(),
+                actual: decision.actual.clone(),
+                reason,
+            };
+            if let Err(e) = self.shared.invoke_callback(fallback) {
+                _ = self.shared.invoke_err_callback(e);
+            }
+        }
+        self.config_decision = Some(decision);
+
+        // The map may not be valid for the new device.
+        let mut controls = self.shared.controls()?;
+        if let Some(map) = controls.channel_map.clone() {
+            if let Err(e) = check_channel_map(&map, self.info.channel_count) {
+                controls.channel_map = None;
+                drop(controls);
+                _ = self.shared.invoke_err_callback(e);
+            }
+        }
 
         let mut config = supported_config.config();
         config.buffer_size = self
             .preferred_buffer_size
             .to_cpal(supported_config.buffer_size(), config.sample_rate.0);
+        self.stream_config = Some(StreamConfigInfo {
+            config,
+            buffer_size_limits: *supported_config.buffer_size(),
+            device_name: device.name().ok(),
+            origin,
+        });
+        self.stream_device = Some(device.clone());
+        self.device = Some(device);
+        Ok(())
+    }
+
+    /// Opens the stream with the device and the configuration selected by
+    /// [`Sink::negotiate`] and starts the playback loop.
+    fn start_stream(&self) -> Result<OutputStream> {
+        let (Some(device), Some(stream_config)) =
+            (&self.stream_device, &self.stream_config)
+        else {
+            return Err(Error::NoOutDevice);
+        };
+
+        let shared = self.shared.clone();
+        let weak_shared = Arc::downgrade(&shared);
+        let stream_device = device.clone();
+        let mut mixer = Mixer::new(shared.clone(), self.info.clone());
+        let clock = self.shared.clock().clone();
+        let stream_timeout = self.stream_timeout;
+        let device_name = stream_config.device_name.clone();
+        let config = stream_config.config.clone();
+        self.shared.reset_first_buffer_frames();
 
         macro_rules! arm {
             ($t:ident, $e:ident) => {
-                device.build_output_stream(
-                    &config,
-                    move |d: &mut [$t], info| {
-                        mixer.mix(
-                            &mut SampleBufferMut::$e(d),
-                            get_play_time(info),
-                        )
-                    },
-                    move |e| {
-                        _ = shared.invoke_err_callback(e.into());
-                    },
-                    //Some(Duration::from_millis(5)),
-                    None,
-                )
+                stream_device
+                    .build_output_stream(
+                        &config,
+                        move |d: &mut [$t], info| {
+                            mixer.mix(
+                                &mut SampleBufferMut::$e(d),
+                                get_play_time(info, clock.now()),
+                                Some(info.timestamp()),
+                            )
+                        },
+                        move |e| {
+                            if matches!(e, StreamError::DeviceNotAvailable)
+                                && !shared.set_device_lost()
+                            {
+                                let lost = CallbackInfo::DeviceDisconnected {
+                                    device_name: device_name.clone(),
+                                    position: shared.cached_time(),
+                                };
+                                if let Err(e) = shared.invoke_callback(lost) {
+                                    _ = shared.invoke_err_callback(e);
+                                }
+                            }
+                            _ = shared.invoke_err_callback(e.into());
+                        },
+                        stream_timeout,
+                    )
+                    .map_err(Error::from)
             };
         }
 
-        let stream = match self.info.sample_format {
-            SampleFormat::I8 => arm!(i8, I8),
-            SampleFormat::I16 => arm!(i16, I16),
-            SampleFormat::I32 => arm!(i32, I32),
-            SampleFormat::I64 => arm!(i64, I64),
-            SampleFormat::U8 => arm!(u8, U8),
-            SampleFormat::U16 => arm!(u16, U16),
-            SampleFormat::U32 => arm!(u32, U32),
-            SampleFormat::U64 => arm!(u64, U64),
-            SampleFormat::F32 => arm!(f32, F32),
-            SampleFormat::F64 => arm!(f64, F64),
-            _ => {
+        // The stream is owned by its own thread, so that the playback loop
+        // can pause it (see `Sink::set_auto_hard_pause`).
+        let format = self.info.sample_format;
+        OutputStream::new(weak_shared, move |pause| {
+            mixer.set_pause_request(pause);
+            match format {
+                SampleFormat::I8 => arm!(i8, I8),
+                SampleFormat::I16 => arm!(i16, I16),
+                SampleFormat::I32 => arm!(i32, I32),
+                SampleFormat::I64 => arm!(i64, I64),
+                SampleFormat::U8 => arm!(u8, U8),
+                SampleFormat::U16 => arm!(u16, U16),
+                SampleFormat::U32 => arm!(u32, U32),
+                SampleFormat::U64 => arm!(u64, U64),
+                SampleFormat::F32 => arm!(f32, F32),
+                SampleFormat::F64 => arm!(f64, F64),
                 // TODO: select other format when this is not supported
-                return Err(Error::UnsupportedSampleFormat);
+                _ => Err(Error::UnsupportedSampleFormat),
             }
-        }?;
-
-        self.device = Some(device);
-
-        self.stream = Some(stream);
-
-        Ok(())
+        })
     }
 
     /// Sets the callback method.
     ///
     /// The function is called when the source ends.
     ///
-    /// The function is called from another thread.
+    /// The function is called from another thread. It may use the sink
+    /// (e.g. to load or prefetch the next source). If the playback loop
+    /// invokes the callback while the function runs on another thread, the
+    /// playback loop doesn't wait, and the function is called with the event
+    /// after it returns on that thread.
     ///
     /// # Errors
     /// - another user of one of the used mutexes panicked while using it
@@ -144,6 +373,102 @@ impl Sink {
         )
     }
 
+    /// Adds function that is invoked on the same events as the callback set
+    /// with [`Sink::on_callback`]. Unlike the callback, any number of
+    /// functions may be subscribed, so independent components can listen to
+    /// the events. The subscribed functions are invoked in the order in
+    /// which they were subscribed, after the callback.
+    ///
+    /// The functions are called from another thread. Returns identifier
+    /// that can be used to remove the function with [`Sink::unsubscribe`].
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn subscribe(
+        &self,
+        mut f: impl FnMut(&CallbackInfo) + Send + 'static,
+    ) -> Result<SubscriptionId> {
+        self.shared.subscribe(Box::new(move |c| f(&c)))
+    }
+
+    /// Creates receiver of the events. The receiver gets the same events as
+    /// the callback set with [`Sink::on_callback`], but they are queued, so
+    /// they can be handled by the thread that owns the receiver at any time
+    /// instead of in the callback.
+    ///
+    /// The queue holds at most 256 events. If it is full, the oldest event
+    /// is dropped, so the playback loop never waits for the receiver. Any
+    /// number of receivers may be created and each gets all the events.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn event_receiver(&self) -> Result<EventReceiver> {
+        let queue = Arc::new(EventQueue::new(EVENT_QUEUE_LEN));
+        self.shared.add_event_queue(&queue)?;
+        Ok(EventReceiver::new(queue))
+    }
+
+    /// Creates future that resolves when the current source plays to its
+    /// end or is stopped. Resolves immediately with
+    /// [`CallbackInfo::SourceEnded`] if no source is playing.
+    ///
+    /// The future doesn't depend on any async runtime.
+    #[cfg(feature = "async")]
+    pub fn played_to_end(&self) -> EventFuture {
+        let fut = EventFuture::new(self.shared.clone(), |e| {
+            matches!(e, CallbackInfo::SourceEnded | CallbackInfo::Stopped)
+        });
+        if self.shared.source_state() != SourceState::Loaded {
+            fut.resolve(CallbackInfo::SourceEnded);
+        }
+        fut
+    }
+
+    /// Creates future that resolves with the next
+    /// [`CallbackInfo::PrefetchTime`] event. This is the time to prefetch
+    /// the next source. See [`Sink::set_prefetch_notify`].
+    ///
+    /// The future doesn't depend on any async runtime.
+    #[cfg(feature = "async")]
+    pub fn prefetch_time(&self) -> EventFuture {
+        EventFuture::new(self.shared.clone(), |e| {
+            matches!(e, CallbackInfo::PrefetchTime { .. })
+        })
+    }
+
+    /// Creates future that resolves with the next
+    /// [`CallbackInfo::PauseEnds`] event, when no sound is playing and
+    /// [`Sink::hard_pause`] can be called.
+    ///
+    /// The future doesn't depend on any async runtime.
+    #[cfg(feature = "async")]
+    pub fn pause_ended(&self) -> EventFuture {
+        EventFuture::new(self.shared.clone(), |e| {
+            matches!(e, CallbackInfo::PauseEnds(_))
+        })
+    }
+
+    /// Removes function added with [`Sink::subscribe`]. Returns false if
+    /// the function was already removed.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn unsubscribe(&self, id: SubscriptionId) -> Result<bool> {
+        self.shared.unsubscribe(id)
+    }
+
     /// Sets the error callback method.
     ///
     /// The funciton is called when an error occures on another thread.
@@ -166,9 +491,87 @@ impl Sink {
         )
     }
 
+    /// Sets the window in which identical consecutive errors are coalesced.
+    ///
+    /// The first error is reported immediately, the repetitions within the
+    /// window are reported as single [`Error::Repeated`] once different error
+    /// occurs or the window passes. [`Duration::ZERO`] disables this.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn set_err_dedup_window(&self, window: Duration) -> Result<()> {
+        self.shared.set_err_dedup_window(window)
+    }
+
+    /// Sets the number of consecutive reads without any samples (or
+    /// consecutive reads that returned error) after which the source is
+    /// ended with [`Error::SourceStalled`]. Zero means no limit. The default
+    /// is 64.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn set_stall_limit(&self, limit: usize) -> Result<()> {
+        self.shared.controls()?.stall_limit = limit;
+        Ok(())
+    }
+
+    /// Opens the audio file at the given path and loads it with
+    /// [`Sink::load`]. The file is decoded with [`Symph`] with the default
+    /// options.
+    ///
+    /// # Errors
+    /// - failed to open the file
+    /// - failed to create [`Symph`] from the file
+    /// - the same as [`Sink::load`]
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn play_file(
+        &mut self,
+        path: impl AsRef<Path>,
+        play: bool,
+    ) -> Result<LoadStatus> {
+        self.load(Symph::open(path, &Default::default())?, play)
+    }
+
+    /// Opens the audio file at the given path and prefetches it with
+    /// [`Sink::prefetch`]. The file is decoded with [`Symph`] with the
+    /// default options.
+    ///
+    /// # Errors
+    /// - failed to open the file
+    /// - failed to create [`Symph`] from the file
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn prefetch_file(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.prefetch(Symph::open(path, &Default::default())?)
+    }
+
     /// Discards the old source and sets the new source. Starts playing if
     /// `play` is set to true.
     ///
+    /// If this is called from a callback invoked by the playback loop (e.g.
+    /// to play next source on [`CallbackInfo::SourceEnded`]), the source is
+    /// prepared on the calling thread and the playback loop swaps it in
+    /// before the next buffer. [`LoadStatus::Deferred`] is returned in that
+    /// case. The stream is not created again, if the source prefers
+    /// different configuration, [`CallbackInfo::ReconfigureRequested`] is
+    /// invoked. If the stream is paused with [`Sink::hard_pause`] or
+    /// released before the next buffer, the source is swapped in by that
+    /// call.
+    ///
     /// # Errors
     /// - another user of one of the used mutexes panicked while using it
     /// - source fails topreferred_config
@@ -177,51 +580,144 @@ impl Sink {
     /// - the current thread already locked one of the used mutexes and didn't
     ///   release them
     pub fn load(
+        &mut self,
+        src: impl Source + 'static,
+        play: bool,
+    ) -> Result<LoadStatus> {
+        self.load_with_options(src, play, &LoadOptions::default())
+    }
+
+    /// Discards the old source and sets the new source with the given
+    /// options. Starts playing if `play` is set to true.
+    ///
+    /// The load is deferred when called from the playback loop, see
+    /// [`Sink::load`].
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    /// - source fails to init
+    /// - `start_at` is set and the source fails to seek
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn load_with_options(
         &mut self,
         mut src: impl Source + 'static,
         play: bool,
-    ) -> Result<()> {
-        src.set_err_callback(self.shared.err_callback());
+        options: &LoadOptions,
+    ) -> Result<LoadStatus> {
+        src.set_err_callback(&self.shared.source_err_callback());
+        if options.clear_queue {
+            self.clear_queue()?;
+        }
+
+        if is_mixer_thread() {
+            return self.defer_load(Box::new(src), play, options);
+        }
 
+        let lost = self.take_lost_device();
         let config = src.preferred_config();
+        let policy = self.shared.controls()?.config_policy;
         if self.device.is_none()
-            || config.as_ref().map(|c| *c != self.info).unwrap_or_default()
+            || self.stream.get_mut()?.is_none()
+            || (self.forced_config.is_none()
+                && config
+                    .as_ref()
+                    .is_some_and(|c| !self.info.satisfies(c, policy)))
         {
-            self.build_out_stream(config)?;
+            self.build_out_stream(config.clone())?;
+        }
+
+        src.init_with(&self.init_context(config.as_ref()))?;
+        if let Some(t) = options.start_at {
+            src.seek(t)?;
         }
 
         let mut controls = self.shared.controls()?;
         let mut source = self.shared.source()?;
 
-        src.init(&self.info)?;
+        let path = SignalPath::new(src.source_spec(), self.info.clone());
+        if self.signal_path.as_ref() != Some(&path) {
+            self.signal_path = Some(path);
+            controls.signal_path_changed = true;
+        }
 
-        controls.play = play;
+        self.shared.set_play(play);
+        if let Some(g) = options.track_gain {
+            self.shared.set_track_gain(db_to_linear(g));
+        }
+        controls.new_source = true;
+        controls.reconfigure = None;
         *source = Some(Box::new(src));
+        self.shared.next_source_generation();
+        self.shared.set_idle(false);
+        self.shared.set_source_state(SourceState::Loaded);
+        self.shared.take_deferred_load();
+        self.shared.set_stopping(None)?;
 
-        if let Some(s) = &self.stream {
-            if play {
-                s.play()?;
-            }
+        drop(source);
+        drop(controls);
+
+        if play {
+            self.play_stream()?;
         }
 
-        Ok(())
+        if lost {
+            self.shared.invoke_callback(CallbackInfo::DeviceChanged(
+                self.info.clone(),
+            ))?;
+        }
+
+        Ok(LoadStatus::Loaded)
     }
 
     /// Resumes the playback of the current source if `play` is true, otherwise
     /// pauses the playback.
     ///
+    /// Pausing doesn't resume the stream if it was paused with
+    /// [`Sink::hard_pause`], so there will be no fade-out in that case.
+    /// Resuming creates the stream again if the device was released with
+    /// [`Sink::release_device`].
+    ///
     /// # Errors
     /// - another user of one of the used mutexes panicked while using it
+    /// - failed to create the stream again
+    /// - the device was disconnected, see [`Sink::check_device`] and
+    ///   [`Sink::restart_device`]
     ///
     /// # Panics
     /// - the current thread already locked one of the used mutexes and didn't
     ///   release them
     pub fn play(&self, play: bool) -> Result<()> {
-        self.shared.controls()?.play = play;
-        if let Some(s) = &self.stream {
-            s.play()?;
+        if !play {
+            return self.pause();
         }
-        Ok(())
+        self.start_playing(false)
+    }
+
+    /// Resumes the playback immediately without the fade-in set with
+    /// [`Sink::set_fade_in`]. If the playback is fading out, it jumps to
+    /// the full volume.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    /// - failed to create the stream again
+    /// - the device was disconnected, see [`Sink::check_device`] and
+    ///   [`Sink::restart_device`]
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn play_now(&self) -> Result<()> {
+        self.start_playing(true)
+    }
+
+    /// Pauses the playback immediately without the fade-out set with
+    /// [`Sink::set_fade_out`]. The output is silent from the next buffer
+    /// and [`CallbackInfo::PauseEnds`] is invoked as with [`Sink::pause`].
+    pub fn pause_now(&self) -> Result<()> {
+        self.handle().pause_now()
     }
 
     /// Pauses the loop that is feeding new samples. This can be used to reduce
@@ -229,25 +725,66 @@ impl Sink {
     ///
     /// It doesn't ignores fade play/pause.
     pub fn hard_pause(&self) -> Result<()> {
-        if let Some(s) = &self.stream {
+        self.apply_deferred_load()?;
+        if let Some(s) = self.stream.lock()?.as_ref() {
             s.pause()?;
         }
+        self.shared.set_stream_running(false);
         Ok(())
     }
 
-    /// Pauses the playback of the current source
+    /// Creates the stream again with the configuration requested by the
+    /// current source (see [`Source::wants_reconfigure`]). The position in
+    /// the source is kept. Returns false if there is no pending request.
+    ///
+    /// The request is reported with [`CallbackInfo::ReconfigureRequested`],
+    /// but this method must be called from outside of the callback. When
+    /// called from the callback, this does nothing and returns false.
     ///
     /// # Errors
     /// - another user of one of the used mutexes panicked while using it
+    /// - failed to create the stream
+    /// - the source failed to init with the new stream
     ///
     /// # Panics
     /// - the current thread already locked one of the used mutexes and didn't
     ///   release them
-    pub fn pause(&self) -> Result<()> {
-        self.play(false)
+    pub fn reconfigure(&mut self) -> Result<bool> {
+        if is_mixer_thread() {
+            return Ok(false);
+        }
+
+        let Some(config) = self.shared.controls()?.reconfigure.take() else {
+            return Ok(false);
+        };
+        if self.forced_config.is_some() {
+            return Ok(false);
+        }
+
+        if *self.released.get_mut() {
+            // The new stream will be created on play.
+            self.renegotiate_released(Some(config))?;
+            return Ok(true);
+        }
+
+        self.rebuild_stream(Some(config))?;
+        if self.shared.play() {
+            self.play_stream()?;
+        }
+        Ok(true)
     }
 
-    /// Resumes the playback of the current source
+    /// Stops the playback and removes the current source, so that its
+    /// resources (e.g. open files) are released. The queued sources and the
+    /// pending prefetch factory are removed too. The source fades out
+    /// before it is dropped and [`CallbackInfo::Stopped`] is invoked after
+    /// that. If the stream is not running, the source is dropped
+    /// immediately.
+    ///
+    /// After this, [`Sink::get_timestamp`] returns
+    /// [`Error::NoSourceIsPlaying`]. New source can be loaded with
+    /// [`Sink::load`]. Use [`Sink::stop_and_release`] to also release the
+    /// device.
     ///
     /// # Errors
     /// - another user of one of the used mutexes panicked while using it
@@ -255,14 +792,14 @@ impl Sink {
     /// # Panics
     /// - the current thread already locked one of the used mutexes and didn't
     ///   release them
-    pub fn resume(&self) -> Result<()> {
-        self.play(true)
+    pub fn stop(&self) -> Result<()> {
+        self.handle().stop()
     }
 
-    /// Sets the volume of the playback, 0 = mute, 1 = full volume.
-    ///
-    /// The value is not clipped so the caller should make sure that the volume
-    /// is in the bounds or the audio may have clipping.
+    /// Stops the playback like [`Sink::stop`] and drops the output stream
+    /// like [`Sink::release_device`]. The source is dropped immediately
+    /// without fade-out, because there is no stream to play the fade.
+    /// [`CallbackInfo::Stopped`] is invoked if there was source.
     ///
     /// # Errors
     /// - another user of one of the used mutexes panicked while using it
@@ -270,14 +807,24 @@ impl Sink {
     /// # Panics
     /// - the current thread already locked one of the used mutexes and didn't
     ///   release them
-    pub fn volume(&self, volume: f32) -> Result<()> {
-        self.shared.controls()?.volume = volume;
+    pub fn stop_and_release(&mut self) -> Result<()> {
+        let src = self.unload()?;
+        self.release_device()?;
+        if src.is_some() {
+            drop(src);
+            self.shared.invoke_callback(CallbackInfo::Stopped)?;
+        }
         Ok(())
     }
 
-    /// Gets the volume of the playback, 0 = mute, 1 = full volume.
+    /// Pauses the playback and takes the current source out of the sink
+    /// without dropping it. The source is removed immediately without any
+    /// fade and no callback is invoked. The queued sources and the pending
+    /// prefetch factory are removed. Returns [`None`] if there is no
+    /// source.
     ///
-    /// The value may not be in the range.
+    /// The source keeps its position, so it continues from where it stopped
+    /// when it is loaded again (e.g. into another sink) with [`Sink::load`].
     ///
     /// # Errors
     /// - another user of one of the used mutexes panicked while using it
@@ -285,77 +832,1103 @@ impl Sink {
     /// # Panics
     /// - the current thread already locked one of the used mutexes and didn't
     ///   release them
-    pub fn get_volume(&self) -> Result<f32> {
-        Ok(self.shared.controls()?.volume)
+    pub fn unload(&self) -> Result<Option<Box<dyn Source>>> {
+        self.handle().unload()
     }
 
-    /// Returns true if the source is playing, otherwise returns false
+    /// Plays the source over the current source (e.g. notification sound
+    /// over music) without creating another stream. The overlay plays even
+    /// if the main source is paused and it is removed when it ends, after
+    /// which [`CallbackInfo::OverlayEnded`] is invoked.
+    ///
+    /// Overlays are not affected by the volume, fades and seeking of the
+    /// main source and they don't affect its timestamp, prefetch or
+    /// [`CallbackInfo::SourceEnded`]. The overlay is initialized with the
+    /// configuration of the device, but with `f32` samples, and it is summed
+    /// with the output through `f32`.
     ///
     /// # Errors
     /// - another user of one of the used mutexes panicked while using it
+    /// - failed to create the stream
+    /// - the source failed to init
     ///
     /// # Panics
     /// - the current thread already locked one of the used mutexes and didn't
     ///   release them
-    pub fn is_playing(&self) -> Result<bool> {
-        Ok(self.shared.controls()?.play)
+    pub fn play_overlay(
+        &mut self,
+        mut src: impl Source + 'static,
+    ) -> Result<OverlayId> {
+        src.set_err_callback(&self.shared.source_err_callback());
+        let in_mixer = is_mixer_thread();
+        if !in_mixer {
+            self.reacquire_device()?;
+            if self.device.is_none() || self.stream.get_mut()?.is_none() {
+                self.build_out_stream(None)?;
+            }
+        }
+
+        src.init_with(&overlay_context(&self.info))?;
+        let id = self.shared.add_overlay(Box::new(src))?;
+        self.shared.set_idle(false);
+        if !in_mixer {
+            self.play_stream()?;
+        }
+        Ok(id)
     }
 
-    /// Seeks to the given position
+    /// Removes the overlay before it ends. Returns false if there is no
+    /// overlay with the identifier (e.g. because it has already ended).
+    /// [`CallbackInfo::OverlayEnded`] is not invoked.
     ///
     /// # Errors
-    /// - no source is playing
-    /// - the source doesn't support this
-    /// - failed to seek
-    pub fn seek_to(&mut self, timestamp: Duration) -> Result<Timestamp> {
-        Ok(self
-            .shared
-            .source()?
-            .as_mut()
-            .ok_or(Error::NoSourceIsPlaying)?
-            .seek(timestamp)?)
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn stop_overlay(&self, id: OverlayId) -> Result<bool> {
+        let mut overlays = self.shared.overlays()?;
+        let len = overlays.len();
+        overlays.retain(|o| o.id != id);
+        Ok(overlays.len() != len)
     }
 
-    /// Seeks by the given amount. If `forward` is true, seeks forward,
-    /// otherwise seeks backward
-    pub fn seek_by(
-        &mut self,
-        time: Duration,
-        forward: bool,
-    ) -> Result<Timestamp> {
-        Ok(self
-            .shared
-            .source()?
-            .as_mut()
-            .ok_or(Error::NoSourceIsPlaying)?
-            .seek_by(time, forward)?)
+    /// Sets the volume of the overlay. Returns false if there is no overlay
+    /// with the identifier.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn set_overlay_volume(
+        &self,
+        id: OverlayId,
+        volume: f32,
+    ) -> Result<bool> {
+        let mut overlays = self.shared.overlays()?;
+        let Some(o) = overlays.iter_mut().find(|o| o.id == id) else {
+            return Ok(false);
+        };
+        o.volume = volume;
+        Ok(true)
     }
 
-    /// Gets the current timestamp and the total length of the currently
-    /// playing source.
+    /// Sets the source that will play after the current source ends, without
+    /// any gap between them. [`CallbackInfo::PrefetchSwapped`] is invoked
+    /// instead of [`CallbackInfo::SourceEnded`] when this happens. The queued
+    /// sources (see [`Sink::queue`]) and pending factory set with
+    /// [`Sink::prefetch_with`] are discarded.
+    ///
+    /// The source is initialized immediately with the current configuration
+    /// of the device, so that the playback loop only switches to it when
+    /// the current source ends. If the device doesn't satisfy the preferred
+    /// configuration of the source, [`CallbackInfo::PrefetchIncompatible`]
+    /// is invoked, the source still plays and reconfiguration is requested
+    /// when it starts. If the configuration of the device changes before
+    /// that, the source is initialized again by the playback loop.
     ///
     /// # Errors
-    /// - no source is playing
-    /// - the source doesn't support this
-    pub fn get_timestamp(&self) -> Result<Timestamp> {
-        self.shared
-            .source()?
-            .as_ref()
-            .ok_or(Error::NoSourceIsPlaying)?
-            .get_time()
-            .ok_or(Error::Unsupported {
-                component: "Source",
-                feature: "getting current timestamp",
-            })
+    /// - another user of one of the used mutexes panicked while using it
+    /// - source fails to init
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn prefetch(&self, src: impl Source + 'static) -> Result<()> {
+        self.handle().prefetch(src)
     }
 
-    /// Sets the fade-in/fade-out time for play/pause
-    pub fn set_fade_len(&mut self, fade: Duration) -> Result<()> {
-        self.shared.controls()?.fade_duration = fade;
-        Ok(())
+    /// Adds source to the end of the queue of sources that will play after
+    /// the current source ends, see [`Sink::prefetch`]. Pending factory set
+    /// with [`Sink::prefetch_with`] is discarded. Returns the number of
+    /// queued sources.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    /// - source fails to init
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn queue(&self, src: Box<dyn Source>) -> Result<usize> {
+        self.handle().queue(src)
     }
 
-    /// Sets the preferred buffer size. None means, use default size.
+    /// Removes all the queued sources and the pending factory set with
+    /// [`Sink::prefetch_with`].
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn clear_queue(&self) -> Result<()> {
+        self.handle().clear_queue()
+    }
+
+    /// Gets the number of queued sources.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn queued_len(&self) -> Result<usize> {
+        Ok(self.shared.prefetch()?.len())
+    }
+
+    /// Checks whether there is prefetched source (see [`Sink::prefetch`]),
+    /// that is whether the queue is not empty.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn has_prefetched(&self) -> Result<bool> {
+        Ok(!self.shared.prefetch()?.is_empty())
+    }
+
+    /// Runs `f` with the prefetched source (the first queued source) without
+    /// removing it, e.g. to get its timestamp. `f` gets [`None`] if there
+    /// is no prefetched source. Use [`Sink::clear_queue`] to cancel it.
+    ///
+    /// The queue is locked while `f` runs. The playback loop doesn't wait
+    /// for it, but if the current source ends meanwhile, it ends without
+    /// continuing with the prefetched source. `f` must not call methods of
+    /// the sink that use the queue (e.g. [`Sink::prefetch`],
+    /// [`Sink::queue`] or this method), it would deadlock.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn with_prefetched<R>(
+        &self,
+        f: impl FnOnce(Option<&mut dyn Source>) -> R,
+    ) -> Result<R> {
+        let mut queue = self.shared.prefetch()?;
+        Ok(f(queue
+            .front_mut()
+            .map(|s| s.src.as_mut() as &mut dyn Source)))
+    }
+
+    /// Sets function that creates the source that will play after the
+    /// current source ends, see [`Sink::prefetch`]. The function is invoked
+    /// on separate thread once the prefetch notification is triggered (see
+    /// [`Sink::set_prefetch_notify`]), or immediately if it was already
+    /// triggered for the current source. Until then, no resources are used
+    /// for the next source.
+    ///
+    /// The queued sources are discarded. The created source is initialized
+    /// on the same thread. If the function or the initialization fails, the
+    /// error is passed to the error callback and no source is prefetched. Source set
+    /// with [`Sink::prefetch`] or [`Sink::queue`] replaces the pending
+    /// function.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    /// - failed to spawn the thread
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn prefetch_with(&self, factory: SourceFactory) -> Result<()> {
+        let err_callback = self.shared.source_err_callback();
+        let factory: SourceFactory = Box::new(move || {
+            let mut src = factory()?;
+            src.set_err_callback(&err_callback);
+            Ok(src)
+        });
+
+        self.shared.prefetch()?.clear();
+        let mut pending = self.shared.prefetch_factory()?;
+        pending.set(Some(factory));
+        if pending.worker {
+            return Ok(());
+        }
+
+        let shared = Arc::downgrade(&self.shared);
+        thread::spawn("prefetch", false, move || prefetcher(shared))
+            .map_err(|e| Error::Other(e.into()))?;
+        pending.worker = true;
+        Ok(())
+    }
+
+    /// Sets the remaining time of the source at which
+    /// [`CallbackInfo::PrefetchTime`] is invoked and the function set with
+    /// [`Sink::prefetch_with`] is invoked. Zero disables the notification.
+    /// The default is 5 seconds. The notification may be invoked again for
+    /// the current source with the new value.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn set_prefetch_notify(&self, remaining: Duration) -> Result<()> {
+        let mut controls = self.shared.controls()?;
+        controls.prefetch_notify = remaining;
+        controls.prefetch_rearm = true;
+        Ok(())
+    }
+
+    /// Sets the played fraction of the source (e.g. 0.95) at which
+    /// [`CallbackInfo::PrefetchTime`] is invoked. The notification is
+    /// invoked when either this or the remaining time set with
+    /// [`Sink::set_prefetch_notify`] is reached. Zero (the default)
+    /// disables this criterion. The notification may be invoked again for
+    /// the current source with the new value.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn set_prefetch_notify_fraction(&self, played: f32) -> Result<()> {
+        let mut controls = self.shared.controls()?;
+        controls.prefetch_notify_fraction = played.clamp(0., 1.);
+        controls.prefetch_rearm = true;
+        Ok(())
+    }
+
+    /// Drops the output stream so that the device is no longer used. The
+    /// source, its position and all the settings are kept. The stream is
+    /// created again with the next [`Sink::play`], [`Sink::resume`] or
+    /// [`Sink::load`].
+    ///
+    /// This is useful to save power when the playback is paused for a long
+    /// time, because [`Sink::hard_pause`] may keep the device active.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn release_device(&mut self) -> Result<()> {
+        self.apply_deferred_load()?;
+        let Some(stream) = self.stream.get_mut()?.take() else {
+            return Ok(());
+        };
+        drop(stream);
+
+        self.shared.set_stream_running(false);
+        *self.released.get_mut() = true;
+        self.shared.invoke_callback(CallbackInfo::DeviceReleased)
+    }
+
+    /// Pauses the playback of the current source
+    pub fn pause(&self) -> Result<()> {
+        self.handle().pause()
+    }
+
+    /// Resumes the playback of the current source
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    /// - failed to create the stream again
+    /// - the device was disconnected, see [`Sink::check_device`] and
+    ///   [`Sink::restart_device`]
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn resume(&self) -> Result<()> {
+        self.play(true)
+    }
+
+    /// Sets the volume of the playback, 0 = mute, 1 = full volume.
+    ///
+    /// The value is not clipped so the caller should make sure that the volume
+    /// is in the bounds or the audio may have clipping.
+    pub fn volume(&self, volume: f32) -> Result<()> {
+        self.handle().volume(volume)
+    }
+
+    /// Sets the volume of the playback in decibels, 0 dB = full volume.
+    /// Values at or below [`crate::converters::gain::SILENCE_DB`] (including
+    /// negative infinity) mute the playback.
+    ///
+    /// This is the same as [`Sink::volume`] with the volume converted to
+    /// linear multiplier.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn volume_db(&self, db: f32) -> Result<()> {
+        self.volume(db_to_volume(db))
+    }
+
+    /// Gets the volume of the playback in decibels. Muted playback has
+    /// volume of negative infinity.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn get_volume_db(&self) -> Result<f32> {
+        Ok(linear_to_db(self.get_volume()?))
+    }
+
+    /// Sets the gain of the current track in decibels (e.g. from ReplayGain
+    /// or EBU R128). The gain is applied on top of the volume (see
+    /// [`Sink::volume`]), so it doesn't change the volume set by the user,
+    /// and fades go to the volume multiplied by the gain.
+    ///
+    /// The gain is kept when other source starts playing. Use
+    /// [`LoadOptions::track_gain`] and [`Sink::set_prefetch_gain`] to change
+    /// it together with the source.
+    pub fn set_track_gain(&self, db: f32) -> Result<()> {
+        self.shared.set_track_gain(db_to_linear(db));
+        Ok(())
+    }
+
+    /// Gets the gain of the current track in decibels, see
+    /// [`Sink::set_track_gain`].
+    pub fn get_track_gain(&self) -> Result<f32> {
+        Ok(linear_to_db(self.shared.track_gain()))
+    }
+
+    /// Sets the track gain in decibels of the prefetched source (the first
+    /// queued source, see [`Sink::prefetch`]). The gain applies from the
+    /// first sample of the source when it starts playing, so the gapless
+    /// playback is normalized. Returns false if there is no prefetched
+    /// source.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn set_prefetch_gain(&self, db: f32) -> Result<bool> {
+        let mut queue = self.shared.prefetch()?;
+        let Some(next) = queue.front_mut() else {
+            return Ok(false);
+        };
+        next.gain = Some(db_to_linear(db));
+        Ok(true)
+    }
+
+    /// Sets the stereo balance of the playback. -1 is full left, 0 is center
+    /// and 1 is full right. The balance only attenuates the channel on the
+    /// other side (see [`crate::converters::gain::balance_gains`]) and it is
+    /// applied only to the first two channels of the output. Changes of the balance are
+    /// ramped over few milliseconds.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn set_balance(&self, balance: f32) -> Result<()> {
+        let balance = if balance.is_nan() {
+            0.
+        } else {
+            balance.clamp(-1., 1.)
+        };
+        self.shared.controls()?.balance = balance;
+        Ok(())
+    }
+
+    /// Gets the stereo balance of the playback, see [`Sink::set_balance`].
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn get_balance(&self) -> Result<f32> {
+        Ok(self.shared.controls()?.balance)
+    }
+
+    /// Sets the routing of the channels. Each item of `map` corresponds to
+    /// output channel of the device and it is the index of the channel
+    /// produced by the source that is played on it, or [`None`] for silence.
+    /// By default, the channels are not routed.
+    ///
+    /// The map must have item for each channel of the device (at most 32).
+    /// If the device changes and the map is no longer valid, it is removed
+    /// and the error is reported to the error callback.
+    ///
+    /// # Errors
+    /// - the map is not valid for the current device
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn set_channel_map(
+        &self,
+        map: Option<Vec<Option<u32>>>,
+    ) -> Result<()> {
+        if let Some(map) = &map {
+            check_channel_map(map, self.info.channel_count)?;
+        }
+        self.shared.controls()?.channel_map = map.map(Into::into);
+        Ok(())
+    }
+
+    /// Sets custom volume envelope of the playback.
+    ///
+    /// The envelope is used instead of the volume set with [`Sink::volume`]
+    /// until the next play/pause fade. Changing the volume (or the track
+    /// gain) cancels the envelope and the volume ramps from the current
+    /// level of the envelope to the new volume, see
+    /// [`Sink::set_volume_ramp`].
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn volume_envelope(
+        &self,
+        envelope: impl VolumeEnvelope + 'static,
+    ) -> Result<()> {
+        self.shared.controls()?.envelope = Some(Box::new(envelope));
+        Ok(())
+    }
+
+    /// Enables or disables [`CallbackInfo::BufferTiming`] events that are
+    /// invoked for each buffer sent to the device.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn enable_timing_events(&self, enable: bool) -> Result<()> {
+        self.shared.controls()?.timing_events = enable;
+        Ok(())
+    }
+
+    /// Gets the volume of the playback, 0 = mute, 1 = full volume.
+    ///
+    /// The value may not be in the range.
+    pub fn get_volume(&self) -> Result<f32> {
+        self.handle().get_volume()
+    }
+
+    /// Gets the volume of the playback without waiting. The volume is not
+    /// protected by any lock, so this never blocks and it is the same as
+    /// [`Sink::get_volume`]. It is kept for symmetry with
+    /// [`Sink::try_get_timestamp`].
+    pub fn try_get_volume(&self) -> Result<f32> {
+        self.handle().try_get_volume()
+    }
+
+    /// Returns true if the source is playing, otherwise returns false
+    pub fn is_playing(&self) -> Result<bool> {
+        self.handle().is_playing()
+    }
+
+    /// Returns true if the source is playing without waiting. The play state
+    /// is not protected by any lock, so this never blocks and it is the same
+    /// as [`Sink::is_playing`]. It is kept for symmetry with
+    /// [`Sink::try_get_timestamp`].
+    pub fn try_is_playing(&self) -> Result<bool> {
+        self.handle().try_is_playing()
+    }
+
+    /// Checks whether there is source loaded. This doesn't lock the source,
+    /// so it is cheap to call it frequently. Source loaded from the playback
+    /// loop is reported after it is applied.
+    pub fn has_source(&self) -> Result<bool> {
+        self.handle().has_source()
+    }
+
+    /// Checks whether the last loaded source has ended and no other source
+    /// was loaded since. This doesn't lock the source.
+    ///
+    /// The source is marked as ended after the playback loop drains it and
+    /// before [`CallbackInfo::SourceEnded`] is invoked, so this is already
+    /// true in the callback. Unlike [`Sink::get_timestamp`], this doesn't
+    /// depend on whether the source supports timestamps.
+    pub fn has_ended(&self) -> Result<bool> {
+        self.handle().has_ended()
+    }
+
+    /// Blocks the current thread until the current source ends or it is
+    /// removed, until new source is loaded or until the `timeout` passes.
+    /// Returns immediately if there is no source. This doesn't use the
+    /// callback, so it works together with [`Sink::on_callback`].
+    ///
+    /// The source doesn't end while it continues with a prefetched or
+    /// queued source (see [`Sink::prefetch`]).
+    ///
+    /// This must not be called from a callback.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn wait_for_end(
+        &self,
+        timeout: Option<Duration>,
+    ) -> Result<WaitStatus> {
+        self.handle().wait_for_end(timeout)
+    }
+
+    /// Seeks to the given position
+    ///
+    /// The source is locked while seeking, so the seek never runs
+    /// concurrently with the playback loop or with other seeks. Concurrent
+    /// seeks are applied in the order in which they aquire the lock.
+    ///
+    /// While scrubbing, this only sets the target position, see
+    /// [`Sink::begin_scrub`]. If seek fade is enabled (see
+    /// [`Sink::set_seek_fade`]), the playback loop seeks after fading out
+    /// and the returned timestamp is the target.
+    ///
+    /// # Errors
+    /// - no source is playing
+    /// - the source doesn't support this
+    /// - failed to seek
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn seek_to(&self, timestamp: Duration) -> Result<Timestamp> {
+        self.handle().seek_to(timestamp)
+    }
+
+    /// Seeks by the given amount. If `forward` is true, seeks forward,
+    /// otherwise seeks backward
+    ///
+    /// The position is read and changed while the source is locked, so
+    /// concurrent relative seeks accumulate (two seeks by 5 s result in seek
+    /// by 10 s).
+    ///
+    /// While scrubbing, this moves the target position, see
+    /// [`Sink::begin_scrub`]. If seek fade is enabled (see
+    /// [`Sink::set_seek_fade`]), the playback loop seeks after fading out
+    /// and the returned timestamp is the target.
+    ///
+    /// # Errors
+    /// - no source is playing
+    /// - the source doesn't support this
+    /// - failed to seek
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn seek_by(&self, time: Duration, forward: bool) -> Result<Timestamp> {
+        self.handle().seek_by(time, forward)
+    }
+
+    /// Sets the length of the fade-out before seeking and the fade-in after
+    /// it while playing, so that seeks don't click. Zero disables the fade
+    /// and the seeks are immediate, this is the default.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn set_seek_fade(&self, fade: Duration) -> Result<()> {
+        self.shared.controls()?.seek_fade = fade;
+        Ok(())
+    }
+
+    /// Starts scrubbing (e.g. while the user drags the seek bar). While
+    /// scrubbing, [`Sink::seek_to`] and [`Sink::seek_by`] only set the target
+    /// position and the playback loop seeks only to the latest target once
+    /// for each buffer. After each seek, `burst` of audio is played at the
+    /// target (even when paused), the rest is silent. Zero `burst` plays
+    /// nothing.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn begin_scrub(&self, burst: Duration) -> Result<()> {
+        let mut controls = self.shared.controls()?;
+        if controls.scrub.is_none() {
+            *self.shared.scrub_target()? = ScrubTarget::default();
+        }
+        controls.scrub = Some(burst);
+        drop(controls);
+        self.shared.set_idle(false);
+        Ok(())
+    }
+
+    /// Stops scrubbing started with [`Sink::begin_scrub`]. The source seeks to
+    /// the last target position, so that the played bursts don't move the
+    /// position. Returns the new timestamp if it seeked.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    /// - failed to seek
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn end_scrub(&self) -> Result<Option<Timestamp>> {
+        self.shared.controls()?.scrub = None;
+        let Some(target) = mem::take(&mut *self.shared.scrub_target()?).last
+        else {
+            return Ok(None);
+        };
+        self.seek_to(target).map(Some)
+    }
+
+    /// Gets the current timestamp and the total length of the currently
+    /// playing source.
+    ///
+    /// While a source fades out after [`Sink::stop`] or during a transition
+    /// inside the source (e.g. the overlap of
+    /// [`crate::source::CrossFadeSource`]), this reports the source that
+    /// will continue playing (so it fails if there is none). Use
+    /// [`Sink::outgoing_timestamp`] to get the timestamp of the source that
+    /// fades out.
+    ///
+    /// # Errors
+    /// - no source is playing
+    /// - the source doesn't support this
+    pub fn get_timestamp(&self) -> Result<Timestamp> {
+        self.handle().get_timestamp()
+    }
+
+    /// Gets the current timestamp and the total length of the currently
+    /// playing source without waiting for the playback loop. If the source
+    /// is used by the playback loop, this returns the timestamp after the
+    /// last buffer that was read from the source (it is updated each
+    /// callback).
+    ///
+    /// # Errors
+    /// - [`Error::WouldBlock`] if the source is locked and there is no
+    ///   timestamp from the playback loop yet
+    /// - no source is playing
+    /// - the source doesn't support this
+    /// - another user of one of the used mutexes panicked while using it
+    pub fn try_get_timestamp(&self) -> Result<Timestamp> {
+        self.handle().try_get_timestamp()
+    }
+
+    /// Gets the timestamp of the source that was removed with [`Sink::stop`]
+    /// and that is still fading out, or of the audio that fades out during a
+    /// transition inside the current source (see [`Source::outgoing_time`]).
+    /// Returns [`None`] once its audio has finished
+    /// ([`CallbackInfo::Stopped`] or [`CallbackInfo::OutgoingEnded`] is
+    /// invoked at that time) or if the source doesn't support getting its
+    /// timestamp.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn outgoing_timestamp(&self) -> Result<Option<Timestamp>> {
+        self.shared.outgoing_time()
+    }
+
+    /// Gets the progress of the current play/pause fade in range `0..=1`.
+    /// Returns [`None`] if there is no fade in progress.
+    ///
+    /// The value is updated for each buffer and reading it doesn't lock.
+    pub fn fade_progress(&self) -> Result<Option<f32>> {
+        Ok(self.shared.fade().map(|(p, _)| p))
+    }
+
+    /// Gets the direction of the current play/pause fade. Returns [`None`] if
+    /// there is no fade in progress.
+    pub fn fade_direction(&self) -> Result<Option<FadeDirection>> {
+        Ok(self.shared.fade().map(|(_, d)| d))
+    }
+
+    /// Enables lightweight analyzer of the output that computes magnitudes
+    /// of `n_bands` (4 to 32) log-spaced frequency bands for each `interval`
+    /// of audio. Get the latest values with [`Sink::band_levels`].
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn enable_bands(
+        &self,
+        n_bands: usize,
+        interval: Duration,
+    ) -> Result<()> {
+        self.shared.controls()?.bands = Some((n_bands.clamp(4, 32), interval));
+        Ok(())
+    }
+
+    /// Disables the band analyzer enabled with [`Sink::enable_bands`].
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn disable_bands(&self) -> Result<()> {
+        self.shared.controls()?.bands = None;
+        self.shared.band_levels()?.clear();
+        Ok(())
+    }
+
+    /// Gets the latest magnitudes of the frequency bands from the lowest
+    /// frequency. The result is empty if the analyzer is disabled or no
+    /// values were computed yet.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn band_levels(&self) -> Result<Vec<f32>> {
+        Ok(self.shared.band_levels()?.clone())
+    }
+
+    /// Sets function that receives the played audio converted to the format
+    /// given by `config` (e.g. 16 kHz mono for speech recognition). The
+    /// channels are mixed down (or duplicated) and the audio is resampled
+    /// with linear interpolation, so it is not suitable for listening.
+    ///
+    /// The function is called from the playback loop with at most 1024
+    /// frames at once, so it must be fast and it must not block. No memory
+    /// is allocated for the tap after this call. Buffers are skipped while
+    /// the tap is being replaced.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn set_analysis_tap(
+        &self,
+        config: AnalysisTapConfig,
+        f: impl FnMut(&[f32]) + Send + 'static,
+    ) -> Result<()> {
+        *self.shared.analysis_tap()? =
+            Some(AnalysisTap::new(config, Box::new(f)));
+        self.shared.controls()?.analysis_tap = true;
+        Ok(())
+    }
+
+    /// Removes the tap set with [`Sink::set_analysis_tap`].
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn clear_analysis_tap(&self) -> Result<()> {
+        self.shared.controls()?.analysis_tap = false;
+        *self.shared.analysis_tap()? = None;
+        Ok(())
+    }
+
+    /// Sets function that receives the samples sent to the device, e.g. for
+    /// visualization. The samples are passed after the volume, fade and all
+    /// the other processing were applied, converted to f32. [`None`]
+    /// removes the function.
+    ///
+    /// The function is called from the playback loop with each buffer, so
+    /// it must be fast and it must not block. Buffers are skipped while the
+    /// function is being replaced.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn on_samples(
+        &self,
+        f: Option<impl FnMut(&SampleTap) + Send + 'static>,
+    ) -> Result<()> {
+        let enable = f.is_some();
+        *self.shared.sample_tap()? = f.map(|f| Box::new(f) as SampleTapFn);
+        self.shared.controls()?.sample_tap = enable;
+        Ok(())
+    }
+
+    /// Enables diagnostic detection of gaps in the playback. Runs of at
+    /// least `min_frames` frames quieter than `threshold_db` played while a
+    /// source is playing are reported with [`CallbackInfo::GapDetected`].
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn enable_gap_detection(
+        &self,
+        threshold_db: f32,
+        min_frames: usize,
+    ) -> Result<()> {
+        self.shared.controls()?.gap_detection =
+            Some((db_to_linear(threshold_db), min_frames.max(1)));
+        Ok(())
+    }
+
+    /// Disables the gap detection enabled with
+    /// [`Sink::enable_gap_detection`].
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn disable_gap_detection(&self) -> Result<()> {
+        self.shared.controls()?.gap_detection = None;
+        Ok(())
+    }
+
+    /// When set to true, the current source starts again from the start when
+    /// it ends, without any gap. [`CallbackInfo::Looped`] is invoked each
+    /// time this happens. If the source doesn't support seeking, the error
+    /// is reported and the source ends as usual. The default is false.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn set_repeat(&self, repeat: bool) -> Result<()> {
+        self.shared.controls()?.repeat = repeat;
+        Ok(())
+    }
+
+    /// When set to true, the playback is paused when the current source
+    /// ends, so that [`Sink::is_playing`] returns false and
+    /// [`CallbackInfo::PauseEnds`] is invoked. The default is false.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn set_pause_on_end(&self, pause: bool) -> Result<()> {
+        self.shared.controls()?.pause_on_end = pause;
+        Ok(())
+    }
+
+    /// When set to true, the playback loop stops reading from the source
+    /// and pauses the stream after the pause fade completes (when
+    /// [`CallbackInfo::PauseEnds`] is invoked). The playback resumes
+    /// transparently with [`Sink::play`] or [`Sink::load`]. The default is
+    /// false.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn set_auto_hard_pause(&self, enable: bool) -> Result<()> {
+        self.shared.controls()?.auto_hard_pause = enable;
+        if !enable {
+            self.shared.set_idle(false);
+        }
+        Ok(())
+    }
+
+    /// Enables or disables the soft limiter of the output. When enabled,
+    /// samples above -1 dBFS are smoothly compressed so that volume above 1
+    /// or boosted channels don't clip. Samples below the threshold are not
+    /// changed. The default is false, so that the playback is bit-exact.
+    ///
+    /// With integer sample formats, the output is mixed in `f32` while the
+    /// limiter is enabled and it is converted to the integer format after
+    /// the samples are limited.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn set_limiter(&self, enable: bool) -> Result<()> {
+        self.shared.controls()?.limiter = enable;
+        Ok(())
+    }
+
+    /// Gets the number of samples changed by the limiter since the sink was
+    /// created, see [`Sink::set_limiter`].
+    pub fn limited_samples(&self) -> u64 {
+        self.shared.limited_samples()
+    }
+
+    /// Enables or disables the passthrough check. When enabled, the output
+    /// is compared with the raw decoded frames of the source while the
+    /// source doesn't need any conversion and the volume is 1. If the output
+    /// differs, [`CallbackInfo::PassthroughMismatch`] is invoked.
+    ///
+    /// This is diagnostic feature, it is supported only by sources that
+    /// implement [`Source::record_raw_hashes`] (e.g.
+    /// [`crate::source::Symph`]) and it is expensive.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn enable_passthrough_check(&self, enable: bool) -> Result<()> {
+        self.shared.controls()?.passthrough_check = enable;
+        Ok(())
+    }
+
+    /// Enables watchdog that invokes [`CallbackInfo::StreamStalled`] when the
+    /// device doesn't request any data for `multiplier` times the duration
+    /// of the buffer while playing. [`None`] disables the watchdog.
+    ///
+    /// The watchdog runs on separate thread and it doesn't fire when the
+    /// stream is paused with [`Sink::hard_pause`].
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    /// - failed to spawn the watchdog thread
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn enable_watchdog(&mut self, multiplier: Option<f32>) -> Result<()> {
+        self.shared.controls()?.watchdog = multiplier;
+        self.shared.wake_watchdog();
+        if multiplier.is_none()
+            || self.watchdog.as_ref().is_some_and(|w| !w.is_finished())
+        {
+            return Ok(());
+        }
+
+        let shared = Arc::downgrade(&self.shared);
+        self.watchdog = Some(
+            thread::spawn("watchdog", false, move || watchdog(shared))
+                .map_err(|e| Error::Other(e.into()))?,
+        );
+        Ok(())
+    }
+
+    /// Sets the fade-in/fade-out time for play/pause. This is the same as
+    /// calling both [`Sink::set_fade_in`] and [`Sink::set_fade_out`].
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn set_fade_len(&self, fade: Duration) -> Result<()> {
+        let mut controls = self.shared.controls()?;
+        controls.fade_in = fade;
+        controls.fade_out = fade;
+        Ok(())
+    }
+
+    /// Sets the fade-in time when the playback resumes
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn set_fade_in(&self, fade: Duration) -> Result<()> {
+        self.shared.controls()?.fade_in = fade;
+        Ok(())
+    }
+
+    /// Sets the fade-out time when the playback pauses
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn set_fade_out(&self, fade: Duration) -> Result<()> {
+        self.shared.controls()?.fade_out = fade;
+        Ok(())
+    }
+
+    /// Sets how long the transition takes when the volume changes while
+    /// playing, so that changing the volume (e.g. with a slider) doesn't
+    /// produce zipper noise. Zero changes the volume immediately. The
+    /// default is 10 ms.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn set_volume_ramp(&self, ramp: Duration) -> Result<()> {
+        self.shared.controls()?.volume_ramp = ramp;
+        Ok(())
+    }
+
+    /// Sets the maximum duration of audio that is read from the source at
+    /// once. Large buffers of the device are filled in multiple chunks and
+    /// the source is unlocked between them, so that other operations (e.g.
+    /// seeking) don't wait for the whole buffer. Zero means no limit, this
+    /// is the default.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn set_max_decode_chunk(&self, chunk: Duration) -> Result<()> {
+        self.shared.controls()?.max_decode_chunk = chunk;
+        Ok(())
+    }
+
+    /// Sets the policy that decides whether the stream must be created again
+    /// when loading source that prefers different configuration. The
+    /// default is [`ConfigPolicy::LosslessFormat`].
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn set_config_policy(&self, policy: ConfigPolicy) -> Result<()> {
+        self.shared.controls()?.config_policy = policy;
+        Ok(())
+    }
+
+    /// Sets the preferred buffer size. None means, use default size.
     ///
     /// Set to small values (such as 1024 or even less) for low latency.
     /// Set to large values (such as 16384) for better performace efficiency.
@@ -363,44 +1936,874 @@ impl Sink {
         self.preferred_buffer_size = size;
     }
 
-    /// Gets the preferred buffer size set by you
-    pub fn get_preferred_buffer_size(&self) -> BufferSize {
-        self.preferred_buffer_size
+    /// Gets the preferred buffer size set by you
+    pub fn get_preferred_buffer_size(&self) -> BufferSize {
+        self.preferred_buffer_size
+    }
+
+    /// Gets the buffer size in frames that is actually used by the stream.
+    /// This is the fixed size requested from the device after it was
+    /// clamped to the supported range (see [`Sink::buffer_size_limits`]).
+    /// If the default size of the device is used, this is the number of
+    /// frames in the first callback of the stream.
+    ///
+    /// Returns [`None`] if there is no stream or the size isn't known yet.
+    pub fn get_actual_buffer_size(&self) -> Option<u32> {
+        match self.stream_config.as_ref()?.config.buffer_size {
+            cpal::BufferSize::Fixed(n) => Some(n),
+            cpal::BufferSize::Default => self.shared.first_buffer_frames(),
+        }
+    }
+
+    /// Gets the range of buffer sizes supported by the device with the
+    /// configuration of the current stream. Returns [`None`] if there is no
+    /// stream yet.
+    pub fn buffer_size_limits(&self) -> Option<SupportedBufferSize> {
+        self.stream_config.as_ref().map(|c| c.buffer_size_limits)
+    }
+
+    /// Gets the configuration negotiated with the device for the current
+    /// stream, including the buffer size and where the configuration came
+    /// from. This is useful when diagnosing resampling. Returns [`None`] if
+    /// there is no stream yet.
+    pub fn get_stream_config(&self) -> Option<&StreamConfigInfo> {
+        self.stream_config.as_ref()
+    }
+
+    /// Gets the latency of the output: how long it takes until the samples
+    /// read from the source are heard. This is the delay reported by the
+    /// device plus the duration of the buffer. Subtract this from the time
+    /// from [`Sink::get_timestamp`] to get the audible position.
+    ///
+    /// Returns zero if the stream didn't request any data yet.
+    pub fn get_latency(&self) -> Result<Duration> {
+        Ok(self.shared.latency())
+    }
+
+    /// Gets info about the configuration of the output device that is
+    /// currently playing
+    pub fn get_info(&self) -> &DeviceConfig {
+        &self.info
+    }
+
+    /// Forces the configuration of the stream. When set, the stream is
+    /// always created with this configuration regardless of the
+    /// configuration preferred by the sources, so the sources must convert
+    /// to it and the stream is never created again because of them.
+    /// [`None`] lets the sources choose the configuration again.
+    ///
+    /// If there is stream, it is created again with the new configuration.
+    ///
+    /// # Errors
+    /// - the device doesn't support the configuration (the configuration is
+    ///   kept and loading fails until different device or configuration is
+    ///   set)
+    /// - failed to create the stream
+    /// - the source failed to init with the new stream
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn set_forced_config(
+        &mut self,
+        config: Option<DeviceConfig>,
+    ) -> Result<()> {
+        if self.forced_config == config {
+            return Ok(());
+        }
+
+        self.forced_config = config;
+        {
+            let mut controls = self.shared.controls()?;
+            controls.forced_config = self.forced_config.is_some();
+            if self.forced_config.is_some() {
+                controls.reconfigure = None;
+            }
+        }
+
+        if *self.released.get_mut() {
+            return self.renegotiate_for_source();
+        }
+        if self.stream.get_mut()?.is_none() {
+            return Ok(());
+        }
+        let config = self
+            .shared
+            .source()?
+            .as_mut()
+            .and_then(|s| s.preferred_config())
+            .or_else(|| Some(self.info.clone()));
+        self.rebuild_stream(config)?;
+        if self.shared.play() {
+            self.play_stream()?;
+        }
+        Ok(())
+    }
+
+    /// Sets how the stream uses the output device and the timeout of the
+    /// blocking operations of the audio host when creating the stream
+    /// ([`None`] = wait indefinitely). If the options change while there is
+    /// stream, the stream is created again (see [`Sink::restart_stream`]).
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    /// - failed to create the stream
+    /// - the source failed to init or seek
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn set_stream_options(
+        &mut self,
+        mode: StreamMode,
+        timeout: Option<Duration>,
+    ) -> Result<()> {
+        // Fails to compile when new mode is added and not handled here.
+        let StreamMode::Shared = mode;
+        if self.stream_timeout == timeout {
+            return Ok(());
+        }
+        self.stream_timeout = timeout;
+        if self.stream.get_mut()?.is_some() {
+            self.restart_stream()?;
+        }
+        Ok(())
+    }
+
+    /// Gets how the current stream uses the output device, see
+    /// [`Sink::set_stream_options`].
+    pub fn stream_mode(&self) -> StreamMode {
+        StreamMode::Shared
+    }
+
+    /// Creates the stream again on the current device with the preferred
+    /// configuration of the current source. The source is initialized with
+    /// the new stream and seeked back to its position before the restart,
+    /// so the playback continues where it was.
+    ///
+    /// This does nothing when called from the callback invoked by the
+    /// playback loop.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    /// - failed to create the stream
+    /// - the source failed to init or seek
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn restart_stream(&mut self) -> Result<()> {
+        let pos = self
+            .shared
+            .source()?
+            .as_ref()
+            .and_then(|s| s.get_time())
+            .map(|t| t.current);
+        self.restart_stream_at(pos)
+    }
+
+    /// Creates the stream again the same way as [`Sink::restart_stream`],
+    /// but the source is seeked to `at` after it is initialized. If `at` is
+    /// [`None`], the source is not seeked and it continues from wherever it
+    /// is after the initialization.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    /// - failed to create the stream
+    /// - the source failed to init or seek
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn restart_stream_at(&mut self, at: Option<Duration>) -> Result<()> {
+        if is_mixer_thread() {
+            return Ok(());
+        }
+
+        let config = self
+            .shared
+            .source()?
+            .as_mut()
+            .and_then(|s| s.preferred_config())
+            .or_else(|| Some(self.info.clone()));
+        self.rebuild_stream_at(config, at)?;
+        if self.shared.play() {
+            self.play_stream()?;
+        }
+        Ok(())
+    }
+
+    /// Gets the configuration forced with [`Sink::set_forced_config`].
+    pub fn get_forced_config(&self) -> Option<&DeviceConfig> {
+        self.forced_config.as_ref()
+    }
+
+    /// Gets how the configuration of the current output stream was selected.
+    /// Returns [`None`] if no stream was created yet.
+    pub fn last_config_decision(&self) -> Option<&ConfigDecision> {
+        self.config_decision.as_ref()
+    }
+
+    /// Gets the path of the signal from the current source to the device.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn signal_path(&self) -> Result<SignalPath> {
+        let spec =
+            self.shared.source()?.as_ref().and_then(|s| s.source_spec());
+        Ok(SignalPath::new(spec, self.info.clone()))
+    }
+
+    /// Gets information about the live threads spawned by the library. This
+    /// includes the threads of all sinks and sources (e.g. read ahead or
+    /// capture).
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    pub fn thread_info(&self) -> Result<Vec<ThreadInfo>> {
+        thread::list()
+    }
+
+    /// Captures the state of the playback. The controls and the source are
+    /// locked at the same time so that the snapshot is consistent.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn snapshot(&self) -> Result<PlaybackSnapshot> {
+        let controls = self.shared.controls()?;
+        let source = self.shared.source()?;
+        Ok(PlaybackSnapshot {
+            volume: self.shared.volume(),
+            fade_in: controls.fade_in,
+            fade_out: controls.fade_out,
+            play: self.shared.play(),
+            device: self.device.as_ref().and_then(|d| d.name().ok()),
+            buffer_size: self.preferred_buffer_size,
+            position: source.as_ref().and_then(|s| s.get_time()),
+        })
+    }
+
+    /// Applies the state from the snapshot and returns the position at which
+    /// the source was, so that the caller can reopen the source and load it
+    /// with [`LoadOptions::start_at`].
+    ///
+    /// The volume and fade length are applied before the play state so that
+    /// resuming fades in to the restored volume. If the device is different
+    /// from the current device, the device is released (see
+    /// [`Sink::release_device`]) and the new device will be used when the
+    /// stream is created again. If the device is no longer available, the
+    /// current device is kept.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    /// - failed to list the devices
+    /// - failed to select configuration of the new device
+    /// - the source failed to init with the new configuration
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn restore(
+        &mut self,
+        snapshot: &PlaybackSnapshot,
+    ) -> Result<Option<Timestamp>> {
+        self.preferred_buffer_size = snapshot.buffer_size;
+
+        let current = self.device.as_ref().and_then(|d| d.name().ok());
+        if let Some(name) = snapshot
+            .device
+            .as_ref()
+            .filter(|d| Some(*d) != current.as_ref())
+        {
+            if let Some(d) = self.find_device(name)? {
+                self.release_device()?;
+                self.set_device(Some(d));
+                self.renegotiate_for_source()?;
+            }
+        }
+
+        {
+            let mut controls = self.shared.controls()?;
+            self.shared.set_volume(snapshot.volume);
+            controls.fade_in = snapshot.fade_in;
+            controls.fade_out = snapshot.fade_out;
+        }
+        self.play(snapshot.play)?;
+
+        Ok(snapshot.position)
+    }
+
+    /// Selects the audio host (e.g. JACK or ASIO) that is used for the
+    /// device enumeration and the default device. [`None`] selects the
+    /// default host of the platform.
+    ///
+    /// If the host is different from the current host, the device is
+    /// released (see [`Sink::release_device`]) and forgotten, so the stream
+    /// will be created on the default device of the new host.
+    ///
+    /// # Errors
+    /// - the host is not available
+    /// - another user of one of the used mutexes panicked while using it
+    /// - the new host has no device with supported configuration
+    /// - the source failed to init with the new configuration
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn set_host(&mut self, host: Option<HostId>) -> Result<()> {
+        if let Some(id) = host {
+            cpal::host_from_id(id).map_err(|_| Error::HostUnavailable(id))?;
+        }
+        if host == self.host {
+            return Ok(());
+        }
+
+        self.release_device()?;
+        self.host = host;
+        self.set_device(None);
+        self.renegotiate_for_source()
+    }
+
+    /// Gets the audio host selected with [`Sink::set_host`], [`None`] if
+    /// the default host is used.
+    pub fn get_host(&self) -> Option<HostId> {
+        self.host
+    }
+
+    /// Gets iterator over all available devices of the selected host
+    ///
+    /// # Errors
+    /// - the selected host is not available
+    /// - failed to list the devices
+    pub fn list_devices(&self) -> Result<Devices> {
+        Ok(self.host()?.devices()?)
+    }
+
+    /// Gets information about all available output devices of the selected
+    /// host. Devices that fail to report their configurations are skipped.
+    ///
+    /// # Errors
+    /// - the selected host is not available
+    /// - failed to list the devices
+    pub fn list_output_devices(&self) -> Result<Vec<DeviceInfo>> {
+        let host = self.host()?;
+        let default = host.default_output_device().and_then(|d| d.name().ok());
+        Ok(host
+            .output_devices()?
+            .filter_map(|d| DeviceInfo::new(&d, default.as_deref()))
+            .collect())
+    }
+
+    /// Finds output device of the selected host with the given name. The
+    /// device can be used with [`Sink::set_device`].
+    ///
+    /// # Errors
+    /// - the selected host is not available
+    /// - failed to list the devices
+    pub fn find_device(&self, name: &str) -> Result<Option<Device>> {
+        Ok(self
+            .host()?
+            .output_devices()?
+            .find(|d| d.name().is_ok_and(|n| n == name)))
+    }
+
+    /// Sets the device with the given name to be used. Returns false if
+    /// there is no such device.
+    pub fn set_device_by_name(&mut self, name: &str) -> Result<bool> {
+        let device = self.find_device(name)?;
+        let found = device.is_some();
+        if found {
+            self.set_device(device);
+        }
+        Ok(found)
     }
 
-    /// Gets info about the configuration of the output device that is
-    /// currently playing
-    pub fn get_info(&self) -> &DeviceConfig {
-        &self.info
-    }
+    /// Checks that audio can be played on the device of this sink (or the
+    /// default device). Plays inaudible test tone on separate stream for
+    /// `duration` and reports the used device and configuration, the timing
+    /// of the callbacks and the errors that occured. The playback of this
+    /// sink is not affected.
+    ///
+    /// The test always takes `duration`, even if the device never requests
+    /// any audio.
+    ///
+    /// # Errors
+    /// - failed to create the stream
+    /// - another user of one of the used mutexes panicked while using it
+    pub fn self_test(&self, duration: Duration) -> Result<SelfTestReport> {
+        let mut sink = Sink::default();
+        sink.host = self.host;
+        sink.set_device(self.device.clone());
+        sink.set_buffer_size(self.preferred_buffer_size);
+
+        let errors = Arc::new(Mutex::new(vec![]));
+        let errs = errors.clone();
+        sink.on_err_callback(Some(move |e: Error| {
+            if let Ok(mut errs) = errs.lock() {
+                errs.push(e.to_string());
+            }
+        }))?;
+
+        let (tone, calls) = TestTone::new();
+        sink.load(tone, true)?;
+        let device = sink.device.as_ref().and_then(|d| d.name().ok());
+        let config = sink.info.clone();
 
-    /// Gets iterator over all available devices
-    pub fn list_devices() -> Result<Devices> {
-        Ok(cpal::default_host().devices()?)
+        std::thread::sleep(duration);
+        drop(sink);
+
+        let calls = calls.lock()?;
+        let errors = std::mem::take(&mut *errors.lock()?);
+        Ok(SelfTestReport::new(
+            device, config, duration, &calls, errors,
+        ))
     }
 
     /// Sets the device to be used
     pub fn set_device(&mut self, device: Option<Device>) {
+        self.explicit_device = device.is_some();
         self.device = device;
     }
+
+    /// When set to true, the stream is created again on the new default
+    /// device when the device is no longer available (e.g. headphones were
+    /// unplugged). This doesn't apply to device set with
+    /// [`Sink::set_device`]. The default is true.
+    ///
+    /// The loss of the device is reported with
+    /// [`CallbackInfo::DeviceDisconnected`] and the stream is created again
+    /// with the next call to [`Sink::load`] or [`Sink::check_device`].
+    /// [`Sink::play`] fails until then. Otherwise [`Sink::play`] fails until
+    /// [`Sink::restart_device`] is called.
+    pub fn set_auto_restart(&mut self, restart: bool) {
+        self.auto_restart = restart;
+    }
+
+    /// Creates the stream again on the default device if the device is no
+    /// longer available and auto restart is enabled (see
+    /// [`Sink::set_auto_restart`]). The playback state and the position in
+    /// the current source are kept and [`CallbackInfo::DeviceChanged`] is
+    /// invoked. Returns true if the stream was created again.
+    ///
+    /// The stream must be created on the thread that owns the sink, so call
+    /// this after [`CallbackInfo::DeviceDisconnected`] is reported (but not
+    /// from the callback).
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    /// - failed to create the stream
+    /// - the source failed to init with the new stream
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn check_device(&mut self) -> Result<bool> {
+        if is_mixer_thread() {
+            return Ok(false);
+        }
+        if !self.take_lost_device() {
+            return Ok(false);
+        }
+
+        self.resume_on_new_device()?;
+        Ok(true)
+    }
+
+    /// Creates the stream again on the default device, e.g. after the
+    /// device was disconnected (see [`CallbackInfo::DeviceDisconnected`]).
+    /// Unlike [`Sink::check_device`], this doesn't depend on the auto
+    /// restart and the device set with [`Sink::set_device`] is forgotten.
+    /// The playback state and the position in the current source are kept
+    /// and [`CallbackInfo::DeviceChanged`] is invoked.
+    ///
+    /// This does nothing when called from the callback invoked by the
+    /// playback loop.
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    /// - failed to create the stream
+    /// - the source failed to init with the new stream
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn restart_device(&mut self) -> Result<()> {
+        if is_mixer_thread() {
+            return Ok(());
+        }
+
+        self.shared.take_device_lost();
+        drop(self.stream.get_mut()?.take());
+        self.device = None;
+        self.explicit_device = false;
+        self.shared.set_stream_running(false);
+        self.resume_on_new_device()
+    }
+}
+
+impl Sink {
+    /// Creates the stream on the default device after the old stream was
+    /// dropped and continues the playback.
+    fn resume_on_new_device(&mut self) -> Result<()> {
+        let config = self
+            .shared
+            .source()?
+            .as_mut()
+            .and_then(|s| s.preferred_config())
+            .or_else(|| Some(self.info.clone()));
+        self.rebuild_stream(config)?;
+        if self.shared.play() {
+            self.play_stream()?;
+        }
+
+        self.shared
+            .invoke_callback(CallbackInfo::DeviceChanged(self.info.clone()))
+    }
+
+    /// Creates the stream again if the device was released. The stream uses
+    /// the device and the configuration that were selected for it before,
+    /// so the sources don't need to be initialized again.
+    fn reacquire_device(&self) -> Result<()> {
+        let mut stream = self.stream.lock()?;
+        if stream.is_some() || !self.released.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        *stream = Some(self.start_stream()?);
+        drop(stream);
+
+        self.mark_reacquired();
+        Ok(())
+    }
+
+    /// Selects the device and the configuration for the released stream
+    /// again without creating the stream, so that the stream created on
+    /// play uses the current settings. The current source is initialized
+    /// with the new configuration and its position is kept.
+    fn renegotiate_released(
+        &mut self,
+        config: Option<DeviceConfig>,
+    ) -> Result<()> {
+        let pos = self
+            .shared
+            .source()?
+            .as_ref()
+            .and_then(|s| s.get_time())
+            .map(|t| t.current);
+        self.negotiate(config.clone())?;
+        self.reinit_sources(config.as_ref(), pos)
+    }
+
+    /// Selects the device and the configuration for the released stream
+    /// again with the configuration preferred by the current source. This
+    /// does nothing if the stream is not released.
+    fn renegotiate_for_source(&mut self) -> Result<()> {
+        if !*self.released.get_mut() {
+            return Ok(());
+        }
+        let config = self
+            .shared
+            .source()?
+            .as_mut()
+            .and_then(|s| s.preferred_config())
+            .or_else(|| Some(self.info.clone()));
+        self.renegotiate_released(config)
+    }
+
+    /// Resumes the stream if there is any.
+    fn play_stream(&self) -> Result<()> {
+        if let Some(s) = self.stream.lock()?.as_ref() {
+            s.play()?;
+            self.shared.set_stream_running(true);
+        }
+        Ok(())
+    }
+
+    /// Creates new stream with the given preferred configuration. The
+    /// current source is initialized with the new configuration and its
+    /// position is kept.
+    fn rebuild_stream(&mut self, config: Option<DeviceConfig>) -> Result<()> {
+        let pos = self
+            .shared
+            .source()?
+            .as_ref()
+            .and_then(|s| s.get_time())
+            .map(|t| t.current);
+        self.rebuild_stream_at(config, pos)
+    }
+
+    /// Creates new stream with the given preferred configuration. The
+    /// current source is initialized with the new configuration and seeked
+    /// to `at` if it isn't already there. If `at` is [`None`], the source
+    /// is not seeked.
+    fn rebuild_stream_at(
+        &mut self,
+        config: Option<DeviceConfig>,
+        at: Option<Duration>,
+    ) -> Result<()> {
+        drop(self.stream.get_mut()?.take());
+        self.build_out_stream(config.clone())?;
+        self.reinit_sources(config.as_ref(), at)
+    }
+
+    /// Initializes the current source and the overlays with the current
+    /// configuration. The source is seeked to `at` the same way as in
+    /// [`Sink::rebuild_stream_at`].
+    fn reinit_sources(
+        &mut self,
+        config: Option<&DeviceConfig>,
+        at: Option<Duration>,
+    ) -> Result<()> {
+        let ctx = self.init_context(config);
+
+        let mut controls = self.shared.controls()?;
+        let mut source = self.shared.source()?;
+        if let Some(s) = source.as_mut() {
+            s.init_with(&ctx)?;
+            let new_pos = s.get_time().map(|t| t.current);
+            if let Some(p) = at.filter(|p| Some(*p) != new_pos) {
+                s.seek(p)?;
+            }
+
+            let path = SignalPath::new(s.source_spec(), self.info.clone());
+            if self.signal_path.as_ref() != Some(&path) {
+                self.signal_path = Some(path);
+                controls.signal_path_changed = true;
+            }
+        }
+        drop(source);
+        drop(controls);
+
+        let ctx = overlay_context(&self.info);
+        for o in self.shared.overlays()?.iter_mut() {
+            o.source.init_with(&ctx)?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks whether the device was lost and it should be replaced with the
+    /// default device. If so, the stream is dropped and the device is
+    /// forgotten, so that the stream will be created on the default device.
+    fn take_lost_device(&mut self) -> bool {
+        let stream = self
+            .stream
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        if !self.auto_restart
+            || self.explicit_device
+            || stream.is_none()
+            || !self.shared.take_device_lost()
+        {
+            return false;
+        }
+
+        drop(stream.take());
+        self.device = None;
+        self.shared.set_stream_running(false);
+        true
+    }
+
+    /// Prepares the source loaded from the playback loop and passes it to the
+    /// playback loop. The stream is running, because this is called from its
+    /// callback, so it is not created again.
+    fn defer_load(
+        &mut self,
+        mut src: Box<dyn Source>,
+        play: bool,
+        options: &LoadOptions,
+    ) -> Result<LoadStatus> {
+        let preferred = src.preferred_config();
+        src.init_with(&self.init_context(preferred.as_ref()))?;
+        if let Some(t) = options.start_at {
+            src.seek(t)?;
+        }
+
+        let path = SignalPath::new(src.source_spec(), self.info.clone());
+        let path_changed = self.signal_path.as_ref() != Some(&path);
+        self.signal_path = Some(path);
+
+        let old = self.shared.defer_load(DeferredLoad {
+            source: src,
+            preferred,
+            path_changed,
+            play,
+            track_gain: options.track_gain.map(db_to_linear),
+        })?;
+        drop(old);
+
+        // The old source is usually already unlocked (e.g. when it ended), so
+        // it can be dropped here instead of in the playback loop.
+        let old = self.shared.try_source().ok().and_then(|mut s| s.take());
+        drop(old);
+
+        Ok(LoadStatus::Deferred)
+    }
+
+    /// Swaps in the source loaded from the playback loop if there is any.
+    /// This is used when the playback loop will not run to do it itself.
+    fn apply_deferred_load(&self) -> Result<()> {
+        if is_mixer_thread() {
+            return Ok(());
+        }
+        let Some(load) = self.shared.take_deferred_load() else {
+            return Ok(());
+        };
+
+        let mut controls = self.shared.controls()?;
+        let mut source = self.shared.source()?;
+        controls.signal_path_changed |= load.path_changed;
+        controls.new_source = true;
+        controls.reconfigure = None;
+        if let Some(g) = load.track_gain {
+            self.shared.set_track_gain(g);
+        }
+        self.shared.set_play(load.play);
+        let old = source.replace(load.source);
+        self.shared.next_source_generation();
+        self.shared.set_idle(false);
+        self.shared.set_source_state(SourceState::Loaded);
+        drop(source);
+        drop(controls);
+        self.shared.set_stopping(None)?;
+        drop(old);
+        Ok(())
+    }
+
+    /// Gets the default output device of the selected host.
+    pub(crate) fn default_device(&self) -> Result<Device> {
+        self.host()?
+            .default_output_device()
+            .ok_or(Error::NoOutDevice)
+    }
+
+    /// Creates the stream with the given configuration, see
+    /// [`SinkBuilder::config`].
+    pub(crate) fn open_stream(&mut self, config: DeviceConfig) -> Result<()> {
+        self.build_out_stream(Some(config))
+    }
+
+    /// Gets the selected audio host.
+    fn host(&self) -> Result<Host> {
+        match self.host {
+            Some(id) => {
+                cpal::host_from_id(id).map_err(|_| Error::HostUnavailable(id))
+            }
+            None => Ok(cpal::default_host()),
+        }
+    }
+
+    /// Creates the context for initializing source with the given preferred
+    /// configuration.
+    fn init_context(&self, preferred: Option<&DeviceConfig>) -> InitContext {
+        InitContext::new(
+            self.info.clone(),
+            preferred,
+            self.preferred_buffer_size.duration(self.info.sample_rate),
+        )
+    }
+
+    /// Resumes the stream and the playback. If `now` is true, the fade-in
+    /// is skipped.
+    fn start_playing(&self, now: bool) -> Result<()> {
+        self.shared.set_idle(false);
+        self.reacquire_device()?;
+        if self.stream.lock()?.is_some() && self.shared.device_lost() {
+            return Err(Error::DeviceDisconnected);
+        }
+        if now {
+            self.shared.set_play_now(true);
+        } else {
+            self.shared.set_play(true);
+        }
+        self.play_stream()
+    }
+
+    /// Reports that the device was acquired again if it was released.
+    fn mark_reacquired(&self) {
+        if self.released.swap(false, Ordering::Relaxed) {
+            if let Err(e) =
+                self.shared.invoke_callback(CallbackInfo::DeviceReacquired)
+            {
+                _ = self.shared.invoke_err_callback(e);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "test-util")]
+impl Sink {
+    /// Creates new sink that uses the given clock for the time based
+    /// features (e.g. the watchdog and error deduplication) instead of the
+    /// real clock.
+    pub fn with_clock(clock: Arc<ManualClock>) -> Self {
+        Self::with_shared(Arc::new(SharedData::with_clock(Clock::Manual(
+            clock,
+        ))))
+    }
 }
 
 impl Default for Sink {
     fn default() -> Self {
+        Self::with_shared(Arc::new(SharedData::new()))
+    }
+}
+
+impl Sink {
+    /// Creates sink with the given shared data and without stream
+    fn with_shared(shared: Arc<SharedData>) -> Self {
         Self {
-            shared: Arc::new(SharedData::new()),
-            stream: None,
+            shared,
+            stream: Mutex::new(None),
             info: DeviceConfig {
                 channel_count: 0,
                 sample_rate: 0,
                 sample_format: SampleFormat::F32,
             },
+            host: None,
             device: None,
+            stream_device: None,
             preferred_buffer_size: BufferSize::Auto,
+            forced_config: None,
+            stream_config: None,
+            signal_path: None,
+            watchdog: None,
+            released: AtomicBool::new(false),
+            config_decision: None,
+            explicit_device: false,
+            auto_restart: true,
+            stream_timeout: None,
         }
     }
 }
 
+impl Drop for Sink {
+    fn drop(&mut self) {
+        // Stop the playback loop before the source is removed.
+        *self
+            .stream
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner) = None;
+        self.shared.set_stream_running(false);
+        _ = self.shared.set_resume_request(None);
+        let src = self.shared.source().ok().and_then(|mut s| s.take());
+        _ = self.handle().clear_queue();
+        // Wake the threads waiting for the end of the source, nothing will
+        // play it anymore.
+        self.shared.set_source_state(SourceState::Empty);
+        drop(src);
+        self.shared.wake_watchdog();
+    }
+}
+
 /// Selects config based on the prefered configuration
 fn select_config(
     prefered: DeviceConfig,
@@ -431,17 +2834,438 @@ fn select_config(
     selected.map(|s| s.with_sample_rate(SampleRate(prefered.sample_rate)))
 }
 
+/// Finds supported configuration that exactly matches the configuration
+pub(crate) fn find_config(
+    config: &DeviceConfig,
+    mut configs: SupportedOutputConfigs,
+) -> Option<SupportedStreamConfig> {
+    configs
+        .find(|c| {
+            c.channels() as u32 == config.channel_count
+                && c.sample_format() == config.sample_format
+                && c.min_sample_rate().0 <= config.sample_rate
+                && c.max_sample_rate().0 >= config.sample_rate
+        })
+        .map(|c| c.with_sample_rate(SampleRate(config.sample_rate)))
+}
+
 impl std::fmt::Debug for Sink {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Sink").field("info", &self.info).finish()
     }
 }
 
-fn get_play_time(info: &OutputCallbackInfo) -> Instant {
-    let now = Instant::now();
-    now + info
-        .timestamp()
-        .playback
-        .duration_since(&info.timestamp().callback)
-        .unwrap_or_default()
+/// Invokes the prefetch factory once the prefetch notification is triggered.
+/// Runs until there is no pending factory or the sink is dropped.
+fn prefetcher(shared: Weak<SharedData>) {
+    loop {
+        let Some(shared) = shared.upgrade() else {
+            return;
+        };
+
+        let factory = match shared.prefetch_factory() {
+            Ok(mut p) if p.factory.is_none() => {
+                p.worker = false;
+                return;
+            }
+            Ok(mut p) if shared.prefetch_due() => {
+                p.factory.take().map(|f| (f, p.generation))
+            }
+            Ok(_) => None,
+            Err(_) => return,
+        };
+
+        if let Some((factory, generation)) = factory {
+            let res = factory().and_then(|s| shared.init_queued(s));
+            let pending = match shared.prefetch_factory() {
+                Ok(p) => p,
+                Err(_) => return,
+            };
+            // The factory was replaced or the queue was cleared while the
+            // source was created. The lock is held while pushing so that the
+            // queue cannot be cleared in between.
+            if pending.generation != generation {
+                continue;
+            }
+            let res = res.and_then(|src| {
+                let mut p = shared.prefetch()?;
+                // Source set explicitly wins over the factory.
+                if p.is_empty() {
+                    p.push_back(src);
+                }
+                Ok(())
+            });
+            drop(pending);
+            if let Err(e) = res {
+                _ = shared.invoke_err_callback(e);
+            }
+            continue;
+        }
+
+        // Poll in real time, the manual clock may never advance.
+        drop(shared);
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Checks that the stream invokes callbacks while playing. Runs until the
+/// watchdog is disabled or the sink is dropped.
+fn watchdog(shared: Weak<SharedData>) {
+    let mut stalled = false;
+    loop {
+        let Some(shared) = shared.upgrade() else {
+            return;
+        };
+        let (multiplier, play) = match shared.controls() {
+            Ok(c) => match c.watchdog {
+                Some(m) => (m, shared.play()),
+                None => return,
+            },
+            Err(_) => return,
+        };
+
+        let (since, buffer) = shared.since_callback();
+        let limit = buffer
+            .mul_f32(multiplier.max(1.))
+            .max(Duration::from_millis(200));
+
+        if play && shared.stream_running() && since > limit {
+            if !stalled {
+                stalled = true;
+                if let Err(e) =
+                    shared.invoke_callback(CallbackInfo::StreamStalled)
+                {
+                    _ = shared.invoke_err_callback(e);
+                }
+            }
+        } else {
+            stalled = false;
+        }
+
+        // The manual clock may never advance, so wait in real time.
+        shared.wait_watchdog(limit / 2);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, sync::mpsc, thread, time::Duration};
+
+    use super::*;
+    use crate::{
+        mixer::tests::{config, mix, mixer_for},
+        output_stream::tests::fake_stream,
+        source::{SilenceSource, SineSource},
+    };
+
+    #[test]
+    fn drop_wakes_wait_for_end() {
+        let sink = Sink::default();
+        *sink.shared.source().unwrap() = Some(Box::new(SineSource::new(440.)));
+        sink.shared.set_source_state(SourceState::Loaded);
+
+        let handle = sink.handle();
+        let (send, recv) = mpsc::channel();
+        thread::spawn(move || {
+            _ = send.send(handle.wait_for_end(None));
+        });
+
+        // Make it likely that the thread already waits.
+        thread::sleep(Duration::from_millis(50));
+        drop(sink);
+
+        let res = recv.recv_timeout(Duration::from_secs(5));
+        assert!(matches!(res, Ok(Ok(WaitStatus::Ended))));
+    }
+
+    #[test]
+    fn load_from_callback_is_deferred() {
+        thread_local! {
+            // Sink is not `Send`, so the callback accesses it through the
+            // thread that mixes.
+            static SINK: RefCell<Option<Sink>> = const { RefCell::new(None) };
+        }
+
+        let (send, recv) = mpsc::channel();
+        thread::spawn(move || {
+            let mut sink = Sink::default();
+            sink.info = config();
+            let shared = sink.shared.clone();
+            let first = SilenceSource::new(Duration::from_millis(15));
+            let (mut mixer, _) = mixer_for(&shared, first);
+            SINK.set(Some(sink));
+
+            let status = Arc::new(Mutex::new(None));
+            let cb_status = status.clone();
+            shared
+                .callback()
+                .set(Some(Box::new(move |info| {
+                    if matches!(info, CallbackInfo::SourceEnded) {
+                        let res = SINK.with_borrow_mut(|s| {
+                            s.as_mut()
+                                .unwrap()
+                                .load(SineSource::new(440.), true)
+                        });
+                        *cb_status.lock().unwrap() = Some(res.ok());
+                    }
+                })))
+                .unwrap();
+
+            mix(&mut mixer, 3);
+            let last = mix(&mut mixer, 1);
+            let status = status.lock().unwrap().take();
+            _ = send.send((status, shared.source_state(), last));
+            SINK.take();
+        });
+
+        // The mixing would never finish if the load deadlocked.
+        let (status, state, last) =
+            recv.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(status, Some(Some(LoadStatus::Deferred)));
+        assert_eq!(state, SourceState::Loaded);
+        assert!(last.iter().any(|s| s.abs() > 0.1));
+    }
+
+    #[test]
+    fn prefetch_from_callback_plays_gaplessly() {
+        thread_local! {
+            static SINK: RefCell<Option<Sink>> = const { RefCell::new(None) };
+        }
+
+        let (send, recv) = mpsc::channel();
+        thread::spawn(move || {
+            let mut sink = Sink::default();
+            sink.info = config();
+            let shared = sink.shared.clone();
+            let first = SilenceSource::new(Duration::from_millis(100));
+            let (mut mixer, _) = mixer_for(&shared, first);
+            shared.controls().unwrap().prefetch_notify =
+                Duration::from_millis(50);
+            SINK.set(Some(sink));
+
+            let status = Arc::new(Mutex::new(vec![]));
+            let cb_status = status.clone();
+            shared
+                .callback()
+                .set(Some(Box::new(move |info| match info {
+                    CallbackInfo::PrefetchTime { .. } => {
+                        let res = SINK.with_borrow(|s| {
+                            s.as_ref().unwrap().prefetch(SineSource::new(440.))
+                        });
+                        cb_status.lock().unwrap().push(res.is_ok());
+                    }
+                    CallbackInfo::SourceEnded => {
+                        cb_status.lock().unwrap().push(false)
+                    }
+                    _ => {}
+                })))
+                .unwrap();
+
+            mix(&mut mixer, 15);
+            let last = mix(&mut mixer, 1);
+            let status = std::mem::take(&mut *status.lock().unwrap());
+            _ = send.send((status, shared.source_state(), last));
+            SINK.take();
+        });
+
+        // The mixing would never finish if the prefetch deadlocked.
+        let (status, state, last) =
+            recv.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(status, [true]);
+        assert_eq!(state, SourceState::Loaded);
+        assert!(last.iter().any(|s| s.abs() > 0.1));
+    }
+
+    #[test]
+    fn end_scrub_returns_to_last_target() {
+        let sink = Sink::default();
+        let src = SilenceSource::new(Duration::from_secs(10));
+        let (mut mixer, _) = mixer_for(&sink.shared, src);
+
+        sink.begin_scrub(Duration::from_millis(5)).unwrap();
+        sink.seek_to(Duration::from_secs(1)).unwrap();
+        // The burst moves the position after the seek.
+        mix(&mut mixer, 1);
+        assert!(
+            sink.get_timestamp().unwrap().current > Duration::from_secs(1)
+        );
+
+        let ts = sink.end_scrub().unwrap().unwrap();
+        assert_eq!(ts.current, Duration::from_secs(1));
+        assert_eq!(
+            sink.get_timestamp().unwrap().current,
+            Duration::from_secs(1)
+        );
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn watchdog_stops_with_manual_clock() {
+        let clock = Arc::new(ManualClock::new());
+        let mut sink = Sink::with_clock(clock.clone());
+        sink.enable_watchdog(Some(2.)).unwrap();
+        let watchdog = sink.watchdog.take().unwrap();
+
+        // The clock doesn't move, but the watchdog must still notice that
+        // it was disabled.
+        sink.enable_watchdog(None).unwrap();
+        let start = std::time::Instant::now();
+        while !watchdog.is_finished() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn pause_keeps_hard_paused_stream() {
+        for (hard, play) in
+            [(false, false), (false, true), (true, false), (true, true)]
+        {
+            let mut sink = Sink::default();
+            let (mut mixer, _) =
+                mixer_for(&sink.shared, SineSource::new(440.));
+            let (stream, playing) = fake_stream(&sink.shared);
+            stream.play().unwrap();
+            *sink.stream.get_mut().unwrap() = Some(stream);
+            sink.shared.set_stream_running(true);
+            sink.shared.controls().unwrap().fade_out =
+                Duration::from_millis(100);
+            // Finish the fade in.
+            mix(&mut mixer, 100);
+            assert_eq!(sink.fade_direction().unwrap(), None);
+
+            if hard {
+                sink.hard_pause().unwrap();
+            }
+            sink.play(play).unwrap();
+
+            let msg = format!("hard: {hard}, play: {play}");
+            let running = !hard || play;
+            assert_eq!(playing.load(Ordering::Relaxed), running, "{msg}");
+            assert_eq!(sink.shared.stream_running(), running, "{msg}");
+            assert_eq!(sink.shared.play(), play, "{msg}");
+            assert!(!sink.shared.take_skip_fade(), "{msg}");
+            assert!(!sink.shared.idle(), "{msg}");
+
+            // Only running stream invokes the playback loop.
+            if running {
+                mix(&mut mixer, 1);
+            }
+            let fade = (running && !play).then_some(FadeDirection::Out);
+            assert_eq!(sink.fade_direction().unwrap(), fade, "{msg}");
+        }
+    }
+
+    #[test]
+    fn concurrent_seeks_accumulate() {
+        let info = DeviceConfig {
+            channel_count: 2,
+            sample_rate: 48000,
+            sample_format: SampleFormat::F32,
+        };
+
+        let sink = Sink::default();
+        let mut src = SilenceSource::new(Duration::from_secs(10));
+        src.init(&info).unwrap();
+        *sink.shared.source().unwrap() = Some(Box::new(src));
+        sink.shared.set_source_state(SourceState::Loaded);
+
+        let threads: Vec<_> = (0..2)
+            .map(|_| {
+                let handle = sink.handle();
+                thread::spawn(move || {
+                    for _ in 0..50 {
+                        handle
+                            .seek_by(Duration::from_millis(10), true)
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        let time = sink.get_timestamp().unwrap();
+        assert_eq!(time.current, Duration::from_secs(1));
+
+        // Seeks through `&self` work while the sink is shared.
+        let sink = &sink;
+        sink.seek_to(Duration::from_secs(2)).unwrap();
+        sink.seek_by(Duration::from_secs(3), false).unwrap();
+        assert_eq!(sink.get_timestamp().unwrap().current, Duration::ZERO);
+    }
+
+    /// Silent seekable source that starts from the beginning when it is
+    /// initialized, like decoders that can't keep their position.
+    #[derive(Default)]
+    struct RestartingSource {
+        frame: u64,
+        rate: u32,
+        channels: usize,
+    }
+
+    impl Source for RestartingSource {
+        fn init(&mut self, info: &DeviceConfig) -> anyhow::Result<()> {
+            self.frame = 0;
+            self.rate = info.sample_rate;
+            self.channels = info.channel_count as usize;
+            Ok(())
+        }
+
+        fn read(
+            &mut self,
+            buffer: &mut SampleBufferMut,
+        ) -> (usize, anyhow::Result<()>) {
+            buffer.silence();
+            self.frame += (buffer.len() / self.channels) as u64;
+            (buffer.len(), Ok(()))
+        }
+
+        fn seek(&mut self, time: Duration) -> anyhow::Result<Timestamp> {
+            self.frame = (time.as_secs_f64() * self.rate as f64) as u64;
+            Ok(self.get_time().unwrap())
+        }
+
+        fn get_time(&self) -> Option<Timestamp> {
+            Some(Timestamp::new(
+                Duration::from_secs_f64(self.frame as f64 / self.rate as f64),
+                Duration::from_secs(10),
+            ))
+        }
+    }
+
+    #[test]
+    fn restart_keeps_position() {
+        let mut sink = Sink::default();
+        sink.info = config();
+        let shared = sink.shared.clone();
+        let (mut mixer, _) = mixer_for(&shared, RestartingSource::default());
+        mix(&mut mixer, 30);
+
+        let time = || {
+            let src = shared.source().unwrap();
+            src.as_ref().unwrap().get_time().unwrap().current
+        };
+        let pos = time();
+        assert_eq!(pos, Duration::from_millis(300));
+
+        // The new stream has different sample rate.
+        sink.info.sample_rate = 44100;
+        sink.reinit_sources(None, Some(pos)).unwrap();
+        assert!(time().abs_diff(pos) < Duration::from_millis(1));
+
+        // The reseek is suppressed.
+        sink.reinit_sources(None, None).unwrap();
+        assert_eq!(time(), Duration::ZERO);
+
+        // Sources that can't tell their position are not seeked.
+        let mut sine = SineSource::new(440.);
+        sine.init(&sink.info).unwrap();
+        *shared.source().unwrap() = Some(Box::new(sine));
+        let src = shared.source().unwrap();
+        assert!(src.as_ref().unwrap().get_time().is_none());
+        drop(src);
+        sink.reinit_sources(None, None).unwrap();
+    }
 }
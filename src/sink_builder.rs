@@ -0,0 +1,192 @@
+use std::time::Duration;
+
+use cpal::{traits::DeviceTrait, Device, HostId};
+
+use crate::{
+    err::{Error, Result},
+    shared::CallbackInfo,
+    sink::find_config,
+    source::{DeviceConfig, Source},
+    BufferSize, LoadStatus, Sink,
+};
+
+/// Function that loads the initial source into the built sink
+type LoadFn = Box<dyn FnOnce(&mut Sink) -> Result<LoadStatus>>;
+
+/// Creates configured [`Sink`] in one go, see [`Sink::builder`].
+///
+/// The settings are validated by [`SinkBuilder::build`], so the sink is
+/// either fully configured or not created at all.
+#[derive(Default)]
+pub struct SinkBuilder {
+    /// Audio host, [`None`] = the default host
+    host: Option<HostId>,
+    /// Output device, [`None`] = the default device of the host
+    device: Option<Device>,
+    /// Preferred buffer size
+    buffer_size: BufferSize,
+    /// Configuration of the stream that is opened when building
+    config: Option<DeviceConfig>,
+    /// Fade-in time when resuming
+    fade_in: Option<Duration>,
+    /// Fade-out time when pausing
+    fade_out: Option<Duration>,
+    /// Remaining time of the source when the prefetch notification is
+    /// invoked
+    prefetch_notify: Option<Duration>,
+    /// Played fraction of the source when the prefetch notification is
+    /// invoked
+    prefetch_notify_fraction: Option<f32>,
+    /// Callback of the sink
+    callback: Option<Box<dyn FnMut(CallbackInfo) + Send>>,
+    /// Error callback of the sink
+    err_callback: Option<Box<dyn FnMut(Error) + Send>>,
+    /// Loads the initial source
+    load: Option<LoadFn>,
+}
+
+impl SinkBuilder {
+    /// Creates builder with the default settings. This is the same as
+    /// [`Sink::builder`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the audio host, see [`Sink::set_host`].
+    pub fn host(mut self, host: HostId) -> Self {
+        self.host = Some(host);
+        self
+    }
+
+    /// Sets the output device, see [`Sink::set_device`]. The device must
+    /// belong to the host set with [`SinkBuilder::host`].
+    pub fn device(mut self, device: Device) -> Self {
+        self.device = Some(device);
+        self
+    }
+
+    /// Sets the preferred buffer size, see [`Sink::set_buffer_size`].
+    pub fn buffer_size(mut self, size: BufferSize) -> Self {
+        self.buffer_size = size;
+        self
+    }
+
+    /// Sets the configuration of the stream. The stream is opened with this
+    /// configuration when building and the build fails if the device
+    /// doesn't support it.
+    pub fn config(mut self, config: DeviceConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Sets the fade-in/fade-out time for play/pause, see
+    /// [`Sink::set_fade_len`].
+    pub fn fade(self, fade: Duration) -> Self {
+        self.fade_in(fade).fade_out(fade)
+    }
+
+    /// Sets the fade-in time when resuming, see [`Sink::set_fade_in`].
+    pub fn fade_in(mut self, fade: Duration) -> Self {
+        self.fade_in = Some(fade);
+        self
+    }
+
+    /// Sets the fade-out time when pausing, see [`Sink::set_fade_out`].
+    pub fn fade_out(mut self, fade: Duration) -> Self {
+        self.fade_out = Some(fade);
+        self
+    }
+
+    /// Sets when the prefetch notification is invoked, see
+    /// [`Sink::set_prefetch_notify`].
+    pub fn prefetch_notify(mut self, remaining: Duration) -> Self {
+        self.prefetch_notify = Some(remaining);
+        self
+    }
+
+    /// Sets the played fraction of the source when the prefetch
+    /// notification is invoked, see [`Sink::set_prefetch_notify_fraction`].
+    pub fn prefetch_notify_fraction(mut self, played: f32) -> Self {
+        self.prefetch_notify_fraction = Some(played);
+        self
+    }
+
+    /// Sets the callback, see [`Sink::on_callback`].
+    pub fn callback(
+        mut self,
+        callback: impl FnMut(CallbackInfo) + Send + 'static,
+    ) -> Self {
+        self.callback = Some(Box::new(callback));
+        self
+    }
+
+    /// Sets the error callback, see [`Sink::on_err_callback`].
+    pub fn err_callback(
+        mut self,
+        callback: impl FnMut(Error) + Send + 'static,
+    ) -> Self {
+        self.err_callback = Some(Box::new(callback));
+        self
+    }
+
+    /// Sets the source that is loaded when building. If `play` is true, it
+    /// starts playing immediately.
+    pub fn source(mut self, src: impl Source + 'static, play: bool) -> Self {
+        self.load = Some(Box::new(move |s| s.load(src, play)));
+        self
+    }
+
+    /// Creates the sink with the settings.
+    ///
+    /// # Errors
+    /// - the host is not available
+    /// - there is no output device
+    /// - the device doesn't support the configuration
+    /// - failed to create the stream
+    /// - the source failed to init
+    pub fn build(self) -> Result<Sink> {
+        let mut sink = Sink::default();
+        sink.set_host(self.host)?;
+
+        if let Some(config) = &self.config {
+            let device = match &self.device {
+                Some(d) => d.clone(),
+                None => sink.default_device()?,
+            };
+            if find_config(config, device.supported_output_configs()?)
+                .is_none()
+            {
+                return Err(Error::Unsupported {
+                    component: "Device",
+                    feature: "the configuration",
+                });
+            }
+        }
+
+        sink.set_device(self.device);
+        sink.set_buffer_size(self.buffer_size);
+        if let Some(fade) = self.fade_in {
+            sink.set_fade_in(fade)?;
+        }
+        if let Some(fade) = self.fade_out {
+            sink.set_fade_out(fade)?;
+        }
+        if let Some(remaining) = self.prefetch_notify {
+            sink.set_prefetch_notify(remaining)?;
+        }
+        if let Some(played) = self.prefetch_notify_fraction {
+            sink.set_prefetch_notify_fraction(played)?;
+        }
+        sink.on_callback(self.callback)?;
+        sink.on_err_callback(self.err_callback)?;
+
+        if let Some(config) = self.config {
+            sink.open_stream(config)?;
+        }
+        if let Some(load) = self.load {
+            load(&mut sink)?;
+        }
+
+        Ok(sink)
+    }
+}
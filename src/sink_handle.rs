@@ -0,0 +1,386 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    err::{Error, Result},
+    shared::{CallbackInfo, SharedData, SourceState},
+    sink::WaitStatus,
+    source::Source,
+    Timestamp,
+};
+
+/// Cheap handle that controls the playback of [`crate::Sink`] from other
+/// threads, see [`crate::Sink::handle`].
+///
+/// The handle can control only the playback loop and resume the stream, the
+/// operations that need to create the stream or the device (e.g. loading
+/// sources or playing after [`crate::Sink::release_device`]) are available
+/// only on the sink. After the sink is dropped, the handle stays valid, but
+/// it has no effect.
+#[derive(Clone)]
+pub struct SinkHandle {
+    /// Data shared with the playback loop
+    shared: Arc<SharedData>,
+}
+
+impl SinkHandle {
+    /// Creates handle to the playback loop with the shared data
+    pub(crate) fn new(shared: Arc<SharedData>) -> Self {
+        Self { shared }
+    }
+
+    /// Resumes the playback if `play` is true, otherwise pauses the
+    /// playback. The stream paused with [`crate::Sink::hard_pause`] (or
+    /// automatically, see [`crate::Sink::set_auto_hard_pause`]) is resumed
+    /// asynchronously by the thread that owns it. Unlike
+    /// [`crate::Sink::play`], this doesn't create the stream.
+    ///
+    /// # Errors
+    /// - the stream was released and it must be created again with the sink,
+    ///   see [`crate::Sink::release_device`]
+    /// - too many requests for the stream are waiting
+    /// - another user of one of the used mutexes panicked while using it
+    pub fn play(&self, play: bool) -> Result<()> {
+        if play {
+            self.shared.set_idle(false);
+        }
+        self.shared.set_play(play);
+        if play {
+            self.shared.resume_stream()?;
+        }
+        Ok(())
+    }
+
+    /// Pauses the playback, see [`crate::Sink::pause`].
+    pub fn pause(&self) -> Result<()> {
+        self.shared.set_play(false);
+        Ok(())
+    }
+
+    /// Pauses the playback immediately without fade-out, see
+    /// [`crate::Sink::pause_now`].
+    pub fn pause_now(&self) -> Result<()> {
+        self.shared.set_play_now(false);
+        Ok(())
+    }
+
+    /// Returns true if the source is playing, otherwise returns false
+    pub fn is_playing(&self) -> Result<bool> {
+        Ok(self.shared.play())
+    }
+
+    /// Returns true if the source is playing, otherwise returns false. This
+    /// never blocks, see [`crate::Sink::try_is_playing`].
+    pub fn try_is_playing(&self) -> Result<bool> {
+        Ok(self.shared.play())
+    }
+
+    /// Sets the volume of the playback, see [`crate::Sink::volume`].
+    pub fn volume(&self, volume: f32) -> Result<()> {
+        self.shared.set_volume(volume);
+        Ok(())
+    }
+
+    /// Gets the volume of the playback, 0 = mute, 1 = full volume.
+    pub fn get_volume(&self) -> Result<f32> {
+        Ok(self.shared.volume())
+    }
+
+    /// Gets the volume of the playback. This never blocks, see
+    /// [`crate::Sink::try_get_volume`].
+    pub fn try_get_volume(&self) -> Result<f32> {
+        Ok(self.shared.volume())
+    }
+
+    /// Checks whether there is source loaded, see
+    /// [`crate::Sink::has_source`].
+    pub fn has_source(&self) -> Result<bool> {
+        Ok(self.shared.source_state() == SourceState::Loaded)
+    }
+
+    /// Checks whether the last loaded source has ended, see
+    /// [`crate::Sink::has_ended`].
+    pub fn has_ended(&self) -> Result<bool> {
+        Ok(self.shared.source_state() == SourceState::Ended)
+    }
+
+    /// Blocks the current thread until the current source ends, see
+    /// [`crate::Sink::wait_for_end`].
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn wait_for_end(
+        &self,
+        timeout: Option<Duration>,
+    ) -> Result<WaitStatus> {
+        self.shared
+            .wait_for_end(timeout.map(|t| Instant::now() + t))
+    }
+
+    /// Stops the playback and removes the current source and the queued
+    /// sources, see [`crate::Sink::stop`].
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn stop(&self) -> Result<()> {
+        let mut source = self.shared.source()?;
+        self.shared.set_play(false);
+        let src = source.take();
+        self.shared.set_source_state(SourceState::Empty);
+        self.shared.set_idle(false);
+        self.shared.take_deferred_load();
+        drop(source);
+        self.clear_queue()?;
+
+        if src.is_none() {
+            return Ok(());
+        }
+
+        if self.shared.stream_running() {
+            self.shared.set_stopping(src)
+        } else {
+            drop(src);
+            self.shared.invoke_callback(CallbackInfo::Stopped)
+        }
+    }
+
+    /// Pauses the playback and takes the current source out of the sink.
+    /// The queued sources are removed, see [`crate::Sink::unload`].
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn unload(&self) -> Result<Option<Box<dyn Source>>> {
+        let mut source = self.shared.source()?;
+        self.shared.set_play(false);
+        let src = source.take();
+        self.shared.set_source_state(SourceState::Empty);
+        self.shared.set_idle(false);
+        self.shared.take_deferred_load();
+        drop(source);
+        self.clear_queue()?;
+        Ok(src)
+    }
+
+    /// Sets the source that will play after the current source ends, see
+    /// [`crate::Sink::prefetch`].
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    /// - source fails to init
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn prefetch(&self, mut src: impl Source + 'static) -> Result<()> {
+        src.set_err_callback(&self.shared.source_err_callback());
+        let src = self.shared.init_queued(Box::new(src))?;
+        self.shared.prefetch_factory()?.set(None);
+        let mut queue = self.shared.prefetch()?;
+        queue.clear();
+        queue.push_back(src);
+        Ok(())
+    }
+
+    /// Adds source to the end of the queue, see [`crate::Sink::queue`].
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    /// - source fails to init
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn queue(&self, mut src: Box<dyn Source>) -> Result<usize> {
+        src.set_err_callback(&self.shared.source_err_callback());
+        let src = self.shared.init_queued(src)?;
+        self.shared.prefetch_factory()?.set(None);
+        let mut queue = self.shared.prefetch()?;
+        queue.push_back(src);
+        Ok(queue.len())
+    }
+
+    /// Removes all the queued sources, see [`crate::Sink::clear_queue`].
+    ///
+    /// # Errors
+    /// - another user of one of the used mutexes panicked while using it
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn clear_queue(&self) -> Result<()> {
+        self.shared.prefetch_factory()?.set(None);
+        self.shared.prefetch()?.clear();
+        Ok(())
+    }
+
+    /// Seeks to the given position, see [`crate::Sink::seek_to`].
+    ///
+    /// # Errors
+    /// - no source is playing
+    /// - the source doesn't support this
+    /// - failed to seek
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn seek_to(&self, timestamp: Duration) -> Result<Timestamp> {
+        if self.shared.controls()?.scrub.is_some() {
+            return self.scrub_to(|_| timestamp);
+        }
+        if let Some(t) = self.fade_seek(|_| timestamp)? {
+            return Ok(t);
+        }
+
+        Ok(self
+            .shared
+            .source()?
+            .as_mut()
+            .ok_or(Error::NoSourceIsPlaying)?
+            .seek(timestamp)?)
+    }
+
+    /// Seeks by the given amount, see [`crate::Sink::seek_by`].
+    ///
+    /// # Errors
+    /// - no source is playing
+    /// - the source doesn't support this
+    /// - failed to seek
+    ///
+    /// # Panics
+    /// - the current thread already locked one of the used mutexes and didn't
+    ///   release them
+    pub fn seek_by(&self, time: Duration, forward: bool) -> Result<Timestamp> {
+        let target = |cur: Duration| {
+            if forward {
+                cur + time
+            } else {
+                cur.saturating_sub(time)
+            }
+        };
+        if self.shared.controls()?.scrub.is_some() {
+            return self.scrub_to(target);
+        }
+        if let Some(t) = self.fade_seek(target)? {
+            return Ok(t);
+        }
+
+        Ok(self
+            .shared
+            .source()?
+            .as_mut()
+            .ok_or(Error::NoSourceIsPlaying)?
+            .seek_by(time, forward)?)
+    }
+
+    /// Gets the current timestamp and the total length of the currently
+    /// playing source, see [`crate::Sink::get_timestamp`].
+    ///
+    /// # Errors
+    /// - no source is playing
+    /// - the source doesn't support this
+    pub fn get_timestamp(&self) -> Result<Timestamp> {
+        self.shared
+            .source()?
+            .as_ref()
+            .ok_or(Error::NoSourceIsPlaying)?
+            .get_time()
+            .ok_or(Error::Unsupported {
+                component: "Source",
+                feature: "getting current timestamp",
+            })
+    }
+
+    /// Gets the current timestamp and the total length of the currently
+    /// playing source without waiting. If the source is used by the playback
+    /// loop, this returns the timestamp after the last read of the playback
+    /// loop.
+    ///
+    /// # Errors
+    /// - the source is locked and the playback loop didn't read it yet
+    /// - no source is playing
+    /// - the source doesn't support this
+    /// - another user of one of the used mutexes panicked while using it
+    pub fn try_get_timestamp(&self) -> Result<Timestamp> {
+        let src = match self.shared.try_source() {
+            Ok(src) => src,
+            Err(Error::WouldBlock) => {
+                return self.shared.cached_time().ok_or(Error::WouldBlock)
+            }
+            Err(e) => return Err(e),
+        };
+        src.as_ref()
+            .ok_or(Error::NoSourceIsPlaying)?
+            .get_time()
+            .ok_or(Error::Unsupported {
+                component: "Source",
+                feature: "getting current timestamp",
+            })
+    }
+
+    /// Sets the scrub target to the position returned by `target` for the
+    /// last target (or the current position if there is no target).
+    fn scrub_to(
+        &self,
+        target: impl FnOnce(Duration) -> Duration,
+    ) -> Result<Timestamp> {
+        let time = self.get_timestamp()?;
+        let mut scrub = self.shared.scrub_target()?;
+        // The position moves with the played bursts, so relative seeks are
+        // from the last target.
+        let target = target(scrub.last.unwrap_or(time.current));
+        scrub.pending = Some(target);
+        scrub.last = Some(target);
+        Ok(Timestamp::new(target, time.total))
+    }
+
+    /// Sets the pending seek of the playback loop to the position returned
+    /// by `target` for the current pending seek (or the current position).
+    /// Returns [`None`] if the seek should be immediate, because the seek
+    /// fade is disabled, the playback is paused or the source doesn't
+    /// report its position.
+    fn fade_seek(
+        &self,
+        target: impl FnOnce(Duration) -> Duration,
+    ) -> Result<Option<Timestamp>> {
+        let mut controls = self.shared.controls()?;
+        if controls.seek_fade.is_zero()
+            || !self.shared.play()
+            || !self.shared.stream_running()
+        {
+            return Ok(None);
+        }
+
+        let Some(time) = self
+            .shared
+            .source()?
+            .as_ref()
+            .ok_or(Error::NoSourceIsPlaying)?
+            .get_time()
+        else {
+            return Ok(None);
+        };
+        let target = target(controls.pending_seek.unwrap_or(time.current));
+        controls.pending_seek = Some(target);
+        Ok(Some(Timestamp::new(target, time.total)))
+    }
+}
+
+impl std::fmt::Debug for SinkHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SinkHandle").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,287 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use cpal::{FromSample, SampleFormat};
+
+use crate::{
+    converters::{channels::ChannelConverter, rate::RateConverter, UniSample},
+    operate_samples,
+    sample_buffer::SampleBufferMut,
+    SourceSpec, Timestamp,
+};
+
+use super::{chain::mul_volume, DeviceConfig, Source, VolumeIterator};
+
+/// Converted samples ready for the device.
+type Converter = RateConverter<f32, ChannelConverter<f32, Samples>>;
+
+/// Source that plays interleaved samples from memory. The samples are shared
+/// with [`Arc`], so cloning the source (e.g. to play the same sound multiple
+/// times) doesn't copy them.
+pub struct BufferSource {
+    /// The interleaved samples
+    data: Arc<[f32]>,
+    /// Number of channels of the samples
+    channels: u32,
+    /// Sample rate of the samples
+    sample_rate: u32,
+    /// Number of channels of the result
+    target_channels: u32,
+    /// Sample rate of the result
+    target_sample_rate: u32,
+    /// The frame in the samples where the converter starts
+    start: u64,
+    /// Number of samples produced by the converter
+    produced: u64,
+    /// Converts the samples to the target config, [`None`] if it should be
+    /// recreated. It is not used if the config matches.
+    converter: Option<Converter>,
+    /// Creates multiplier for each sample
+    volume: VolumeIterator,
+}
+
+impl Source for BufferSource {
+    fn init(&mut self, info: &DeviceConfig) -> Result<()> {
+        // Keep the position when the config changes.
+        self.start = self.frame();
+        self.produced = 0;
+        self.converter = None;
+        self.target_channels = info.channel_count;
+        self.target_sample_rate = info.sample_rate;
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut SampleBufferMut) -> (usize, Result<()>) {
+        if self.config_matches() {
+            if let Some(b) = buffer.as_f32_mut() {
+                return (self.copy_samples(b), Ok(()));
+            }
+        }
+        operate_samples!(buffer, b, (self.read_samples(b), Ok(())))
+    }
+
+    fn preferred_config(&mut self) -> Option<DeviceConfig> {
+        Some(DeviceConfig {
+            channel_count: self.channels,
+            sample_rate: self.sample_rate,
+            sample_format: SampleFormat::F32,
+        })
+    }
+
+    fn volume(&mut self, volume: VolumeIterator) -> bool {
+        self.volume = volume;
+        true
+    }
+
+    fn seek(&mut self, time: Duration) -> Result<Timestamp> {
+        let frame = (time.as_secs_f64() * self.sample_rate as f64).round();
+        self.start = (frame as u64).min(self.frames());
+        self.produced = 0;
+        self.converter = None;
+        Ok(self.timestamp())
+    }
+
+    fn source_spec(&self) -> Option<SourceSpec> {
+        Some(SourceSpec {
+            channel_count: self.channels,
+            sample_rate: self.sample_rate,
+            bits_per_sample: Some(32),
+            codec: None,
+        })
+    }
+
+    fn get_time(&self) -> Option<Timestamp> {
+        Some(self.timestamp())
+    }
+}
+
+impl Clone for BufferSource {
+    /// Creates new source with the same samples, playing from the start.
+    fn clone(&self) -> Self {
+        Self::from_arc(self.data.clone(), self.channels, self.sample_rate)
+    }
+}
+
+impl BufferSource {
+    /// Creates source that plays the given interleaved samples with the
+    /// given number of channels and sample rate.
+    ///
+    /// # Panics
+    /// - `channels` or `sample_rate` is 0
+    pub fn new(samples: Vec<f32>, channels: u32, sample_rate: u32) -> Self {
+        Self::from_arc(samples.into(), channels, sample_rate)
+    }
+
+    /// Creates source that plays the given interleaved samples of any type.
+    /// The samples are converted to [`f32`].
+    ///
+    /// # Panics
+    /// - `channels` or `sample_rate` is 0
+    pub fn from_samples<S>(
+        samples: &[S],
+        channels: u32,
+        sample_rate: u32,
+    ) -> Self
+    where
+        S: cpal::Sample,
+        f32: FromSample<S>,
+    {
+        Self::from_arc(
+            samples.iter().map(|s| f32::from_sample_(*s)).collect(),
+            channels,
+            sample_rate,
+        )
+    }
+
+    /// Creates source that plays the given shared interleaved samples.
+    ///
+    /// # Panics
+    /// - `channels` or `sample_rate` is 0
+    pub fn from_arc(
+        samples: Arc<[f32]>,
+        channels: u32,
+        sample_rate: u32,
+    ) -> Self {
+        assert!(channels != 0, "channel count must not be 0");
+        assert!(sample_rate != 0, "sample rate must not be 0");
+        Self {
+            data: samples,
+            channels,
+            sample_rate,
+            target_channels: 0,
+            target_sample_rate: 0,
+            start: 0,
+            produced: 0,
+            converter: None,
+            volume: VolumeIterator::constant(1.),
+        }
+    }
+
+    /// Gets the shared samples.
+    pub fn samples(&self) -> &Arc<[f32]> {
+        &self.data
+    }
+
+    /// Reads the converted samples to the buffer. Returns number of written
+    /// samples.
+    fn read_samples<T: UniSample>(&mut self, buffer: &mut [T]) -> usize
+    where
+        T::Float: From<f32>,
+    {
+        if self.target_channels == 0 {
+            return 0;
+        }
+
+        let len = buffer.len() - buffer.len() % self.target_channels as usize;
+        let start = self.start as usize * self.channels as usize;
+        let cnt = if self.config_matches() {
+            // The config matches, don't convert the channels and rate.
+            let src = &self.data[self.position()..];
+            let cnt = len.min(src.len());
+            for (d, s) in buffer[..cnt].iter_mut().zip(src) {
+                *d = T::from_sample(*s).mul_amp(self.volume.next_vol().into());
+            }
+            cnt
+        } else {
+            // The converter is not boxed, so recreating it doesn't allocate.
+            let conv = self.converter.get_or_insert_with(|| {
+                RateConverter::new(
+                    ChannelConverter::new(
+                        Samples {
+                            data: self.data.clone(),
+                            pos: start,
+                        },
+                        self.channels,
+                        self.target_channels,
+                    ),
+                    self.sample_rate,
+                    self.target_sample_rate,
+                )
+            });
+
+            let mut cnt = 0;
+            for (d, s) in buffer[..len].iter_mut().zip(conv) {
+                *d = T::from_sample(s).mul_amp(self.volume.next_vol().into());
+                cnt += 1;
+            }
+            cnt
+        };
+        self.produced += cnt as u64;
+        cnt
+    }
+
+    /// Copies the samples directly to the buffer. Can be used only if the
+    /// config matches. Returns the number of written samples.
+    fn copy_samples(&mut self, buffer: &mut [f32]) -> usize {
+        let len = buffer.len() - buffer.len() % self.target_channels as usize;
+        let src = &self.data[self.position()..];
+        let cnt = len.min(src.len());
+        buffer[..cnt].copy_from_slice(&src[..cnt]);
+        if !self.volume.is_unity() {
+            mul_volume(&mut buffer[..cnt], &mut self.volume);
+        }
+        self.produced += cnt as u64;
+        cnt
+    }
+
+    /// Checks whether the target config matches the samples, so that they
+    /// don't have to be converted.
+    fn config_matches(&self) -> bool {
+        self.channels == self.target_channels
+            && self.sample_rate == self.target_sample_rate
+    }
+
+    /// Gets the index of the next sample when the config matches.
+    fn position(&self) -> usize {
+        let start = self.start as usize * self.channels as usize;
+        (start + self.produced as usize).min(self.data.len())
+    }
+
+    /// Gets the total number of frames.
+    fn frames(&self) -> u64 {
+        (self.data.len() / self.channels as usize) as u64
+    }
+
+    /// Gets the current frame in the samples.
+    fn frame(&self) -> u64 {
+        if self.target_channels == 0 {
+            return self.start;
+        }
+        let out = self.produced / self.target_channels as u64;
+        let frame = self.start
+            + out * self.sample_rate as u64 / self.target_sample_rate as u64;
+        frame.min(self.frames())
+    }
+
+    /// Gets the current position and the total duration.
+    fn timestamp(&self) -> Timestamp {
+        let rate = self.sample_rate as f64;
+        Timestamp::new(
+            Duration::from_secs_f64(self.frame() as f64 / rate),
+            Duration::from_secs_f64(self.frames() as f64 / rate),
+        )
+    }
+}
+
+/// Iterator over the shared samples.
+struct Samples {
+    /// The samples
+    data: Arc<[f32]>,
+    /// Index of the next sample
+    pos: usize,
+}
+
+impl Iterator for Samples {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let res = self.data.get(self.pos).copied();
+        self.pos += res.is_some() as usize;
+        res
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = self.data.len().saturating_sub(self.pos);
+        (n, Some(n))
+    }
+}
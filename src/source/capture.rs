@@ -0,0 +1,202 @@
+use std::{
+    sync::{
+        mpsc::{self, Sender},
+        Arc, Mutex, PoisonError,
+    },
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    Device, SampleFormat, SizedSample, Stream, StreamConfig,
+};
+
+use crate::{
+    callback::Callback, converters::convert_sample, thread, Error, SourceSpec,
+};
+
+use super::{
+    DeviceConfig, Source, StreamProducer, StreamSource, VolumeIterator,
+};
+
+/// Source that plays audio captured from input device (e.g. microphone).
+///
+/// The input stream is started when the source is created and it is stopped
+/// when the source is dropped. The captured audio is delayed by the latency
+/// given in [`CaptureSource::new`] to compensate for the differences in the
+/// timing of the input and the output. Underruns and overruns are reported
+/// to the error callback.
+pub struct CaptureSource {
+    /// Plays the captured samples
+    stream: StreamSource,
+    /// Stops the capture thread when dropped
+    _stop: Sender<()>,
+    /// Callback for the errors of the input stream
+    err_callback: Arc<Mutex<Callback<Error>>>,
+}
+
+impl Source for CaptureSource {
+    fn set_err_callback(&mut self, err_callback: &Callback<Error>) {
+        *self
+            .err_callback
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = err_callback.clone();
+        self.stream.set_err_callback(err_callback);
+    }
+
+    fn init(&mut self, info: &DeviceConfig) -> Result<()> {
+        self.stream.init(info)
+    }
+
+    fn read(
+        &mut self,
+        buffer: &mut crate::sample_buffer::SampleBufferMut,
+    ) -> (usize, Result<()>) {
+        self.stream.read(buffer)
+    }
+
+    fn preferred_config(&mut self) -> Option<DeviceConfig> {
+        self.stream.preferred_config()
+    }
+
+    fn volume(&mut self, volume: VolumeIterator) -> bool {
+        self.stream.volume(volume)
+    }
+
+    fn source_spec(&self) -> Option<SourceSpec> {
+        self.stream.source_spec()
+    }
+}
+
+impl CaptureSource {
+    /// Creates source that captures audio from the given input device and
+    /// starts the capture. If `device` is [`None`], the default input
+    /// device is used. `latency` is the delay of the captured audio.
+    ///
+    /// # Errors
+    /// - there is no default input device
+    /// - failed to get the configuration of the device
+    /// - failed to start the input stream
+    pub fn new(
+        device: Option<Device>,
+        latency: Duration,
+    ) -> crate::err::Result<Self> {
+        let device = match device {
+            Some(d) => d,
+            None => cpal::default_host()
+                .default_input_device()
+                .ok_or(Error::NoInDevice)?,
+        };
+        let supported = device.default_input_config()?;
+        let format = supported.sample_format();
+        let config = supported.config();
+        let channels = config.channels as u32;
+        let rate = config.sample_rate.0;
+
+        // Buffer twice the latency so that there is space for the jitter.
+        let latency = (latency.as_secs_f64() * rate as f64).round() as usize;
+        let (mut producer, stream) = StreamSource::new(
+            channels,
+            rate,
+            (latency * 2).max(rate as usize),
+        );
+        producer.write(&vec![0.; latency * channels as usize]);
+
+        let err_callback = Arc::new(Mutex::new(Callback::default()));
+        let (res_send, res_recv) = mpsc::channel();
+        let (stop_send, stop_recv) = mpsc::channel::<()>();
+        let errs = err_callback.clone();
+        thread::spawn("capture", false, move || {
+            let stream =
+                build_input_stream(&device, &config, format, producer, errs)
+                    .and_then(|s| {
+                        s.play().map_err(Error::from)?;
+                        Ok(s)
+                    });
+            match stream {
+                Ok(s) => {
+                    _ = res_send.send(Ok(()));
+                    // Wait until the source is dropped.
+                    _ = stop_recv.recv();
+                    drop(s);
+                }
+                Err(e) => _ = res_send.send(Err(e)),
+            }
+        })?;
+
+        res_recv.recv().map_err(|_| {
+            Error::Other(anyhow!("The capture thread panicked"))
+        })??;
+
+        Ok(Self {
+            stream,
+            _stop: stop_send,
+            err_callback,
+        })
+    }
+}
+
+/// Reports error of the input stream to the current error callback.
+fn report(err_callback: &Mutex<Callback<Error>>, err: Error) {
+    // Don't hold the lock while the callback runs.
+    let callback = err_callback
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    _ = callback.invoke(err);
+}
+
+/// Builds the input stream that writes the samples to `producer`.
+fn build_input_stream(
+    device: &Device,
+    config: &StreamConfig,
+    format: SampleFormat,
+    producer: StreamProducer,
+    err_callback: Arc<Mutex<Callback<Error>>>,
+) -> crate::err::Result<Stream> {
+    let build = match format {
+        SampleFormat::I8 => build_typed::<i8>,
+        SampleFormat::I16 => build_typed::<i16>,
+        SampleFormat::I32 => build_typed::<i32>,
+        SampleFormat::I64 => build_typed::<i64>,
+        SampleFormat::U8 => build_typed::<u8>,
+        SampleFormat::U16 => build_typed::<u16>,
+        SampleFormat::U32 => build_typed::<u32>,
+        SampleFormat::U64 => build_typed::<u64>,
+        SampleFormat::F32 => build_typed::<f32>,
+        SampleFormat::F64 => build_typed::<f64>,
+        _ => return Err(Error::UnsupportedSampleFormat),
+    };
+    build(device, config, producer, err_callback)
+}
+
+/// Builds the input stream with the sample type `T`.
+fn build_typed<T>(
+    device: &Device,
+    config: &StreamConfig,
+    mut producer: StreamProducer,
+    err_callback: Arc<Mutex<Callback<Error>>>,
+) -> crate::err::Result<Stream>
+where
+    T: SizedSample,
+    f32: cpal::FromSample<T>,
+{
+    let stream_err = err_callback.clone();
+    let mut buffer = vec![];
+    let mut overrun = false;
+    Ok(device.build_input_stream(
+        config,
+        move |d: &[T], _| {
+            buffer.clear();
+            buffer.extend(d.iter().map(|s| convert_sample::<_, f32>(*s)));
+            let full = producer.write(&buffer) < buffer.len();
+            if full && !overrun {
+                report(&err_callback, Error::CaptureOverrun);
+            }
+            overrun = full;
+        },
+        move |e| report(&stream_err, e.into()),
+        None,
+    )?)
+}
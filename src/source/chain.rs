@@ -0,0 +1,273 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use cpal::Sample;
+
+use crate::{
+    callback::Callback, operate_samples, sample_buffer::SampleBufferMut,
+    Error, SourceSpec, Timestamp,
+};
+
+use super::{DeviceConfig, InitContext, Source, VolumeIterator};
+
+/// Source that plays the given sources one after another without any gap.
+/// The sink sees all the sources as one continuous source.
+#[derive(Default)]
+pub struct ChainSource {
+    /// The chained sources
+    sources: Vec<Box<dyn Source>>,
+    /// Index of the source that currently plays
+    current: usize,
+    /// Number of sources that were already started at least once
+    started: usize,
+    /// True if the current source is initialized
+    ready: bool,
+    /// True if the current source ended and it is being flushed
+    flushing: bool,
+    /// The last configuration given to [`Source::init_with`]
+    ctx: Option<InitContext>,
+    /// Callback for errors given to all the sources
+    err_callback: Option<Callback<Error>>,
+    /// Creates multiplier for each sample
+    volume: VolumeIterator,
+}
+
+impl Source for ChainSource {
+    fn set_err_callback(&mut self, err_callback: &Callback<Error>) {
+        for s in &mut self.sources {
+            s.set_err_callback(err_callback);
+        }
+        self.err_callback = Some(err_callback.clone());
+    }
+
+    fn init(&mut self, info: &DeviceConfig) -> Result<()> {
+        self.init_with(&InitContext::new(info.clone(), None, None))
+    }
+
+    fn init_with(&mut self, ctx: &InitContext) -> Result<()> {
+        self.ctx = Some(ctx.clone());
+        if self.ready {
+            if let Some(s) = self.sources.get_mut(self.current) {
+                s.init_with(ctx)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut SampleBufferMut) -> (usize, Result<()>) {
+        let (n, res) = self.read_sources(buffer);
+        self.apply_volume(&mut buffer.slice(..n));
+        (n, res)
+    }
+
+    fn flush(&mut self, buffer: &mut SampleBufferMut) -> usize {
+        let Some(s) = self.sources.get_mut(self.current) else {
+            return 0;
+        };
+        let n = s.flush(buffer);
+        self.apply_volume(&mut buffer.slice(..n));
+        n
+    }
+
+    fn preferred_config(&mut self) -> Option<DeviceConfig> {
+        self.sources.get_mut(self.current)?.preferred_config()
+    }
+
+    fn volume(&mut self, volume: VolumeIterator) -> bool {
+        self.volume = volume;
+        true
+    }
+
+    fn seek(&mut self, time: Duration) -> Result<Timestamp> {
+        let Some(totals) = self.totals() else {
+            return Err(Error::Unsupported {
+                component: "ChainSource",
+                feature: "seeking without known lengths",
+            }
+            .into());
+        };
+
+        let mut start = Duration::ZERO;
+        let mut idx = totals.len().saturating_sub(1);
+        for (i, t) in totals.iter().enumerate() {
+            if time < start + *t || i == idx {
+                idx = i;
+                break;
+            }
+            start += *t;
+        }
+
+        if idx >= self.sources.len() {
+            return Ok(Timestamp::new(Duration::ZERO, Duration::ZERO));
+        }
+
+        self.current = idx;
+        self.flushing = false;
+        self.start_current(false)?;
+        self.sources[idx].seek(time.saturating_sub(start))?;
+        self.get_time()
+            .ok_or(Error::CannotDetermineTimestamp.into())
+    }
+
+    fn take_spec_change(&mut self) -> Option<(SourceSpec, SourceSpec)> {
+        self.sources.get_mut(self.current)?.take_spec_change()
+    }
+
+    fn wants_reconfigure(&self) -> Option<DeviceConfig> {
+        self.sources.get(self.current)?.wants_reconfigure()
+    }
+
+    fn source_spec(&self) -> Option<SourceSpec> {
+        self.sources.get(self.current)?.source_spec()
+    }
+
+    fn get_time(&self) -> Option<Timestamp> {
+        // Called on the audio thread, so it must not allocate.
+        let mut before = Duration::ZERO;
+        let mut total = Duration::ZERO;
+        for (i, s) in self.sources.iter().enumerate() {
+            let len = s.get_time()?.total;
+            if i < self.current {
+                before += len;
+            }
+            total += len;
+        }
+        let current = self
+            .sources
+            .get(self.current)
+            .map_or(Some(Duration::ZERO), |s| {
+                s.get_time().map(|t| t.current)
+            })?;
+        Some(Timestamp::new(before + current, total))
+    }
+}
+
+impl ChainSource {
+    /// Creates source that plays the given sources one after another.
+    pub fn new(sources: Vec<Box<dyn Source>>) -> Self {
+        let mut res = Self::default();
+        for s in sources {
+            res.push(s);
+        }
+        res
+    }
+
+    /// Adds source to the end of the chain.
+    pub fn push(&mut self, src: impl Source + 'static) {
+        let mut src: Box<dyn Source> = Box::new(src);
+        if let Some(cb) = &self.err_callback {
+            src.set_err_callback(cb);
+        }
+        self.sources.push(src);
+    }
+
+    /// Gets the number of chained sources.
+    pub fn len(&self) -> usize {
+        self.sources.len()
+    }
+
+    /// Checks if there are no chained sources.
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    /// Gets the index of the source that currently plays.
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// Reads from the sources until the buffer is full or the last source
+    /// ends. Returns the number of written samples.
+    fn read_sources(
+        &mut self,
+        buffer: &mut SampleBufferMut,
+    ) -> (usize, Result<()>) {
+        let len = buffer.len();
+        let mut pos = 0;
+        loop {
+            if self.current >= self.sources.len() {
+                return (pos, Ok(()));
+            }
+
+            if !self.ready {
+                if let Err(e) = self.start_current(true) {
+                    // Skip the source that failed to start.
+                    self.current += 1;
+                    return (pos, Err(e));
+                }
+            }
+
+            let last = self.current + 1 == self.sources.len();
+            let src = &mut self.sources[self.current];
+            if !self.flushing {
+                let (n, res) = src.read(&mut buffer.slice(pos..));
+                pos += n;
+                if res.is_err() || pos == len {
+                    return (pos, res);
+                }
+                if last {
+                    // The last source is flushed by the sink.
+                    return (pos, Ok(()));
+                }
+                self.flushing = true;
+            }
+
+            loop {
+                let n = src.flush(&mut buffer.slice(pos..));
+                pos += n;
+                if pos == len {
+                    return (pos, Ok(()));
+                }
+                if n == 0 {
+                    break;
+                }
+            }
+
+            self.flushing = false;
+            self.ready = false;
+            self.current += 1;
+        }
+    }
+
+    /// Initializes the current source. If `rewind` is true and the source
+    /// already played, it is seeked to the start.
+    fn start_current(&mut self, rewind: bool) -> Result<()> {
+        self.ready = false;
+        let src = &mut self.sources[self.current];
+        if let Some(ctx) = &self.ctx {
+            src.init_with(ctx)?;
+        }
+        if rewind && self.current < self.started {
+            src.seek(Duration::ZERO)?;
+        }
+        self.started = self.started.max(self.current + 1);
+        self.ready = true;
+        Ok(())
+    }
+
+    /// Gets the total lengths of all the sources, [`None`] if any of the
+    /// lengths is unknown.
+    fn totals(&self) -> Option<Vec<Duration>> {
+        self.sources
+            .iter()
+            .map(|s| s.get_time().map(|t| t.total))
+            .collect()
+    }
+
+    /// Applies the volume to the samples.
+    fn apply_volume(&mut self, buffer: &mut SampleBufferMut) {
+        operate_samples!(buffer, b, mul_volume(b, &mut self.volume))
+    }
+}
+
+/// Multiplies the samples by the volume.
+pub(super) fn mul_volume<T: Sample>(
+    data: &mut [T],
+    volume: &mut VolumeIterator,
+) where
+    T::Float: From<f32>,
+{
+    for s in data {
+        *s = s.mul_amp(volume.next_vol().into());
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    f32::consts::PI,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::Result;
+use cpal::FromSample;
+
+use crate::{operate_samples, sample_buffer::SampleBufferMut};
+
+use super::{DeviceConfig, Source, VolumeIterator};
+
+/// Test signal that plays gated tone only on single output channel, e.g. to
+/// identify the speakers. The channels are in the order of the device.
+pub struct ChannelId {
+    /// Index of the channel with the tone, shared with the handles
+    active: Arc<AtomicUsize>,
+    /// Frequency of the tone
+    frequency: f32,
+    /// How long the tone plays in each period
+    on: Duration,
+    /// How long is silence in each period
+    off: Duration,
+    /// Number of channels of the result
+    channels: usize,
+    /// How much to step on the x axis for each frame
+    iter_step: f32,
+    /// The x axis of the sine function
+    iter: f32,
+    /// Number of frames of the tone in each period
+    on_frames: usize,
+    /// Number of frames in each period
+    period_frames: usize,
+    /// Index of the current frame in the period
+    frame: usize,
+    /// Creates multiplier for each sample
+    volume: VolumeIterator,
+}
+
+/// Handle that changes the active channel of [`ChannelId`] while it plays
+#[derive(Debug, Clone)]
+pub struct ChannelIdHandle(Arc<AtomicUsize>);
+
+impl Source for ChannelId {
+    fn init(&mut self, info: &DeviceConfig) -> Result<()> {
+        let rate = info.sample_rate as f32;
+        self.channels = info.channel_count as usize;
+        self.iter_step = 2. * PI * self.frequency / rate;
+        self.on_frames = (self.on.as_secs_f32() * rate) as usize;
+        self.period_frames =
+            self.on_frames + (self.off.as_secs_f32() * rate) as usize;
+        self.frame = 0;
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut SampleBufferMut) -> (usize, Result<()>) {
+        operate_samples!(buffer, b, {
+            self.generate(b);
+            (b.len(), Ok(()))
+        })
+    }
+
+    fn volume(&mut self, volume: VolumeIterator) -> bool {
+        self.volume = volume;
+        true
+    }
+}
+
+impl ChannelId {
+    /// Creates source that plays tone with the frequency `tone_hz` on the
+    /// output channel `active_channel`. The tone plays for `on` and than
+    /// there is silence for `off`, this repeats infinitely. If `off` is
+    /// zero, the tone plays continuously.
+    pub fn new(
+        active_channel: usize,
+        tone_hz: f32,
+        on: Duration,
+        off: Duration,
+    ) -> Self {
+        Self {
+            active: Arc::new(AtomicUsize::new(active_channel)),
+            frequency: tone_hz,
+            on,
+            off,
+            channels: 0,
+            iter_step: 0.,
+            iter: 0.,
+            on_frames: 0,
+            period_frames: 0,
+            frame: 0,
+            volume: VolumeIterator::constant(1.),
+        }
+    }
+
+    /// Gets handle that can change the active channel while the source
+    /// plays.
+    pub fn handle(&self) -> ChannelIdHandle {
+        ChannelIdHandle(self.active.clone())
+    }
+
+    /// Generates the gated tone on the active channel
+    fn generate<T: FromSample<f32> + Clone>(&mut self, data: &mut [T]) {
+        if self.channels == 0 {
+            data.fill(T::from_sample_(0.));
+            return;
+        }
+
+        let active = self.active.load(Ordering::Relaxed);
+        for frame in data.chunks_mut(self.channels) {
+            let tone = self.frame < self.on_frames;
+            let val = if tone { self.iter.sin() } else { 0. };
+            for (i, s) in frame.iter_mut().enumerate() {
+                let vol = self.volume.next_vol();
+                *s = T::from_sample_(if i == active { val * vol } else { 0. });
+            }
+
+            self.iter += self.iter_step;
+            if self.iter > 2. * PI {
+                self.iter -= 2. * PI
+            }
+            self.frame += 1;
+            if self.frame >= self.period_frames {
+                // Start each tone at zero crossing
+                self.frame = 0;
+                self.iter = 0.;
+            }
+        }
+    }
+}
+
+impl ChannelIdHandle {
+    /// Sets the channel that plays the tone. Indexes out of the range of the
+    /// device channels produce silence.
+    pub fn set_channel(&self, channel: usize) {
+        self.0.store(channel, Ordering::Relaxed);
+    }
+
+    /// Gets the channel that plays the tone.
+    pub fn channel(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
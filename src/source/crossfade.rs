@@ -0,0 +1,470 @@
+use std::{f32::consts::FRAC_PI_2, time::Duration};
+
+use anyhow::Result;
+use cpal::{FromSample, Sample};
+
+use crate::{
+    callback::Callback,
+    operate_samples,
+    sample_buffer::{BufferSample, SampleBuffer, SampleBufferMut},
+    Error, SourceSpec, Timestamp,
+};
+
+use super::{
+    chain::mul_volume, DeviceConfig, InitContext, Source, VolumeIterator,
+};
+
+/// Number of frames of the scratch buffer if the buffer size of the output
+/// is not known
+const SCRATCH_FRAMES: usize = 1024;
+
+/// Which part of the crossfade plays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    /// Only the first source plays.
+    First,
+    /// The sources overlap, contains the number of frames since the start
+    /// of the overlap.
+    Overlap(usize),
+    /// Only the second source plays.
+    Second,
+}
+
+/// Source that plays the first source and during its last `overlap` fades it
+/// out while fading in the second source. Then it continues with the second
+/// source. The fade uses equal power gain curves.
+///
+/// The start of the overlap is determined from [`Source::get_time`] of the
+/// first source. If the first source doesn't report its time, the second
+/// source starts when the first source ends.
+///
+/// During the overlap, [`Source::get_time`] reports the second source (from
+/// the start of the overlap) and [`Source::outgoing_time`] reports the first
+/// source. [`Source::take_outgoing_ended`] returns true after the first
+/// source finished fading out.
+pub struct CrossFadeSource {
+    /// The source that fades out
+    first: Box<dyn Source>,
+    /// The source that fades in
+    second: Box<dyn Source>,
+    /// Duration of the overlap
+    overlap: Duration,
+    /// The current part of the crossfade
+    phase: Phase,
+    /// True if the first source already ended
+    first_ended: bool,
+    /// True if the first source finished playing (it ended or faded out)
+    first_done: bool,
+    /// True if the first source finished and it wasn't reported yet
+    outgoing_ended: bool,
+    /// True if the second source was already read
+    second_started: bool,
+    /// The configuration of the output
+    config: Option<DeviceConfig>,
+    /// Buffer into which the second source is read during the overlap
+    scratch: Option<SampleBuffer>,
+    /// Creates multiplier for each sample
+    volume: VolumeIterator,
+}
+
+impl Source for CrossFadeSource {
+    fn set_err_callback(&mut self, err_callback: &Callback<Error>) {
+        self.first.set_err_callback(err_callback);
+        self.second.set_err_callback(err_callback);
+    }
+
+    fn init(&mut self, info: &DeviceConfig) -> Result<()> {
+        self.init_with(&InitContext::new(info.clone(), None, None))
+    }
+
+    fn init_with(&mut self, ctx: &InitContext) -> Result<()> {
+        self.config = Some(ctx.config.clone());
+        let frames = ctx
+            .buffer_duration
+            .map_or(SCRATCH_FRAMES, |d| self.to_frames(d).max(1));
+        let len = frames * ctx.config.channel_count.max(1) as usize;
+        // Allocate the scratch buffer here, so that it is not allocated in
+        // the playback loop.
+        if !self.scratch.as_ref().is_some_and(|s| {
+            s.format() == ctx.config.sample_format && s.len() == len
+        }) {
+            self.scratch = Some(
+                SampleBuffer::silent(ctx.config.sample_format, len)
+                    .ok_or(Error::UnsupportedSampleFormat)?,
+            );
+        }
+        if self.phase != Phase::Second {
+            self.first.init_with(ctx)?;
+        }
+        self.second.init_with(ctx)
+    }
+
+    fn read(&mut self, buffer: &mut SampleBufferMut) -> (usize, Result<()>) {
+        let (n, res) = self.read_sources(buffer);
+        operate_samples!(
+            buffer.slice(..n),
+            b,
+            mul_volume(b, &mut self.volume)
+        );
+        (n, res)
+    }
+
+    fn flush(&mut self, buffer: &mut SampleBufferMut) -> usize {
+        if self.phase != Phase::Second {
+            return 0;
+        }
+        let n = self.second.flush(buffer);
+        operate_samples!(
+            buffer.slice(..n),
+            b,
+            mul_volume(b, &mut self.volume)
+        );
+        n
+    }
+
+    fn preferred_config(&mut self) -> Option<DeviceConfig> {
+        self.current_mut().preferred_config()
+    }
+
+    fn volume(&mut self, volume: VolumeIterator) -> bool {
+        self.volume = volume;
+        true
+    }
+
+    fn seek(&mut self, time: Duration) -> Result<Timestamp> {
+        let (Some(first), Some(_)) =
+            (self.first.get_time(), self.second.get_time())
+        else {
+            return Err(Error::Unsupported {
+                component: "CrossFadeSource",
+                feature: "seeking without known lengths",
+            }
+            .into());
+        };
+
+        let start = first.total.saturating_sub(self.overlap);
+        self.first_ended = false;
+        self.first_done = time >= first.total;
+        self.outgoing_ended = false;
+        if time < start {
+            self.first.seek(time)?;
+            if self.second_started {
+                self.second.seek(Duration::ZERO)?;
+                self.second_started = false;
+            }
+            self.phase = Phase::First;
+        } else if time < first.total {
+            self.first.seek(time)?;
+            self.second.seek(time - start)?;
+            self.second_started = true;
+            self.phase = Phase::Overlap(self.to_frames(time - start));
+        } else {
+            self.second.seek(time - start)?;
+            self.second_started = true;
+            self.phase = Phase::Second;
+        }
+
+        self.get_time()
+            .ok_or(Error::CannotDetermineTimestamp.into())
+    }
+
+    fn take_spec_change(&mut self) -> Option<(SourceSpec, SourceSpec)> {
+        self.current_mut().take_spec_change()
+    }
+
+    fn wants_reconfigure(&self) -> Option<DeviceConfig> {
+        self.current().wants_reconfigure()
+    }
+
+    fn source_spec(&self) -> Option<SourceSpec> {
+        self.current().source_spec()
+    }
+
+    fn outgoing_time(&self) -> Option<Timestamp> {
+        if matches!(self.phase, Phase::Overlap(_)) && !self.first_done {
+            self.first.get_time()
+        } else {
+            None
+        }
+    }
+
+    fn take_outgoing_ended(&mut self) -> bool {
+        std::mem::take(&mut self.outgoing_ended)
+    }
+
+    fn get_time(&self) -> Option<Timestamp> {
+        let first = self.first.get_time()?;
+        let second = self.second.get_time()?;
+        let start = first.total.saturating_sub(self.overlap);
+        let current = if self.phase == Phase::First {
+            first.current
+        } else {
+            start + second.current
+        };
+        Some(Timestamp::new(current, start + second.total))
+    }
+}
+
+impl CrossFadeSource {
+    /// Creates source that crossfades from `first` to `second` during the
+    /// last `overlap` of `first`.
+    pub fn new(
+        first: impl Source + 'static,
+        second: impl Source + 'static,
+        overlap: Duration,
+    ) -> Self {
+        Self {
+            first: Box::new(first),
+            second: Box::new(second),
+            overlap,
+            phase: Phase::First,
+            first_ended: false,
+            first_done: false,
+            outgoing_ended: false,
+            second_started: false,
+            config: None,
+            scratch: None,
+            volume: VolumeIterator::constant(1.),
+        }
+    }
+
+    /// Gets the source that plays (or the one that fades out during the
+    /// overlap).
+    fn current(&self) -> &dyn Source {
+        if self.phase == Phase::Second {
+            self.second.as_ref()
+        } else {
+            self.first.as_ref()
+        }
+    }
+
+    /// Gets the source that plays (or the one that fades out during the
+    /// overlap).
+    fn current_mut(&mut self) -> &mut dyn Source {
+        if self.phase == Phase::Second {
+            self.second.as_mut()
+        } else {
+            self.first.as_mut()
+        }
+    }
+
+    /// Reads the sources and mixes them during the overlap. Returns the
+    /// number of written samples.
+    fn read_sources(
+        &mut self,
+        buffer: &mut SampleBufferMut,
+    ) -> (usize, Result<()>) {
+        let Some(config) = self.config.clone() else {
+            return (0, Ok(()));
+        };
+        let channels = config.channel_count as usize;
+        if self.phase == Phase::Second || channels == 0 {
+            return self.second.read(buffer);
+        }
+
+        let len = buffer.len();
+        let mut fade_start = match self.phase {
+            Phase::First => self.overlap_offset(len, channels),
+            _ => Some(0),
+        };
+
+        let (first_len, res) = if self.first_ended {
+            buffer.silence();
+            (0, Ok(()))
+        } else {
+            self.first.read(buffer)
+        };
+        if res.is_err() {
+            return (first_len, res);
+        }
+        if first_len < len && !self.first_ended {
+            self.first_ended = true;
+            buffer.slice(first_len..).silence();
+            // Fall back to start of the second source.
+            fade_start = fade_start.or(Some(first_len));
+        }
+
+        let Some(start) = fade_start else {
+            return (len, Ok(()));
+        };
+
+        let fade = self.to_frames(self.overlap);
+        let mut pos = match self.phase {
+            Phase::Overlap(p) => p,
+            _ => 0,
+        };
+
+        let Some(scratch) = self
+            .scratch
+            .as_mut()
+            .filter(|s| s.format() == buffer.format() && !s.is_empty())
+        else {
+            return (first_len, Err(Error::UnsupportedSampleFormat.into()));
+        };
+
+        // The second source is read in chunks of the size of the scratch
+        // buffer. After it ends, the first source is still faded with
+        // silence.
+        let mut second_len = 0;
+        let mut second_ended = false;
+        let mut res = Ok(());
+        let mut off = start;
+        while off < len {
+            let n = (len - off).min(scratch.len());
+            let mut chunk = scratch.as_mut();
+            let chunk = &mut chunk.slice(..n);
+            chunk.silence();
+            if !second_ended {
+                let (cnt, r) = self.second.read(chunk);
+                second_len += cnt;
+                second_ended = cnt < n || r.is_err();
+                res = r;
+            }
+            mix(
+                &mut buffer.slice(off..off + n),
+                chunk,
+                channels,
+                &mut pos,
+                fade,
+            );
+            off += n;
+        }
+        self.second_started = true;
+
+        self.phase = if pos >= fade {
+            Phase::Second
+        } else {
+            Phase::Overlap(pos)
+        };
+
+        let ended = self.first_ended || self.phase == Phase::Second;
+        if ended && !self.first_done {
+            self.first_done = true;
+            self.outgoing_ended = true;
+        }
+        if start + second_len < len && ended {
+            (first_len.max(start + second_len), res)
+        } else {
+            (len, res)
+        }
+    }
+
+    /// Gets the offset in samples of the start of the overlap in buffer of
+    /// the given length. [`None`] if the overlap doesn't start in the
+    /// buffer or if the time of the first source is unknown.
+    fn overlap_offset(&self, len: usize, channels: usize) -> Option<usize> {
+        let time = self.first.get_time()?;
+        let remaining = time.total.saturating_sub(time.current);
+        let offset =
+            self.to_frames(remaining.saturating_sub(self.overlap)) * channels;
+        (offset < len).then_some(offset)
+    }
+
+    /// Converts the time to number of frames of the output.
+    fn to_frames(&self, time: Duration) -> usize {
+        let rate = self.config.as_ref().map_or(0, |c| c.sample_rate);
+        (time.as_secs_f64() * rate as f64).round() as usize
+    }
+}
+
+/// Mixes `second` into `out` with equal power gain curves. `pos` is the
+/// frame in the fade and `fade` is the length of the fade in frames.
+fn mix(
+    out: &mut SampleBufferMut,
+    second: &mut SampleBufferMut,
+    channels: usize,
+    pos: &mut usize,
+    fade: usize,
+) {
+    operate_samples!(out, o, {
+        if let Some(s) = BufferSample::typed(second) {
+            mix_samples(o, s, channels, pos, fade)
+        }
+    })
+}
+
+/// Mixes `second` into `out` with equal power gain curves.
+fn mix_samples<T>(
+    out: &mut [T],
+    second: &[T],
+    channels: usize,
+    pos: &mut usize,
+    fade: usize,
+) where
+    T: Sample + FromSample<f32>,
+    f32: FromSample<T>,
+{
+    for (o, s) in out
+        .chunks_exact_mut(channels)
+        .zip(second.chunks_exact(channels))
+    {
+        let x = if fade == 0 {
+            1.
+        } else {
+            (*pos as f32 / fade as f32).min(1.)
+        };
+        let (a, b) = ((x * FRAC_PI_2).cos(), (x * FRAC_PI_2).sin());
+        for (o, s) in o.iter_mut().zip(s) {
+            let v = f32::from_sample_(*o) * a + f32::from_sample_(*s) * b;
+            *o = T::from_sample_(v);
+        }
+        *pos += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use cpal::SampleFormat;
+
+    use super::CrossFadeSource;
+    use crate::{
+        sample_buffer::SampleBufferMut,
+        source::{BufferSource, DeviceConfig, InitContext, Source},
+    };
+
+    /// Creates 100 ms of stereo sine with the given frequency.
+    fn sine(freq: f32) -> BufferSource {
+        let samples = (0..4800)
+            .flat_map(|i| {
+                let s =
+                    (i as f32 / 48000. * freq * std::f32::consts::TAU).sin();
+                [s, s]
+            })
+            .collect();
+        BufferSource::new(samples, 2, 48000)
+    }
+
+    /// Plays crossfade of two sines initialized with the given buffer
+    /// duration and returns the output.
+    fn render(buffer_duration: Option<Duration>) -> Vec<f32> {
+        let config = DeviceConfig {
+            channel_count: 2,
+            sample_rate: 48000,
+            sample_format: SampleFormat::F32,
+        };
+        let mut src = CrossFadeSource::new(
+            sine(440.),
+            sine(660.),
+            Duration::from_millis(50),
+        );
+        src.init_with(&InitContext::new(config, None, buffer_duration))
+            .unwrap();
+
+        let mut out = vec![0.; 48000 / 100 * 2 * 20];
+        for chunk in out.chunks_mut(4800) {
+            let (_, res) = src.read(&mut SampleBufferMut::F32(chunk));
+            res.unwrap();
+        }
+        out
+    }
+
+    #[test]
+    fn small_scratch_mixes_in_chunks() {
+        let whole = render(None);
+        let chunked = render(Some(Duration::from_millis(1)));
+        assert!(whole.iter().any(|s| s.abs() > 0.1));
+        assert_eq!(whole, chunked);
+    }
+}
@@ -0,0 +1,209 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use cpal::{FromSample, Sample};
+
+use crate::{
+    converters::convert_sample, operate_samples,
+    sample_buffer::SampleBufferMut, Timestamp,
+};
+
+use super::{chain::mul_volume, DeviceConfig, Source, VolumeIterator};
+
+/// Information given to the closure of [`FnSource`].
+#[derive(Debug, Clone, Default)]
+pub struct GenCtx {
+    /// Sample rate of the output
+    sample_rate: u32,
+    /// Number of channels of the output
+    channels: u32,
+    /// Number of frames generated so far
+    frame: u64,
+}
+
+impl GenCtx {
+    /// Gets the sample rate of the output.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Gets the number of channels of the output. The closure should write
+    /// interleaved samples with this number of channels.
+    pub fn channels(&self) -> u32 {
+        self.channels
+    }
+
+    /// Gets the number of frames generated so far (index of the first frame
+    /// that will be generated).
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Gets the time of the first frame that will be generated.
+    pub fn time(&self) -> Duration {
+        if self.sample_rate == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(
+                self.frame as f64 / self.sample_rate as f64,
+            )
+        }
+    }
+}
+
+/// Source that generates the samples with a closure.
+///
+/// The closure receives [`GenCtx`] and a buffer of interleaved samples
+/// filled with zeros. When the output is [`f32`], the buffer is the output
+/// buffer itself, so the samples are not copied. The closure returns the
+/// number of samples it has written. If it writes less samples than the
+/// length of the buffer, the source ends.
+///
+/// # Example
+/// ```rust,ignore
+/// use raplay::source::FnSource;
+///
+/// // 440 Hz sine wave
+/// let src = FnSource::new(|ctx, out| {
+///     let ch = ctx.channels() as usize;
+///     for (i, frame) in out.chunks_exact_mut(ch).enumerate() {
+///         let t = (ctx.frame() + i as u64) as f32 / ctx.sample_rate() as f32;
+///         frame.fill((t * 440. * std::f32::consts::TAU).sin());
+///     }
+///     out.len()
+/// });
+/// ```
+pub struct FnSource<F>
+where
+    F: FnMut(&mut GenCtx, &mut [f32]) -> usize + Send,
+{
+    /// The generating closure
+    f: F,
+    /// Information given to the closure
+    ctx: GenCtx,
+    /// Maximum duration of the source
+    duration: Option<Duration>,
+    /// Buffer for the samples generated by the closure
+    buffer: Vec<f32>,
+    /// Creates multiplier for each sample
+    volume: VolumeIterator,
+}
+
+impl<F> Source for FnSource<F>
+where
+    F: FnMut(&mut GenCtx, &mut [f32]) -> usize + Send,
+{
+    fn init(&mut self, info: &DeviceConfig) -> Result<()> {
+        // Keep the position when the sample rate changes.
+        let time = self.ctx.time();
+        self.ctx.sample_rate = info.sample_rate;
+        self.ctx.channels = info.channel_count;
+        self.ctx.frame = self.to_frames(time);
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut SampleBufferMut) -> (usize, Result<()>) {
+        if let Some(b) = buffer.as_f32_mut() {
+            return (self.generate_f32(b), Ok(()));
+        }
+        operate_samples!(buffer, b, (self.generate(b), Ok(())))
+    }
+
+    fn volume(&mut self, volume: VolumeIterator) -> bool {
+        self.volume = volume;
+        true
+    }
+
+    fn get_time(&self) -> Option<Timestamp> {
+        let total = self.duration?;
+        Some(Timestamp::new(self.ctx.time().min(total), total))
+    }
+}
+
+impl<F> FnSource<F>
+where
+    F: FnMut(&mut GenCtx, &mut [f32]) -> usize + Send,
+{
+    /// Creates source that generates the samples with the given closure.
+    pub fn new(f: F) -> Self {
+        Self {
+            f,
+            ctx: GenCtx::default(),
+            duration: None,
+            buffer: vec![],
+            volume: VolumeIterator::constant(1.),
+        }
+    }
+
+    /// Limits the duration of the source. The source ends after the
+    /// duration and [`Source::get_time`] reports the duration.
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = Some(duration);
+        self
+    }
+
+    /// Generates the samples with the closure directly to `data`. Returns
+    /// the number of written samples.
+    fn generate_f32(&mut self, data: &mut [f32]) -> usize {
+        let channels = self.ctx.channels as usize;
+        if channels == 0 {
+            return 0;
+        }
+
+        let len = self.max_len(data.len());
+        data[..len].fill(0.);
+        let n = (self.f)(&mut self.ctx, &mut data[..len]).min(len);
+        if !self.volume.is_unity() {
+            mul_volume(&mut data[..n], &mut self.volume);
+        }
+        self.ctx.frame += (n / channels) as u64;
+        n
+    }
+
+    /// Gets the maximum number of samples that can be generated to buffer
+    /// of length `len`. There must be at least one channel.
+    fn max_len(&self, len: usize) -> usize {
+        let channels = self.ctx.channels as usize;
+        let len = len - len % channels;
+        match self.duration {
+            Some(d) => {
+                let remaining =
+                    self.to_frames(d).saturating_sub(self.ctx.frame);
+                len.min(remaining as usize * channels)
+            }
+            None => len,
+        }
+    }
+
+    /// Generates the samples with the closure and writes them to `data`.
+    /// Returns the number of written samples.
+    fn generate<T: Sample + FromSample<f32>>(
+        &mut self,
+        data: &mut [T],
+    ) -> usize
+    where
+        T::Float: From<f32>,
+    {
+        let channels = self.ctx.channels as usize;
+        if channels == 0 {
+            return 0;
+        }
+
+        let len = self.max_len(data.len());
+        self.buffer.clear();
+        self.buffer.resize(len, 0.);
+        let n = (self.f)(&mut self.ctx, &mut self.buffer).min(len);
+
+        for (d, s) in data.iter_mut().zip(&self.buffer[..n]) {
+            *d = convert_sample::<_, T>(*s)
+                .mul_amp(self.volume.next_vol().into());
+        }
+        self.ctx.frame += (n / channels) as u64;
+        n
+    }
+
+    /// Converts the time to number of frames.
+    fn to_frames(&self, time: Duration) -> u64 {
+        (time.as_secs_f64() * self.ctx.sample_rate as f64).round() as u64
+    }
+}
@@ -1,17 +1,49 @@
-use std::time::Duration;
+use std::{collections::VecDeque, time::Duration};
 
 use anyhow::Result;
 use cpal::SampleFormat;
 
 use crate::{
-    callback::Callback, sample_buffer::SampleBufferMut, Error, Timestamp,
+    callback::Callback,
+    converters::gain::{db_to_volume, SILENCE_DB},
+    sample_buffer::SampleBufferMut,
+    Error, SourceSpec, Timestamp,
 };
 
+pub mod buffer;
+pub mod capture;
+pub mod chain;
+pub mod channel_id;
+pub mod crossfade;
+pub mod fn_source;
+pub mod noise;
+pub mod saw;
+pub mod silence;
 pub mod sine;
+pub mod square;
+pub mod stream;
+pub mod sweep;
 pub mod symph;
+pub mod triangle;
 
+mod read_ahead;
+mod wave;
+
+pub use buffer::BufferSource;
+pub use capture::CaptureSource;
+pub use chain::ChainSource;
+pub use channel_id::{ChannelId, ChannelIdHandle};
+pub use crossfade::CrossFadeSource;
+pub use fn_source::{FnSource, GenCtx};
+pub use noise::NoiseSource;
+pub use saw::SawSource;
+pub use silence::SilenceSource;
 pub use sine::SineSource;
+pub use square::SquareSource;
+pub use stream::{StreamProducer, StreamSource};
+pub use sweep::{SweepKind, SweepSource};
 pub use symph::Symph;
+pub use triangle::TriangleSource;
 
 // TODO: better selecting algorithm (if not sample rate at least channel count)
 // TODO: fallback sample format when unsupported sample rate
@@ -24,6 +56,91 @@ pub struct DeviceConfig {
     pub sample_format: SampleFormat,
 }
 
+/// Decides when configuration of the device satisfies the configuration
+/// preferred by a source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConfigPolicy {
+    /// The configuration must be exactly the same.
+    Exact,
+    /// The sample rate and number of channels must be the same, but the
+    /// sample format may differ if the conversion is lossless (e.g. I16 to
+    /// F32).
+    #[default]
+    LosslessFormat,
+}
+
+impl DeviceConfig {
+    /// Checks whether this configuration satisfies the `preferred`
+    /// configuration with the given policy. Different sample rate or number
+    /// of channels never satisfies the preferred configuration.
+    pub fn satisfies(
+        &self,
+        preferred: &DeviceConfig,
+        policy: ConfigPolicy,
+    ) -> bool {
+        if self.sample_rate != preferred.sample_rate
+            || self.channel_count != preferred.channel_count
+        {
+            return false;
+        }
+
+        match policy {
+            ConfigPolicy::Exact => {
+                self.sample_format == preferred.sample_format
+            }
+            ConfigPolicy::LosslessFormat => {
+                is_lossless(preferred.sample_format, self.sample_format)
+            }
+        }
+    }
+}
+
+/// Checks whether conversion from the sample format `from` to `to` is
+/// lossless.
+fn is_lossless(from: SampleFormat, to: SampleFormat) -> bool {
+    let from_bits = from.sample_size() * 8;
+    match (from.is_float(), to.is_float()) {
+        _ if from == to => true,
+        (true, true) => to.sample_size() >= from.sample_size(),
+        (false, true) => {
+            // number of bits of the mantissa
+            let mantissa = if to == SampleFormat::F32 { 24 } else { 53 };
+            from_bits <= mantissa
+        }
+        (false, false) => to.sample_size() >= from.sample_size(),
+        (true, false) => false,
+    }
+}
+
+/// Information about the output delivered to [`Source::init_with`]
+#[non_exhaustive]
+#[derive(PartialEq, Debug, Clone)]
+pub struct InitContext {
+    /// Configuration of the output device
+    pub config: DeviceConfig,
+    /// True if the configuration preferred by the source is used (or the
+    /// source has no preference), false if the audio of the source will be
+    /// converted
+    pub preferred_honored: bool,
+    /// Preferred duration of the buffer of the output if it is known
+    pub buffer_duration: Option<Duration>,
+}
+
+impl InitContext {
+    /// Creates new init context
+    pub fn new(
+        config: DeviceConfig,
+        preferred: Option<&DeviceConfig>,
+        buffer_duration: Option<Duration>,
+    ) -> Self {
+        Self {
+            preferred_honored: preferred.is_none_or(|p| *p == config),
+            config,
+            buffer_duration,
+        }
+    }
+}
+
 /// Source of audio samples
 pub trait Source: Send {
     /// Set the error callback. The callback should be used when source
@@ -37,10 +154,50 @@ pub trait Source: Send {
     /// Init may be called multiple times to update the info
     fn init(&mut self, info: &DeviceConfig) -> Result<()>;
 
+    /// Delivers configuration with additional information about the output
+    /// to the source. This is what the sink calls, it is called again
+    /// whenever the output stream changes. By default this calls
+    /// [`Source::init`].
+    fn init_with(&mut self, ctx: &InitContext) -> Result<()> {
+        self.init(&ctx.config)
+    }
+
     /// Reads data from the source into the buffer, returns number of written
     /// samples
+    ///
+    /// The samples are interleaved in the channel layout of the device given
+    /// to [`Source::init`], the sink doesn't convert them, so the source can
+    /// address each output channel directly.
+    ///
+    /// The returned values are interpreted as follows:
+    /// - `(buffer.len(), Ok(()))`: the source may produce more samples.
+    /// - `(n, Ok(()))` where `n < buffer.len()`: the source reached its end,
+    ///   the first `n` samples are valid and the source will not be read
+    ///   again.
+    /// - `(n, Err(e))`: the source encountered error, the first `n` samples
+    ///   are valid. The error is reported and the rest of the buffer is
+    ///   silenced. The source is not considered ended and it will be read
+    ///   again, so it should report its end with `Ok` once it cannot
+    ///   continue.
+    ///
+    /// Source that temporarily has no data should write silence instead of
+    /// returning no samples. If the source repeatedly returns error (with or
+    /// without producing any samples), it is considered stalled and it is
+    /// ended with [`Error::SourceStalled`] (see
+    /// [`crate::Sink::set_stall_limit`]).
     fn read(&mut self, buffer: &mut SampleBufferMut) -> (usize, Result<()>);
 
+    /// Writes the samples that are still buffered in the source after it
+    /// reported its end. Returns the number of written samples.
+    ///
+    /// This is called after [`Source::read`] reports the end of the source
+    /// and it is called again while it fills the whole buffer. Sources that
+    /// delay the audio (e.g. filters) should write the remaining audio here.
+    fn flush(&mut self, buffer: &mut SampleBufferMut) -> usize {
+        _ = buffer;
+        0
+    }
+
     /// Gets the preffered configuration.
     fn preferred_config(&mut self) -> Option<DeviceConfig> {
         None
@@ -59,7 +216,8 @@ pub trait Source: Send {
         false
     }
 
-    /// Seeks to the given timestamp in the source.
+    /// Seeks to the given timestamp in the source. The time is in the time
+    /// of the source, see [`Source::get_time`].
     fn seek(&mut self, time: Duration) -> Result<Timestamp> {
         // just to ignore the warning but don't have to change the name
         _ = time;
@@ -71,18 +229,17 @@ pub trait Source: Send {
     }
 
     /// Seeks in the given direction. If `forward` is `true`, seeks forward,
-    /// otherwise seeks back
+    /// otherwise seeks back.
+    ///
+    /// The default implementation uses [`Source::get_time`] and
+    /// [`Source::seek`]. Seeking back before the start seeks to the start
+    /// and seeking forward after the end seeks to the end.
     fn seek_by(&mut self, time: Duration, forward: bool) -> Result<Timestamp> {
         if let Some(t) = self.get_time() {
             if forward {
-                self.seek(t.current + time)
+                self.seek((t.current + time).min(t.total))
             } else {
-                self.seek(
-                    t.current
-                        .checked_sub(time)
-                        .unwrap_or_default()
-                        .min(t.total),
-                )
+                self.seek(t.current.saturating_sub(time))
             }
         } else {
             Err(Error::Unsupported {
@@ -93,8 +250,67 @@ pub trait Source: Send {
         }
     }
 
+    /// Returns the previous and the new native properties of the audio if
+    /// they changed since the last call (e.g. the number of channels changed
+    /// in the middle of the stream). This is called by the playback loop
+    /// after each read.
+    fn take_spec_change(&mut self) -> Option<(SourceSpec, SourceSpec)> {
+        None
+    }
+
+    /// Returns the configuration that the source wants the device to use if
+    /// it changed during the playback (e.g. the sample rate of chained
+    /// stream changed). This is polled by the playback loop after each read
+    /// and the request is forwarded to the sink. The source should stop
+    /// returning the configuration when it is initialized again with
+    /// [`Source::init`].
+    fn wants_reconfigure(&self) -> Option<DeviceConfig> {
+        None
+    }
+
+    /// Enables or disables recording of hashes of the raw decoded frames
+    /// (before any conversion). Returns false if the source doesn't support
+    /// this. This is used by [`crate::Sink::enable_passthrough_check`].
+    fn record_raw_hashes(&mut self, enable: bool) -> bool {
+        _ = enable;
+        false
+    }
+
+    /// Moves the hashes of the raw frames that were read since the last call
+    /// to `out`. This is called by the playback loop after each read if the
+    /// recording is enabled with [`Source::record_raw_hashes`].
+    fn take_raw_hashes(&mut self, out: &mut VecDeque<u64>) {
+        _ = out;
+    }
+
+    /// Gets the native properties of the audio produced by the source.
+    fn source_spec(&self) -> Option<SourceSpec> {
+        None
+    }
+
+    /// Gets the time of the audio that fades out during a transition inside
+    /// the source (e.g. the first source of [`CrossFadeSource`] during the
+    /// overlap). [`Source::get_time`] reports the audio that fades in at that
+    /// time. Returns [`None`] if there is no transition.
+    fn outgoing_time(&self) -> Option<Timestamp> {
+        None
+    }
+
+    /// Returns true once after the audio that faded out during a transition
+    /// inside the source finished playing. This is called by the playback
+    /// loop after each read.
+    fn take_outgoing_ended(&mut self) -> bool {
+        false
+    }
+
     /// Gets the current time and whole length
     ///
+    /// The times are in the time of the source: one second is one second of
+    /// the source audio at its native sample rate, regardless of the sample
+    /// rate of the device. Calculations of the remaining time should use
+    /// only these values and not mix them with the number of samples written
+    /// to the device, because they differ by the resampling ratio.
+    ///
     /// # Returns
     /// (current timestamp, total duration)
     fn get_time(&self) -> Option<Timestamp> {
@@ -102,6 +318,159 @@ pub trait Source: Send {
     }
 }
 
+/// Boxed source (e.g. returned by [`crate::Sink::unload`]) can be loaded
+/// again.
+impl Source for Box<dyn Source> {
+    fn set_err_callback(&mut self, err_callback: &Callback<Error>) {
+        (**self).set_err_callback(err_callback)
+    }
+
+    fn init(&mut self, info: &DeviceConfig) -> Result<()> {
+        (**self).init(info)
+    }
+
+    fn init_with(&mut self, ctx: &InitContext) -> Result<()> {
+        (**self).init_with(ctx)
+    }
+
+    fn read(&mut self, buffer: &mut SampleBufferMut) -> (usize, Result<()>) {
+        (**self).read(buffer)
+    }
+
+    fn flush(&mut self, buffer: &mut SampleBufferMut) -> usize {
+        (**self).flush(buffer)
+    }
+
+    fn preferred_config(&mut self) -> Option<DeviceConfig> {
+        (**self).preferred_config()
+    }
+
+    fn volume(&mut self, volume: VolumeIterator) -> bool {
+        (**self).volume(volume)
+    }
+
+    fn seek(&mut self, time: Duration) -> Result<Timestamp> {
+        (**self).seek(time)
+    }
+
+    fn seek_by(&mut self, time: Duration, forward: bool) -> Result<Timestamp> {
+        (**self).seek_by(time, forward)
+    }
+
+    fn take_spec_change(&mut self) -> Option<(SourceSpec, SourceSpec)> {
+        (**self).take_spec_change()
+    }
+
+    fn wants_reconfigure(&self) -> Option<DeviceConfig> {
+        (**self).wants_reconfigure()
+    }
+
+    fn record_raw_hashes(&mut self, enable: bool) -> bool {
+        (**self).record_raw_hashes(enable)
+    }
+
+    fn take_raw_hashes(&mut self, out: &mut VecDeque<u64>) {
+        (**self).take_raw_hashes(out)
+    }
+
+    fn source_spec(&self) -> Option<SourceSpec> {
+        (**self).source_spec()
+    }
+
+    fn outgoing_time(&self) -> Option<Timestamp> {
+        (**self).outgoing_time()
+    }
+
+    fn take_outgoing_ended(&mut self) -> bool {
+        (**self).take_outgoing_ended()
+    }
+
+    fn get_time(&self) -> Option<Timestamp> {
+        (**self).get_time()
+    }
+}
+
+/// Custom shape of volume that can be used in [`VolumeIterator::Custom`].
+///
+/// Each call to [`VolumeEnvelope::next_vol`] yields the volume for a single
+/// sample (not frame) in the target sample rate.
+///
+/// # Example
+/// Half-cosine fade-in:
+/// ```rust,ignore
+/// use std::f32::consts::PI;
+/// use raplay::source::VolumeEnvelope;
+///
+/// #[derive(Clone)]
+/// struct CosFade {
+///     tick: usize,
+///     len: usize,
+///     channels: usize,
+///     channel: usize,
+/// }
+///
+/// impl VolumeEnvelope for CosFade {
+///     fn next_vol(&mut self) -> f32 {
+///         let t = (self.tick.min(self.len) as f32) / self.len as f32;
+///         self.channel += 1;
+///         if self.channel == self.channels {
+///             self.channel = 0;
+///             self.tick += 1;
+///         }
+///         (1. - (t * PI).cos()) / 2.
+///     }
+///
+///     fn until_target(&self) -> Option<usize> {
+///         Some(self.len.saturating_sub(self.tick))
+///     }
+///
+///     fn clone_box(&self) -> Box<dyn VolumeEnvelope> {
+///         Box::new(self.clone())
+///     }
+/// }
+///
+/// sink.volume_envelope(CosFade {
+///     tick: 0,
+///     len: 48000,
+///     channels: 2,
+///     channel: 0,
+/// })?;
+/// ```
+pub trait VolumeEnvelope: Send {
+    /// Gets the volume for the next sample.
+    fn next_vol(&mut self) -> f32;
+
+    /// Behave as if [`VolumeEnvelope::next_vol`] was called `n` times.
+    fn skip_vol(&mut self, n: usize) {
+        for _ in 0..n {
+            self.next_vol();
+        }
+    }
+
+    /// Returns the number of ticks remaining to get to the target volume.
+    /// Returns [`None`] if there is no target.
+    fn until_target(&self) -> Option<usize> {
+        None
+    }
+
+    /// Clones the envelope into a box.
+    fn clone_box(&self) -> Box<dyn VolumeEnvelope>;
+}
+
+impl Clone for Box<dyn VolumeEnvelope> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+impl std::fmt::Debug for dyn VolumeEnvelope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VolumeEnvelope")
+            .field("until_target", &self.until_target())
+            .finish()
+    }
+}
+
 /// Iterates over volume of sequence of samples
 /// A sample should be multiplied by the value returned by the iterator.
 ///
@@ -109,10 +478,13 @@ pub trait Source: Send {
 ///
 /// Calling [`Iterator::next`] never returns [`None`], if you don't
 /// want to get the [`Option`] you can use [`VolumeIterator::next_vol`].
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub enum VolumeIterator {
     /// Constant volume
     Constant(f32),
+    /// Volume with custom shape. The volume set by the user is ignored while
+    /// the custom envelope is used.
+    Custom(Box<dyn VolumeEnvelope>),
     /// Changes the volume in linear time, than transitions to the constant
     Linear {
         /// The starting volume
@@ -140,7 +512,7 @@ impl VolumeIterator {
         Self::Constant(volume)
     }
 
-    /// Creates volume iterator that changes lineary with time.
+    /// Creates volume iterator that changes linearly with time.
     ///
     /// The volume will start at the `start` volume and it will end at the
     /// `target` volume in `tick_count` samples
@@ -161,7 +533,7 @@ impl VolumeIterator {
         }
     }
 
-    /// Creates volume iterator that changes lineary with time.
+    /// Creates volume iterator that changes linearly with time.
     ///
     /// The volume will start at the `start` volume and it will end at the
     /// `target` volume in the given `duration` if the rate is the given `rate`
@@ -184,6 +556,52 @@ impl VolumeIterator {
         }
     }
 
+    /// Creates volume iterator that changes linearly in decibels, so that
+    /// the change is perceived as linear.
+    ///
+    /// The volume will start at `start_db` and it will end at `target_db` in
+    /// `tick_count` samples. Volumes at or below
+    /// [`crate::converters::gain::SILENCE_DB`] are silent. The iterator is
+    /// custom envelope, so the volume set by the user is ignored while it is
+    /// used.
+    pub fn db_linear(
+        start_db: f32,
+        target_db: f32,
+        tick_count: usize,
+        channels: usize,
+    ) -> Self {
+        let start = start_db.max(SILENCE_DB);
+        let target = target_db.max(SILENCE_DB);
+        Self::Custom(Box::new(DbFade {
+            start,
+            step: (target - start) / tick_count.max(1) as f32,
+            tick: 0,
+            len: tick_count,
+            channels: channels.max(1),
+            channel: 0,
+        }))
+    }
+
+    /// Creates volume iterator that changes linearly in decibels, see
+    /// [`VolumeIterator::db_linear`].
+    ///
+    /// The volume will start at `start_db` and it will end at `target_db` in
+    /// the given `duration` if the rate is the given `rate`
+    pub fn db_time_rate(
+        start_db: f32,
+        target_db: f32,
+        rate: u32,
+        duration: Duration,
+        channels: usize,
+    ) -> Self {
+        Self::db_linear(
+            start_db,
+            target_db,
+            (rate as f32 * duration.as_secs_f32()) as usize,
+            channels,
+        )
+    }
+
     /// Transforms this volume iterator to a linear iterator starting at
     /// the current volume and ending at the `target` volume in `tick_count`
     /// samples
@@ -197,6 +615,10 @@ impl VolumeIterator {
             Self::Constant(c) => {
                 *self = Self::linear(*c, target, tick_count, channels)
             }
+            Self::Custom(c) => {
+                let start = c.clone_box().next_vol();
+                *self = Self::linear(start, target, tick_count, channels)
+            }
             Self::Linear {
                 base,
                 step,
@@ -205,7 +627,7 @@ impl VolumeIterator {
                 ..
             } => {
                 *self = Self::linear(
-                    *base + *step * *cur_count as f32 * *multiplier,
+                    (*base + *step * *cur_count as f32) * *multiplier,
                     target,
                     tick_count,
                     channels,
@@ -240,6 +662,7 @@ impl VolumeIterator {
     pub fn until_target(&self) -> Option<usize> {
         match self {
             Self::Constant(_) => None,
+            Self::Custom(c) => c.until_target(),
             Self::Linear {
                 cur_count,
                 target_count,
@@ -256,6 +679,7 @@ impl VolumeIterator {
     pub fn set_volume(&mut self, volume: f32, target: bool) {
         match self {
             Self::Constant(_) => *self = Self::Constant(volume),
+            Self::Custom(_) => {}
             Self::Linear {
                 base,
                 multiplier,
@@ -273,10 +697,17 @@ impl VolumeIterator {
         }
     }
 
+    /// Checks whether the volume is constant 1, so that it doesn't have to
+    /// be applied.
+    pub(crate) fn is_unity(&self) -> bool {
+        matches!(self, Self::Constant(v) if *v == 1.)
+    }
+
     /// behave as if the next_vol function was called n times
     pub fn skip_vol(&mut self, n: usize) {
         match self {
             Self::Constant(_) => {}
+            Self::Custom(c) => c.skip_vol(n),
             Self::Linear {
                 base,
                 step,
@@ -309,6 +740,7 @@ impl VolumeIterator {
     pub fn next_vol(&mut self) -> f32 {
         match self {
             Self::Constant(vol) => *vol,
+            Self::Custom(c) => c.next_vol(),
             Self::Linear {
                 base,
                 step,
@@ -324,7 +756,11 @@ impl VolumeIterator {
                     *cur_channel = 0;
                     *cur_count += 1;
                     if cur_count >= target_count {
-                        *self = Self::Constant(ret)
+                        // Stay at the target, not at the last step before
+                        // it.
+                        let target = (*base + *step * *target_count as f32)
+                            * *multiplier;
+                        *self = Self::Constant(target)
                     }
                 }
                 ret
@@ -333,6 +769,24 @@ impl VolumeIterator {
     }
 }
 
+impl VolumeEnvelope for VolumeIterator {
+    fn next_vol(&mut self) -> f32 {
+        VolumeIterator::next_vol(self)
+    }
+
+    fn skip_vol(&mut self, n: usize) {
+        VolumeIterator::skip_vol(self, n)
+    }
+
+    fn until_target(&self) -> Option<usize> {
+        VolumeIterator::until_target(self)
+    }
+
+    fn clone_box(&self) -> Box<dyn VolumeEnvelope> {
+        Box::new(self.clone())
+    }
+}
+
 impl Iterator for VolumeIterator {
     type Item = f32;
 
@@ -340,6 +794,7 @@ impl Iterator for VolumeIterator {
         Some(self.next_vol())
     }
 
+    /// The iterator never ends, the same as [`std::iter::repeat`].
     fn size_hint(&self) -> (usize, Option<usize>) {
         (usize::MAX, None)
     }
@@ -350,3 +805,81 @@ impl Default for VolumeIterator {
         VolumeIterator::Constant(1.)
     }
 }
+
+/// Volume envelope that changes linearly in decibels
+#[derive(Debug, Clone)]
+struct DbFade {
+    /// The starting volume in decibels
+    start: f32,
+    /// Change of the volume in decibels in single tick
+    step: f32,
+    /// Current tick
+    tick: usize,
+    /// Number of ticks of the fade
+    len: usize,
+    /// Number of channels, each volume is repeated this amount of times
+    channels: usize,
+    /// The current channel index
+    channel: usize,
+}
+
+impl VolumeEnvelope for DbFade {
+    fn next_vol(&mut self) -> f32 {
+        let db = self.start + self.step * self.tick.min(self.len) as f32;
+        self.channel += 1;
+        if self.channel == self.channels {
+            self.channel = 0;
+            self.tick += 1;
+        }
+        db_to_volume(db)
+    }
+
+    fn skip_vol(&mut self, n: usize) {
+        let n = self.channel + n;
+        self.tick = self.tick.saturating_add(n / self.channels);
+        self.channel = n % self.channels;
+    }
+
+    fn until_target(&self) -> Option<usize> {
+        Some(self.len.saturating_sub(self.tick))
+    }
+
+    fn clone_box(&self) -> Box<dyn VolumeEnvelope> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_seek_by_clamps() {
+        // 100 ms of stereo
+        let mut src = BufferSource::new(vec![0.; 9600], 2, 48000);
+        src.init(&DeviceConfig {
+            channel_count: 2,
+            sample_rate: 48000,
+            sample_format: SampleFormat::F32,
+        })
+        .unwrap();
+        src.seek(Duration::from_millis(30)).unwrap();
+
+        let t = src.seek_by(Duration::from_millis(20), false).unwrap();
+        assert_eq!(t.current, Duration::from_millis(10));
+        let t = src.seek_by(Duration::from_secs(1), false).unwrap();
+        assert_eq!(t.current, Duration::ZERO);
+
+        let t = src.seek_by(Duration::from_millis(60), true).unwrap();
+        assert_eq!(t.current, Duration::from_millis(60));
+        let t = src.seek_by(Duration::from_secs(1), true).unwrap();
+        assert_eq!(t.current, t.total);
+        assert_eq!(t.total, Duration::from_millis(100));
+    }
+
+    #[test]
+    fn default_seek_by_needs_time() {
+        let mut src = SineSource::new(440.);
+        assert!(src.seek_by(Duration::from_secs(1), true).is_err());
+    }
+}
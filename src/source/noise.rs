@@ -0,0 +1,232 @@
+use std::{
+    mem,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use cpal::FromSample;
+
+use crate::{operate_samples, sample_buffer::SampleBufferMut};
+
+use super::{wave::fill_frames, Source, VolumeIterator};
+
+/// Seed used when the given seed is 0 (xorshift can't start from 0).
+const FALLBACK_SEED: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// Color of the noise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NoiseColor {
+    /// Same power at all frequencies.
+    White,
+    /// Power decreasing by 3 dB per octave.
+    Pink,
+}
+
+/// Source of white or pink noise. Each channel has its own independent noise
+/// unless [`NoiseSource::correlated`] is set.
+pub struct NoiseSource {
+    /// Color of the noise
+    color: NoiseColor,
+    /// When true, all channels have the same samples
+    correlated: bool,
+    /// State of the xorshift generator
+    rng: u64,
+    /// Number of channels of the result
+    channels: u32,
+    /// State of the pink noise filter for each channel
+    pink: Vec<[f32; 7]>,
+    /// Creates multiplier for each sample
+    volume: VolumeIterator,
+}
+
+impl Source for NoiseSource {
+    fn init(&mut self, info: &super::DeviceConfig) -> Result<()> {
+        self.channels = info.channel_count;
+        self.pink = vec![[0.; 7]; self.channels as usize];
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut SampleBufferMut) -> (usize, Result<()>) {
+        operate_samples!(buffer, b, {
+            self.generate(b);
+            (b.len(), Ok(()))
+        })
+    }
+
+    fn volume(&mut self, volume: super::VolumeIterator) -> bool {
+        self.volume = volume;
+        true
+    }
+}
+
+impl NoiseSource {
+    /// Creates source that generates infinite white noise.
+    pub fn white() -> Self {
+        Self::new(NoiseColor::White)
+    }
+
+    /// Creates source that generates infinite pink noise.
+    pub fn pink() -> Self {
+        Self::new(NoiseColor::Pink)
+    }
+
+    /// Sets the seed of the random generator. The same seed will produce the
+    /// same noise. By default the seed is taken from the current time.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = if seed == 0 { FALLBACK_SEED } else { seed };
+        self
+    }
+
+    /// When true, all the channels will have the same noise. Otherwise (the
+    /// default) each channel has its own independent noise.
+    pub fn correlated(mut self, correlated: bool) -> Self {
+        self.correlated = correlated;
+        self
+    }
+
+    fn new(color: NoiseColor) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default();
+        Self {
+            color,
+            correlated: false,
+            rng: FALLBACK_SEED,
+            channels: 0,
+            pink: vec![],
+            volume: VolumeIterator::constant(1.),
+        }
+        .with_seed(seed)
+    }
+
+    /// Generates the noise
+    fn generate<T: FromSample<f32> + Clone>(&mut self, data: &mut [T]) {
+        if self.correlated {
+            let mut volume = mem::take(&mut self.volume);
+            fill_frames(data, self.channels, &mut volume, || {
+                self.next_sample(0)
+            });
+            self.volume = volume;
+            return;
+        }
+
+        if self.channels == 0 {
+            return;
+        }
+        for frame in data.chunks_exact_mut(self.channels as usize) {
+            let vol = self.volume.next_vol();
+            for (c, s) in frame.iter_mut().enumerate() {
+                *s = T::from_sample_(self.next_sample(c) * vol);
+            }
+        }
+    }
+
+    /// Gets the next sample for the given channel.
+    fn next_sample(&mut self, channel: usize) -> f32 {
+        let white = self.next_white();
+        match self.color {
+            NoiseColor::White => white,
+            NoiseColor::Pink => pink_filter(&mut self.pink[channel], white),
+        }
+    }
+
+    /// Gets the next white noise sample in range -1 to 1.
+    fn next_white(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        // Use the top 24 bits, that is the precision of f32.
+        (self.rng >> 40) as f32 / (1 << 23) as f32 - 1.
+    }
+}
+
+/// Filters white noise to pink noise (Paul Kellet's refined method). The
+/// result is scaled to be roughly in range -1 to 1.
+fn pink_filter(b: &mut [f32; 7], white: f32) -> f32 {
+    b[0] = 0.99886 * b[0] + white * 0.0555179;
+    b[1] = 0.99332 * b[1] + white * 0.0750759;
+    b[2] = 0.96900 * b[2] + white * 0.153852;
+    b[3] = 0.86650 * b[3] + white * 0.3104856;
+    b[4] = 0.55000 * b[4] + white * 0.5329522;
+    b[5] = -0.7616 * b[5] - white * 0.0168980;
+    let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+    b[6] = white * 0.115926;
+    (pink * 0.15).clamp(-1., 1.)
+}
+
+#[cfg(test)]
+mod tests {
+    use cpal::SampleFormat;
+
+    use super::*;
+    use crate::source::DeviceConfig;
+
+    /// Generates one second of the noise at 48 kHz.
+    fn generate(mut src: NoiseSource, channels: u32) -> Vec<f32> {
+        src.init(&DeviceConfig {
+            channel_count: channels,
+            sample_rate: 48000,
+            sample_format: SampleFormat::F32,
+        })
+        .unwrap();
+        let mut data = vec![0.; 48000 * channels as usize];
+        src.read(&mut SampleBufferMut::F32(&mut data)).1.unwrap();
+        data
+    }
+
+    fn rms(data: &[f32]) -> f32 {
+        (data.iter().map(|s| s * s).sum::<f32>() / data.len() as f32).sqrt()
+    }
+
+    fn mean(data: &[f32]) -> f32 {
+        data.iter().sum::<f32>() / data.len() as f32
+    }
+
+    #[test]
+    fn white_noise_rms() {
+        // Uniform distribution in -1 to 1 has RMS of 1 / sqrt(3).
+        let data = generate(NoiseSource::white().with_seed(1), 1);
+        let expected = 1. / 3_f32.sqrt();
+        assert!((rms(&data) - expected).abs() < 0.01, "{}", rms(&data));
+        assert!(mean(&data).abs() < 0.01);
+    }
+
+    #[test]
+    fn pink_noise_rms() {
+        // The filter scales the noise to about half of the RMS of white
+        // noise, so that the peaks rarely clip.
+        let data = generate(NoiseSource::pink().with_seed(1), 1);
+        assert!((0.22..0.32).contains(&rms(&data)), "{}", rms(&data));
+        assert!(mean(&data).abs() < 0.05);
+
+        // Most of the power is in low frequencies, so the differences of
+        // neighbouring samples are small. For white noise, the ratio is
+        // sqrt(2).
+        let diff: Vec<_> = data.windows(2).map(|w| w[1] - w[0]).collect();
+        let ratio = rms(&diff) / rms(&data);
+        assert!(ratio < 0.8, "{ratio}");
+    }
+
+    #[test]
+    fn seed_is_reproducible() {
+        let a = generate(NoiseSource::pink().with_seed(42), 2);
+        let b = generate(NoiseSource::pink().with_seed(42), 2);
+        let c = generate(NoiseSource::pink().with_seed(43), 2);
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn channels_are_independent() {
+        let data = generate(NoiseSource::white().with_seed(7), 2);
+        let corr = data.chunks(2).map(|f| f[0] * f[1]).sum::<f32>()
+            / (data.len() / 2) as f32
+            / (rms(&data) * rms(&data));
+        assert!(corr.abs() < 0.05, "{corr}");
+
+        let data =
+            generate(NoiseSource::white().with_seed(7).correlated(true), 2);
+        assert!(data.chunks(2).all(|f| f[0] == f[1]));
+    }
+}
@@ -0,0 +1,371 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Seek, SeekFrom},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+};
+
+use symphonia::core::io::MediaSource;
+
+use crate::thread;
+
+/// Default number of bytes read ahead.
+pub const DEFAULT_READ_AHEAD: usize = 256 * 1024;
+
+/// Maximum number of bytes read at once by the reader thread.
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// Media source that wraps any [`Read`] + [`Seek`] (e.g. a network
+/// stream). The data is read ahead on a separate thread, so that slow
+/// reader doesn't starve the decoder.
+///
+/// Use [`ReadSource`] for streams that cannot seek.
+pub struct ReadSeekSource {
+    /// Reads the data on another thread
+    reader: ReadAhead,
+    /// Length of the stream in bytes if it is known
+    byte_len: Option<u64>,
+}
+
+impl ReadSeekSource {
+    /// Creates media source from the seekable reader. `byte_len` is the
+    /// length of the stream in bytes if it is known.
+    pub fn new<T>(inner: T, byte_len: Option<u64>) -> Self
+    where
+        T: Read + Seek + Send + 'static,
+    {
+        Self::with_read_ahead(inner, byte_len, DEFAULT_READ_AHEAD)
+    }
+
+    /// Creates media source from the seekable reader that reads ahead at
+    /// most `read_ahead` bytes. `byte_len` is the length of the stream in
+    /// bytes if it is known.
+    pub fn with_read_ahead<T>(
+        inner: T,
+        byte_len: Option<u64>,
+        read_ahead: usize,
+    ) -> Self
+    where
+        T: Read + Seek + Send + 'static,
+    {
+        Self {
+            reader: ReadAhead::new(inner, read_ahead),
+            byte_len,
+        }
+    }
+}
+
+impl Read for ReadSeekSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl Seek for ReadSeekSource {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.reader.seek(pos)
+    }
+}
+
+impl MediaSource for ReadSeekSource {
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        self.byte_len
+    }
+}
+
+/// Media source that wraps any [`Read`] that cannot seek (e.g. a radio
+/// stream). The data is read ahead on a separate thread, so that slow
+/// reader doesn't starve the decoder.
+///
+/// Seeking with [`crate::source::Symph`] created from this source returns
+/// [`crate::Error::Unsupported`].
+pub struct ReadSource {
+    /// Reads the data on another thread
+    reader: ReadAhead,
+    /// Length of the stream in bytes if it is known
+    byte_len: Option<u64>,
+}
+
+impl ReadSource {
+    /// Creates media source from the reader. `byte_len` is the length of the
+    /// stream in bytes if it is known.
+    pub fn new<T>(inner: T, byte_len: Option<u64>) -> Self
+    where
+        T: Read + Send + 'static,
+    {
+        Self::with_read_ahead(inner, byte_len, DEFAULT_READ_AHEAD)
+    }
+
+    /// Creates media source from the reader that reads ahead at most
+    /// `read_ahead` bytes. `byte_len` is the length of the stream in bytes
+    /// if it is known.
+    pub fn with_read_ahead<T>(
+        inner: T,
+        byte_len: Option<u64>,
+        read_ahead: usize,
+    ) -> Self
+    where
+        T: Read + Send + 'static,
+    {
+        Self {
+            reader: ReadAhead::new(NoSeek(inner), read_ahead),
+            byte_len,
+        }
+    }
+}
+
+impl Read for ReadSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl Seek for ReadSource {
+    /// Only seeking forward within the data that was read ahead is
+    /// supported.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.reader.seek(pos)
+    }
+}
+
+impl MediaSource for ReadSource {
+    fn is_seekable(&self) -> bool {
+        false
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        self.byte_len
+    }
+}
+
+/// Reader that fails to seek.
+struct NoSeek<T: Read>(T);
+
+impl<T: Read> Read for NoSeek<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<T: Read> Seek for NoSeek<T> {
+    fn seek(&mut self, _: SeekFrom) -> io::Result<u64> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the stream cannot seek",
+        ))
+    }
+}
+
+/// State shared with the reader thread.
+#[derive(Default)]
+struct State {
+    /// Data that was read ahead
+    data: VecDeque<u8>,
+    /// Position in the stream of the first byte in `data`
+    pos: u64,
+    /// True if the reader reached the end of the stream
+    eof: bool,
+    /// Error from the reader
+    err: Option<io::Error>,
+    /// Requested seek
+    seek: Option<SeekFrom>,
+    /// Result of the requested seek
+    seek_res: Option<io::Result<u64>>,
+    /// True if the reader thread should stop
+    stop: bool,
+}
+
+/// Shared data of [`ReadAhead`].
+struct Shared {
+    /// The state
+    state: Mutex<State>,
+    /// Notified when the state changes
+    changed: Condvar,
+}
+
+impl Shared {
+    /// Locks the state. Poisoned lock is recovered, the state is always
+    /// valid.
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Waits until the state changes.
+    fn wait<'a>(&self, guard: MutexGuard<'a, State>) -> MutexGuard<'a, State> {
+        self.changed.wait(guard).unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Reads the data from reader on a separate thread.
+struct ReadAhead {
+    /// State shared with the thread
+    shared: Arc<Shared>,
+}
+
+impl ReadAhead {
+    /// Starts reading from the reader on a new thread. At most `capacity`
+    /// bytes are read ahead.
+    fn new<T>(inner: T, capacity: usize) -> Self
+    where
+        T: Read + Seek + Send + 'static,
+    {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State::default()),
+            changed: Condvar::new(),
+        });
+        let thread_shared = shared.clone();
+        // The decoder waits for the data, so the thread runs with higher
+        // priority to avoid underruns.
+        let spawned = thread::spawn("read-ahead", true, move || {
+            read_thread(inner, &thread_shared, capacity.max(1))
+        });
+
+        if let Err(e) = spawned {
+            shared.lock().err = Some(e);
+        }
+
+        Self { shared }
+    }
+
+    /// Reads the data that was read ahead. Blocks if there is no data.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.shared.lock();
+        while state.data.is_empty() && !state.eof && state.err.is_none() {
+            state = self.shared.wait(state);
+        }
+
+        if let Some(e) = state.err.take() {
+            return Err(e);
+        }
+
+        let n = buf.len().min(state.data.len());
+        for (d, s) in buf.iter_mut().zip(state.data.drain(..n)) {
+            *d = s;
+        }
+        state.pos += n as u64;
+        self.shared.changed.notify_all();
+        Ok(n)
+    }
+
+    /// Seeks in the stream. Seeking forward within the data that was read
+    /// ahead doesn't seek the reader.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let mut state = self.shared.lock();
+        let target = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::Current(d) => state.pos.checked_add_signed(d),
+            SeekFrom::End(_) => None,
+        };
+
+        if let Some(t) = target {
+            let end = state.pos + state.data.len() as u64;
+            if t >= state.pos && t <= end {
+                let skip = (t - state.pos) as usize;
+                state.data.drain(..skip);
+                state.pos = t;
+                self.shared.changed.notify_all();
+                return Ok(t);
+            }
+        }
+
+        state.seek = Some(target.map_or(pos, SeekFrom::Start));
+        state.seek_res = None;
+        state.data.clear();
+        self.shared.changed.notify_all();
+        loop {
+            if let Some(res) = state.seek_res.take() {
+                return res;
+            }
+            state = self.shared.wait(state);
+        }
+    }
+}
+
+impl Drop for ReadAhead {
+    fn drop(&mut self) {
+        // The thread is not joined, it may be blocked in read.
+        self.shared.lock().stop = true;
+        self.shared.changed.notify_all();
+    }
+}
+
+/// Reads from `inner` to the shared state until it is stopped.
+fn read_thread<T: Read + Seek>(
+    mut inner: T,
+    shared: &Shared,
+    capacity: usize,
+) {
+    let mut chunk = vec![0; CHUNK_SIZE.min(capacity)];
+    loop {
+        let mut state = shared.lock();
+        while !state.stop
+            && state.seek.is_none()
+            && (state.eof
+                || state.err.is_some()
+                || state.data.len() >= capacity)
+        {
+            state = shared.wait(state);
+        }
+
+        if state.stop {
+            return;
+        }
+
+        if let Some(pos) = state.seek.take() {
+            drop(state);
+            let res = inner.seek(pos);
+            let mut state = shared.lock();
+            state.data.clear();
+            state.eof = false;
+            state.err = None;
+            if let Ok(p) = res {
+                state.pos = p;
+            }
+            state.seek_res = Some(res);
+            shared.changed.notify_all();
+            continue;
+        }
+
+        let len = chunk.len().min(capacity - state.data.len());
+        drop(state);
+        let res = inner.read(&mut chunk[..len]);
+
+        let mut state = shared.lock();
+        if state.seek.is_some() {
+            // The data is from before the seek.
+            continue;
+        }
+        match res {
+            Ok(0) => state.eof = true,
+            Ok(n) => state.data.extend(&chunk[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => state.err = Some(e),
+        }
+        shared.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn read_ahead_requests_elevation() {
+        let mut src = ReadSeekSource::new(Cursor::new(vec![1_u8; 64]), None);
+        let mut buf = [0; 64];
+        // The thread is listed before it produces any data.
+        assert!(src.read(&mut buf).unwrap() > 0);
+
+        let threads = thread::list().unwrap();
+        let info = threads
+            .iter()
+            .find(|t| t.name == "raplay-read-ahead")
+            .unwrap();
+        assert!(info.elevation_requested);
+    }
+}
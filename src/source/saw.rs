@@ -0,0 +1,68 @@
+use anyhow::Result;
+use cpal::FromSample;
+
+use crate::{operate_samples, sample_buffer::SampleBufferMut};
+
+use super::{
+    wave::{fill_frames, poly_blep},
+    Source, VolumeIterator,
+};
+
+/// Source of sawtooth waves. The jumps are smoothed with polyBLEP, so that
+/// high frequencies don't alias too much.
+pub struct SawSource {
+    /// Frequency of the sawtooth wave
+    frequency: f32,
+    /// Number of channels of the result
+    channels: u32,
+    /// How much the phase moves for each sample
+    phase_step: f64,
+    /// Phase in the period, 0 to 1
+    phase: f64,
+    /// Creates multiplier for each sample
+    volume: VolumeIterator,
+}
+
+impl Source for SawSource {
+    fn init(&mut self, info: &super::DeviceConfig) -> Result<()> {
+        self.channels = info.channel_count;
+        self.phase_step = self.frequency as f64 / info.sample_rate as f64;
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut SampleBufferMut) -> (usize, Result<()>) {
+        operate_samples!(buffer, b, {
+            self.generate(b);
+            (b.len(), Ok(()))
+        })
+    }
+
+    fn volume(&mut self, volume: super::VolumeIterator) -> bool {
+        self.volume = volume;
+        true
+    }
+}
+
+impl SawSource {
+    /// Creates source that generates infinite sawtooth wave with the given
+    /// frequency
+    pub fn new(frequency: f32) -> Self {
+        Self {
+            frequency,
+            channels: 0,
+            phase_step: 0.,
+            phase: 0.,
+            volume: VolumeIterator::constant(1.),
+        }
+    }
+
+    /// Generates sawtooth wave
+    fn generate<T: FromSample<f32> + Clone>(&mut self, data: &mut [T]) {
+        fill_frames(data, self.channels, &mut self.volume, || {
+            let t = self.phase as f32;
+            let res = 2. * t - 1. - poly_blep(t, self.phase_step as f32);
+            self.phase = (self.phase + self.phase_step).fract();
+            res
+        });
+    }
+}
@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::{
+    operate_samples,
+    sample_buffer::{write_silence, SampleBufferMut},
+    Timestamp,
+};
+
+use super::Source;
+
+/// Source of silence with a fixed duration.
+pub struct SilenceSource {
+    /// Total duration of the silence
+    duration: Duration,
+    /// Number of channels of the result
+    channels: u32,
+    /// Sample rate of the result
+    sample_rate: u32,
+    /// Total number of frames
+    total: u64,
+    /// Number of frames already produced
+    frame: u64,
+}
+
+impl Source for SilenceSource {
+    fn init(&mut self, info: &super::DeviceConfig) -> Result<()> {
+        // Keep the position when the sample rate changes.
+        let pos = self.position();
+        self.channels = info.channel_count;
+        self.sample_rate = info.sample_rate;
+        self.total = self.to_frames(self.duration);
+        self.frame = self.to_frames(pos);
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut SampleBufferMut) -> (usize, Result<()>) {
+        if self.channels == 0 {
+            return (0, Ok(()));
+        }
+        operate_samples!(buffer, b, {
+            let frames = (b.len() / self.channels as usize)
+                .min((self.total - self.frame) as usize);
+            let len = frames * self.channels as usize;
+            write_silence(&mut b[..len]);
+            self.frame += frames as u64;
+            (len, Ok(()))
+        })
+    }
+
+    fn seek(&mut self, time: Duration) -> Result<Timestamp> {
+        self.frame = self.to_frames(time).min(self.total);
+        Ok(self.timestamp())
+    }
+
+    fn get_time(&self) -> Option<Timestamp> {
+        Some(self.timestamp())
+    }
+}
+
+impl SilenceSource {
+    /// Creates source that generates silence with the given duration.
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            channels: 0,
+            sample_rate: 0,
+            total: 0,
+            frame: 0,
+        }
+    }
+
+    /// Gets the current position and the total duration.
+    fn timestamp(&self) -> Timestamp {
+        Timestamp::new(self.position(), self.duration)
+    }
+
+    /// Gets the time of the current frame.
+    fn position(&self) -> Duration {
+        if self.sample_rate == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(
+                self.frame as f64 / self.sample_rate as f64,
+            )
+            .min(self.duration)
+        }
+    }
+
+    /// Converts the time to number of frames.
+    fn to_frames(&self, time: Duration) -> u64 {
+        (time.as_secs_f64() * self.sample_rate as f64).round() as u64
+    }
+}
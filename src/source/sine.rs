@@ -5,7 +5,7 @@ use cpal::FromSample;
 
 use crate::{operate_samples, sample_buffer::SampleBufferMut};
 
-use super::{Source, VolumeIterator};
+use super::{wave::fill_frames, Source, VolumeIterator};
 
 /// Source of sine waves
 pub struct SineSource {
@@ -55,16 +55,14 @@ impl SineSource {
     }
 
     /// Generates sine wave
-    fn generate<T: FromSample<f32> + Clone>(&mut self, mut data: &mut [T]) {
-        while data.len() >= self.channels as usize {
-            let val =
-                T::from_sample_(self.iter.sin() * self.volume.next_vol());
-            data[..self.channels as usize].fill(val);
-            data = &mut data[self.channels as usize..];
+    fn generate<T: FromSample<f32> + Clone>(&mut self, data: &mut [T]) {
+        fill_frames(data, self.channels, &mut self.volume, || {
+            let res = self.iter.sin();
             self.iter += self.iter_step;
             if self.iter > 2. * PI {
                 self.iter -= 2. * PI
             }
-        }
+            res
+        });
     }
 }
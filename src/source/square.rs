@@ -0,0 +1,150 @@
+use anyhow::Result;
+use cpal::FromSample;
+
+use crate::{operate_samples, sample_buffer::SampleBufferMut};
+
+use super::{
+    wave::{fill_frames, poly_blep},
+    Source, VolumeIterator,
+};
+
+/// Source of square waves. The edges are smoothed with polyBLEP, so that
+/// high frequencies don't alias too much.
+pub struct SquareSource {
+    /// Frequency of the square wave
+    frequency: f32,
+    /// Fraction of the period in which the wave is high
+    duty: f32,
+    /// Number of channels of the result
+    channels: u32,
+    /// How much the phase moves for each sample
+    phase_step: f32,
+    /// Phase in the period, 0 to 1
+    phase: f32,
+    /// Creates multiplier for each sample
+    volume: VolumeIterator,
+}
+
+impl Source for SquareSource {
+    fn init(&mut self, info: &super::DeviceConfig) -> Result<()> {
+        self.channels = info.channel_count;
+        self.phase_step = self.frequency / info.sample_rate as f32;
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut SampleBufferMut) -> (usize, Result<()>) {
+        operate_samples!(buffer, b, {
+            self.generate(b);
+            (b.len(), Ok(()))
+        })
+    }
+
+    fn volume(&mut self, volume: super::VolumeIterator) -> bool {
+        self.volume = volume;
+        true
+    }
+}
+
+impl SquareSource {
+    /// Creates source that generates infinite square wave with the given
+    /// frequency and duty cycle of 0.5
+    pub fn new(frequency: f32) -> Self {
+        Self {
+            frequency,
+            duty: 0.5,
+            channels: 0,
+            phase_step: 0.,
+            phase: 0.,
+            volume: VolumeIterator::constant(1.),
+        }
+    }
+
+    /// Sets the duty cycle: the fraction of the period in which the wave is
+    /// high. It is clamped to 0 - 1.
+    pub fn with_duty_cycle(mut self, duty: f32) -> Self {
+        self.duty = duty.clamp(0., 1.);
+        self
+    }
+
+    /// Generates square wave
+    fn generate<T: FromSample<f32> + Clone>(&mut self, data: &mut [T]) {
+        let (duty, dt) = (self.duty, self.phase_step);
+        fill_frames(data, self.channels, &mut self.volume, || {
+            let res = sample(self.phase, dt, duty);
+            self.phase += dt;
+            self.phase -= self.phase.floor();
+            res
+        });
+    }
+}
+
+/// Gets the value of square wave with the given duty cycle at the phase `t`.
+fn sample(t: f32, dt: f32, duty: f32) -> f32 {
+    let naive = if t < duty { 1. } else { -1. };
+    if duty <= 0. || duty >= 1. {
+        return naive;
+    }
+    let fall = (t + 1. - duty).fract();
+    naive + poly_blep(t, dt) - poly_blep(fall, dt)
+}
+
+#[cfg(test)]
+mod tests {
+    use cpal::SampleFormat;
+
+    use super::*;
+    use crate::source::DeviceConfig;
+
+    /// Generates `frames` frames of the wave at 48 kHz.
+    fn generate(
+        mut src: SquareSource,
+        channels: u32,
+        frames: usize,
+    ) -> Vec<f32> {
+        src.init(&DeviceConfig {
+            channel_count: channels,
+            sample_rate: 48000,
+            sample_format: SampleFormat::F32,
+        })
+        .unwrap();
+        let mut data = vec![0.; frames * channels as usize];
+        let (cnt, res) = src.read(&mut SampleBufferMut::F32(&mut data));
+        res.unwrap();
+        assert_eq!(cnt, data.len());
+        data
+    }
+
+    #[test]
+    fn duty_cycle() {
+        for duty in [0.1, 0.25, 0.5, 0.8] {
+            // 100 periods of 480 samples
+            let data = generate(
+                SquareSource::new(100.).with_duty_cycle(duty),
+                1,
+                48000,
+            );
+            let high = data.iter().filter(|s| **s > 0.).count();
+            let ratio = high as f32 / data.len() as f32;
+            assert!((ratio - duty).abs() < 0.005, "{duty}: {ratio}");
+        }
+    }
+
+    #[test]
+    fn full_duty_cycle_is_constant() {
+        let high =
+            generate(SquareSource::new(100.).with_duty_cycle(2.), 1, 960);
+        assert!(high.iter().all(|s| *s == 1.));
+        let low =
+            generate(SquareSource::new(100.).with_duty_cycle(-1.), 1, 960);
+        assert!(low.iter().all(|s| *s == -1.));
+    }
+
+    #[test]
+    fn edges_are_smoothed() {
+        let data = generate(SquareSource::new(5000.), 2, 4800);
+        assert!(data.iter().all(|s| s.abs() <= 1.));
+        // The edges have samples between the levels.
+        assert!(data.iter().any(|s| s.abs() < 0.9));
+        assert!(data.chunks(2).all(|f| f[0] == f[1]));
+    }
+}
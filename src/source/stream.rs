@@ -0,0 +1,343 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::Result;
+use cpal::{FromSample, Sample, SampleFormat};
+
+use crate::{
+    callback::Callback,
+    converters::{convert_sample, do_channels_rate},
+    operate_samples,
+    sample_buffer::SampleBufferMut,
+    Error, SourceSpec,
+};
+
+use super::{chain::mul_volume, DeviceConfig, Source, VolumeIterator};
+
+/// Lock-free ring buffer with single producer and single consumer.
+struct Ring {
+    /// The samples stored as bits of [`f32`]
+    data: Box<[AtomicU32]>,
+    /// Total number of samples read, written only by the consumer
+    read: AtomicUsize,
+    /// Total number of samples written, written only by the producer
+    written: AtomicUsize,
+    /// True if the producer will not write any more samples
+    closed: AtomicBool,
+    /// Number of channels of the samples
+    channels: u32,
+    /// Sample rate of the samples
+    sample_rate: u32,
+}
+
+impl Ring {
+    /// Gets the number of samples that can be read.
+    fn available(&self) -> usize {
+        let written = self.written.load(Ordering::Acquire);
+        written.wrapping_sub(self.read.load(Ordering::Acquire))
+    }
+
+    /// Writes the samples, returns the number of written samples. Must be
+    /// called only by the producer.
+    fn push(&self, samples: &[f32]) -> usize {
+        let written = self.written.load(Ordering::Relaxed);
+        let free = self.data.len() - self.available();
+        let n = samples.len().min(free);
+        for (i, s) in samples[..n].iter().enumerate() {
+            let idx = written.wrapping_add(i) % self.data.len();
+            self.data[idx].store(s.to_bits(), Ordering::Relaxed);
+        }
+        self.written
+            .store(written.wrapping_add(n), Ordering::Release);
+        n
+    }
+
+    /// Reads the samples to `out`, returns the number of read samples. Must
+    /// be called only by the consumer.
+    fn pop(&self, out: &mut [f32]) -> usize {
+        let read = self.read.load(Ordering::Relaxed);
+        let n = out.len().min(self.available());
+        for (i, s) in out[..n].iter_mut().enumerate() {
+            let idx = read.wrapping_add(i) % self.data.len();
+            *s = f32::from_bits(self.data[idx].load(Ordering::Relaxed));
+        }
+        self.read.store(read.wrapping_add(n), Ordering::Release);
+        n
+    }
+}
+
+/// Writes samples to [`StreamSource`] from another thread. The stream is
+/// closed when the producer is dropped.
+///
+/// There may be only one writer, so writing requires mutable reference.
+pub struct StreamProducer {
+    /// The shared buffer
+    ring: Arc<Ring>,
+}
+
+impl StreamProducer {
+    /// Writes interleaved samples to the stream without blocking. Returns
+    /// the number of written samples, it is less than the length of
+    /// `samples` if the buffer is full. Only whole frames are written.
+    pub fn write(&mut self, samples: &[f32]) -> usize {
+        let channels = self.ring.channels as usize;
+        let free = self.free();
+        self.ring
+            .push(&samples[..samples.len().min(free) / channels * channels])
+    }
+
+    /// Gets the number of samples that can be written without blocking.
+    pub fn free(&self) -> usize {
+        self.ring.data.len() - self.ring.available()
+    }
+
+    /// Gets the duration of the samples that are buffered and not yet
+    /// played.
+    pub fn latency(&self) -> Duration {
+        let frames = self.ring.available() / self.ring.channels as usize;
+        Duration::from_secs_f64(frames as f64 / self.ring.sample_rate as f64)
+    }
+
+    /// Closes the stream. The source ends after it plays all the buffered
+    /// samples.
+    pub fn close(&mut self) {
+        self.ring.closed.store(true, Ordering::Release);
+    }
+
+    /// Checks if the stream is closed.
+    pub fn is_closed(&self) -> bool {
+        self.ring.closed.load(Ordering::Acquire)
+    }
+}
+
+impl Drop for StreamProducer {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// Source that plays samples written with [`StreamProducer`] from another
+/// thread. When it runs out of samples it plays silence and reports
+/// [`Error::StreamUnderrun`] to the error callback once until there are
+/// samples again.
+pub struct StreamSource {
+    /// The shared buffer
+    ring: Arc<Ring>,
+    /// Number of channels of the result
+    target_channels: u32,
+    /// Sample rate of the result
+    target_sample_rate: u32,
+    /// Converts the samples to the output, [`None`] if it should be
+    /// recreated
+    converter: Option<Box<dyn Iterator<Item = f32> + Send>>,
+    /// Set by the reader when it runs out of samples
+    starved: Arc<AtomicBool>,
+    /// True if the underrun was already reported
+    underrun: bool,
+    /// Callback for the underrun errors
+    err_callback: Callback<Error>,
+    /// Creates multiplier for each sample
+    volume: VolumeIterator,
+}
+
+impl Source for StreamSource {
+    fn set_err_callback(&mut self, err_callback: &Callback<Error>) {
+        self.err_callback = err_callback.clone();
+    }
+
+    fn init(&mut self, info: &DeviceConfig) -> Result<()> {
+        self.target_channels = info.channel_count;
+        self.target_sample_rate = info.sample_rate;
+        self.converter = None;
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut SampleBufferMut) -> (usize, Result<()>) {
+        // The converter may hold part of a frame, so it must be used until
+        // it is recreated.
+        if self.converter.is_none()
+            && self.ring.channels == self.target_channels
+            && self.ring.sample_rate == self.target_sample_rate
+        {
+            if let Some(b) = buffer.as_f32_mut() {
+                return (self.copy_samples(b), Ok(()));
+            }
+        }
+        operate_samples!(buffer, b, (self.read_samples(b), Ok(())))
+    }
+
+    fn preferred_config(&mut self) -> Option<DeviceConfig> {
+        Some(DeviceConfig {
+            channel_count: self.ring.channels,
+            sample_rate: self.ring.sample_rate,
+            sample_format: SampleFormat::F32,
+        })
+    }
+
+    fn volume(&mut self, volume: VolumeIterator) -> bool {
+        self.volume = volume;
+        true
+    }
+
+    fn source_spec(&self) -> Option<SourceSpec> {
+        Some(SourceSpec {
+            channel_count: self.ring.channels,
+            sample_rate: self.ring.sample_rate,
+            bits_per_sample: Some(32),
+            codec: None,
+        })
+    }
+}
+
+impl StreamSource {
+    /// Creates the stream with the given number of channels and sample rate
+    /// of the samples. The buffer can hold `capacity` frames.
+    ///
+    /// # Panics
+    /// - `channels`, `sample_rate` or `capacity` is 0
+    pub fn new(
+        channels: u32,
+        sample_rate: u32,
+        capacity: usize,
+    ) -> (StreamProducer, Self) {
+        assert!(channels != 0, "channel count must not be 0");
+        assert!(sample_rate != 0, "sample rate must not be 0");
+        assert!(capacity != 0, "capacity must not be 0");
+
+        let ring = Arc::new(Ring {
+            data: (0..capacity * channels as usize)
+                .map(|_| AtomicU32::new(0))
+                .collect(),
+            read: AtomicUsize::new(0),
+            written: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+            channels,
+            sample_rate,
+        });
+
+        let src = Self {
+            ring: ring.clone(),
+            target_channels: 0,
+            target_sample_rate: 0,
+            converter: None,
+            starved: Arc::new(AtomicBool::new(false)),
+            underrun: false,
+            err_callback: Callback::default(),
+            volume: VolumeIterator::constant(1.),
+        };
+        (StreamProducer { ring }, src)
+    }
+
+    /// Reads the samples from the buffer directly to the output. Can be used
+    /// only if the config matches. Returns the number of written samples.
+    fn copy_samples(&mut self, data: &mut [f32]) -> usize {
+        let len = data.len() - data.len() % self.target_channels as usize;
+        // Check before reading so that samples written just before closing
+        // are not lost.
+        let closed = self.ring.closed.load(Ordering::Acquire);
+        let mut cnt = self.ring.pop(&mut data[..len]);
+        if cnt != len && !closed {
+            data[cnt..len].fill(0.);
+            cnt = len;
+            if !self.underrun {
+                _ = self.err_callback.invoke(Error::StreamUnderrun);
+            }
+            self.underrun = true;
+        } else {
+            self.underrun = false;
+        }
+
+        if !self.volume.is_unity() {
+            mul_volume(&mut data[..cnt], &mut self.volume);
+        }
+        cnt
+    }
+
+    /// Reads the samples from the buffer and converts them to the output.
+    /// Returns the number of written samples.
+    fn read_samples<T>(&mut self, data: &mut [T]) -> usize
+    where
+        T: Sample + FromSample<f32>,
+        T::Float: From<f32>,
+    {
+        if self.target_channels == 0 {
+            return 0;
+        }
+
+        let conv = self.converter.get_or_insert_with(|| {
+            let reader = RingReader {
+                ring: self.ring.clone(),
+                frame: vec![0.; self.ring.channels as usize],
+                pos: self.ring.channels as usize,
+                starved: self.starved.clone(),
+            };
+            Box::new(do_channels_rate(
+                reader,
+                self.ring.channels,
+                self.target_channels,
+                self.ring.sample_rate,
+                self.target_sample_rate,
+            ))
+        });
+
+        let len = data.len() - data.len() % self.target_channels as usize;
+        let mut cnt = 0;
+        for (d, s) in data[..len].iter_mut().zip(conv) {
+            *d = convert_sample::<_, T>(s)
+                .mul_amp(self.volume.next_vol().into());
+            cnt += 1;
+        }
+
+        let starved = self.starved.swap(false, Ordering::Relaxed);
+        if starved && !self.underrun {
+            _ = self.err_callback.invoke(Error::StreamUnderrun);
+        }
+        self.underrun = starved;
+
+        // The stream ended, keep only whole frames.
+        cnt - cnt % self.target_channels as usize
+    }
+}
+
+/// Reads whole frames from the ring buffer. When there are no samples it
+/// produces silence and sets `starved`. It ends when the stream is closed
+/// and all the samples are read.
+struct RingReader {
+    /// The shared buffer
+    ring: Arc<Ring>,
+    /// The last read frame
+    frame: Vec<f32>,
+    /// Position of the next sample in `frame`
+    pos: usize,
+    /// Set when there are no samples
+    starved: Arc<AtomicBool>,
+}
+
+impl Iterator for RingReader {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos == self.frame.len() {
+            self.pos = 0;
+            // Check before reading so that samples written just before
+            // closing are not lost.
+            let closed = self.ring.closed.load(Ordering::Acquire);
+            if self.ring.pop(&mut self.frame) != self.frame.len() {
+                if closed {
+                    return None;
+                }
+                self.frame.fill(0.);
+                self.starved.store(true, Ordering::Relaxed);
+            }
+        }
+
+        let res = self.frame[self.pos];
+        self.pos += 1;
+        Some(res)
+    }
+}
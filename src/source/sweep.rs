@@ -0,0 +1,267 @@
+use std::{f64::consts::TAU, time::Duration};
+
+use anyhow::Result;
+use cpal::FromSample;
+
+use crate::{operate_samples, sample_buffer::SampleBufferMut, Timestamp};
+
+use super::{wave::fill_frames, Source, VolumeIterator};
+
+/// How the frequency of [`SweepSource`] changes over time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SweepKind {
+    /// The frequency changes exponentially, so each octave takes the same
+    /// time. Falls back to linear if any of the frequencies is not positive.
+    #[default]
+    Logarithmic,
+    /// The frequency changes linearly.
+    Linear,
+}
+
+/// Source of sine wave with frequency changing from start to end over the
+/// given duration (chirp).
+pub struct SweepSource {
+    /// Frequency at the start
+    start: f64,
+    /// Frequency at the end
+    end: f64,
+    /// Total duration of the sweep
+    duration: Duration,
+    /// How the frequency changes
+    kind: SweepKind,
+    /// Number of channels of the result
+    channels: u32,
+    /// Sample rate of the result
+    sample_rate: u32,
+    /// Total number of frames
+    total: u64,
+    /// Number of frames already produced
+    frame: u64,
+    /// Creates multiplier for each sample
+    volume: VolumeIterator,
+}
+
+impl Source for SweepSource {
+    fn init(&mut self, info: &super::DeviceConfig) -> Result<()> {
+        // Keep the position when the sample rate changes.
+        let pos = self.position();
+        self.channels = info.channel_count;
+        self.sample_rate = info.sample_rate;
+        self.total = self.to_frames(self.duration);
+        self.frame = self.to_frames(pos);
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut SampleBufferMut) -> (usize, Result<()>) {
+        if self.channels == 0 {
+            return (0, Ok(()));
+        }
+        operate_samples!(buffer, b, {
+            let frames = (b.len() / self.channels as usize)
+                .min((self.total - self.frame) as usize);
+            let len = frames * self.channels as usize;
+            self.generate(&mut b[..len]);
+            (len, Ok(()))
+        })
+    }
+
+    fn volume(&mut self, volume: super::VolumeIterator) -> bool {
+        self.volume = volume;
+        true
+    }
+
+    fn seek(&mut self, time: Duration) -> Result<Timestamp> {
+        self.frame = self.to_frames(time).min(self.total);
+        Ok(self.timestamp())
+    }
+
+    fn get_time(&self) -> Option<Timestamp> {
+        Some(self.timestamp())
+    }
+}
+
+impl SweepSource {
+    /// Creates source that generates logarithmic sine sweep from `start_hz`
+    /// to `end_hz` that lasts `duration`.
+    pub fn new(start_hz: f32, end_hz: f32, duration: Duration) -> Self {
+        Self {
+            start: start_hz as f64,
+            end: end_hz as f64,
+            duration,
+            kind: SweepKind::default(),
+            channels: 0,
+            sample_rate: 0,
+            total: 0,
+            frame: 0,
+            volume: VolumeIterator::constant(1.),
+        }
+    }
+
+    /// Sets how the frequency changes over time.
+    pub fn with_kind(mut self, kind: SweepKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// Generates the sweep
+    fn generate<T: FromSample<f32> + Clone>(&mut self, data: &mut [T]) {
+        let mut volume = std::mem::take(&mut self.volume);
+        fill_frames(data, self.channels, &mut volume, || {
+            let t = self.frame as f64 / self.sample_rate as f64;
+            self.frame += 1;
+            self.phase(t).sin() as f32
+        });
+        self.volume = volume;
+    }
+
+    /// Gets the phase of the sweep at the time `t` in seconds. The phase is
+    /// the integral of the frequency, so it is continuous.
+    fn phase(&self, t: f64) -> f64 {
+        let len = self.duration.as_secs_f64();
+        if len == 0. {
+            return 0.;
+        }
+
+        let ratio = self.end / self.start;
+        let log = self.kind == SweepKind::Logarithmic
+            && self.start > 0.
+            && self.end > 0.
+            && ratio != 1.;
+
+        if log {
+            let k = ratio.ln();
+            TAU * self.start * len / k * ((t / len * k).exp() - 1.)
+        } else {
+            TAU * (self.start * t + (self.end - self.start) * t * t / 2. / len)
+        }
+    }
+
+    /// Gets the current position and the total duration.
+    fn timestamp(&self) -> Timestamp {
+        Timestamp::new(self.position(), self.duration)
+    }
+
+    /// Gets the time of the current frame.
+    fn position(&self) -> Duration {
+        if self.sample_rate == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(
+                self.frame as f64 / self.sample_rate as f64,
+            )
+            .min(self.duration)
+        }
+    }
+
+    /// Converts the time to number of frames.
+    fn to_frames(&self, time: Duration) -> u64 {
+        (time.as_secs_f64() * self.sample_rate as f64).round() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cpal::SampleFormat;
+
+    use super::*;
+    use crate::source::DeviceConfig;
+
+    const RATE: f64 = 48000.;
+
+    /// Generates the whole sweep as mono at 48 kHz.
+    fn generate(mut src: SweepSource) -> Vec<f32> {
+        src.init(&DeviceConfig {
+            channel_count: 1,
+            sample_rate: RATE as u32,
+            sample_format: SampleFormat::F32,
+        })
+        .unwrap();
+        let mut res = vec![];
+        let mut data = vec![0.; 1000];
+        loop {
+            let (cnt, err) = src.read(&mut SampleBufferMut::F32(&mut data));
+            err.unwrap();
+            res.extend_from_slice(&data[..cnt]);
+            if cnt < data.len() {
+                return res;
+            }
+        }
+    }
+
+    /// Measures the frequency in the 20 ms window centered at `t` seconds
+    /// from the times of the zero crossings.
+    fn frequency(data: &[f32], t: f64) -> f64 {
+        let center = (t * RATE) as usize;
+        let window = &data[center - 480..center + 480];
+        let crossings: Vec<_> = window
+            .windows(2)
+            .enumerate()
+            .filter(|(_, w)| (w[0] < 0.) != (w[1] < 0.))
+            .map(|(i, w)| i as f64 + (w[0] / (w[0] - w[1])) as f64)
+            .collect();
+        let first = crossings.first().unwrap();
+        let last = crossings.last().unwrap();
+        (crossings.len() - 1) as f64 / 2. / ((last - first) / RATE)
+    }
+
+    fn check_frequency(data: &[f32], t: f64, expected: f64) {
+        let freq = frequency(data, t);
+        let err = (freq - expected).abs() / expected;
+        assert!(err < 0.02, "at {t} s: {freq} Hz, expected {expected} Hz");
+    }
+
+    #[test]
+    fn logarithmic_frequency() {
+        let data =
+            generate(SweepSource::new(200., 12800., Duration::from_secs(2)));
+        assert_eq!(data.len(), 96000);
+        // Each third of a second is one octave.
+        for t in [0.1, 0.5, 1., 1.5, 1.9] {
+            check_frequency(&data, t, 200. * 2_f64.powf(t * 3.));
+        }
+    }
+
+    #[test]
+    fn linear_frequency() {
+        let src = SweepSource::new(200., 5200., Duration::from_secs(2))
+            .with_kind(SweepKind::Linear);
+        let data = generate(src);
+        for t in [0.1, 0.5, 1., 1.5, 1.9] {
+            check_frequency(&data, t, 200. + 2500. * t);
+        }
+    }
+
+    #[test]
+    fn phase_is_continuous() {
+        let data =
+            generate(SweepSource::new(20., 10000., Duration::from_secs(1)));
+        // The largest possible step of sine at the end frequency
+        let max = (TAU * 10000. / RATE) as f32;
+        assert!(data.windows(2).all(|w| (w[1] - w[0]).abs() <= max));
+    }
+
+    #[test]
+    fn seek_and_time() {
+        let mut src = SweepSource::new(100., 1000., Duration::from_secs(1));
+        src.init(&DeviceConfig {
+            channel_count: 2,
+            sample_rate: RATE as u32,
+            sample_format: SampleFormat::F32,
+        })
+        .unwrap();
+
+        let t = src.seek(Duration::from_millis(990)).unwrap();
+        assert_eq!(t.current, Duration::from_millis(990));
+        assert_eq!(t.total, Duration::from_secs(1));
+
+        // Only 10 ms remain.
+        let mut data = vec![0.; 2000];
+        let (cnt, res) = src.read(&mut SampleBufferMut::F32(&mut data));
+        res.unwrap();
+        assert_eq!(cnt, 960);
+        assert_eq!(src.get_time().unwrap().current, Duration::from_secs(1));
+
+        let t = src.seek(Duration::from_secs(5)).unwrap();
+        assert_eq!(t.current, t.total);
+    }
+}
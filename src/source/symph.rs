@@ -1,13 +1,25 @@
-use std::time::Duration;
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    time::Duration,
+};
 
 use cpal::{SampleFormat, I24, U24};
 use symphonia::{
     core::{
         audio::AudioBufferRef,
-        codecs::Decoder,
+        codecs::{CodecRegistry, Decoder},
         formats::{SeekMode, SeekTo},
         io::{MediaSource, MediaSourceStream, MediaSourceStreamOptions},
-        probe::ProbeResult,
+        meta::MetadataRevision,
+        probe::{Hint, ProbeResult},
         sample::Sample,
         units::Time,
     },
@@ -15,14 +27,16 @@ use symphonia::{
 };
 use thiserror::Error;
 
+pub use super::read_ahead::{ReadSeekSource, ReadSource, DEFAULT_READ_AHEAD};
 pub use symphonia::core::formats::FormatOptions;
 
 use crate::{
     callback::Callback,
     converters::{do_channels_rate, interleave, UniSample},
     err, operate_samples,
+    passthrough::FrameHasher,
     sample_buffer::SampleBufferMut,
-    Timestamp,
+    SourceSpec, Timestamp,
 };
 
 use super::{DeviceConfig, Source, VolumeIterator};
@@ -49,8 +63,33 @@ pub struct Symph {
     volume: VolumeIterator,
     /// The timestamp of the last frame
     last_ts: u64,
+    /// The timestamp of the end of the last frame
+    end_ts: u64,
+    /// True if the unexpected end of the stream was already reported
+    end_reported: bool,
+    /// Index into the buffer, where to stop reading samples (in frames)
+    buffer_end: Option<usize>,
+    /// The loop points of the audio
+    loop_points: Option<LoopPoints>,
+    /// When true, the audio loops between the loop points
+    loop_enabled: bool,
+    /// True if the source jumped to the loop start and the samples before
+    /// the loop start should be skipped
+    loop_jumped: bool,
+    /// Number of bytes read from the source while probing the format
+    probe_bytes: u64,
+    /// The previous and the new spec if the spec changed mid-stream
+    spec_change: Option<(SourceSpec, SourceSpec)>,
+    /// Hashes the raw decoded frames if enabled
+    raw_hasher: Option<FrameHasher>,
+    /// Configuration requested after the sample rate changed mid-stream
+    reconfigure: Option<DeviceConfig>,
+    /// Number of successfully decoded packets
+    packets: Arc<AtomicU64>,
     /// Error callback for recoverable errors.
     err_callback: Callback<err::Error>,
+    /// False if the media source cannot seek
+    seekable: bool,
 }
 
 impl Symph {
@@ -64,28 +103,63 @@ impl Symph {
         source: T,
         opt: &SymphOptions,
     ) -> err::Result<Symph> {
-        let stream = MediaSourceStream::new(
-            Box::new(source),
-            MediaSourceStreamOptions::default(),
-        );
+        Self::try_new_hinted(source, opt, &Hint::new())
+    }
 
-        let pres = get_probe()
-            .format(
-                &Default::default(),
-                stream,
-                &opt.format,
-                &Default::default(),
-            )
-            .map_err(Error::SymphInner)?;
+    /// Opens the file at the given path and creates `Symph` from it. The
+    /// extension of the file is used as hint when probing the format.
+    ///
+    /// # Errors
+    /// - failed to open the file
+    /// - the format of the source cannot be determined
+    /// - no default track is found
+    /// - no decoder was found for the codec, insufficient codec parameters
+    pub fn open(
+        path: impl AsRef<Path>,
+        opt: &SymphOptions,
+    ) -> err::Result<Symph> {
+        let path = path.as_ref();
+        let mut hint = Hint::new();
+        if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+            hint.with_extension(ext);
+        }
+        Self::try_new_hinted(File::open(path)?, opt, &hint)
+    }
+
+    /// Creates new `Symph` and uses the hint when probing the format.
+    fn try_new_hinted<T: MediaSource + 'static>(
+        source: T,
+        opt: &SymphOptions,
+        hint: &Hint,
+    ) -> err::Result<Symph> {
+        let seekable = source.is_seekable();
+        let (pres, probe_bytes) = probe_format(source, opt, hint)?;
+        Self::with_codecs(pres, probe_bytes, seekable, get_codecs())
+    }
+
+    /// Creates new `Symph` from the probed format with decoder from the
+    /// given registry.
+    fn with_codecs(
+        mut pres: ProbeResult,
+        probe_bytes: u64,
+        seekable: bool,
+        codecs: &CodecRegistry,
+    ) -> err::Result<Symph> {
+        let loop_points = read_loop_points(&mut pres);
 
         // TODO: select other track if the default is unavailable
         let track =
             pres.format.default_track().ok_or(Error::CantSelectTrack)?;
         let track_id = track.id;
 
-        let decoder = get_codecs()
+        let decoder = codecs
             .make(&track.codec_params, &Default::default())
-            .map_err(Error::SymphInner)?;
+            .map_err(|e| match e {
+                symphonia::core::errors::Error::Unsupported(_) => {
+                    Error::UnsupportedCodec(e)
+                }
+                e => Error::SymphInner(e),
+            })?;
 
         Ok(Symph {
             target_sample_rate: 0,
@@ -98,7 +172,88 @@ impl Symph {
             buffer_start: None,
             volume: VolumeIterator::constant(1.),
             last_ts: 0,
+            end_ts: 0,
+            end_reported: false,
+            buffer_end: None,
+            loop_points,
+            loop_enabled: false,
+            loop_jumped: false,
+            probe_bytes,
+            spec_change: None,
+            raw_hasher: None,
+            reconfigure: None,
+            packets: Arc::default(),
             err_callback: Callback::default(),
+            seekable,
+        })
+    }
+
+    /// Gets the number of bytes that were read from the source while probing
+    /// the format. This includes data read ahead into the internal buffer.
+    pub fn probe_bytes(&self) -> u64 {
+        self.probe_bytes
+    }
+
+    /// Gets the loop points of the audio. They are read from the metadata
+    /// (`LOOPSTART` with `LOOPLENGTH` or `LOOPEND`) or set with
+    /// [`Symph::set_loop_points`].
+    pub fn loop_points(&self) -> Option<LoopPoints> {
+        self.loop_points
+    }
+
+    /// Sets the loop points of the audio.
+    pub fn set_loop_points(&mut self, loop_points: Option<LoopPoints>) {
+        self.loop_points = loop_points.filter(|l| l.end > l.start);
+    }
+
+    /// When enabled, the audio will seamlessly jump from the loop end to the
+    /// loop start. This has no effect if there are no loop points.
+    pub fn set_loop_enabled(&mut self, enabled: bool) {
+        self.loop_enabled = enabled;
+    }
+
+    /// Checks whether looping between the loop points is enabled.
+    pub fn is_loop_enabled(&self) -> bool {
+        self.loop_enabled
+    }
+
+    /// Gets information about the audio without creating the decoder.
+    ///
+    /// This is much cheaper than creating [`Symph`] and no audio is decoded.
+    ///
+    /// # Errors
+    /// - the format of the source cannot be determined
+    /// - no default track is found
+    pub fn probe<T: MediaSource + 'static>(
+        source: T,
+        opt: &SymphOptions,
+    ) -> err::Result<ProbeInfo> {
+        let (pres, _) = probe_format(source, opt, &Hint::new())?;
+
+        let track =
+            pres.format.default_track().ok_or(Error::CantSelectTrack)?;
+        let par = &track.codec_params;
+
+        let duration = if let (Some(time_base), Some(n_frames)) =
+            (par.time_base, par.n_frames)
+        {
+            let t = time_base.calc_time(n_frames);
+            Some(
+                Duration::from_secs(t.seconds)
+                    + Duration::from_secs_f64(t.frac),
+            )
+        } else {
+            None
+        };
+
+        let codec = get_codecs().get_codec(par.codec);
+
+        Ok(ProbeInfo {
+            duration,
+            sample_rate: par.sample_rate,
+            channel_count: par.channels.map(|c| c.count() as u32),
+            codec_name: codec.map(|c| c.short_name),
+            decoder_available: codec.is_some(),
         })
     }
 }
@@ -111,9 +266,14 @@ impl Source for Symph {
     fn init(&mut self, info: &DeviceConfig) -> anyhow::Result<()> {
         self.target_sample_rate = info.sample_rate;
         self.target_channels = info.channel_count;
+        self.reconfigure = None;
         Ok(())
     }
 
+    fn wants_reconfigure(&self) -> Option<DeviceConfig> {
+        self.reconfigure.clone()
+    }
+
     fn read(
         &mut self,
         buffer: &mut SampleBufferMut,
@@ -129,7 +289,9 @@ impl Source for Symph {
         let mut spec = dec.spec();
 
         if spec.rate == 0 && dec.frames() == 0 {
-            self.decode_packet().ok()?;
+            if !self.decode_packet().ok()? {
+                return None;
+            }
             self.buffer_start = Some(0);
             dec = self.decoder.last_decoded();
             spec = dec.spec();
@@ -160,10 +322,7 @@ impl Source for Symph {
 
     fn seek(&mut self, time: Duration) -> anyhow::Result<Timestamp> {
         let par = self.decoder.codec_params();
-        let time = Time::new(
-            time.as_secs(),
-            time.as_secs_f64() - time.as_secs_f64().trunc(),
-        );
+        let time = to_time(time);
 
         let seek_to = if let (Some(time_base), Some(max)) =
             (par.time_base, par.n_frames)
@@ -180,12 +339,75 @@ impl Source for Symph {
             }
         };
 
-        let pos = self.probed.format.seek(SeekMode::Coarse, seek_to)?;
+        self.seek_format(seek_to)
+    }
 
-        self.buffer_start = None;
-        self.last_ts = pos.actual_ts;
-        self.get_time()
-            .ok_or(err::Error::CannotDetermineTimestamp.into())
+    /// Seeks relative to the last decoded timestamp in the units of the
+    /// time base of the track, so that the position doesn't drift by the
+    /// rounding of the times.
+    fn seek_by(
+        &mut self,
+        time: Duration,
+        forward: bool,
+    ) -> anyhow::Result<Timestamp> {
+        let par = self.decoder.codec_params();
+        let Some(time_base) = par.time_base else {
+            return Err(err::Error::CannotDetermineTimestamp.into());
+        };
+
+        let delta = time_base.calc_timestamp(to_time(time));
+        let ts = if forward {
+            self.last_ts.saturating_add(delta)
+        } else {
+            self.last_ts.saturating_sub(delta)
+        };
+        let ts = match par.n_frames {
+            Some(max) => ts.min(max.saturating_sub(1)),
+            None => ts,
+        };
+
+        self.seek_format(SeekTo::TimeStamp {
+            ts,
+            track_id: self.track_id,
+        })
+    }
+
+    fn record_raw_hashes(&mut self, enable: bool) -> bool {
+        self.raw_hasher =
+            enable.then(|| FrameHasher::new(self.source_channels));
+        true
+    }
+
+    fn take_raw_hashes(&mut self, out: &mut VecDeque<u64>) {
+        if let Some(h) = &mut self.raw_hasher {
+            h.take(out);
+        }
+    }
+
+    fn take_spec_change(&mut self) -> Option<(SourceSpec, SourceSpec)> {
+        self.spec_change.take()
+    }
+
+    fn source_spec(&self) -> Option<SourceSpec> {
+        let par = self.decoder.codec_params();
+
+        let channel_count = if self.source_channels != 0 {
+            self.source_channels
+        } else {
+            par.channels?.count() as u32
+        };
+        let sample_rate = if self.source_sample_rate != 0 {
+            self.source_sample_rate
+        } else {
+            par.sample_rate?
+        };
+
+        Some(SourceSpec {
+            channel_count,
+            sample_rate,
+            bits_per_sample: par.bits_per_sample,
+            codec: get_codecs().get_codec(par.codec).map(|c| c.short_name),
+        })
     }
 
     fn get_time(&self) -> Option<Timestamp> {
@@ -213,6 +435,28 @@ impl Source for Symph {
 }
 
 impl Symph {
+    /// Seeks the format reader and resets the decoding state.
+    fn seek_format(&mut self, seek_to: SeekTo) -> anyhow::Result<Timestamp> {
+        if !self.seekable {
+            return Err(err::Error::Unsupported {
+                component: "Symph",
+                feature: "seeking in unseekable stream",
+            }
+            .into());
+        }
+
+        let pos = self.probed.format.seek(SeekMode::Coarse, seek_to)?;
+
+        self.buffer_start = None;
+        self.buffer_end = None;
+        self.loop_jumped = false;
+        self.last_ts = pos.actual_ts;
+        self.end_ts = pos.actual_ts;
+        self.end_reported = false;
+        self.get_time()
+            .ok_or(err::Error::CannotDetermineTimestamp.into())
+    }
+
     /// Continues decoding the audio
     fn decode<T: UniSample>(
         &mut self,
@@ -229,24 +473,167 @@ impl Symph {
             let i = self.read_buffer(&mut buffer, index);
             buffer = &mut buffer[i..];
             readed += i;
+            if let Err(e) = self.check_loop_end() {
+                return (readed, Err(e));
+            }
         }
 
+        let mut just_jumped = false;
         while !buffer.is_empty() {
             match self.decode_packet() {
-                Ok(_) => {}
+                Ok(true) => {}
+                Ok(false) if self.is_looping() && !just_jumped => {
+                    if let Err(e) = self.jump_to_loop_start() {
+                        return (readed, Err(e));
+                    }
+                    just_jumped = true;
+                    continue;
+                }
+                Ok(false) => return (readed, self.check_end()),
                 Err(e) => return (readed, Err(e)),
             }
+            just_jumped = false;
 
-            let i = self.read_buffer(&mut buffer, 0);
+            let Some(start) = self.loop_bounds() else {
+                continue;
+            };
+
+            let i = self.read_buffer(
+                &mut buffer,
+                start * self.source_channels as usize,
+            );
             buffer = &mut buffer[i..];
             readed += i;
+            if let Err(e) = self.check_loop_end() {
+                return (readed, Err(e));
+            }
         }
 
         (readed, Ok(()))
     }
 
-    /// Decodes the next packet
-    fn decode_packet(&mut self) -> Result<(), Error> {
+    /// Checks whether the audio loops.
+    fn is_looping(&self) -> bool {
+        self.loop_enabled && self.loop_points.is_some()
+    }
+
+    /// Limits the last decoded packet by the loop points. Returns the first
+    /// frame of the packet that should be read or [`None`] if the whole
+    /// packet should be skipped.
+    fn loop_bounds(&mut self) -> Option<usize> {
+        self.buffer_end = None;
+        let lp = match self.loop_points {
+            Some(lp) if self.loop_enabled => lp,
+            _ => return Some(0),
+        };
+        let start_ts = self.frames_to_ts(lp.start);
+        let end_ts = self.frames_to_ts(lp.end);
+
+        let mut start = 0;
+        if self.loop_jumped {
+            if self.end_ts <= start_ts {
+                return None;
+            }
+            self.loop_jumped = false;
+            start = self.ts_to_frames(start_ts.saturating_sub(self.last_ts));
+        }
+
+        if self.last_ts < end_ts && self.end_ts > end_ts {
+            self.buffer_end = Some(self.ts_to_frames(end_ts - self.last_ts));
+        }
+
+        Some(start)
+    }
+
+    /// Jumps to the loop start if the loop end was reached.
+    fn check_loop_end(&mut self) -> Result<(), Error> {
+        if self.buffer_start.is_none() && self.buffer_end.take().is_some() {
+            self.jump_to_loop_start()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Seeks to the loop start.
+    fn jump_to_loop_start(&mut self) -> Result<(), Error> {
+        let Some(lp) = self.loop_points else {
+            return Ok(());
+        };
+
+        let pos = self.probed.format.seek(
+            SeekMode::Accurate,
+            SeekTo::TimeStamp {
+                ts: self.frames_to_ts(lp.start),
+                track_id: self.track_id,
+            },
+        )?;
+        self.decoder.reset();
+
+        self.buffer_start = None;
+        self.buffer_end = None;
+        self.last_ts = pos.actual_ts;
+        self.end_ts = pos.actual_ts;
+        self.loop_jumped = true;
+        Ok(())
+    }
+
+    /// Converts timestamp to number of frames.
+    fn ts_to_frames(&self, ts: u64) -> usize {
+        match self.decoder.codec_params().time_base {
+            Some(tb) if self.source_sample_rate != 0 => {
+                (ts as u128
+                    * tb.numer as u128
+                    * self.source_sample_rate as u128
+                    / tb.denom as u128) as usize
+            }
+            _ => ts as usize,
+        }
+    }
+
+    /// Converts number of frames to timestamp.
+    fn frames_to_ts(&self, frames: u64) -> u64 {
+        match self.decoder.codec_params().time_base {
+            Some(tb) if self.source_sample_rate != 0 => {
+                (frames as u128 * tb.denom as u128
+                    / (tb.numer as u128 * self.source_sample_rate as u128))
+                    as u64
+            }
+            _ => frames,
+        }
+    }
+
+    /// Checks whether the stream ended where it should. Returns error the
+    /// first time it is called on stream that ended too soon.
+    fn check_end(&mut self) -> Result<(), Error> {
+        if self.end_reported {
+            return Ok(());
+        }
+        self.end_reported = true;
+
+        let par = self.decoder.codec_params();
+        let (Some(time_base), Some(n_frames)) = (par.time_base, par.n_frames)
+        else {
+            return Ok(());
+        };
+
+        // tolerate small differences caused by encoder delay and padding,
+        // but not so large that a short stream could miss half its length
+        let padding = par.delay.unwrap_or_default() as u64
+            + par.padding.unwrap_or_default() as u64;
+        let tolerance = time_base
+            .calc_timestamp(Time::new(0, 0.1))
+            .min(n_frames / 10)
+            + padding;
+        if self.end_ts + tolerance < n_frames {
+            Err(Error::UnexpectedEnd)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Decodes the next packet. Returns false if the end of the stream was
+    /// reached.
+    fn decode_packet(&mut self) -> Result<bool, Error> {
         loop {
             let packet = loop {
                 match self.probed.format.next_packet() {
@@ -255,20 +642,28 @@ impl Symph {
                             continue;
                         }
                         self.last_ts = p.ts;
+                        self.end_ts = p.ts + p.dur;
                         break p;
                     }
                     Err(symphonia::core::errors::Error::ResetRequired) => {
                         self.decoder.reset()
                     }
+                    Err(symphonia::core::errors::Error::IoError(e))
+                        if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+                    {
+                        return Ok(false);
+                    }
                     Err(e) => return Err(e.into()),
                 }
             };
 
             break match self.decoder.decode(&packet) {
                 Ok(d) => {
-                    self.source_sample_rate = d.spec().rate;
-                    self.source_channels = d.spec().channels.count() as u32;
-                    Ok(())
+                    let rate = d.spec().rate;
+                    let channels = d.spec().channels.count() as u32;
+                    self.packets.fetch_add(1, Ordering::Relaxed);
+                    self.update_spec(rate, channels);
+                    Ok(true)
                 }
                 // Try to recover from recoverable errors.
                 Err(symphonia::core::errors::Error::ResetRequired) => continue,
@@ -286,6 +681,39 @@ impl Symph {
         }
     }
 
+    /// Updates the spec of the decoded audio. If the spec changed in the
+    /// middle of the stream, the change is recorded so that it can be
+    /// reported.
+    fn update_spec(&mut self, rate: u32, channels: u32) {
+        if self.source_channels == channels && self.source_sample_rate == rate
+        {
+            return;
+        }
+
+        let old = (self.source_channels != 0)
+            .then(|| self.source_spec())
+            .flatten();
+
+        // The previous packet was fully read (new packet is decoded only
+        // when `buffer_start` is `None`), so the position in the decoded
+        // buffer is always on frame boundary of the new spec.
+        self.buffer_start = None;
+        self.source_sample_rate = rate;
+        self.source_channels = channels;
+        if let Some(h) = &mut self.raw_hasher {
+            h.set_channels(channels);
+        }
+
+        if old.is_some() && rate != self.target_sample_rate {
+            self.reconfigure = self.preferred_config();
+        }
+
+        if let (Some(old), Some(new)) = (old, self.source_spec()) {
+            let old = self.spec_change.take().map_or(old, |(o, _)| o);
+            self.spec_change = (old != new).then_some((old, new));
+        }
+    }
+
     /// reads from the decoders buffer into the given buffer, returns number
     /// of written samples
     fn read_buffer<T: UniSample>(
@@ -303,20 +731,59 @@ impl Symph {
         let samples = self.decoder.last_decoded();
         let mut i = 0;
 
+        let direct = self.source_channels == self.target_channels
+            && self.source_sample_rate == self.target_sample_rate;
+        let unity = self.volume.is_unity();
+
         macro_rules! arm {
             ($mnam:ident, $map:expr, $src:ident) => {{
+                if direct {
+                    // The config matches, interleave the samples directly
+                    // to the buffer without the converters.
+                    let planes = $src.planes();
+                    let planes = planes.planes();
+                    let channels = planes.len().max(1);
+                    let frames = planes.first().map_or(0, |p| p.len());
+                    let end = self.buffer_end.unwrap_or(frames).min(frames)
+                        * channels;
+                    let mut idx = start;
+                    while idx < end && i < buffer.len() {
+                        let $mnam = &planes[idx % channels][idx / channels];
+                        let s = $map;
+                        if let Some(h) = &mut self.raw_hasher {
+                            h.push(s);
+                        }
+                        buffer[i] = if unity {
+                            T::from_sample(s)
+                        } else {
+                            T::from_sample(s)
+                                .mul_amp(self.volume.next_vol().into())
+                        };
+                        i += 1;
+                        idx += 1;
+                    }
+                    self.buffer_start = (idx < end).then_some(idx);
+                    return i;
+                }
+
                 let mut len = 0;
                 let mut last_index = 0;
                 for s in do_channels_rate(
                     interleave($src.planes().planes().iter().map(|i| {
-                        let slice =
-                            &i[start / self.source_channels as usize..];
+                        let end =
+                            self.buffer_end.unwrap_or(i.len()).min(i.len());
+                        let start = start / self.source_channels as usize;
+                        let slice = &i[start.min(end)..end];
                         len += slice.len();
                         slice.iter()
                     }))
                     .map(|$mnam| {
                         last_index += 1;
-                        $map
+                        let s = $map;
+                        if let Some(h) = &mut self.raw_hasher {
+                            h.push(s);
+                        }
+                        s
                     }),
                     self.source_channels,
                     self.target_channels,
@@ -331,10 +798,13 @@ impl Symph {
                     }
                 }
 
+                // The converters may read ahead to the middle of frame, keep
+                // the position on frame boundary.
+                let channels = self.source_channels.max(1) as usize;
                 self.buffer_start = if last_index == len {
                     None
                 } else {
-                    Some(last_index + start)
+                    Some((last_index + start) / channels * channels)
                 }
             }};
         }
@@ -360,9 +830,398 @@ impl Symph {
     }
 }
 
+/// Runs the whole pipeline of decoding and converting the source to
+/// completion without playing it. The audio is converted to 2 channels at
+/// 48000 Hz and discarded. This is intended for testing the robustness of
+/// the decoding on malformed files (e.g. from fuzz targets), it should never
+/// panic or loop forever.
+///
+/// The source is played with [`crate::render`], so it goes through the same
+/// playback loop as with [`crate::Sink`] (e.g. the buffered samples are
+/// flushed after the end and the source is stopped if it stalls).
+///
+/// # Errors
+/// - the source cannot be opened (see [`Symph::try_new`])
+/// - the source fails to init
+#[cfg(any(test, feature = "test-util"))]
+pub fn decode_all<T: MediaSource + 'static>(
+    source: T,
+    opt: &SymphOptions,
+) -> err::Result<DecodeSummary> {
+    let src = Symph::try_new(source, opt)?;
+    let packets = src.packets.clone();
+
+    let config = DeviceConfig {
+        channel_count: 2,
+        sample_rate: 48000,
+        sample_format: SampleFormat::F32,
+    };
+    let mut iter = crate::render(Box::new(src), config, 2048)?;
+
+    let summary = Arc::new(Mutex::new(DecodeSummary::default()));
+    let errs = summary.clone();
+    iter.sink().on_err_callback(Some(move |e| {
+        let mut s = errs.lock().unwrap_or_else(PoisonError::into_inner);
+        match e {
+            err::Error::SourceStalled(_) => s.stalled = true,
+            err::Error::Symph(
+                Error::SymphRecoverable(_) | Error::UnexpectedEnd,
+            ) => s.recoverable_errors += 1,
+            _ => s.errors += 1,
+        }
+    }))?;
+
+    let samples = iter.by_ref().map(|b| b.len() as u64).sum();
+    drop(iter);
+
+    let mut summary = summary.lock().unwrap_or_else(PoisonError::into_inner);
+    summary.samples = samples;
+    summary.packets = packets.load(Ordering::Relaxed);
+    Ok(summary.clone())
+}
+
+/// Result of [`decode_all`]
+#[cfg(any(test, feature = "test-util"))]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DecodeSummary {
+    /// Number of successfully decoded packets
+    pub packets: u64,
+    /// Number of errors reported through the error callback (e.g.
+    /// recoverable decode errors or truncated stream)
+    pub recoverable_errors: u64,
+    /// Number of other errors (e.g. reads that returned error)
+    pub errors: u64,
+    /// Number of rendered samples (2 channels at 48000 Hz), this includes
+    /// the silence after the end in the last buffer
+    pub samples: u64,
+    /// True if the source stopped producing samples without ending
+    pub stalled: bool,
+}
+
+/// Converts the duration to symphonia time.
+fn to_time(time: Duration) -> Time {
+    Time::new(
+        time.as_secs(),
+        time.as_secs_f64() - time.as_secs_f64().trunc(),
+    )
+}
+
+/// Reads the loop points from the metadata.
+fn read_loop_points(pres: &mut ProbeResult) -> Option<LoopPoints> {
+    let mut start = None;
+    let mut length = None;
+    let mut end = None;
+
+    let mut read = |rev: &MetadataRevision| {
+        for tag in rev.tags() {
+            let value = tag.value.to_string().trim().parse::<u64>().ok();
+            match tag.key.to_uppercase().as_str() {
+                "LOOPSTART" => start = value.or(start),
+                "LOOPLENGTH" => length = value.or(length),
+                "LOOPEND" => end = value.or(end),
+                _ => {}
+            }
+        }
+    };
+
+    if let Some(rev) = pres.metadata.get().as_ref().and_then(|m| m.current()) {
+        read(rev);
+    }
+    if let Some(rev) = pres.format.metadata().current() {
+        read(rev);
+    }
+
+    let start = start?;
+    let end = end.or(length.map(|l| start + l))?;
+    (end > start).then_some(LoopPoints { start, end })
+}
+
+/// Probes the format of the source. Returns the probe result and the
+/// number of bytes read while probing.
+fn probe_format<T: MediaSource + 'static>(
+    source: T,
+    opt: &SymphOptions,
+    hint: &Hint,
+) -> err::Result<(ProbeResult, u64)> {
+    if opt.probe == ProbeDepth::Deep && source.is_seekable() {
+        return probe_deep(source, opt, hint);
+    }
+
+    let (res, read) = probe_limited(source, opt, hint);
+    Ok((res?, read))
+}
+
+/// Probes the format of the seekable source without limit. If symphonia
+/// doesn't find the format marker within its search limit, the search
+/// continues further in the source.
+fn probe_deep<T: MediaSource + 'static>(
+    source: T,
+    opt: &SymphOptions,
+    hint: &Hint,
+) -> err::Result<(ProbeResult, u64)> {
+    // Markers may span the boundary of the searched data and the stream
+    // reads ahead, so the searches overlap.
+    const OVERLAP: u64 = 64 * 1024 + 16;
+    const SEARCH_LIMIT: u64 = 1024 * 1024;
+
+    let inner = Arc::new(Mutex::new(source));
+    let mut base = 0;
+    let mut total = 0;
+    loop {
+        let source = OffsetSource::new(inner.clone(), base)?;
+        let (res, read) = probe_limited(source, opt, hint);
+        total += read;
+        match res {
+            Err(Error::UnknownFormat(_)) if read > SEARCH_LIMIT => {
+                base += read - OVERLAP;
+            }
+            res => return Ok((res?, total)),
+        }
+    }
+}
+
+/// Probes the format of the source with the limit given by the options.
+/// Returns the result and the number of bytes read while probing.
+fn probe_limited<T: MediaSource + 'static>(
+    source: T,
+    opt: &SymphOptions,
+    hint: &Hint,
+) -> (Result<ProbeResult, Error>, u64) {
+    let state = Arc::new(ProbeState {
+        read: AtomicU64::new(0),
+        active: AtomicBool::new(true),
+    });
+
+    let stream = MediaSourceStream::new(
+        Box::new(ProbeLimit {
+            inner: source,
+            state: state.clone(),
+            limit: opt.probe.limit(),
+            skip_id3: opt.probe == ProbeDepth::Shallow,
+            header: [0; ID3_HEADER_LEN],
+            header_len: 0,
+        }),
+        MediaSourceStreamOptions::default(),
+    );
+
+    // Symphonia may panic on malformed headers (e.g. zero sample rate).
+    let res = panic::catch_unwind(AssertUnwindSafe(|| {
+        get_probe().format(hint, stream, &opt.format, &Default::default())
+    }))
+    .map_err(|_| Error::ProbePanic)
+    .and_then(|r| {
+        r.map_err(|e| match e {
+            symphonia::core::errors::Error::Unsupported(_) => {
+                Error::UnknownFormat(e)
+            }
+            e => Error::SymphInner(e),
+        })
+    });
+
+    // The format reader keeps reading through the limiter, stop counting.
+    state.active.store(false, Ordering::Relaxed);
+    (res, state.read.load(Ordering::Relaxed))
+}
+
+/// Length of the header of ID3v2 tag
+const ID3_HEADER_LEN: usize = 10;
+
+/// Gets the total size of ID3v2 tag with the given header. Returns [`None`]
+/// if the header is not ID3v2 header.
+fn id3_size(header: &[u8; ID3_HEADER_LEN]) -> Option<u64> {
+    if &header[..3] != b"ID3" || header[6..].iter().any(|b| b & 0x80 != 0) {
+        return None;
+    }
+    let size = header[6..]
+        .iter()
+        .fold(0_u64, |s, b| (s << 7) | u64::from(*b));
+    let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+    Some(ID3_HEADER_LEN as u64 + size + footer)
+}
+
+/// State of the probing shared with [`ProbeLimit`]
+struct ProbeState {
+    /// Number of bytes read from the source while probing
+    read: AtomicU64,
+    /// True while probing, the reads are not limited or counted after that
+    active: AtomicBool,
+}
+
+/// Media source that limits the number of bytes that can be read while
+/// probing the format.
+struct ProbeLimit<T: MediaSource> {
+    /// The actual source
+    inner: T,
+    /// State shared with the prober
+    state: Arc<ProbeState>,
+    /// Maximum number of bytes that can be read from the source
+    limit: u64,
+    /// When true, ID3v2 tag at the start of the source doesn't count to the
+    /// limit
+    skip_id3: bool,
+    /// The start of the source, used to detect ID3v2 tag
+    header: [u8; ID3_HEADER_LEN],
+    /// Number of valid bytes in `header`
+    header_len: usize,
+}
+
+impl<T: MediaSource> ProbeLimit<T> {
+    /// Extends the limit by the size of ID3v2 tag once its header is read.
+    fn check_id3(&mut self, data: &[u8]) {
+        let len = data.len().min(ID3_HEADER_LEN - self.header_len);
+        self.header[self.header_len..self.header_len + len]
+            .copy_from_slice(&data[..len]);
+        self.header_len += len;
+        if self.header_len == ID3_HEADER_LEN {
+            self.skip_id3 = false;
+            if let Some(size) = id3_size(&self.header) {
+                self.limit = self.limit.saturating_add(size);
+            }
+        }
+    }
+}
+
+impl<T: MediaSource> Read for ProbeLimit<T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if !self.state.active.load(Ordering::Relaxed) {
+            return self.inner.read(buf);
+        }
+
+        let read = self.state.read.load(Ordering::Relaxed);
+        let remaining = self.limit.saturating_sub(read);
+        let len = buf.len().min(remaining.min(usize::MAX as u64) as usize);
+        let n = self.inner.read(&mut buf[..len])?;
+        if self.skip_id3 {
+            self.check_id3(&buf[..n]);
+        }
+        self.state.read.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+impl<T: MediaSource> Seek for ProbeLimit<T> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+impl<T: MediaSource> MediaSource for ProbeLimit<T> {
+    fn is_seekable(&self) -> bool {
+        self.inner.is_seekable()
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        self.inner.byte_len()
+    }
+}
+
+/// Shared seekable source that starts at the given offset. Used to probe
+/// the same source multiple times from different positions.
+struct OffsetSource<T: MediaSource> {
+    /// The shared source
+    inner: Arc<Mutex<T>>,
+    /// Position in the inner source that is the start of this source
+    base: u64,
+}
+
+impl<T: MediaSource> OffsetSource<T> {
+    /// Creates source that starts at `base` and seeks there.
+    fn new(inner: Arc<Mutex<T>>, base: u64) -> std::io::Result<Self> {
+        let mut res = Self { inner, base };
+        res.seek(SeekFrom::Start(0))?;
+        Ok(res)
+    }
+
+    /// Locks the inner source
+    fn lock(&self) -> MutexGuard<'_, T> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<T: MediaSource> Read for OffsetSource<T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.lock().read(buf)
+    }
+}
+
+impl<T: MediaSource> Seek for OffsetSource<T> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(p) => SeekFrom::Start(self.base + p),
+            p => p,
+        };
+        Ok(self.lock().seek(pos)?.saturating_sub(self.base))
+    }
+}
+
+impl<T: MediaSource> MediaSource for OffsetSource<T> {
+    fn is_seekable(&self) -> bool {
+        self.lock().is_seekable()
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        self.lock().byte_len().map(|l| l.saturating_sub(self.base))
+    }
+}
+
+/// Loop points of audio in frames of the source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoopPoints {
+    /// The first frame of the loop
+    pub start: u64,
+    /// The frame after the last frame of the loop
+    pub end: u64,
+}
+
+/// Information about audio obtained with [`Symph::probe`]
+#[derive(Debug, Clone)]
+pub struct ProbeInfo {
+    /// Total duration of the audio if it is known
+    pub duration: Option<Duration>,
+    /// Sample rate of the audio if it is known
+    pub sample_rate: Option<u32>,
+    /// Number of channels of the audio if it is known
+    pub channel_count: Option<u32>,
+    /// Short name of the codec, [`None`] if the codec is not supported
+    pub codec_name: Option<&'static str>,
+    /// True if there is decoder available for the codec
+    pub decoder_available: bool,
+}
+
+/// How much data may be read when probing the format of the source
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ProbeDepth {
+    /// Read at most 64 KiB after the ID3v2 tag at the start of the source.
+    /// Use this for slow sources with well formed files.
+    Shallow,
+    /// Use the default limit of symphonia (1 MiB of searching for the
+    /// format marker).
+    #[default]
+    Default,
+    /// Search the whole source for the format marker. Only seekable sources
+    /// can be searched past the limit of symphonia, others behave as with
+    /// [`ProbeDepth::Default`].
+    Deep,
+    /// Read at most the given number of bytes.
+    Limit(u64),
+}
+
+impl ProbeDepth {
+    /// Gets the maximum number of bytes that may be read while probing.
+    fn limit(&self) -> u64 {
+        match self {
+            Self::Shallow => 64 * 1024,
+            Self::Default | Self::Deep => u64::MAX,
+            Self::Limit(l) => *l,
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct SymphOptions {
     pub format: FormatOptions,
+    /// How much data may be read when probing the format
+    pub probe: ProbeDepth,
 }
 
 /// Error type for the symph
@@ -371,6 +1230,18 @@ pub enum Error {
     /// Cannot select track to decode
     #[error("Failed to select a track")]
     CantSelectTrack,
+    /// The stream ended before its reported length
+    #[error("The stream ended unexpectedly, the file may be truncated")]
+    UnexpectedEnd,
+    /// The format of the source is not known
+    #[error("Unknown format: {0}")]
+    UnknownFormat(symphonia::core::errors::Error),
+    /// The format is known but there is no decoder for the codec
+    #[error("Unsupported codec: {0}")]
+    UnsupportedCodec(symphonia::core::errors::Error),
+    /// Symphonia panicked while probing the format, the file is malformed
+    #[error("Failed to read the format, the file may be malformed")]
+    ProbePanic,
     /// Recoverable error from symphonia
     #[error("Recoverable symphonia error: {0}")]
     SymphRecoverable(symphonia::core::errors::Error),
@@ -378,3 +1249,446 @@ pub enum Error {
     #[error(transparent)]
     SymphInner(#[from] symphonia::core::errors::Error),
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use symphonia::core::{
+        audio::{AsAudioBufferRef, AudioBuffer, Layout, Signal, SignalSpec},
+        codecs::{
+            decl_codec_type, CodecDescriptor, CodecParameters, CodecType,
+            DecoderOptions, FinalizeResult,
+        },
+        errors::unsupported_error,
+        formats::{Cue, FormatReader, Packet, SeekedTo, Track},
+        io::ReadBytes,
+        meta::{Metadata, MetadataLog},
+        probe::{Descriptor, Instantiate, Probe},
+        units::TimeBase,
+    };
+
+    use super::*;
+
+    /// Creates wav file with `frames` frames of stereo 48000 Hz sine.
+    fn wav(frames: usize) -> Vec<u8> {
+        let data_len = (frames * 2 * 2) as u32;
+        let mut res = vec![];
+        res.extend_from_slice(b"RIFF");
+        res.extend_from_slice(&(36 + data_len).to_le_bytes());
+        res.extend_from_slice(b"WAVEfmt ");
+        res.extend_from_slice(&16_u32.to_le_bytes());
+        res.extend_from_slice(&1_u16.to_le_bytes());
+        res.extend_from_slice(&2_u16.to_le_bytes());
+        res.extend_from_slice(&48000_u32.to_le_bytes());
+        res.extend_from_slice(&(48000_u32 * 4).to_le_bytes());
+        res.extend_from_slice(&4_u16.to_le_bytes());
+        res.extend_from_slice(&16_u16.to_le_bytes());
+        res.extend_from_slice(b"data");
+        res.extend_from_slice(&data_len.to_le_bytes());
+        for i in 0..frames {
+            let s = ((i as f32 * 0.05).sin() * 10000.) as i16;
+            res.extend_from_slice(&s.to_le_bytes());
+            res.extend_from_slice(&s.to_le_bytes());
+        }
+        res
+    }
+
+    /// Creates ID3v2.4 tag with `padding` bytes of padding.
+    fn id3(padding: usize) -> Vec<u8> {
+        let mut res = b"ID3\x04\x00\x00".to_vec();
+        res.extend((0..4).rev().map(|i| ((padding >> (7 * i)) & 0x7f) as u8));
+        res.resize(ID3_HEADER_LEN + padding, 0);
+        res
+    }
+
+    fn options(probe: ProbeDepth) -> SymphOptions {
+        SymphOptions {
+            probe,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn id3_size_reads_syncsafe_integer() {
+        let tag = id3(0x1234);
+        let header: [u8; ID3_HEADER_LEN] = tag[..10].try_into().unwrap();
+        assert_eq!(id3_size(&header), Some(10 + 0x1234));
+        assert_eq!(id3_size(b"RIFF\0\0\0\0\0\0"), None);
+    }
+
+    #[test]
+    fn shallow_probe_skips_id3() {
+        let mut data = id3(100 * 1024);
+        data.extend(wav(4800));
+
+        let src = Cursor::new(data.clone());
+        let res = Symph::try_new(src, &options(ProbeDepth::Shallow));
+        assert!(res.is_ok());
+
+        let src = Cursor::new(data);
+        let res = Symph::try_new(src, &options(ProbeDepth::Limit(64 * 1024)));
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn shallow_probe_is_limited() {
+        let mut data = vec![0; 128 * 1024];
+        data.extend(wav(4800));
+
+        let src = Cursor::new(data);
+        let res = Symph::try_new(src, &options(ProbeDepth::Shallow));
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn deep_probe_searches_whole_source() {
+        let mut data = vec![0; 3 * 1024 * 1024];
+        data.extend(wav(4800));
+
+        let src = Cursor::new(data.clone());
+        let res = Symph::try_new(src, &options(ProbeDepth::Default));
+        assert!(res.is_err());
+
+        let src = Cursor::new(data);
+        let symph = Symph::try_new(src, &options(ProbeDepth::Deep)).unwrap();
+        assert!(symph.probe_bytes() > 3 * 1024 * 1024);
+        let time = symph.get_time().unwrap();
+        assert_eq!(time.total, Duration::from_millis(100));
+    }
+
+    /// Decodes the file in separate thread so that hang fails the test.
+    fn decode_file(path: &std::path::Path) -> err::Result<DecodeSummary> {
+        let data = std::fs::read(path).unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            _ = tx.send(decode_all(Cursor::new(data), &Default::default()));
+        });
+        rx.recv_timeout(Duration::from_secs(10))
+            .unwrap_or_else(|e| panic!("decoding {path:?} failed: {e}"))
+    }
+
+    #[test]
+    fn decode_corpus() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/corpus");
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|e| e == "py") {
+                continue;
+            }
+
+            let res = decode_file(&path);
+            let name = path.file_name().unwrap().to_string_lossy();
+            if name.starts_with("valid") {
+                let summary = res.unwrap();
+                assert!(summary.packets > 0, "{name}: {summary:?}");
+                assert!(summary.samples >= 4800 * 2, "{name}: {summary:?}");
+                assert_eq!(summary.errors, 0, "{name}: {summary:?}");
+                assert!(!summary.stalled, "{name}: {summary:?}");
+            }
+        }
+    }
+
+    /// Codec of [`SpecStream`]
+    const SPEC_CODEC: CodecType = decl_codec_type(b"spec");
+
+    /// Format with packets of any spec. The stream starts with `SPEC` and
+    /// the length in milliseconds (u32). Each packet has the channel count
+    /// (u8), sample rate (u32) and frame count (u16) followed by the
+    /// interleaved f32 samples.
+    struct SpecStream {
+        reader: MediaSourceStream,
+        tracks: Vec<Track>,
+        metadata: MetadataLog,
+        ts: u64,
+    }
+
+    /// Decodes packets of [`SpecStream`], the spec of the decoded buffer is
+    /// given by each packet.
+    struct SpecDecoder {
+        params: CodecParameters,
+        buf: AudioBuffer<f32>,
+    }
+
+    impl FormatReader for SpecStream {
+        fn try_new(
+            mut reader: MediaSourceStream,
+            _options: &FormatOptions,
+        ) -> symphonia::core::errors::Result<Self> {
+            reader.read_quad_bytes()?;
+            let len = reader.read_u32()?;
+            let mut params = CodecParameters::new();
+            params
+                .for_codec(SPEC_CODEC)
+                .with_time_base(TimeBase::new(1, 1000))
+                .with_n_frames(len.into());
+            Ok(Self {
+                reader,
+                tracks: vec![Track::new(0, params)],
+                metadata: MetadataLog::default(),
+                ts: 0,
+            })
+        }
+
+        fn cues(&self) -> &[Cue] {
+            &[]
+        }
+
+        fn metadata(&mut self) -> Metadata<'_> {
+            self.metadata.metadata()
+        }
+
+        fn seek(
+            &mut self,
+            _mode: SeekMode,
+            _to: SeekTo,
+        ) -> symphonia::core::errors::Result<SeekedTo> {
+            unsupported_error("seek")
+        }
+
+        fn tracks(&self) -> &[Track] {
+            &self.tracks
+        }
+
+        fn next_packet(&mut self) -> symphonia::core::errors::Result<Packet> {
+            let header = self.reader.read_boxed_slice_exact(7)?;
+            let channels = header[0] as usize;
+            let rate = u32::from_le_bytes(header[1..5].try_into().unwrap());
+            let frames = u16::from_le_bytes(header[5..].try_into().unwrap());
+            let samples = self
+                .reader
+                .read_boxed_slice_exact(frames as usize * channels * 4)?;
+
+            let dur = frames as u64 * 1000 / rate as u64;
+            let data = [header, samples].concat();
+            let packet = Packet::new_from_slice(0, self.ts, dur, &data);
+            self.ts += dur;
+            Ok(packet)
+        }
+
+        fn into_inner(self: Box<Self>) -> MediaSourceStream {
+            self.reader
+        }
+    }
+
+    impl Decoder for SpecDecoder {
+        fn try_new(
+            params: &CodecParameters,
+            _options: &DecoderOptions,
+        ) -> symphonia::core::errors::Result<Self> {
+            Ok(Self {
+                params: params.clone(),
+                buf: AudioBuffer::unused(),
+            })
+        }
+
+        fn supported_codecs() -> &'static [CodecDescriptor] {
+            &[CodecDescriptor {
+                codec: SPEC_CODEC,
+                short_name: "spec",
+                long_name: "Spec changing test codec",
+                inst_func: |par, opt| Ok(Box::new(Self::try_new(par, opt)?)),
+            }]
+        }
+
+        fn reset(&mut self) {}
+
+        fn codec_params(&self) -> &CodecParameters {
+            &self.params
+        }
+
+        fn decode(
+            &mut self,
+            packet: &Packet,
+        ) -> symphonia::core::errors::Result<AudioBufferRef<'_>> {
+            let data = packet.buf();
+            let layout = if data[0] == 1 {
+                Layout::Mono
+            } else {
+                Layout::Stereo
+            };
+            let rate = u32::from_le_bytes(data[1..5].try_into().unwrap());
+            let frames = u16::from_le_bytes(data[5..7].try_into().unwrap());
+            let spec = SignalSpec::new_with_layout(rate, layout);
+            if *self.buf.spec() != spec {
+                self.buf = AudioBuffer::new(u16::MAX.into(), spec);
+            }
+
+            self.buf.clear();
+            self.buf.render_reserved(Some(frames as usize));
+            let channels = data[0] as usize;
+            for (i, s) in data[7..].chunks_exact(4).enumerate() {
+                self.buf.chan_mut(i % channels)[i / channels] =
+                    f32::from_le_bytes(s.try_into().unwrap());
+            }
+            Ok(self.buf.as_audio_buffer_ref())
+        }
+
+        fn finalize(&mut self) -> FinalizeResult {
+            FinalizeResult::default()
+        }
+
+        fn last_decoded(&self) -> AudioBufferRef<'_> {
+            self.buf.as_audio_buffer_ref()
+        }
+    }
+
+    /// Creates [`SpecStream`] with packets of 10 ms. Each packet is given by
+    /// its sample rate and the frame that is repeated in the whole packet.
+    fn spec_stream(packets: &[(u32, &[f32])]) -> Symph {
+        let mut data = b"SPEC".to_vec();
+        data.extend_from_slice(&(packets.len() as u32 * 10).to_le_bytes());
+        for (rate, frame) in packets {
+            let frames = (rate / 100) as u16;
+            data.push(frame.len() as u8);
+            data.extend_from_slice(&rate.to_le_bytes());
+            data.extend_from_slice(&frames.to_le_bytes());
+            for s in (0..frames).flat_map(|_| frame.iter()) {
+                data.extend_from_slice(&s.to_le_bytes());
+            }
+        }
+
+        let mut probe = Probe::default();
+        probe.register(&Descriptor {
+            short_name: "spec",
+            long_name: "Spec changing test format",
+            extensions: &[],
+            mime_types: &[],
+            markers: &[b"SPEC"],
+            score: |_| 255,
+            inst: Instantiate::Format(|src, opt| {
+                Ok(Box::new(SpecStream::try_new(src, opt)?))
+            }),
+        });
+        let mut codecs = CodecRegistry::new();
+        codecs.register_all::<SpecDecoder>();
+
+        let mss = MediaSourceStream::new(
+            Box::new(Cursor::new(data)),
+            Default::default(),
+        );
+        let pres = probe
+            .format(
+                &Hint::new(),
+                mss,
+                &Default::default(),
+                &Default::default(),
+            )
+            .unwrap();
+        Symph::with_codecs(pres, 0, false, &codecs).unwrap()
+    }
+
+    /// Reads `len` samples from the source.
+    fn read_f32(symph: &mut Symph, len: usize) -> Vec<f32> {
+        let mut data = vec![0.; len];
+        let (cnt, res) = symph.read(&mut SampleBufferMut::F32(&mut data));
+        res.unwrap();
+        data.truncate(cnt);
+        data
+    }
+
+    const MONO: &[f32] = &[0.5];
+    const STEREO: &[f32] = &[0.25, -0.25];
+
+    #[test]
+    fn spec_change_is_reported_on_frame_boundary() {
+        // 30 ms of mono 44.1 kHz followed by 30 ms of stereo 48 kHz
+        let mut packets = vec![(44100, MONO); 3];
+        packets.extend([(48000, STEREO); 3]);
+        let mut symph = spec_stream(&packets);
+
+        let mono_config = DeviceConfig {
+            channel_count: 1,
+            sample_rate: 44100,
+            sample_format: SampleFormat::F32,
+        };
+        assert_eq!(symph.preferred_config(), Some(mono_config.clone()));
+        symph
+            .init(&DeviceConfig {
+                channel_count: 2,
+                ..mono_config
+            })
+            .unwrap();
+
+        // Two mono packets
+        let data = read_f32(&mut symph, 441 * 4);
+        assert!(data.chunks(2).all(|f| f == [0.5, 0.]));
+        assert_eq!(symph.take_spec_change(), None);
+        let mono = symph.source_spec().unwrap();
+        assert_eq!((mono.channel_count, mono.sample_rate), (1, 44100));
+
+        // The rest of the mono packet and part of the stereo packet
+        let data = read_f32(&mut symph, 441 * 2 + 200);
+        assert_eq!(data.len(), 441 * 2 + 200);
+        assert!(data[..441 * 2].chunks(2).all(|f| f == [0.5, 0.]));
+
+        let stereo = symph.source_spec().unwrap();
+        assert_eq!((stereo.channel_count, stereo.sample_rate), (2, 48000));
+        assert_eq!(symph.take_spec_change(), Some((mono, stereo)));
+        assert_eq!(symph.take_spec_change(), None);
+
+        let stereo_config = DeviceConfig {
+            channel_count: 2,
+            sample_rate: 48000,
+            sample_format: SampleFormat::F32,
+        };
+        assert_eq!(symph.wants_reconfigure(), Some(stereo_config.clone()));
+        assert_eq!(symph.preferred_config(), Some(stereo_config.clone()));
+
+        // After reconfiguration, the samples are read directly and the
+        // frames of the new spec are not misaligned.
+        symph.init(&stereo_config).unwrap();
+        assert_eq!(symph.wants_reconfigure(), None);
+        let data = read_f32(&mut symph, 48000);
+        assert!(!data.is_empty());
+        assert!(data.chunks(2).all(|f| f == STEREO));
+    }
+
+    #[test]
+    fn change_back_is_not_reported() {
+        let mut symph =
+            spec_stream(&[(44100, MONO), (48000, STEREO), (44100, MONO)]);
+        symph
+            .init(&DeviceConfig {
+                channel_count: 2,
+                sample_rate: 44100,
+                sample_format: SampleFormat::F32,
+            })
+            .unwrap();
+
+        // Unreported changes are merged.
+        read_f32(&mut symph, 48000);
+        assert_eq!(symph.take_spec_change(), None);
+    }
+
+    #[test]
+    fn seek_by_clamps_to_file() {
+        let src = Cursor::new(wav(4800));
+        let mut symph = Symph::try_new(src, &SymphOptions::default()).unwrap();
+        let config = DeviceConfig {
+            channel_count: 2,
+            sample_rate: 48000,
+            sample_format: cpal::SampleFormat::F32,
+        };
+        symph.init(&config).unwrap();
+        let mut data = vec![0.; 960];
+        symph.read(&mut SampleBufferMut::F32(&mut data)).1.unwrap();
+
+        let start = symph.seek_by(Duration::from_secs(1), false).unwrap();
+        assert_eq!(start.current, Duration::ZERO);
+        assert_eq!(start.total, Duration::from_millis(100));
+
+        let end = symph.seek_by(Duration::from_secs(1), true).unwrap();
+        // The seek is coarse, so it lands on the start of the last packet.
+        assert!(end.current <= end.total);
+        assert!(
+            end.total - end.current < Duration::from_millis(10),
+            "{end:?}"
+        );
+
+        // Only the last packet remains.
+        let (cnt, res) = symph.read(&mut SampleBufferMut::F32(&mut data));
+        res.unwrap();
+        assert!(cnt < data.len());
+    }
+}
@@ -0,0 +1,41 @@
+use cpal::FromSample;
+
+use super::VolumeIterator;
+
+/// Fills the interleaved buffer with samples from `sample`. Each call to
+/// `sample` produces one frame, the value is scaled by `volume` and written
+/// to all the channels. Incomplete frame at the end is left untouched.
+pub(super) fn fill_frames<T: FromSample<f32> + Clone>(
+    data: &mut [T],
+    channels: u32,
+    volume: &mut VolumeIterator,
+    mut sample: impl FnMut() -> f32,
+) {
+    if channels == 0 {
+        return;
+    }
+    if let VolumeIterator::Constant(v) = *volume {
+        // Don't query the volume for each frame.
+        for frame in data.chunks_exact_mut(channels as usize) {
+            frame.fill(T::from_sample_(sample() * v));
+        }
+        return;
+    }
+    for frame in data.chunks_exact_mut(channels as usize) {
+        frame.fill(T::from_sample_(sample() * volume.next_vol()));
+    }
+}
+
+/// Correction of the step at phase 0 (polyBLEP), `dt` is the phase step of
+/// one sample.
+pub(super) fn poly_blep(t: f32, dt: f32) -> f32 {
+    if t < dt {
+        let t = t / dt;
+        2. * t - t * t - 1.
+    } else if t > 1. - dt {
+        let t = (t - 1.) / dt;
+        t * t + 2. * t + 1.
+    } else {
+        0.
+    }
+}
@@ -0,0 +1,176 @@
+use std::time::Instant;
+
+use cpal::{FromSample, Sample};
+
+/// Format of the audio produced by the analysis tap, see
+/// [`crate::Sink::set_analysis_tap`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnalysisTapConfig {
+    /// Sample rate of the tapped audio
+    pub sample_rate: u32,
+    /// Number of channels of the tapped audio
+    pub channels: u32,
+}
+
+impl AnalysisTapConfig {
+    /// Creates new tap configuration
+    pub fn new(sample_rate: u32, channels: u32) -> Self {
+        Self {
+            sample_rate,
+            channels,
+        }
+    }
+}
+
+/// Function that receives the tapped audio
+pub(crate) type TapFn = Box<dyn FnMut(&[f32]) + Send>;
+
+/// Function that receives the samples sent to the device
+pub(crate) type SampleTapFn = Box<dyn FnMut(&SampleTap) + Send>;
+
+/// Samples sent to the device, see [`crate::Sink::on_samples`]
+#[derive(Debug, Clone, Copy)]
+pub struct SampleTap<'a> {
+    /// Interleaved samples converted to f32
+    pub samples: &'a [f32],
+    /// Number of channels of the device
+    pub channels: u32,
+    /// Sample rate of the device
+    pub sample_rate: u32,
+    /// Time when the samples will be played
+    pub play_time: Instant,
+}
+
+/// Maximum number of frames passed to the tap function at once
+const CHUNK_FRAMES: usize = 1024;
+
+/// Converts the output to the format of the tap with cheap downmix and linear
+/// resampling and passes it to the tap function.
+pub(crate) struct AnalysisTap {
+    /// The output format
+    config: AnalysisTapConfig,
+    /// Function that receives the converted audio
+    f: TapFn,
+    /// Sample rate and number of channels of the input
+    input: (u32, u32),
+    /// Step of the position for each output frame (input rate / output rate)
+    step: f64,
+    /// Position of the next output frame between the previous and the
+    /// current input frame
+    pos: f64,
+    /// True if the previous frame is valid
+    started: bool,
+    /// The previous input frame, mixed to the output channels
+    prev: Vec<f32>,
+    /// The current input frame, mixed to the output channels
+    cur: Vec<f32>,
+    /// Index of the channel in the current input frame
+    channel: usize,
+    /// Converted samples that weren't passed to the function yet
+    out: Vec<f32>,
+}
+
+impl AnalysisTap {
+    /// Creates new tap. All the memory is allocated here.
+    pub(crate) fn new(config: AnalysisTapConfig, f: TapFn) -> Self {
+        let channels = config.channels.max(1) as usize;
+        Self {
+            config,
+            f,
+            input: (0, 0),
+            step: 1.,
+            pos: 0.,
+            started: false,
+            prev: vec![0.; channels],
+            cur: vec![0.; channels],
+            channel: 0,
+            out: Vec::with_capacity(CHUNK_FRAMES * channels),
+        }
+    }
+
+    /// Converts the output samples of a device with the given sample rate
+    /// and number of channels and passes them to the tap function. The
+    /// converter is reset if the format of the device changes.
+    pub(crate) fn process<T>(&mut self, data: &[T], rate: u32, channels: u32)
+    where
+        T: Sample,
+        f32: FromSample<T>,
+    {
+        if self.input != (rate, channels) {
+            self.reset(rate, channels);
+        }
+
+        let in_channels = channels.max(1) as usize;
+        let out_channels = self.cur.len();
+        for s in data {
+            let s = f32::from_sample_(*s);
+            if out_channels == 1 {
+                self.cur[0] += s / in_channels as f32;
+            } else if self.channel < out_channels {
+                self.cur[self.channel] = s;
+            }
+
+            self.channel += 1;
+            if self.channel < in_channels {
+                continue;
+            }
+            self.channel = 0;
+
+            // Duplicate the channels if the output has more channels
+            for c in in_channels..out_channels {
+                self.cur[c] = self.cur[c % in_channels];
+            }
+
+            self.push_frame();
+        }
+
+        self.flush();
+    }
+
+    /// Resamples with the completed current frame
+    fn push_frame(&mut self) {
+        if !self.started {
+            self.started = true;
+            std::mem::swap(&mut self.prev, &mut self.cur);
+            self.cur.fill(0.);
+            return;
+        }
+
+        while self.pos < 1. {
+            if self.out.len() + self.cur.len() > self.out.capacity() {
+                self.flush();
+            }
+            let pos = self.pos as f32;
+            self.out.extend(
+                self.prev
+                    .iter()
+                    .zip(&self.cur)
+                    .map(|(a, b)| a + (b - a) * pos),
+            );
+            self.pos += self.step;
+        }
+        self.pos -= 1.;
+
+        std::mem::swap(&mut self.prev, &mut self.cur);
+        self.cur.fill(0.);
+    }
+
+    /// Passes the converted samples to the tap function
+    fn flush(&mut self) {
+        if !self.out.is_empty() {
+            (self.f)(&self.out);
+            self.out.clear();
+        }
+    }
+
+    /// Resets the converter for input with the given format
+    fn reset(&mut self, rate: u32, channels: u32) {
+        self.input = (rate, channels);
+        self.step = rate.max(1) as f64 / self.config.sample_rate.max(1) as f64;
+        self.pos = 0.;
+        self.started = false;
+        self.channel = 0;
+        self.cur.fill(0.);
+        self.out.clear();
+    }
+}
@@ -0,0 +1,169 @@
+//! Utilities for checking the output of the converters against stored
+//! golden outputs. Enabled with the `test-util` feature.
+//!
+//! The golden outputs of the converters of this crate are stored in
+//! `tests/golden`. Run the tests with the environment variable
+//! `RAPLAY_UPDATE_GOLDEN` set to update them after intended change of the
+//! converters.
+
+use std::{
+    f32::consts::PI,
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use crate::converters::{channels, rate};
+
+#[cfg(feature = "test-util")]
+pub use crate::clock::ManualClock;
+
+/// Generates sine sweep from `start` to `end` frequency (Hz) with the given
+/// `sample_rate` and `len` samples.
+pub fn sine_sweep(
+    start: f32,
+    end: f32,
+    sample_rate: u32,
+    len: usize,
+) -> Vec<f32> {
+    let rate = sample_rate as f32;
+    let mut phase = 0_f32;
+    (0..len)
+        .map(|i| {
+            let f = start + (end - start) * i as f32 / len.max(1) as f32;
+            let s = phase.sin();
+            phase = (phase + 2. * PI * f / rate) % (2. * PI);
+            s
+        })
+        .collect()
+}
+
+/// Generates `len` samples with single impulse at `at`.
+pub fn impulse(at: usize, len: usize) -> Vec<f32> {
+    (0..len).map(|i| if i == at { 1. } else { 0. }).collect()
+}
+
+/// Generates interleaved signal with `channels` channels and `frames`
+/// frames where each channel has constant value that identifies it. The
+/// channel `n` has the value `(n + 1) / channels`.
+pub fn channel_id(channels: u32, frames: usize) -> Vec<f32> {
+    (0..frames * channels as usize)
+        .map(|i| ((i % channels as usize) + 1) as f32 / channels as f32)
+        .collect()
+}
+
+/// Converts the sample rate of the signal with the rate converter.
+pub fn render_rate(
+    signal: &[f32],
+    source_rate: u32,
+    target_rate: u32,
+) -> Vec<f32> {
+    rate(signal.iter().copied(), source_rate, target_rate).collect()
+}
+
+/// Converts the channel count of the interleaved signal with the channel
+/// converter.
+pub fn render_channels(
+    signal: &[f32],
+    source_channels: u32,
+    target_channels: u32,
+) -> Vec<f32> {
+    channels(signal.iter().copied(), source_channels, target_channels)
+        .collect()
+}
+
+/// Result of comparison of two signals
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Comparison {
+    /// The maximum absolute difference of samples
+    pub max_error: f32,
+    /// Signal to noise ratio in decibels where noise is the difference
+    /// between the signals. Infinite if the signals are the same.
+    pub snr: f32,
+    /// True if the signals have the same length
+    pub same_len: bool,
+}
+
+impl Comparison {
+    /// Checks whether the signals have the same length and the maximum
+    /// error is within the tolerance.
+    pub fn within(&self, tolerance: f32) -> bool {
+        self.same_len && self.max_error <= tolerance
+    }
+}
+
+/// Compares the `output` to the `expected` signal.
+pub fn compare(output: &[f32], expected: &[f32]) -> Comparison {
+    let mut max_error = 0_f32;
+    let mut signal = 0_f64;
+    let mut noise = 0_f64;
+    for (o, e) in output.iter().zip(expected) {
+        let d = o - e;
+        max_error = max_error.max(d.abs());
+        signal += (*e as f64) * (*e as f64);
+        noise += (d as f64) * (d as f64);
+    }
+
+    let snr = if noise == 0. {
+        f32::INFINITY
+    } else {
+        (10. * (signal / noise).log10()) as f32
+    };
+
+    Comparison {
+        max_error,
+        snr,
+        same_len: output.len() == expected.len(),
+    }
+}
+
+/// Reads golden output stored as little endian `f32` samples.
+pub fn read_golden(path: impl AsRef<Path>) -> io::Result<Vec<f32>> {
+    let data = fs::read(path)?;
+    if data.len() % 4 != 0 {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "golden file length is not multiple of 4",
+        ));
+    }
+    Ok(data
+        .chunks_exact(4)
+        .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect())
+}
+
+/// Writes golden output as little endian `f32` samples.
+pub fn write_golden(path: impl AsRef<Path>, data: &[f32]) -> io::Result<()> {
+    let bytes: Vec<u8> = data.iter().flat_map(|s| s.to_le_bytes()).collect();
+    fs::write(path, bytes)
+}
+
+/// Compares the `output` to the golden output stored at `path`. If the
+/// environment variable `RAPLAY_UPDATE_GOLDEN` is set, the golden file is
+/// written with the `output` and the comparison is exact.
+///
+/// # Errors
+/// - the golden file doesn't exist and `RAPLAY_UPDATE_GOLDEN` is not set
+/// - failed to read or write the golden file
+pub fn check_golden(
+    path: impl AsRef<Path>,
+    output: &[f32],
+) -> io::Result<Comparison> {
+    let path = path.as_ref();
+    if std::env::var_os("RAPLAY_UPDATE_GOLDEN").is_some() {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        write_golden(path, output)?;
+    } else if !path.exists() {
+        return Err(io::Error::new(
+            ErrorKind::NotFound,
+            format!(
+                "golden file {} is missing, set RAPLAY_UPDATE_GOLDEN to \
+                create it",
+                path.display()
+            ),
+        ));
+    }
+    Ok(compare(output, &read_golden(path)?))
+}
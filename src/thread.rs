@@ -0,0 +1,131 @@
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, PoisonError,
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::err::Result;
+
+/// Information about a thread spawned by the library
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThreadInfo {
+    /// Name of the thread
+    pub name: String,
+    /// True if the thread tried to raise its priority
+    pub elevation_requested: bool,
+    /// True if the thread requested elevated priority and it succeeded
+    pub elevated: bool,
+}
+
+/// Live threads spawned by the library, identified by unique id
+static THREADS: Mutex<Vec<(u64, ThreadInfo)>> = Mutex::new(Vec::new());
+
+/// Id of the next spawned thread
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Gets information about the live threads spawned by the library
+pub(crate) fn list() -> Result<Vec<ThreadInfo>> {
+    Ok(THREADS.lock()?.iter().map(|(_, t)| t.clone()).collect())
+}
+
+/// Spawns new thread with the given name. If `elevate` is true, the thread
+/// will try to raise its priority. The thread is listed in the registry
+/// while it runs.
+pub(crate) fn spawn<F, R>(
+    name: &str,
+    elevate: bool,
+    f: F,
+) -> io::Result<JoinHandle<R>>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let name = format!("raplay-{name}");
+    thread::Builder::new().name(name.clone()).spawn(move || {
+        let elevated = elevate && elevate_priority();
+        let _registration = Registration::new(ThreadInfo {
+            name,
+            elevation_requested: elevate,
+            elevated,
+        });
+        f()
+    })
+}
+
+/// Entry in the list of live threads. It is removed on drop, so the thread
+/// is unlisted even if it panics.
+struct Registration(u64);
+
+impl Registration {
+    /// Adds the current thread to the list of live threads
+    fn new(info: ThreadInfo) -> Self {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        THREADS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push((id, info));
+        Self(id)
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        THREADS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|(id, _)| *id != self.0);
+    }
+}
+
+/// Tries to raise the priority of the current thread. Returns true on
+/// success.
+#[cfg(target_os = "linux")]
+fn elevate_priority() -> bool {
+    // SAFETY: the parameters are valid and the function only affects the
+    // current thread.
+    unsafe {
+        let policy = libc::SCHED_RR;
+        let param = libc::sched_param {
+            sched_priority: libc::sched_get_priority_min(policy).max(1),
+        };
+        libc::pthread_setschedparam(libc::pthread_self(), policy, &param) == 0
+    }
+}
+
+/// Tries to raise the priority of the current thread. Returns true on
+/// success.
+#[cfg(not(target_os = "linux"))]
+fn elevate_priority() -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    fn is_listed(name: &str) -> bool {
+        list().unwrap().iter().any(|t| t.name == name)
+    }
+
+    #[test]
+    fn panicked_thread_is_unlisted() {
+        let (listed_send, listed_recv) = mpsc::channel();
+        let (panic_send, panic_recv) = mpsc::channel::<()>();
+        let handle = spawn("test-panic", false, move || {
+            listed_send.send(is_listed("raplay-test-panic")).unwrap();
+            _ = panic_recv.recv();
+            panic!("test panic");
+        })
+        .unwrap();
+
+        assert!(listed_recv.recv().unwrap());
+        drop(panic_send);
+        assert!(handle.join().is_err());
+        assert!(!is_listed("raplay-test-panic"));
+    }
+}
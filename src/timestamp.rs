@@ -14,4 +14,10 @@ impl Timestamp {
     pub fn new(current: Duration, total: Duration) -> Self {
         Self { current, total }
     }
+
+    /// Gets the remaining time until the end. This is in the time of the
+    /// source (see [`crate::source::Source::get_time`]).
+    pub fn remaining(&self) -> Duration {
+        self.total.saturating_sub(self.current)
+    }
 }
@@ -0,0 +1,90 @@
+#!/usr/bin/env python3
+"""Generates the corpus of valid and malformed files used by the decoding
+robustness tests. The output is deterministic."""
+
+import math
+import os
+import random
+import struct
+
+os.chdir(os.path.dirname(os.path.abspath(__file__)))
+
+
+def sine(i):
+    return math.sin(i * 2 * math.pi * 440 / 48000)
+
+
+def pcm(frames, ch=2):
+    return b''.join(
+        struct.pack('<h', int(8000 * sine(i)))
+        for i in range(frames) for _ in range(ch))
+
+
+def wav(data, fmt=1, ch=2, rate=48000, bits=16, datalen=None):
+    ba = ch * bits // 8
+    f = struct.pack(
+        '<HHIIHH', fmt, ch, rate, (rate * ba) & 0xffffffff, ba, bits)
+    if datalen is None:
+        datalen = len(data)
+    body = b'WAVE' + struct.pack('<4sI', b'fmt ', len(f)) + f
+    body += struct.pack('<4sI', b'data', datalen) + data
+    return b'RIFF' + struct.pack('<I', len(body)) + body
+
+
+def ext80(v):
+    e = int(math.floor(math.log2(v)))
+    return struct.pack('>HQ', e + 16383, int(v * 2 ** (63 - e)))
+
+
+def aiff(frames, ch=2, rate=48000):
+    data = b''.join(
+        struct.pack('>h', int(8000 * sine(i)))
+        for i in range(frames) for _ in range(ch))
+    comm = struct.pack('>hIh', ch, frames, 16) + ext80(rate)
+    ssnd = struct.pack('>II', 0, 0) + data
+    body = b'AIFF' + struct.pack('>4sI', b'COMM', len(comm)) + comm
+    body += struct.pack('>4sI', b'SSND', len(ssnd)) + ssnd
+    return b'FORM' + struct.pack('>I', len(body)) + body
+
+
+rng = random.Random(754)
+flt = b''.join(
+    struct.pack('<f', 0.25 * sine(i)) for i in range(4800) for _ in range(2))
+
+files = {
+    'valid.wav': wav(pcm(4800)),
+    'valid_float.wav': wav(flt, fmt=3, bits=32),
+    'valid.aiff': aiff(4800),
+}
+v = files['valid.wav']
+a = files['valid.aiff']
+
+files['truncated_header.wav'] = v[:30]
+files['truncated_data.wav'] = v[:len(v) // 2 + 1]
+files['zero_channels.wav'] = wav(pcm(4800), ch=0)
+files['huge_rate.wav'] = wav(pcm(4800), rate=0xffffffff)
+files['zero_rate.wav'] = wav(pcm(4800), rate=0)
+files['huge_data_size.wav'] = wav(pcm(4800), datalen=0xfffffff0)
+files['odd_bits.wav'] = wav(pcm(4800), bits=13)
+files['truncated.aiff'] = a[:len(a) // 3]
+
+for name, src in [('bitflip.wav', v), ('bitflip.aiff', a)]:
+    for k in range(4):
+        b = bytearray(src)
+        for _ in range(8):
+            b[rng.randrange(len(b))] ^= 1 << rng.randrange(8)
+        files[name.replace('.', f'_{k}.')] = bytes(b)
+
+# flips in the headers
+for name, src, hlen in [('header_flip.wav', v, 44), ('header_flip.aiff', a, 54)]:
+    for k in range(4):
+        b = bytearray(src)
+        for _ in range(2):
+            b[rng.randrange(hlen)] ^= 1 << rng.randrange(8)
+        files[name.replace('.', f'_{k}.')] = bytes(b)
+
+files['garbage.bin'] = bytes(rng.randrange(256) for _ in range(4096))
+
+for name, data in files.items():
+    with open(name, 'wb') as f:
+        f.write(data)